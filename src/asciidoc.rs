@@ -0,0 +1,188 @@
+//! AsciiDoc output for Asciidoctor toolchains, a document per note or per notebook.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::render::{render, Markup};
+use crate::writer::{ExportInfo, NoteWriter};
+use pulldown_cmark::Tag;
+use std::io::Write;
+
+/// URLs with a scheme are links by themselves; other targets need the `link:` macro.
+fn link_target(url: &str) -> String {
+    if url.contains("://") || url.starts_with("mailto:") {
+        url.to_string()
+    } else {
+        format!("link:{}", url)
+    }
+}
+
+/// Write `note` as a standalone document, with its metadata as header attributes.
+pub fn write_document<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    let title = note.title.as_deref().unwrap_or("untitled");
+    writeln!(writer, "= {}", title)?;
+    if let Some(ref created) = note.created {
+        writeln!(writer, ":created: {}", created.format("%F %R"))?;
+    }
+    if let Some(ref updated) = note.updated {
+        writeln!(writer, ":revdate: {}", updated.format("%F %R"))?;
+    }
+    if !note.tags.is_empty() {
+        writeln!(writer, ":keywords: {}", note.tags.join(", "))?;
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        writeln!(writer, ":source-url: {}", source_url)?;
+    }
+    writeln!(writer)?;
+
+    let content = note.content_markdown(enml);
+    writeln!(writer, "{}", to_asciidoc(&content, 1))?;
+
+    Ok(())
+}
+
+/// Write `note` as a section of a notebook document, with its metadata in a line below the
+/// title since sections can't have attributes of their own.
+pub fn write_section<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    let title = note.title.as_deref().unwrap_or("untitled");
+    writeln!(writer, "== {}\n", title)?;
+    let mut metadata = Vec::new();
+    if let Some(ref created) = note.created {
+        metadata.push(format!("Created: {}", created.format("%F %R")));
+    }
+    if !note.tags.is_empty() {
+        metadata.push(format!("Tags: {}", note.tags.join(", ")));
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        metadata.push(format!("Source: {}", source_url));
+    }
+    if !metadata.is_empty() {
+        writeln!(writer, "_{}_\n", metadata.join(" · "))?;
+    }
+
+    let content = note.content_markdown(enml);
+    writeln!(writer, "{}\n", to_asciidoc(&content, 2))?;
+
+    Ok(())
+}
+
+/// Writes a notebook as a single document, with a section per note.
+pub struct DocumentWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> DocumentWriter<W> {
+    /// Write the document to `writer`.
+    pub fn new(writer: W) -> Self {
+        DocumentWriter { writer }
+    }
+}
+
+impl<W: Write> NoteWriter for DocumentWriter<W> {
+    fn begin(&mut self, export: &ExportInfo) -> Result<()> {
+        writeln!(self.writer, "= {}\n", export.name)?;
+        Ok(())
+    }
+
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        write_section(&mut self.writer, note, enml)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Render Markdown as AsciiDoc, with headings demoted by `heading_offset` levels to fit under
+/// the note title.
+pub fn to_asciidoc(markdown: &str, heading_offset: usize) -> String {
+    render(markdown, &mut AsciiDoc { heading_offset })
+}
+
+struct AsciiDoc {
+    heading_offset: usize,
+}
+
+impl Markup for AsciiDoc {
+    fn start(&mut self, out: &mut String, tag: Tag<'_>) {
+        match tag {
+            Tag::Rule => out.push_str("'''\n\n"),
+            Tag::Header(level) => {
+                out.push_str(&"=".repeat(level as usize + self.heading_offset));
+                out.push(' ');
+            }
+            Tag::BlockQuote => out.push_str("____\n"),
+            Tag::CodeBlock(ref lang) if lang.is_empty() => out.push_str("----\n"),
+            Tag::CodeBlock(lang) => out.push_str(&format!("[source,{}]\n----\n", lang)),
+            Tag::Table(_) => out.push_str("|===\n"),
+            Tag::TableCell => out.push('|'),
+            Tag::Emphasis => out.push('_'),
+            Tag::Strong => out.push('*'),
+            Tag::Code => out.push('`'),
+            Tag::Link(url, _) => out.push_str(&format!("{}[", link_target(&url))),
+            Tag::Image(url, _) => out.push_str(&format!("image:{}[", url)),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, out: &mut String, tag: Tag<'_>) {
+        match tag {
+            Tag::Header(_) => out.push_str("\n\n"),
+            Tag::BlockQuote => out.push_str("____\n\n"),
+            Tag::CodeBlock(_) => out.push_str("----\n\n"),
+            // A blank line after the first row makes it the header.
+            Tag::TableHead => out.push_str("\n\n"),
+            Tag::TableRow => out.push('\n'),
+            Tag::Table(_) => out.push_str("|===\n\n"),
+            Tag::Emphasis => out.push('_'),
+            Tag::Strong => out.push('*'),
+            Tag::Code => out.push('`'),
+            Tag::Link(_, _) | Tag::Image(_, _) => out.push(']'),
+            _ => {}
+        }
+    }
+
+    fn item(&mut self, depth: usize, number: Option<usize>) -> String {
+        let marker = if number.is_some() { "." } else { "*" };
+        format!("{} ", marker.repeat(depth))
+    }
+
+    fn html(&mut self, out: &mut String, html: &str) {
+        out.push_str("++++\n");
+        out.push_str(html);
+        out.push_str("++++\n\n");
+    }
+
+    fn inline_html(&mut self, out: &mut String, html: &str) {
+        out.push_str(&format!("pass:[{}]", html));
+    }
+
+    fn footnote_reference(&mut self, out: &mut String, name: &str) {
+        out.push_str(&format!("footnote:{}[]", name));
+    }
+
+    fn hard_break(&mut self, out: &mut String) {
+        out.push_str(" +\n");
+    }
+}
+
+#[test]
+fn test_to_asciidoc() {
+    let markdown = "# Shopping\n\n- **milk**\n- eggs\n  1. brown\n\n\
+                    See [site](http://x.com) and ![cat.png](files/cat.png)\n\n\
+                    | a | b |\n| --- | --- |\n| 1 | 2 |";
+    assert_eq!(
+        to_asciidoc(markdown, 1),
+        "== Shopping\n\n* *milk*\n* eggs\n.. brown\n\n\
+         See http://x.com[site] and image:files/cat.png[cat.png]\n\n\
+         |===\n| a | b \n\n| 1 | 2 \n|==="
+    );
+}
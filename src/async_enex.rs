@@ -0,0 +1,179 @@
+//! Parses a .enex export from an `AsyncRead`, for use in async code without blocking on input.
+//!
+//! Input is buffered a note at a time and each note is parsed by `EnexParser`, so a note's
+//! content is never parsed until all of it has been read. Notes are split at `</note>`, which
+//! can't otherwise appear in an export since content and attributes escape `<`. Positions in
+//! errors are thus relative to the start of the note rather than the file.
+
+use crate::enex::{EnexParser, Note};
+use crate::error::Result;
+use crate::quirks::Quirks;
+use chrono::{DateTime, Local};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const NOTE_END: &[u8] = b"</note>";
+const EXPORT_END: &[u8] = b"</en-export>";
+
+/// Like `EnexParser`, but `next` is async.
+///
+/// # Example
+///
+/// ```
+/// use enex2mf::AsyncEnexParser;
+/// # async fn titles() -> enex2mf::Result<Vec<String>> {
+/// let buf: &[u8] = b"<en-export><note><title>foo</title></note></en-export>";
+/// let mut parser = AsyncEnexParser::new(buf);
+/// let mut titles = Vec::new();
+/// while let Some(note) = parser.next().await {
+///     titles.extend(note?.title);
+/// }
+/// # Ok(titles)
+/// # }
+/// ```
+pub struct AsyncEnexParser<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    /// How much of `buffer` is known not to contain `</note>`.
+    scanned: usize,
+    lenient: bool,
+    /// Set by the first note, as later ones are parsed without `<en-export>`.
+    quirks: Option<Quirks>,
+    export_date: Option<DateTime<Local>>,
+    started: bool,
+    done: bool,
+    skipped: Vec<String>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncEnexParser<R> {
+    /// Parse the export read from `reader`.
+    pub fn new(reader: R) -> Self {
+        AsyncEnexParser {
+            reader,
+            buffer: Vec::new(),
+            scanned: 0,
+            lenient: false,
+            quirks: None,
+            export_date: None,
+            started: false,
+            done: false,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// See `EnexParser::lenient`.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// See `EnexParser::with_quirks`.
+    pub fn with_quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// The next note, or `None` at the end of the export or after an error.
+    pub async fn next(&mut self) -> Option<Result<Note>> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_note().await;
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result.transpose()
+    }
+
+    /// The export date, once the first note (or the end of an empty export) has been read.
+    pub fn export_date(&self) -> Option<DateTime<Local>> {
+        self.export_date
+    }
+
+    /// Unrecognized elements skipped so far in lenient mode.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    async fn read_note(&mut self) -> Result<Option<Note>> {
+        loop {
+            let from = self.scanned.saturating_sub(NOTE_END.len() - 1);
+            if let Some(pos) = find(&self.buffer[from..], NOTE_END) {
+                let chunk: Vec<u8> = self.buffer.drain(..from + pos + NOTE_END.len()).collect();
+                self.scanned = 0;
+                return self.parse(&chunk, false);
+            }
+            self.scanned = self.buffer.len();
+
+            let mut block = [0; 8192];
+            let n = self.reader.read(&mut block).await?;
+            if n == 0 {
+                let rest = std::mem::take(&mut self.buffer);
+                return self.parse(&rest, true);
+            }
+            self.buffer.extend_from_slice(&block[..n]);
+        }
+    }
+
+    /// Parse a piece of the export holding at most one note. Unless it's the first, it's missing
+    /// `<en-export>`; unless it's the last, it's missing `</en-export>`.
+    fn parse(&mut self, chunk: &[u8], last: bool) -> Result<Option<Note>> {
+        let mut xml = Vec::with_capacity(chunk.len() + 32);
+        if self.started {
+            xml.extend_from_slice(b"<en-export>");
+        }
+        xml.extend_from_slice(chunk);
+        if !last {
+            xml.extend_from_slice(EXPORT_END);
+        }
+
+        let mut parser = EnexParser::new(&xml[..]).lenient(self.lenient);
+        if let Some(quirks) = self.quirks {
+            parser = parser.with_quirks(quirks);
+        }
+        parser.start()?;
+        if !self.started {
+            self.export_date = parser.export_date();
+            self.quirks = Some(parser.quirks());
+            self.started = true;
+        }
+        let note = parser.next().transpose();
+        let rest = parser.next().transpose();
+        self.skipped.extend_from_slice(parser.skipped());
+        let note = note?;
+        rest?;
+        Ok(note)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[test]
+fn test_async_parser() {
+    let enex = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <en-export export-date="20181226T083916Z">
+        <note><title>foo</title><content><![CDATA[<en-note>a &lt;/note&gt;</en-note>]]></content></note>
+        <note><title>bar</title><future/></note>
+        </en-export>"#;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut parser = AsyncEnexParser::new(enex.as_bytes()).lenient(true);
+        let foo = parser.next().await.unwrap().unwrap();
+        assert_eq!(foo.title.as_deref(), Some("foo"));
+        assert!(parser.export_date().is_some());
+        let bar = parser.next().await.unwrap().unwrap();
+        assert_eq!(bar.title.as_deref(), Some("bar"));
+        assert!(parser.next().await.is_none());
+        assert_eq!(parser.skipped(), ["future"]);
+
+        let truncated = &enex[..enex.len() - "</en-export>".len()];
+        let mut parser = AsyncEnexParser::new(truncated.as_bytes()).lenient(true);
+        assert!(parser.next().await.unwrap().is_ok());
+        assert!(parser.next().await.unwrap().is_ok());
+        assert!(parser.next().await.unwrap().is_err());
+        assert!(parser.next().await.is_none());
+    });
+}
@@ -0,0 +1,986 @@
+//! Writes note resources to disk (or inlines them) so converted notes can link to them.
+
+use crate::enex::{Note, Resource};
+use crate::enml::{self, Media};
+use crate::error::Result;
+use crate::images::Shrink;
+use crate::naming::{self, NameStyle, UniqueNames};
+use crate::zip::{Checksum, ZipWriter};
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Where to put attachments and how to link them.
+#[derive(Clone, Debug, Default)]
+pub struct AttachmentOptions {
+    /// Directory to extract attachments into; they are dropped if unset.
+    pub dir: Option<PathBuf>,
+    /// Embed images as `data:` URIs instead of extracting them.
+    pub inline_images: bool,
+    /// Directory that links to extracted files are relative to, if not the working directory.
+    pub links_relative_to: Option<PathBuf>,
+    /// What to do with the other form of resources that have `<alternate-data>`.
+    pub alternate_data: AlternateData,
+    /// Name extracted files after the attachment rather than by hash, if set.
+    pub names: Option<AttachmentNames>,
+    /// Shrink extracted images, if set.
+    pub shrink: Option<Shrink>,
+    /// How extracted files are arranged in `dir`.
+    pub layout: AttachmentLayout,
+    /// Collect attachments in this archive rather than extract them to `dir`, if set.
+    pub archive: Option<AttachmentArchive>,
+}
+
+/// How extracted files are arranged in the attachments directory, to suit the app that reads
+/// the notes.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AttachmentLayout {
+    /// All in the directory, as MindForger and Obsidian keep them.
+    #[default]
+    Shared,
+    /// In a folder per note named after its title, e.g. `Recipes.assets`, as Typora does.
+    PerNote,
+    /// In folders by the year and month the note was created, e.g. `2020/01`, as static site
+    /// generators like Hugo often do.
+    Date,
+}
+
+impl AttachmentLayout {
+    /// The directory in `dir` for the attachments of `note`.
+    pub fn note_dir(self, dir: &Path, note: &Note) -> PathBuf {
+        match self {
+            AttachmentLayout::Shared => dir.to_owned(),
+            AttachmentLayout::PerNote => {
+                let title = note.title.as_deref().unwrap_or("untitled");
+                dir.join(format!("{}.assets", naming::sanitize(title)))
+            }
+            AttachmentLayout::Date => match note.created {
+                Some(created) => dir.join(created.format("%Y/%m").to_string()),
+                None => dir.join("undated"),
+            },
+        }
+    }
+}
+
+/// How to name extracted files.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentNaming {
+    /// By the MD5 hash of the contents, e.g. `5d41402abc4b2a76b9719d911017c592.png`.
+    #[default]
+    Hash,
+    /// After the attachment's file name, else the note's title, else the hash.
+    Original,
+}
+
+/// Names of files extracted under `AttachmentNaming::Original`, unique in each directory and
+/// shared by all the threads converting notes into it. An attachment found in several notes still
+/// gets one file.
+#[derive(Clone, Debug, Default)]
+pub struct AttachmentNames {
+    style: NameStyle,
+    dirs: Arc<Mutex<HashMap<PathBuf, DirNames>>>,
+}
+
+#[derive(Debug, Default)]
+struct DirNames {
+    unique: UniqueNames,
+    /// The file each hash was given.
+    by_hash: HashMap<String, String>,
+}
+
+impl AttachmentNames {
+    /// Names made from titles and file names in `style`.
+    pub fn new(style: NameStyle) -> Self {
+        AttachmentNames {
+            style,
+            ..AttachmentNames::default()
+        }
+    }
+
+    /// The name of the file in `dir` with the MD5 hash `hash` and `size` bytes: `stem` and
+    /// `extension`, followed by a counter if that's taken, in this run or by a different file from
+    /// an earlier one.
+    fn name(
+        &self,
+        dir: &Path,
+        (hash, size): (&str, u64),
+        stem: &str,
+        extension: &str,
+    ) -> Result<String> {
+        loop {
+            // Locked only to pick a name, not while a file that's already there is read.
+            let name = {
+                let mut dirs = self.dirs.lock().unwrap_or_else(|e| e.into_inner());
+                let names = dirs.entry(dir.to_owned()).or_insert_with(|| DirNames {
+                    unique: UniqueNames::new(self.style),
+                    ..DirNames::default()
+                });
+                if let Some(name) = names.by_hash.get(hash) {
+                    return Ok(name.clone());
+                }
+                names.unique.file_name(stem, extension)
+            };
+            let path = dir.join(&name);
+            if !path.exists() || same_contents(&path, hash, size)? {
+                let mut dirs = self.dirs.lock().unwrap_or_else(|e| e.into_inner());
+                let names = dirs.entry(dir.to_owned()).or_default();
+                // Another thread may have named the same file meanwhile.
+                let name = names.by_hash.entry(hash.to_string()).or_insert(name);
+                return Ok(name.clone());
+            }
+        }
+    }
+}
+
+/// Whether the file at `path` has `size` bytes and the MD5 hash `hash`, reading it a piece at a
+/// time.
+fn same_contents(path: &Path, hash: &str, size: u64) -> Result<bool> {
+    if fs::metadata(path)?.len() != size {
+        return Ok(false);
+    }
+    let mut context = md5::Context::new();
+    io::copy(&mut File::open(path)?, &mut context)?;
+    Ok(format!("{:x}", context.compute()) == hash)
+}
+
+/// What to do with a resource's `<alternate-data>`, e.g. the rendering Evernote keeps of an ink
+/// note. Only extracted resources have it written.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlternateData {
+    /// Only write `<data>`.
+    #[default]
+    Ignore,
+    /// Write and link to the alternate data instead, where there is any.
+    Prefer,
+    /// Write the alternate data too, next to `<data>` with `.alternate` in its name.
+    Both,
+}
+
+/// A zip archive to collect attachments in, for archiving rather than importing them. Files are
+/// in a folder named after the archive, e.g. `files/` in `files.zip`, so links to them work once
+/// it's unzipped where it is, and `manifest.json` lists each file with its hash and the notes it's
+/// attached to. Files are added as notes are converted, by all threads, and the archive is
+/// completed by `finish`.
+///
+/// Attachments are archived as they are, without alternate data or shrinking.
+#[derive(Clone)]
+pub struct AttachmentArchive {
+    path: PathBuf,
+    state: Arc<Mutex<ArchiveState>>,
+}
+
+#[derive(Default)]
+struct ArchiveState {
+    /// Created with the first file, so there's no archive without attachments.
+    zip: Option<ZipWriter<BufWriter<File>>>,
+    manifest: Vec<ManifestEntry>,
+    /// Indexes in `manifest` by file.
+    by_file: HashMap<String, usize>,
+}
+
+struct ManifestEntry {
+    file: String,
+    hash: String,
+    name: Option<String>,
+    mime: Option<String>,
+    notes: Vec<String>,
+}
+
+/// The manifest's name in the archive.
+const MANIFEST: &str = "manifest.json";
+
+impl AttachmentArchive {
+    /// Collect attachments in the zip archive `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        AttachmentArchive {
+            path: path.into(),
+            state: Arc::default(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add `resource` of `note`, the `index`th of its resources, unless its file is already in.
+    /// Returns the hash and the media linking to where the file will be unzipped.
+    fn add(
+        &self,
+        resource: &Resource,
+        (note, index): (&Note, usize),
+        options: &AttachmentOptions,
+    ) -> Result<(String, Media)> {
+        // Decoded twice, for the checksums and then into the archive, rather than held in memory.
+        let mut checksum = Checksum::default();
+        let hash = format!("{:x}", resource.decode_to(&mut checksum)?);
+        let base = self.path.parent().unwrap_or_else(|| Path::new(""));
+        let folder = base.join(self.path.file_stem().unwrap_or_default());
+        let dir = options.layout.note_dir(&folder, note);
+        let name = match options.names {
+            Some(ref names) => {
+                let (stem, extension) =
+                    original_name(resource, note.title.as_deref(), index, &hash);
+                names.name(&dir, (&hash, checksum.size()), &stem, &extension)?
+            }
+            None => with_extension(&hash, resource.mime.as_deref()),
+        };
+        let path = dir.join(&name);
+        let file = relative_path(&path, base)
+            .unwrap_or_else(|| PathBuf::from(&name))
+            .to_string_lossy()
+            .replace('\\', "/");
+        let title = note.title.clone().unwrap_or_default();
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.by_file.get(&file) {
+            Some(&i) => {
+                let notes = &mut state.manifest[i].notes;
+                if !notes.contains(&title) {
+                    notes.push(title);
+                }
+            }
+            None => {
+                if state.zip.is_none() {
+                    fs::create_dir_all(base)?;
+                    let file = BufWriter::new(File::create(&self.path)?);
+                    state.zip = Some(ZipWriter::new(file));
+                }
+                if let Some(ref mut zip) = state.zip {
+                    zip.add_with(&file, checksum.crc(), checksum.size(), |writer| {
+                        resource.decode_to(writer)?;
+                        Ok(())
+                    })?;
+                }
+                debug!("archived {} ({} bytes)", file, checksum.size());
+                let i = state.manifest.len();
+                state.by_file.insert(file.clone(), i);
+                state.manifest.push(ManifestEntry {
+                    file,
+                    hash: hash.clone(),
+                    name: resource.file_name.clone(),
+                    mime: resource.mime.clone(),
+                    notes: vec![title],
+                });
+            }
+        }
+        drop(state);
+
+        let target = options
+            .links_relative_to
+            .as_deref()
+            .and_then(|base| relative_path(&path, base))
+            .unwrap_or(path);
+        let media = Media {
+            target: target.to_string_lossy().into_owned(),
+            name: resource.file_name.clone().unwrap_or(name),
+            mime: resource.mime.clone(),
+            size: Some(checksum.size()),
+            recognition: recognition(resource),
+            attachment: resource.attributes.attachment,
+            width: resource.width,
+        };
+        Ok((hash, media))
+    }
+
+    /// Add the manifest and finish the archive, if any attachments were added. Returns how many.
+    pub fn finish(&self) -> Result<usize> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut zip = match state.zip.take() {
+            Some(zip) => zip,
+            None => return Ok(0),
+        };
+        let files: Vec<_> = state
+            .manifest
+            .iter()
+            .map(|entry| {
+                json!({
+                    "file": entry.file,
+                    "hash": entry.hash,
+                    "name": entry.name,
+                    "mime": entry.mime,
+                    "notes": entry.notes,
+                })
+            })
+            .collect();
+        let manifest = format!("{:#}\n", json!({ "files": files }));
+        zip.add(MANIFEST, manifest.as_bytes())?;
+        zip.finish()?;
+        Ok(state.manifest.len())
+    }
+}
+
+impl fmt::Debug for AttachmentArchive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AttachmentArchive")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// Guess a file extension for common Evernote attachment types.
+pub fn extension(mime: &str) -> Option<&'static str> {
+    match mime {
+        "image/png" => Some("png"),
+        "image/jpeg" | "image/jpg" | "image/pjpeg" => Some("jpg"),
+        "image/gif" => Some("gif"),
+        "image/bmp" => Some("bmp"),
+        "image/svg+xml" => Some("svg"),
+        "image/tiff" => Some("tiff"),
+        "image/webp" => Some("webp"),
+        "application/pdf" => Some("pdf"),
+        "audio/wav" | "audio/x-wav" => Some("wav"),
+        "audio/mpeg" => Some("mp3"),
+        "audio/amr" => Some("amr"),
+        "text/plain" => Some("txt"),
+        "text/html" => Some("html"),
+        _ => None,
+    }
+}
+
+/// Guess the MIME type of a file by its extension, the other way around from `extension`.
+pub fn mime_type(extension: &str) -> Option<&'static str> {
+    match extension.to_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "bmp" => Some("image/bmp"),
+        "svg" => Some("image/svg+xml"),
+        "tif" | "tiff" => Some("image/tiff"),
+        "webp" => Some("image/webp"),
+        "pdf" => Some("application/pdf"),
+        "wav" => Some("audio/wav"),
+        "mp3" => Some("audio/mpeg"),
+        "amr" => Some("audio/amr"),
+        "txt" => Some("text/plain"),
+        "html" | "htm" => Some("text/html"),
+        _ => None,
+    }
+}
+
+/// Guess the MIME type of `data` by its first bytes, for data that doesn't say, like
+/// `<alternate-data>`.
+pub fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if data.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else {
+        None
+    }
+}
+
+/// The stem and extension to name `resource` by under `AttachmentNaming::Original`: its file
+/// name, else the title of its note and its `index` among the note's resources, else `hash`. The
+/// extension is that of the MIME type where it's known, and the file name's otherwise.
+fn original_name(
+    resource: &Resource,
+    title: Option<&str>,
+    index: usize,
+    hash: &str,
+) -> (String, String) {
+    let mime_extension = resource.mime.as_deref().and_then(extension);
+    if let Some(ref file_name) = resource.file_name {
+        let path = Path::new(file_name);
+        let (stem, own_extension) = match path.extension().and_then(|ext| ext.to_str()) {
+            // Not an extension, but part of a name like `Notes v1.2 draft`.
+            Some(ext) if ext.is_empty() || ext.len() > 5 || ext.contains(' ') => {
+                (file_name.as_str(), None)
+            }
+            Some(ext) => (
+                path.file_stem().and_then(|s| s.to_str()).unwrap_or(""),
+                Some(ext),
+            ),
+            None => (file_name.as_str(), None),
+        };
+        let extension = match (own_extension, mime_extension) {
+            // `.jpeg` for `image/jpeg` is as good as `.jpg`.
+            (Some(own), Some(mime)) if mime_type(own) == mime_type(mime) => own.to_lowercase(),
+            (_, Some(mime)) => mime.to_string(),
+            (Some(own), None) => own.to_string(),
+            (None, None) => String::new(),
+        };
+        if !stem.trim().is_empty() {
+            return (stem.to_string(), extension);
+        }
+    }
+    let extension = mime_extension.unwrap_or("").to_string();
+    match title {
+        Some(title) if !title.trim().is_empty() => (format!("{} {}", title, index + 1), extension),
+        _ => (hash.to_string(), extension),
+    }
+}
+
+/// Decode `resource` into `dir` a piece at a time, named by the MD5 hash Evernote uses to
+/// reference it, or as `options.names` names it. Returns the hash and the media linking to the
+/// file.
+///
+/// The same attachment often appears in many notes. Since the name is the hash, a file that
+/// already exists has the same contents, so it is shared rather than written again. Alternate
+/// data is written and linked as `options.alternate_data` says.
+fn extract_resource(
+    resource: &Resource,
+    (title, index): (Option<&str>, usize),
+    dir: &Path,
+    options: &AttachmentOptions,
+) -> Result<(String, Media)> {
+    let hash = format!("{:x}", resource.hash()?);
+    let alternate = match options.alternate_data {
+        AlternateData::Ignore => None,
+        AlternateData::Prefer | AlternateData::Both => match resource.alternate_spilled {
+            // Linked into place below, so only the first bytes are read, to sniff its type.
+            Some(ref spilled) => {
+                let mut head = Vec::new();
+                File::open(spilled.path())?.take(8).read_to_end(&mut head)?;
+                Some(head)
+            }
+            None => resource.decode_alternate()?,
+        },
+    };
+
+    let name =
+        |(hash, size): (&str, u64), stem: &str, mime: Option<&str>, extension: &str| match options
+            .names
+        {
+            Some(ref names) => names.name(dir, (hash, size), stem, extension),
+            None => Ok(with_extension(hash, mime)),
+        };
+    let (stem, extension) = original_name(resource, title, index, &hash);
+
+    let mut preferred = None;
+    if let Some(data) = alternate {
+        let mime = sniff_mime(&data);
+        let size = match resource.alternate_spilled {
+            Some(ref spilled) => fs::metadata(spilled.path())?.len(),
+            None => data.len() as u64,
+        };
+        let name = name(
+            (&format!("{}.alternate", hash), size),
+            &format!("{} alternate", stem),
+            mime,
+            mime.and_then(self::extension).unwrap_or(""),
+        )?;
+        let size = match resource.alternate_spilled {
+            Some(ref spilled) => link_once(dir, &name, spilled.path())?,
+            None => write_once(dir, &name, |file| Ok(file.write_all(&data)?))?,
+        };
+        if options.alternate_data == AlternateData::Prefer {
+            preferred = Some((name, mime.map(String::from), size, None));
+        }
+    }
+    let shrunk = match (&preferred, &options.shrink) {
+        (None, Some(shrink)) => shrink.shrink(resource)?.map(|shrunk| (shrink, shrunk)),
+        _ => None,
+    };
+    let (file_name, mime, size, width) = match (preferred, shrunk) {
+        (Some(preferred), _) => preferred,
+        (None, Some((shrink, shrunk))) => {
+            let extension = self::extension(shrunk.mime).unwrap_or("");
+            let size = shrunk.data.len() as u64;
+            let name = name((&hash, size), &stem, Some(shrunk.mime), extension)?;
+            let size = write_once(dir, &name, |file| shrink.write(&shrunk, file))?;
+            (
+                name,
+                Some(shrunk.mime.to_string()),
+                size,
+                Some(shrunk.width),
+            )
+        }
+        (None, None) => {
+            let size = resource.size()?;
+            let name = name((&hash, size), &stem, resource.mime.as_deref(), &extension)?;
+            let size = match resource.spilled {
+                Some(ref spilled) => link_once(dir, &name, spilled.path())?,
+                None => write_once(dir, &name, |file| resource.decode_to(file).map(drop))?,
+            };
+            (name, resource.mime.clone(), size, resource.width)
+        }
+    };
+    let path = dir.join(&file_name);
+
+    let links_relative_to = options.links_relative_to.as_deref();
+    let target = links_relative_to
+        .and_then(|base| relative_path(&path, base))
+        .unwrap_or(path);
+    let media = Media {
+        target: target.to_string_lossy().into_owned(),
+        name: resource.file_name.clone().unwrap_or(file_name),
+        mime,
+        size: Some(size),
+        recognition: recognition(resource),
+        attachment: resource.attributes.attachment,
+        width,
+    };
+    Ok((hash, media))
+}
+
+/// `stem` with the extension for `mime`, if one is known.
+fn with_extension(stem: &str, mime: Option<&str>) -> String {
+    match mime.and_then(extension) {
+        Some(ext) => format!("{}.{}", stem, ext),
+        None => stem.to_string(),
+    }
+}
+
+/// Write the file `name` in `dir` with `write`, unless it's already written. Returns the size of
+/// the file.
+fn write_once(
+    dir: &Path,
+    name: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<u64> {
+    let path = dir.join(name);
+    match fs::metadata(&path) {
+        Ok(metadata) => {
+            debug!("{} is already written", path.display());
+            Ok(metadata.len())
+        }
+        Err(_) => write_file(dir, &path, write),
+    }
+}
+
+/// Like `write_once`, but with the contents of the file `from`, which is linked into place where
+/// the file system allows rather than copied.
+fn link_once(dir: &Path, name: &str, from: &Path) -> Result<u64> {
+    let path = dir.join(name);
+    if let Ok(metadata) = fs::metadata(&path) {
+        debug!("{} is already written", path.display());
+        return Ok(metadata.len());
+    }
+    fs::create_dir_all(dir)?;
+    match fs::hard_link(from, &path) {
+        Ok(()) => Ok(fs::metadata(&path)?.len()),
+        Err(_) => write_file(dir, &path, |file| {
+            io::copy(&mut File::open(from)?, file)?;
+            Ok(())
+        }),
+    }
+}
+
+/// Write `path` in `dir` with `write`, through a temporary file so that `path` only ever has the
+/// whole contents. Returns the size of the file.
+fn write_file(
+    dir: &Path,
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<u64> {
+    // Other threads may be extracting into the same directory.
+    static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+    fs::create_dir_all(dir)?;
+    let temp = dir.join(format!(
+        ".enex2mf-{}-{}.tmp",
+        process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = BufWriter::new(File::create(&temp)?);
+    let written = write(&mut file).and_then(|_| {
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        Ok(file.metadata()?.len())
+    });
+    match written {
+        Ok(len) => {
+            fs::rename(&temp, path)?;
+            debug!("wrote {} ({} bytes)", path.display(), len);
+            Ok(len)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// `path` relative to the directory `base`, going up with `..` as needed. Returns `None` if that
+/// can't be worked out without touching the file system, e.g. `base` contains `..`.
+pub(crate) fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    if path.has_root() != base.has_root() {
+        return None;
+    }
+    let path: Vec<_> = path
+        .components()
+        .filter(|&c| c != Component::CurDir)
+        .collect();
+    let base: Vec<_> = base
+        .components()
+        .filter(|&c| c != Component::CurDir)
+        .collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for component in &base[common..] {
+        match component {
+            Component::Normal(_) => relative.push(".."),
+            _ => return None,
+        }
+    }
+    relative.extend(&path[common..]);
+    Some(relative)
+}
+
+/// The words Evernote recognized in `resource`, e.g. by OCR of an image.
+pub fn recognition(resource: &Resource) -> Option<String> {
+    if resource.recognition.is_empty() {
+        None
+    } else {
+        Some(resource.recognition.join(" "))
+    }
+}
+
+fn inline_resource(resource: &Resource, data: &[u8]) -> Media {
+    let mime = resource
+        .mime
+        .as_deref()
+        .unwrap_or("application/octet-stream");
+    Media {
+        target: format!("data:{};base64,{}", mime, base64::encode(data)),
+        name: resource.file_name.clone().unwrap_or_default(),
+        mime: resource.mime.clone(),
+        size: Some(data.len() as u64),
+        recognition: recognition(resource),
+        attachment: resource.attributes.attachment,
+        width: resource.width,
+    }
+}
+
+/// A mismatch between the resources of a note and the `<en-media>` hashes of its content, found
+/// by `verify`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HashMismatch {
+    /// A resource whose MD5 hash no `<en-media>` refers to, likely because its data was
+    /// corrupted, with its file name if it has one.
+    Unreferenced { hash: String, name: Option<String> },
+    /// An `<en-media>` hash that no resource has, so the media can't be shown.
+    Missing(String),
+}
+
+impl fmt::Display for HashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashMismatch::Unreferenced { hash, name } => write!(
+                f,
+                "attachment {} has the hash {}, which the note doesn't refer to; its data may be \
+                 corrupted",
+                name.as_deref().unwrap_or("without a name"),
+                hash
+            ),
+            HashMismatch::Missing(hash) => {
+                write!(f, "<en-media> refers to {}, which no attachment has", hash)
+            }
+        }
+    }
+}
+
+/// Check the MD5 hashes of the resources of `note` against those its content refers to.
+pub fn verify(note: &Note) -> Result<Vec<HashMismatch>> {
+    let referenced = enml::media_hashes(note.content.as_deref().unwrap_or(""));
+    let mut hashes = Vec::with_capacity(note.resources.len());
+    let mut mismatches = Vec::new();
+    for resource in &note.resources {
+        let hash = format!("{:x}", resource.hash()?);
+        if !referenced.contains(&hash) {
+            mismatches.push(HashMismatch::Unreferenced {
+                hash: hash.clone(),
+                name: resource.file_name.clone(),
+            });
+        }
+        hashes.push(hash);
+    }
+    for hash in referenced {
+        if !hashes.contains(&hash) {
+            let missing = HashMismatch::Missing(hash);
+            if !mismatches.contains(&missing) {
+                mismatches.push(missing);
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Inline or extract all resources of `note`, returning the media to link from its content keyed
+/// by hash. Resources that are neither inlined nor extracted are left out.
+pub fn media(note: &Note, options: &AttachmentOptions) -> Result<HashMap<String, Media>> {
+    let mut media = HashMap::new();
+    for (index, resource) in note.resources.iter().enumerate() {
+        let is_image = !resource.attributes.attachment
+            && resource
+                .mime
+                .as_deref()
+                .is_some_and(|mime| mime.starts_with("image/"));
+        let inline = is_image && options.inline_images;
+        if !inline && options.dir.is_none() && options.archive.is_none() {
+            continue;
+        }
+
+        let (hash, item) = match (&options.archive, &options.dir) {
+            (Some(archive), _) if !inline => archive.add(resource, (note, index), options)?,
+            (None, Some(dir)) if !inline => {
+                let position = (note.title.as_deref(), index);
+                let dir = options.layout.note_dir(dir, note);
+                extract_resource(resource, position, &dir, options)?
+            }
+            _ => {
+                let data = resource.decode()?;
+                let hash = format!("{:x}", md5::compute(&data));
+                (hash, inline_resource(resource, &data))
+            }
+        };
+        media.insert(hash, item);
+    }
+    Ok(media)
+}
+
+#[test]
+fn test_relative_path() {
+    let path = Path::new("out/files/a.png");
+    assert_eq!(
+        relative_path(path, Path::new("out")),
+        Some(PathBuf::from("files/a.png"))
+    );
+    assert_eq!(
+        relative_path(path, Path::new("./out/pages")),
+        Some(PathBuf::from("../files/a.png"))
+    );
+    assert_eq!(relative_path(path, Path::new("/out")), None);
+    assert_eq!(relative_path(path, Path::new("../out")), None);
+}
+
+#[test]
+fn test_shared_files() {
+    let dir = std::env::temp_dir().join(format!("enex2mf-attachments-{}", process::id()));
+    let options = AttachmentOptions {
+        dir: Some(dir.clone()),
+        ..AttachmentOptions::default()
+    };
+    let image = || Resource::new(b"GIF89a", "image/gif");
+    let first = Note::builder().resource(image()).build();
+    let second = Note::builder().resource(image()).build();
+    let first = media(&first, &options).unwrap();
+    let second = media(&second, &options).unwrap();
+    assert_eq!(first.values().next(), second.values().next());
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_decoded_resources() {
+    use crate::enex::EnexParser;
+
+    let dir = std::env::temp_dir().join(format!("enex2mf-decoded-{}", process::id()));
+    let buf = "<en-export><note><resource><data>R0lG\nODlh</data><mime>image/gif</mime>\
+               </resource></note></en-export>";
+    let note = EnexParser::new(buf.as_bytes())
+        .decode_resources_to(&dir)
+        .next()
+        .unwrap()
+        .unwrap();
+    let resource = &note.resources[0];
+    assert_eq!(resource.data, None);
+    let spilled = resource.spilled.as_ref().unwrap().path().to_owned();
+    assert_eq!(spilled.parent(), Some(dir.as_path()));
+    assert_eq!(resource.hash().unwrap(), md5::compute(b"GIF89a"));
+
+    let options = AttachmentOptions {
+        dir: Some(dir.clone()),
+        ..AttachmentOptions::default()
+    };
+    let media = media(&note, &options).unwrap();
+    let path = dir.join(&media.values().next().unwrap().target);
+    drop(note);
+    assert!(!spilled.exists());
+    assert_eq!(fs::read(&path).unwrap(), b"GIF89a");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_alternate_data() {
+    let dir = std::env::temp_dir().join(format!("enex2mf-alternate-{}", process::id()));
+    let resource = Resource {
+        alternate_data: Some(base64::encode(b"%PDF-1.4")),
+        ..Resource::new(b"GIF89a", "image/gif")
+    };
+    let note = Note::builder().resource(resource).build();
+    let mut options = AttachmentOptions {
+        dir: Some(dir.clone()),
+        alternate_data: AlternateData::Both,
+        ..AttachmentOptions::default()
+    };
+    let both = media(&note, &options).unwrap();
+    let hash = "1ac2109d47dbc72551f71df89d01ed18";
+    assert_eq!(both[hash].mime.as_deref(), Some("image/gif"));
+    assert!(dir.join(format!("{}.gif", hash)).exists());
+    assert!(dir.join(format!("{}.alternate.pdf", hash)).exists());
+
+    options.alternate_data = AlternateData::Prefer;
+    let preferred = media(&note, &options).unwrap();
+    assert_eq!(preferred[hash].mime.as_deref(), Some("application/pdf"));
+    assert_eq!(preferred[hash].size, Some(8));
+    assert!(preferred[hash].target.ends_with(".alternate.pdf"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_original_names() {
+    let dir = std::env::temp_dir().join(format!("enex2mf-names-{}", process::id()));
+    let options = AttachmentOptions {
+        dir: Some(dir.clone()),
+        names: Some(AttachmentNames::new(NameStyle::default())),
+        ..AttachmentOptions::default()
+    };
+    let named = |name: &str, data: &[u8], mime| Resource {
+        file_name: Some(name.to_string()),
+        ..Resource::new(data, mime)
+    };
+    let note = Note::builder()
+        .title("Cat: pics")
+        .resource(named("cat.jpeg", b"cat", "image/jpeg"))
+        .resource(named("cat.heic", b"kitten", "image/png"))
+        .resource(Resource::new(b"GIF89a", "image/gif"))
+        .build();
+    let first = media(&note, &options).unwrap();
+    let mut names: Vec<&str> = first.values().map(|media| &media.target[..]).collect();
+    names.sort_unstable();
+    let expected: Vec<String> = ["Cat- pics 3.gif", "cat.jpeg", "cat.png"]
+        .iter()
+        .map(|name| dir.join(name).to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(names, expected);
+
+    // The same attachment keeps its name, and an earlier run's file of another isn't overwritten.
+    let second = Note::builder()
+        .resource(named("cat.jpeg", b"cat", "image/jpeg"))
+        .build();
+    assert_eq!(media(&second, &options).unwrap().len(), 1);
+    let rerun = AttachmentOptions {
+        names: Some(AttachmentNames::new(NameStyle::default())),
+        ..options.clone()
+    };
+    let other = Note::builder()
+        .resource(named("cat.jpeg", b"dog", "image/jpeg"))
+        .build();
+    let other = media(&other, &rerun).unwrap();
+    assert!(other
+        .values()
+        .next()
+        .unwrap()
+        .target
+        .ends_with("cat 2.jpeg"));
+    assert_eq!(fs::read(dir.join("cat.jpeg")).unwrap(), b"cat");
+    let rerun = AttachmentOptions {
+        names: Some(AttachmentNames::new(NameStyle::default())),
+        ..options.clone()
+    };
+    let same = media(&second, &rerun).unwrap();
+    assert!(same.values().next().unwrap().target.ends_with("cat.jpeg"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_layout() {
+    use chrono::{Local, TimeZone};
+
+    let dir = Path::new("files");
+    let note = Note::builder()
+        .title("Soup: hot")
+        .created(Local.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap())
+        .build();
+    assert_eq!(AttachmentLayout::Shared.note_dir(dir, &note), dir);
+    assert_eq!(
+        AttachmentLayout::PerNote.note_dir(dir, &note),
+        Path::new("files/Soup- hot.assets")
+    );
+    assert_eq!(
+        AttachmentLayout::Date.note_dir(dir, &note),
+        Path::new("files/2020/01")
+    );
+    assert_eq!(
+        AttachmentLayout::Date.note_dir(dir, &Note::default()),
+        Path::new("files/undated")
+    );
+}
+
+#[test]
+fn test_archive() {
+    let dir = std::env::temp_dir().join(format!("enex2mf-archive-{}", process::id()));
+    let archive = AttachmentArchive::new(dir.join("files.zip"));
+    let options = AttachmentOptions {
+        archive: Some(archive.clone()),
+        links_relative_to: Some(dir.clone()),
+        ..AttachmentOptions::default()
+    };
+    let image = || Resource::new(b"GIF89a", "image/gif");
+    let soup = Note::builder().title("Soup").resource(image()).build();
+    let stew = Note::builder().title("Stew").resource(image()).build();
+    let soup = media(&soup, &options).unwrap();
+    assert_eq!(
+        soup.values().next().unwrap().target,
+        "files/1ac2109d47dbc72551f71df89d01ed18.gif"
+    );
+    media(&stew, &options).unwrap();
+
+    // Spilled resources are copied into the archive from their file.
+    let buf = "<en-export><note><title>Pad</title><resource><data>c2NyaWJibGVz</data>\
+               <mime>text/plain</mime></resource></note></en-export>";
+    let pad = crate::enex::EnexParser::new(buf.as_bytes())
+        .memory_limit(1)
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(pad.resources[0].spilled.is_some());
+    let pad = media(&pad, &options).unwrap();
+    assert_eq!(pad.values().next().unwrap().size, Some(9));
+    assert_eq!(archive.finish().unwrap(), 2);
+
+    let zip = fs::read(dir.join("files.zip")).unwrap();
+    assert!(zip.windows(9).any(|window| window == b"scribbles"));
+    let mut checksum = Checksum::default();
+    checksum.write_all(b"scribbles").unwrap();
+    let crc = checksum.crc().to_le_bytes();
+    assert!(zip.windows(4).any(|window| window == crc));
+    let manifest = String::from_utf8_lossy(&zip);
+    assert!(manifest.contains("\"notes\": [\n        \"Soup\",\n        \"Stew\"\n      ]"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_verify() {
+    assert_eq!(
+        enml::media_hashes("<en-media type='image/png' hash='AB'/><en-media  hash=\"cd\">"),
+        ["ab", "cd"]
+    );
+
+    let gif = "1ac2109d47dbc72551f71df89d01ed18";
+    let content = |hash: &str| format!("<en-note><en-media hash=\"{}\"/></en-note>", hash);
+    let note = |content: &str| {
+        Note::builder()
+            .content(content)
+            .resource(Resource {
+                file_name: Some("a.gif".to_string()),
+                ..Resource::new(b"GIF89a", "image/gif")
+            })
+            .build()
+    };
+    assert_eq!(verify(&note(&content(gif))).unwrap(), []);
+    assert_eq!(
+        verify(&note(&content("00"))).unwrap(),
+        [
+            HashMismatch::Unreferenced {
+                hash: gif.to_string(),
+                name: Some("a.gif".to_string())
+            },
+            HashMismatch::Missing("00".to_string())
+        ]
+    );
+}
@@ -0,0 +1,71 @@
+//! Bear output: one Markdown file per note for Bear's Markdown import, with the title as a
+//! heading and the tags inline, where Bear finds them, rather than as metadata.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use std::io::Write;
+
+/// Attachments go in this folder next to the notes, which Bear imports from links relative to
+/// the note files.
+pub const ASSETS_DIR: &str = "assets";
+
+/// `tag` as Bear writes it inline: `#tag`, or `#multi word#` if it has spaces. Runs of spaces
+/// would be read as the end of the tag, and `#` as the start of another, so they're left out.
+pub fn inline_tag(tag: &str) -> Option<String> {
+    let tag = tag.replace('#', "");
+    let words: Vec<&str> = tag.split_whitespace().collect();
+    let tag = words.join(" ").trim_matches('/').to_string();
+    match words.len() {
+        0 => None,
+        1 => Some(format!("#{}", tag)),
+        _ => Some(format!("#{}#", tag)),
+    }
+}
+
+/// Write a single note in Bear's Markdown: the title, the content, then a line of tags.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    let title = note.title.as_deref().unwrap_or("untitled");
+    writeln!(writer, "# {}\n", title)?;
+
+    let content = note.content_markdown(enml);
+    if !content.is_empty() {
+        writeln!(writer, "{}\n", content)?;
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        writeln!(writer, "Source: <{}>\n", source_url)?;
+    }
+
+    let tags: Vec<String> = note.tags.iter().filter_map(|tag| inline_tag(tag)).collect();
+    if !tags.is_empty() {
+        writeln!(writer, "{}", tags.join(" "))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_note() {
+    assert_eq!(inline_tag("to do"), Some("#to do#".to_string()));
+    assert_eq!(
+        inline_tag("#projects/home"),
+        Some("#projects/home".to_string())
+    );
+    assert_eq!(inline_tag(" # "), None);
+
+    let note = Note::builder()
+        .title("Soup")
+        .tag("food")
+        .tag("to  cook")
+        .content("<en-note><div>Stir</div></en-note>")
+        .build();
+    let mut output = Vec::new();
+    write_note(&mut output, &note, &EnmlOptions::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "# Soup\n\nStir\n\n#food #to cook#\n"
+    );
+}
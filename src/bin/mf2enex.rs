@@ -0,0 +1,148 @@
+//! The other way around from enex2mf: writes Markdown notes, such as MindForger outlines or what
+//! enex2mf converted, as an ENEX export to import into Evernote or other apps reading exports.
+
+#![allow(clippy::result_large_err)]
+
+use clap::{crate_version, App, Arg, ErrorKind};
+use enex2mf::enml::EnmlOptions;
+use enex2mf::export::EnexWriter;
+use enex2mf::outline;
+use enex2mf::output::SafeFile;
+use enex2mf::{Error, ExportInfo, NoteWriter, Result};
+use log::{info, LevelFilter};
+use std::fs;
+use std::io::{self, stdin, stdout, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn app() -> App<'static, 'static> {
+    App::new("mf2enex")
+        .version(crate_version!())
+        .about("Writes Markdown notes and MindForger outlines as an Evernote export.")
+        .arg(
+            Arg::with_name("input")
+                .required(true)
+                .multiple(true)
+                .value_name("INPUT.md")
+                .help("Markdown files or directories of them, or - for stdin"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("FILE.enex")
+                .help("Writes the export to FILE.enex instead of stdout"),
+        )
+        .arg(
+            Arg::with_name("force")
+                .short("f")
+                .long("force")
+                .help("Overwrites the output file if it exists"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Lists the files read"),
+        )
+}
+
+/// The Markdown files of `input`: itself, or those in it and its subdirectories if it's a
+/// directory, in order of their paths.
+fn markdown_files(input: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !input.is_dir() {
+        files.push(input.to_owned());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(input)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if path.is_dir() {
+            markdown_files(&path, files)?;
+        } else if matches!(extension, Some("md" | "markdown")) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Write the notes of `inputs` as an export to `writer`.
+fn run<W: Write>(inputs: &[PathBuf], writer: W) -> Result<()> {
+    let mut export = EnexWriter::new(writer);
+    export.begin(&ExportInfo::default())?;
+    for input in inputs {
+        let (markdown, name, dir) = if input.as_os_str() == "-" {
+            let mut markdown = String::new();
+            stdin().read_to_string(&mut markdown)?;
+            (markdown, "Untitled".to_string(), PathBuf::from("."))
+        } else {
+            info!("reading {}", input.display());
+            let name = input.file_stem().unwrap_or_default();
+            let dir = input.parent().unwrap_or_else(|| Path::new("."));
+            let markdown = fs::read_to_string(input).map_err(|err| {
+                let message = format!("Can't open {}: {}", input.display(), err);
+                io::Error::new(err.kind(), message)
+            })?;
+            (
+                markdown,
+                name.to_string_lossy().into_owned(),
+                dir.to_owned(),
+            )
+        };
+        for note in outline::read_notes(&markdown, &name, &dir)? {
+            export.write_note(&note, &EnmlOptions::default())?;
+        }
+    }
+    export.finish()
+}
+
+fn main() {
+    let matches = app().get_matches();
+    let level = if matches.is_present("verbose") {
+        LevelFilter::Info
+    } else {
+        LevelFilter::Warn
+    };
+    env_logger::Builder::new()
+        .filter_module("enex2mf", level)
+        .filter_module(module_path!(), level)
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .init();
+
+    let output = matches.value_of_os("output").map(PathBuf::from);
+    if let Some(ref path) = output {
+        if path.exists() && !matches.is_present("force") {
+            let message = format!("{} exists; use --force to overwrite it", path.display());
+            clap::Error::with_description(&message, ErrorKind::ArgumentConflict).exit()
+        }
+    }
+    let mut inputs = Vec::new();
+    for input in matches.values_of_os("input").expect("input is required") {
+        if let Err(err) = markdown_files(Path::new(input), &mut inputs) {
+            eprintln!("mf2enex: {}: {}", Path::new(input).display(), err);
+            process::exit(1);
+        }
+    }
+
+    let result = match output {
+        Some(ref path) => SafeFile::create(path)
+            .map_err(Error::from)
+            .and_then(|mut file| {
+                run(&inputs, BufWriter::new(&mut file))?;
+                Ok(file.commit()?)
+            }),
+        None => run(&inputs, BufWriter::new(stdout().lock())),
+    };
+    match result {
+        Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(err) => {
+            eprintln!("mf2enex: {}", err);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
@@ -0,0 +1,151 @@
+//! Repairing CDATA sections that contain `]]>`. Note content is wrapped in CDATA, which `]]>`
+//! ends; Evernote splits such content between sections, but other apps writing exports don't,
+//! which the XML parser can't get past. A `]]>` only ends a section here if an end tag or another
+//! section follows it; others are split as Evernote would.
+
+use std::io::{self, Read};
+
+const BLOCK_LEN: usize = 64 * 1024;
+
+const CDATA_START: &[u8] = b"<![CDATA[";
+const CDATA_END: &[u8] = b"]]>";
+
+/// What `]]>` inside a section is replaced with: the end of the section, and a new one starting
+/// with `>`.
+const SPLIT_END: &[u8] = b"]]]]><![CDATA[>";
+
+/// Reads an export with `]]>` inside CDATA sections split between sections.
+pub struct CdataRepair<R> {
+    inner: R,
+    /// Read but not yet repaired.
+    input: Vec<u8>,
+    repaired: Vec<u8>,
+    pos: usize,
+    in_cdata: bool,
+    eof: bool,
+}
+
+impl<R: Read> CdataRepair<R> {
+    pub fn new(inner: R) -> Self {
+        CdataRepair {
+            inner,
+            input: Vec::new(),
+            repaired: Vec::new(),
+            pos: 0,
+            in_cdata: false,
+            eof: false,
+        }
+    }
+
+    /// Read and repair the next block, returning false at the end.
+    fn fill(&mut self) -> io::Result<bool> {
+        self.repaired.clear();
+        self.pos = 0;
+        while self.repaired.is_empty() {
+            if self.eof && self.input.is_empty() {
+                return Ok(false);
+            }
+            if !self.eof {
+                let start = self.input.len();
+                self.input.resize(start + BLOCK_LEN, 0);
+                let n = self.inner.read(&mut self.input[start..])?;
+                self.input.truncate(start + n);
+                self.eof = n == 0;
+            }
+            let used = self.repair();
+            self.input.drain(..used);
+        }
+        Ok(true)
+    }
+
+    /// Move what can be repaired of `input` to `repaired`, returning how many bytes were used.
+    /// What can't be told yet, e.g. a `]]>` at the end, is left for the next read.
+    fn repair(&mut self) -> usize {
+        let input = &self.input;
+        let mut copied = 0;
+        let mut i = 0;
+        let used = loop {
+            if !self.in_cdata {
+                match find(&input[i..], CDATA_START) {
+                    Some(at) => {
+                        i += at + CDATA_START.len();
+                        self.in_cdata = true;
+                    }
+                    None if self.eof => break input.len(),
+                    // Keep what may be the start of a `<![CDATA[`.
+                    None => break input.len().saturating_sub(CDATA_START.len() - 1).max(i),
+                }
+                continue;
+            }
+            let at = match find(&input[i..], CDATA_END) {
+                Some(at) => i + at,
+                None if self.eof => break input.len(),
+                None => break input.len().saturating_sub(CDATA_END.len() - 1).max(i),
+            };
+            let end = at + CDATA_END.len();
+            let after = &input[end..];
+            let after = &after[after
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .unwrap_or(after.len())..];
+            if after.len() < CDATA_START.len() && !self.eof {
+                break at;
+            }
+            if after.is_empty() || after.starts_with(b"</") || after.starts_with(CDATA_START) {
+                self.in_cdata = false;
+            } else {
+                self.repaired.extend_from_slice(&input[copied..at]);
+                self.repaired.extend_from_slice(SPLIT_END);
+                copied = end;
+            }
+            i = end;
+        };
+        self.repaired.extend_from_slice(&input[copied..used]);
+        used
+    }
+}
+
+impl<R: Read> Read for CdataRepair<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.repaired.len() && !self.fill()? {
+            return Ok(0);
+        }
+        let n = (&self.repaired[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Where `needle` first appears in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    while let Some(at) = haystack[start..].iter().position(|&b| b == needle[0]) {
+        let at = start + at;
+        if haystack[at..].starts_with(needle) {
+            return Some(at);
+        }
+        start = at + 1;
+    }
+    None
+}
+
+#[test]
+fn test_cdata_repair() {
+    let repair = |bytes: &[u8]| {
+        let mut text = String::new();
+        CdataRepair::new(bytes).read_to_string(&mut text).unwrap();
+        text
+    };
+    assert_eq!(
+        repair(b"<c><![CDATA[<en-note>a]]> b</en-note>]]></c>"),
+        "<c><![CDATA[<en-note>a]]]]><![CDATA[> b</en-note>]]></c>"
+    );
+    let evernote = "<c><![CDATA[a]]]]><![CDATA[>b]]>\n</c><d>c ]]&gt;</d>";
+    assert_eq!(repair(evernote.as_bytes()), evernote);
+    // A `]]>` split between reads.
+    let long = format!("<c><![CDATA[{}]]> b]]></c>", "a".repeat(BLOCK_LEN - 14));
+    assert_eq!(
+        repair(long.as_bytes()),
+        long.replacen("]]> b", "]]]]><![CDATA[> b", 1)
+    );
+}
@@ -0,0 +1,172 @@
+//! Converts Evernote's code blocks to fenced code blocks. Evernote stores them as a `<div>` styled
+//! with `-en-codeblock:true` (or, in older notes, only a monospace font) holding a `<div>` per
+//! line, which html2md would otherwise turn into paragraphs with the indentation collapsed.
+
+use html2md::common::get_tag_attr;
+use html2md::containers::ContainerHandler;
+use html2md::{Handle, NodeData, StructuredPrinter, TagHandler, TagHandlerFactory};
+
+/// Fonts that mark a `<div>` as code.
+const MONOSPACE_FONTS: &[&str] = &[
+    "monospace",
+    "courier",
+    "courier new",
+    "consolas",
+    "menlo",
+    "monaco",
+    "source code pro",
+];
+
+pub struct DivHandlerFactory;
+
+impl TagHandlerFactory for DivHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(DivHandler { code: false })
+    }
+}
+
+/// Replaces a code block `<div>` with a fenced code block. Other `<div>`s are left to html2md.
+struct DivHandler {
+    code: bool,
+}
+
+impl TagHandler for DivHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        self.code = get_tag_attr(tag, "style").is_some_and(|style| is_code_style(&style));
+        if !self.code {
+            ContainerHandler.handle(tag, printer);
+            return;
+        }
+
+        let mut code = String::new();
+        code_text(tag, &mut code);
+        let code = code.trim_start_matches('\n').trim_end();
+        let longest_run = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+        let fence = "`".repeat(longest_run.max(2) + 1);
+        printer.insert_newline();
+        printer.insert_newline();
+        printer.append_str(&format!(
+            "{}{}\n{}\n{}",
+            fence,
+            guess_language(code).unwrap_or(""),
+            code,
+            fence
+        ));
+    }
+
+    fn after_handle(&mut self, printer: &mut StructuredPrinter) {
+        ContainerHandler.after_handle(printer);
+    }
+
+    fn skip_descendants(&self) -> bool {
+        self.code
+    }
+}
+
+/// Whether a `style` attribute marks a code block.
+fn is_code_style(style: &str) -> bool {
+    style.split(';').any(|declaration| {
+        let (property, value) = match declaration.split_once(':') {
+            Some((property, value)) => (property.trim(), value.trim().to_lowercase()),
+            None => return false,
+        };
+        match property {
+            "-en-codeblock" => value == "true",
+            "font-family" => value
+                .split(',')
+                .next()
+                .map(|font| font.trim().trim_matches(|c| c == '\'' || c == '"'))
+                .is_some_and(|font| MONOSPACE_FONTS.contains(&font)),
+            _ => false,
+        }
+    })
+}
+
+/// Append the text of `node` to `code`, with a line per `<div>` or `<br>`. Evernote keeps
+/// indentation as non-breaking spaces.
+fn code_text(node: &Handle, code: &mut String) {
+    for child in node.children.borrow().iter() {
+        match child.data {
+            NodeData::Text { ref contents } => {
+                let text = contents.borrow();
+                // Whitespace between the lines' elements, not in them.
+                if !(text.trim().is_empty() && text.contains('\n')) {
+                    code.push_str(&text.replace('\u{a0}', " "));
+                }
+            }
+            NodeData::Element { ref name, .. } => match &*name.local {
+                "br" => code.push('\n'),
+                "div" | "p" | "pre" | "li" => {
+                    if !code.is_empty() && !code.ends_with('\n') {
+                        code.push('\n');
+                    }
+                    code_text(child, code);
+                    if !code.ends_with('\n') {
+                        code.push('\n');
+                    }
+                }
+                _ => code_text(child, code),
+            },
+            _ => {}
+        }
+    }
+}
+
+/// The language of `code`, if it is obvious from its first line.
+fn guess_language(code: &str) -> Option<&'static str> {
+    let first = code.lines().next()?.trim();
+    let language = match first {
+        _ if first.starts_with("#!") => {
+            let interpreter = first.rsplit(['/', ' ']).next()?;
+            match interpreter {
+                "sh" | "bash" | "zsh" => "sh",
+                "python" | "python3" => "python",
+                "perl" => "perl",
+                "ruby" => "ruby",
+                "node" => "javascript",
+                _ => return None,
+            }
+        }
+        _ if first.starts_with("<?php") => "php",
+        _ if first.starts_with("<?xml") => "xml",
+        _ if first.starts_with("#include") => "c",
+        _ if first.starts_with("package ") && first.ends_with(';') => "java",
+        _ if first.starts_with("use ") && first.ends_with(';') => "rust",
+        _ if first.starts_with("fn ") || first.starts_with("pub fn ") => "rust",
+        _ if first.starts_with("def ") && first.ends_with(':') => "python",
+        _ if first.starts_with("import ") && !first.ends_with(';') => "python",
+        _ if first.starts_with("$ ") => "console",
+        _ => {
+            let upper = first.to_uppercase();
+            if ["SELECT ", "INSERT ", "UPDATE ", "CREATE TABLE "]
+                .iter()
+                .any(|keyword| upper.starts_with(keyword))
+            {
+                "sql"
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(language)
+}
+
+#[test]
+fn test_code_blocks() {
+    use crate::enml::{handlers, EnmlOptions};
+
+    let content = "<en-note><div>Run:</div>\
+        <div style=\"box-sizing: border-box; font-family: Monaco, Menlo, monospace; -en-codeblock:true;\">\
+        <div>#!/bin/sh</div><div>if true; then</div><div>\u{a0}\u{a0}echo `date`</div><div><br/></div>\
+        <div>fi</div></div><div>Done</div></en-note>";
+    let md = html2md::parse_html_custom(content, &handlers(&EnmlOptions::default()));
+    assert_eq!(
+        md,
+        "Run:\n\n```sh\n#!/bin/sh\nif true; then\n  echo `date`\n\nfi\n```\n\nDone"
+    );
+
+    let content = "<en-note><div style=\"font-family: 'Courier New'\">x = 1</div></en-note>";
+    let md = html2md::parse_html_custom(content, &handlers(&EnmlOptions::default()));
+    assert_eq!(md, "```\nx = 1\n```");
+    assert!(!is_code_style("font-family: Helvetica, monospace"));
+}
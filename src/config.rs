@@ -0,0 +1,103 @@
+//! Defaults for command-line options, read from `enex2mf.toml` in the current directory or the
+//! file given with `--config`. Options given on the command line take precedence.
+
+use enex2mf::attachments::{AlternateData, AttachmentLayout, AttachmentNaming};
+use enex2mf::convert::OnError;
+use enex2mf::enml::{BodyMode, HighlightMode, OcrMode};
+use enex2mf::frontmatter::MetadataStyle;
+use enex2mf::mindforger::LocationLink;
+use enex2mf::naming::{Duplicates, NameStyle};
+use enex2mf::Format;
+use enex2mf::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PATH: &str = "enex2mf.toml";
+
+/// Keys are named like the corresponding long options, e.g. `output-dir = "notes"`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub format: Option<Format>,
+    pub output_dir: Option<PathBuf>,
+    pub mf_repo: Option<PathBuf>,
+    pub git_commit: bool,
+    pub file_names: Option<NameStyle>,
+    pub duplicates: Option<Duplicates>,
+    pub attachments: Option<PathBuf>,
+    pub attachments_zip: Option<PathBuf>,
+    pub inline_images: bool,
+    pub alternate_data: Option<AlternateData>,
+    pub attachment_names: Option<AttachmentNaming>,
+    pub attachment_layout: Option<AttachmentLayout>,
+    pub max_image_width: Option<u32>,
+    pub ocr: Option<OcrMode>,
+    pub highlight: Option<HighlightMode>,
+    pub body: Option<BodyMode>,
+    pub metadata: Option<MetadataStyle>,
+    pub missing_dates: Option<String>,
+    pub date_format: Option<String>,
+    pub timezone: Option<String>,
+    /// Like `--importance`, e.g. `[importance]` and `important = 4`.
+    pub importance: HashMap<String, u8>,
+    /// Like `--urgency`.
+    pub urgency: HashMap<String, u8>,
+    pub author: bool,
+    pub location: Option<LocationLink>,
+    pub reminders: bool,
+    pub application_data: bool,
+    pub lenient: bool,
+    pub no_quirks: bool,
+    pub skip_resources: bool,
+    pub verify_hashes: bool,
+    pub on_error: Option<OnError>,
+    pub empty_placeholder: Option<String>,
+    pub merge: bool,
+    /// Like `--tag`, only convert notes with at least one of these tags.
+    pub tags: Vec<String>,
+    pub title_match: Option<String>,
+    pub title_exclude: Option<String>,
+    pub tag_map: Option<PathBuf>,
+    pub tag_hierarchy: Option<String>,
+    pub quiet: bool,
+    #[cfg(feature = "images")]
+    pub shrink_images: Option<u32>,
+    #[cfg(feature = "evernote")]
+    pub evernote_host: Option<String>,
+    /// Like `--evernote-token`; keep the file private.
+    #[cfg(feature = "evernote")]
+    pub evernote_token: Option<String>,
+}
+
+/// Read the config at `path`, or `enex2mf.toml` if it exists. Without either, all defaults are
+/// those of the command line.
+pub fn load(path: Option<&Path>) -> Result<Config> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(DEFAULT_PATH).exists() => Path::new(DEFAULT_PATH),
+        None => return Ok(Config::default()),
+    };
+    parse(&fs::read_to_string(path)?)
+}
+
+fn parse(text: &str) -> Result<Config> {
+    Ok(toml::from_str(text)?)
+}
+
+#[test]
+fn test_parse() {
+    let config = parse(
+        "format = \"obsidian\"\noutput-dir = \"vault\"\nocr = \"comment\"\ntags = [\"work\"]\n",
+    )
+    .unwrap();
+    assert_eq!(config.format, Some(Format::Obsidian));
+    assert_eq!(config.output_dir, Some(PathBuf::from("vault")));
+    assert_eq!(config.ocr, Some(OcrMode::Comment));
+    assert_eq!(config.tags, vec!["work"]);
+    assert!(!config.lenient);
+
+    assert!(parse("fromat = \"org\"").is_err());
+    assert!(parse("format = \"word\"").is_err());
+}
@@ -0,0 +1,569 @@
+//! The whole pipeline in one call: parse an export, convert its notes, and write them.
+
+use crate::attachments::{self, AttachmentLayout, AttachmentOptions};
+use crate::enex::{EnexParser, Limits, Note};
+use crate::enml::{self, BodyMode, EnmlOptions, HighlightMode, Hooks, OcrMode};
+use crate::error::{Error, Result};
+use crate::links::{NoteIndex, NoteLinks};
+use crate::mindforger::MetadataOptions;
+use crate::quirks::Quirks;
+use crate::tags::TagMap;
+use crate::text;
+use crate::writer::{ExportInfo, Format};
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use log::warn;
+use regex::Regex;
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// What to convert and how.
+#[derive(Clone, Debug, Default)]
+pub struct ConvertOptions {
+    pub format: Format,
+    /// Name of the notebook, for formats with a header. Defaults to one from the export date.
+    pub name: Option<String>,
+    /// Reminders and application data to include in MindForger metadata.
+    pub metadata: MetadataOptions,
+    pub attachments: AttachmentOptions,
+    pub ocr: OcrMode,
+    /// How to mark highlighted text, or as suits the format, per `Format::highlight`.
+    pub highlight: Option<HighlightMode>,
+    pub body: BodyMode,
+    /// Passphrase for encrypted sections.
+    pub passphrase: Option<String>,
+    /// Skip unrecognized elements instead of failing.
+    pub lenient: bool,
+    /// Parse exports from other apps as strictly as Evernote's, rather than tolerating their
+    /// quirks (see `Producer::quirks`).
+    pub no_quirks: bool,
+    /// See `EnexParser::memory_limit`.
+    pub memory_limit: Option<usize>,
+    /// See `EnexParser::skip_resources`.
+    pub skip_resources: bool,
+    /// Check the hashes of resources against the content, warning about mismatches (see
+    /// `attachments::verify`).
+    pub verify_hashes: bool,
+    /// See `EnexParser::with_limits`.
+    pub limits: Limits,
+    /// Only convert notes with at least one of these tags, or all notes if empty.
+    pub tags: Vec<String>,
+    /// Only convert notes whose titles match, and not those whose titles match `title_exclude`.
+    /// Titles are matched as in the export, before untitled notes get one from their text.
+    pub title_match: Option<Regex>,
+    pub title_exclude: Option<Regex>,
+    /// Tags to rename or drop, before they're split by `tag_separators`.
+    pub tag_map: TagMap,
+    /// Characters that separate the levels of nested tags, e.g. `:` in `projects:home`. Such
+    /// tags are written with `/` between levels, which Obsidian, Logseq and Bear nest.
+    pub tag_separators: Vec<char>,
+    /// Where the converted notes are written, to point links between them at their files.
+    pub note_index: Option<Arc<NoteIndex>>,
+    /// Custom handlers for elements of note content.
+    pub hooks: Hooks,
+    /// What to date notes without a creation or update time.
+    pub missing_dates: MissingDates,
+    /// What to do about notes that can't be read.
+    pub on_error: OnError,
+    /// Text for the body of empty notes, or `None` to leave them blank.
+    pub empty_placeholder: Option<String>,
+    /// See `EnmlOptions::max_image_width`.
+    pub max_image_width: Option<u32>,
+}
+
+/// The body the command line gives empty notes.
+pub const EMPTY_PLACEHOLDER: &str = "This note is empty.";
+
+/// What to do about a note that can't be read, e.g. because it has an invalid date or
+/// attachment. Errors in the XML of the export end the conversion either way.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// Stop converting.
+    #[default]
+    Fail,
+    /// Leave the note out, and go on with the next.
+    Skip,
+    /// Write a note saying what went wrong in its place, and go on with the next.
+    Stub,
+}
+
+/// The time given to notes that have neither a creation nor an update time. A note with only one
+/// of them gets it for both either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MissingDates {
+    /// Leave the note undated.
+    Keep,
+    /// When the export was made, or when the export file was last modified if it doesn't say.
+    #[default]
+    Export,
+    /// When the export file was last modified, or the export date if it isn't a file.
+    File,
+    Fixed(DateTime<Local>),
+}
+
+impl MissingDates {
+    /// Parse `keep`, `export`, `file`, or a date like `2018-12-19` or `2018-12-19T11:13:04Z`.
+    pub fn parse(value: &str) -> Option<MissingDates> {
+        match value {
+            "keep" => Some(MissingDates::Keep),
+            "export" => Some(MissingDates::Export),
+            "file" => Some(MissingDates::File),
+            _ => {
+                let time = match DateTime::parse_from_rfc3339(value) {
+                    Ok(time) => time.with_timezone(&Local),
+                    Err(_) => {
+                        let date = NaiveDate::parse_from_str(value, "%F").ok()?;
+                        Local
+                            .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+                            .earliest()?
+                    }
+                };
+                Some(MissingDates::Fixed(time))
+            }
+        }
+    }
+
+    /// The time for undated notes of `export`.
+    fn time(self, export: &ExportInfo) -> Option<DateTime<Local>> {
+        match self {
+            MissingDates::Keep => None,
+            MissingDates::Export => export.export_date.or(export.file_modified),
+            MissingDates::File => export.file_modified.or(export.export_date),
+            MissingDates::Fixed(time) => Some(time),
+        }
+    }
+}
+
+impl ConvertOptions {
+    /// A parser for the export read from `reader`, configured per these options.
+    pub fn parser<R: Read>(&self, reader: R) -> EnexParser<R> {
+        let mut parser = EnexParser::with_limits(reader, self.limits)
+            .lenient(self.lenient)
+            .recover(self.on_error != OnError::Fail)
+            .skip_resources(self.skip_resources);
+        if self.no_quirks {
+            parser = parser.with_quirks(Quirks::default());
+        }
+        // Decoded next to where they're extracted, so that they're moved there rather than
+        // written again.
+        if let (Some(dir), None) = (&self.attachments.dir, &self.attachments.archive) {
+            parser = parser.decode_resources_to(dir);
+        }
+        match self.memory_limit {
+            Some(limit) => parser.memory_limit(limit),
+            None => parser,
+        }
+    }
+
+    /// Whether to convert `note`: its title matches `title_match` and not `title_exclude`, where
+    /// set, and it has any of the `tags` (ignoring case, like Evernote), or there are none.
+    pub fn includes(&self, note: &Note) -> bool {
+        let title = note.title.as_deref().unwrap_or_default();
+        if self
+            .title_match
+            .as_ref()
+            .is_some_and(|re| !re.is_match(title))
+            || self
+                .title_exclude
+                .as_ref()
+                .is_some_and(|re| re.is_match(title))
+        {
+            return false;
+        }
+        self.tags.is_empty()
+            || note.tags.iter().any(|tag| {
+                self.tags
+                    .iter()
+                    .any(|wanted| wanted.to_lowercase() == tag.to_lowercase())
+            })
+    }
+
+    /// Apply the options that change notes rather than how they're written, e.g. to their tags.
+    /// Untitled notes get a title from their first line of text, and undated notes the date
+    /// `missing_dates` gives for `export`.
+    pub fn prepare(&self, note: &mut Note, export: &ExportInfo) {
+        note.created = note
+            .created
+            .or(note.updated)
+            .or_else(|| self.missing_dates.time(export));
+        note.updated = note.updated.or(note.created);
+        if note.is_untitled() {
+            let markdown = note.content_markdown(&EnmlOptions::default());
+            if let Some(title) = text::title(&markdown) {
+                note.title = Some(title);
+            }
+        }
+        note.tags = self.tag_map.apply(&note.tags);
+        if self.tag_separators.is_empty() {
+            return;
+        }
+        let mut tags: Vec<String> = Vec::with_capacity(note.tags.len());
+        for tag in &note.tags {
+            let path: Vec<&str> = tag
+                .split(&self.tag_separators[..])
+                .map(str::trim)
+                .filter(|level| !level.is_empty())
+                .collect();
+            let path = path.join("/");
+            if !path.is_empty() && !tags.contains(&path) {
+                tags.push(path);
+            }
+        }
+        note.tags = tags;
+    }
+
+    /// Replace the content of `note` by the placeholder if it's empty, returning whether it was.
+    pub fn fill_empty(&self, note: &mut Note) -> bool {
+        if !note.is_empty() {
+            return false;
+        }
+        note.content = Some(match self.empty_placeholder {
+            Some(ref text) => format!(
+                "<en-note><div><i>{}</i></div></en-note>",
+                enml::escape(text)
+            ),
+            None => "<en-note></en-note>".to_string(),
+        });
+        true
+    }
+
+    /// With `verify_hashes`, a warning for each resource of `note` that its content doesn't refer
+    /// to, and each reference to a resource it doesn't have.
+    pub fn verify(&self, note: &Note) -> Result<Vec<String>> {
+        if !self.verify_hashes || self.skip_resources {
+            return Ok(Vec::new());
+        }
+        let title = note.title.as_deref().unwrap_or("untitled");
+        let mut warnings = Vec::new();
+        for mismatch in attachments::verify(note)? {
+            let warning = format!("{}: {}", title, mismatch);
+            warn!("{}", warning);
+            warnings.push(warning);
+        }
+        Ok(warnings)
+    }
+
+    /// A warning naming the notes linked to that weren't converted, with `note_index`, if any.
+    pub fn unresolved_links(&self) -> Option<String> {
+        let unresolved = self.note_index.as_ref()?.unresolved();
+        if unresolved.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "links to notes not converted: {}",
+            unresolved.join(", ")
+        ))
+    }
+
+    /// Per-note options for converting the content of `note`, extracting its attachments as
+    /// `attachments` says.
+    pub fn enml_options(
+        &self,
+        note: &Note,
+        attachments: &AttachmentOptions,
+    ) -> Result<EnmlOptions> {
+        let mut media = attachments::media(note, attachments)?;
+        // Obsidian finds attachments by file name, like the links it makes itself. Files named by
+        // hash in a single folder can't be mistaken for others in the vault.
+        let by_name = self.format == Format::Obsidian
+            && attachments.layout == AttachmentLayout::Shared
+            && attachments.names.is_none()
+            && attachments.archive.is_none();
+        if by_name {
+            for item in media
+                .values_mut()
+                .filter(|item| !item.target.starts_with("data:"))
+            {
+                if let Some(name) = Path::new(&item.target).file_name() {
+                    item.target = name.to_string_lossy().into_owned();
+                }
+            }
+        }
+        Ok(EnmlOptions {
+            media,
+            ocr: self.ocr,
+            highlight: self.highlight.unwrap_or(self.format.highlight()),
+            body: self.body,
+            passphrase: self.passphrase.clone(),
+            wiki_links: self.format == Format::Obsidian,
+            note_links: self.note_index.as_ref().map(|index| NoteLinks {
+                index: index.clone(),
+                relative_to: attachments.links_relative_to.clone().unwrap_or_default(),
+            }),
+            max_image_width: self.max_image_width,
+            hooks: self.hooks.clone(),
+        })
+    }
+
+    /// Where attachments go for this format: JEX and Notion archives contain them.
+    pub fn stream_attachments(&self) -> AttachmentOptions {
+        match self.format {
+            Format::Jex | Format::Notion => AttachmentOptions::default(),
+            _ => self.attachments.clone(),
+        }
+    }
+}
+
+/// What `convert` did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Notes written, after filtering by tag.
+    pub notes: usize,
+    /// Attachments of the notes written.
+    pub attachments: usize,
+    /// Problems that didn't stop the conversion: elements skipped and times left out in lenient
+    /// mode, attachments that don't match the content with `verify_hashes`, and links to notes
+    /// that weren't converted.
+    pub warnings: Vec<String>,
+    /// Notes that couldn't be read, and why, unless failing on errors.
+    pub failed: Vec<String>,
+    /// Titles of the notes written with the placeholder body, having no content.
+    pub empty: Vec<String>,
+    /// The notebooks written, in order.
+    pub notebooks: Vec<NotebookSummary>,
+}
+
+/// A notebook written and how many of its notes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotebookSummary {
+    pub name: String,
+    /// When the export was made, if known.
+    pub export_date: Option<DateTime<Local>>,
+    pub notes: usize,
+}
+
+impl Summary {
+    /// Add what was done converting another export, e.g. in parallel.
+    pub fn merge(&mut self, other: Summary) {
+        self.notes += other.notes;
+        self.attachments += other.attachments;
+        self.warnings.extend(other.warnings);
+        self.failed.extend(other.failed);
+        self.empty.extend(other.empty);
+        self.notebooks.extend(other.notebooks);
+    }
+}
+
+/// A note standing in for one that couldn't be read because of `error`, with `OnError::Stub`.
+pub fn stub_note(error: &Error) -> Note {
+    let message = enml::escape(&error.to_string());
+    Note::builder()
+        .title("Note not converted")
+        .content(format!(
+            "<en-note><div>This note couldn't be read: {}</div></en-note>",
+            message
+        ))
+        .build()
+}
+
+/// Convert the export read from `reader` to a single notebook written to `writer`.
+pub fn convert(reader: impl Read, writer: impl Write, options: &ConvertOptions) -> Result<Summary> {
+    let mut backend = options
+        .format
+        .stream_writer(writer, options.metadata.clone())
+        .ok_or_else(|| Error::Unsupported(format!("{:?} needs a directory", options.format)))?;
+    let attachments = options.stream_attachments();
+    let mut parser = options.parser(reader);
+    parser.start()?;
+    let mut export = ExportInfo::from_parser(&parser);
+    if let Some(ref name) = options.name {
+        export.name = name.clone();
+    }
+
+    let mut summary = Summary::default();
+    backend.begin(&export)?;
+    for (index, note) in parser.by_ref().enumerate() {
+        let mut note = match (note, options.on_error) {
+            (Ok(note), _) => note,
+            (Err(err), OnError::Fail) => return Err(err),
+            (Err(err), on_error) => {
+                summary.failed.push(format!("note {}: {}", index + 1, err));
+                if on_error == OnError::Skip {
+                    continue;
+                }
+                stub_note(&err)
+            }
+        };
+        if !options.includes(&note) {
+            continue;
+        }
+        options.prepare(&mut note, &export);
+        if options.fill_empty(&mut note) {
+            let title = note.title.as_deref().unwrap_or("untitled");
+            summary.empty.push(title.to_string());
+        }
+        summary.warnings.extend(options.verify(&note)?);
+        let enml = options.enml_options(&note, &attachments)?;
+        backend.write_note(&note, &enml)?;
+        summary.notes += 1;
+        summary.attachments += note.resources.len();
+    }
+    backend.finish()?;
+    summary.notebooks.push(NotebookSummary {
+        name: export.name,
+        export_date: export.export_date,
+        notes: summary.notes,
+    });
+
+    summary.warnings.extend_from_slice(parser.warnings());
+    summary.warnings.extend(options.unresolved_links());
+    Ok(summary)
+}
+
+#[test]
+fn test_convert() {
+    let enex = r#"<en-export export-date="20181226T083916Z">
+        <note><title>foo</title><tag>a</tag><future/></note>
+        <note><title>bar</title></note>
+        </en-export>"#;
+    let options = ConvertOptions {
+        format: Format::Markdown,
+        lenient: true,
+        tags: vec!["A".to_string()],
+        empty_placeholder: Some(EMPTY_PLACEHOLDER.to_string()),
+        missing_dates: MissingDates::Keep,
+        ..ConvertOptions::default()
+    };
+    let mut out = Vec::new();
+    let summary = convert(enex.as_bytes(), &mut out, &options).unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            notes: 1,
+            attachments: 0,
+            warnings: vec!["skipped unexpected <future>".to_string()],
+            failed: Vec::new(),
+            empty: vec!["foo".to_string()],
+            notebooks: vec![NotebookSummary {
+                name: "Evernote export 2018-12-26".to_string(),
+                export_date: summary.notebooks[0].export_date,
+                notes: 1,
+            }],
+        }
+    );
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "# foo\n\n*Tags: a*\n\n*This note is empty.*\n\n"
+    );
+
+    let options = ConvertOptions {
+        format: Format::Obsidian,
+        ..ConvertOptions::default()
+    };
+    assert!(convert(enex.as_bytes(), Vec::new(), &options).is_err());
+}
+
+#[test]
+fn test_title_filter() {
+    let options = ConvertOptions {
+        title_match: Some(Regex::new("(?i)^meeting").unwrap()),
+        title_exclude: Some(Regex::new("draft").unwrap()),
+        ..ConvertOptions::default()
+    };
+    let titles = ["Meeting notes", "meeting draft", "Notes from meeting", ""];
+    let included: Vec<&str> = titles
+        .iter()
+        .copied()
+        .filter(|&title| options.includes(&Note::builder().title(title).build()))
+        .collect();
+    assert_eq!(included, ["Meeting notes"]);
+}
+
+#[test]
+fn test_tag_hierarchy() {
+    let options = ConvertOptions {
+        tag_separators: vec!['/', ':'],
+        ..ConvertOptions::default()
+    };
+    let mut note = Note::builder()
+        .tag("projects: home")
+        .tag("projects/home")
+        .tag("a//b")
+        .tag("flat")
+        .build();
+    options.prepare(&mut note, &ExportInfo::default());
+    assert_eq!(note.tags, ["projects/home", "a/b", "flat"]);
+}
+
+#[test]
+fn test_missing_dates() {
+    let export = ExportInfo::from_date(Some(Local.with_ymd_and_hms(2020, 1, 2, 0, 0, 0).unwrap()));
+    let options = ConvertOptions {
+        missing_dates: MissingDates::parse("file").unwrap(),
+        ..ConvertOptions::default()
+    };
+    let mut note = Note::builder().title("t").build();
+    options.prepare(&mut note, &export);
+    assert_eq!(note.created, export.export_date);
+    assert_eq!(note.updated, export.export_date);
+
+    let updated = Local.with_ymd_and_hms(2019, 5, 6, 7, 8, 9).unwrap();
+    let mut note = Note::builder().title("t").updated(updated).build();
+    options.prepare(&mut note, &export);
+    assert_eq!(note.created, Some(updated));
+
+    assert_eq!(
+        MissingDates::parse("2018-12-19"),
+        Some(MissingDates::Fixed(
+            Local.with_ymd_and_hms(2018, 12, 19, 0, 0, 0).unwrap()
+        ))
+    );
+    assert_eq!(MissingDates::parse("yesterday"), None);
+
+    // By default, undated notes get the export date, else the file's.
+    let modified = Local.with_ymd_and_hms(2021, 3, 4, 0, 0, 0).unwrap();
+    let export = ExportInfo {
+        file_modified: Some(modified),
+        ..ExportInfo::default()
+    };
+    let mut note = Note::builder().title("t").build();
+    ConvertOptions::default().prepare(&mut note, &export);
+    assert_eq!(note.created, Some(modified));
+}
+
+#[test]
+fn test_warnings() {
+    let enex = "<en-export><note><title>a</title><created>soon</created>\
+                <content><![CDATA[<en-note><a href=\"evernote:///view/1/s1/x/x/\">Gone</a>\
+                <en-media hash=\"0123\" type=\"image/png\"/></en-note>]]></content>\
+                </note></en-export>";
+    let options = ConvertOptions {
+        format: Format::Markdown,
+        lenient: true,
+        verify_hashes: true,
+        note_index: Some(Arc::new(NoteIndex::default())),
+        ..ConvertOptions::default()
+    };
+    let summary = convert(enex.as_bytes(), Vec::new(), &options).unwrap();
+    let warnings = &summary.warnings;
+    assert_eq!(warnings.len(), 3, "{:?}", warnings);
+    assert!(warnings[0].starts_with("a: <en-media> refers to 0123"));
+    assert!(warnings[1].contains("left out <created>"));
+    assert_eq!(warnings[2], "links to notes not converted: Gone");
+}
+
+#[test]
+fn test_on_error() {
+    let enex = "<en-export><note><title>a</title><created>soon</created><tag>x</tag></note>\
+                <note><title>b</title></note></en-export>";
+    let options = |on_error| ConvertOptions {
+        format: Format::Markdown,
+        on_error,
+        ..ConvertOptions::default()
+    };
+    assert!(convert(enex.as_bytes(), Vec::new(), &options(OnError::Fail)).is_err());
+
+    let mut out = Vec::new();
+    let summary = convert(enex.as_bytes(), &mut out, &options(OnError::Skip)).unwrap();
+    assert_eq!(summary.notes, 1);
+    assert_eq!(summary.failed.len(), 1);
+    assert!(String::from_utf8(out).unwrap().starts_with("# b\n"));
+
+    let mut out = Vec::new();
+    let summary = convert(enex.as_bytes(), &mut out, &options(OnError::Stub)).unwrap();
+    assert_eq!(summary.notes, 2);
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("# Note not converted\n\nThis note couldn't be read: "));
+}
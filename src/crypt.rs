@@ -0,0 +1,136 @@
+//! Decrypts `<en-crypt>` sections, which Evernote produces with "Encrypt selected text".
+//!
+//! Newer clients use AES-128-CBC with PBKDF2-derived keys and an HMAC. The legacy format is RC2
+//! with an MD5-derived key and a CRC32 checksum prefix.
+
+use aes::Aes128;
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockDecryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use rc2::cipher::generic_array::GenericArray;
+use rc2::cipher::BlockDecrypt;
+use rc2::Rc2;
+use sha2::Sha256;
+
+const AES_SIGNATURE: &[u8] = b"ENC0";
+const AES_ITERATIONS: u32 = 50000;
+const AES_KEY_LEN: usize = 16;
+const AES_SALT_LEN: usize = 16;
+const AES_IV_LEN: usize = 16;
+const AES_HMAC_LEN: usize = 32;
+
+/// Decrypt the base64 `data` of an `<en-crypt cipher=...>` element to its ENML fragment. Returns
+/// `None` if the passphrase is wrong or the data is malformed.
+pub fn decrypt(cipher: &str, data: &str, passphrase: &str) -> Option<String> {
+    let data: Vec<u8> = data.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let data = base64::decode(&data).ok()?;
+    let plaintext = match cipher {
+        "AES" => decrypt_aes(&data, passphrase)?,
+        "RC2" => decrypt_rc2(&data, passphrase)?,
+        _ => return None,
+    };
+    String::from_utf8(plaintext).ok()
+}
+
+fn pbkdf2_key(passphrase: &str, salt: &[u8]) -> [u8; AES_KEY_LEN] {
+    let mut key = [0; AES_KEY_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, AES_ITERATIONS, &mut key);
+    key
+}
+
+/// The layout is `ENC0`, key salt, HMAC key salt, IV, ciphertext, then an HMAC-SHA256 of
+/// everything before it.
+fn decrypt_aes(data: &[u8], passphrase: &str) -> Option<Vec<u8>> {
+    let header_len = AES_SIGNATURE.len() + 2 * AES_SALT_LEN + AES_IV_LEN;
+    if data.len() < header_len + AES_HMAC_LEN || !data.starts_with(AES_SIGNATURE) {
+        return None;
+    }
+    let (body, hmac) = data.split_at(data.len() - AES_HMAC_LEN);
+    let (salt, rest) = body[AES_SIGNATURE.len()..].split_at(AES_SALT_LEN);
+    let (hmac_salt, rest) = rest.split_at(AES_SALT_LEN);
+    let (iv, ciphertext) = rest.split_at(AES_IV_LEN);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&pbkdf2_key(passphrase, hmac_salt)).ok()?;
+    mac.update(body);
+    mac.verify_slice(hmac).ok()?;
+
+    cbc::Decryptor::<Aes128>::new_from_slices(&pbkdf2_key(passphrase, salt), iv)
+        .ok()?
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .ok()
+}
+
+/// RC2-ECB with 64 effective key bits. The plaintext is NUL-padded and starts with the first
+/// four hex digits of the CRC32 of the rest, which tells us whether the passphrase was right.
+fn decrypt_rc2(data: &[u8], passphrase: &str) -> Option<Vec<u8>> {
+    if data.is_empty() || !data.len().is_multiple_of(8) {
+        return None;
+    }
+    let cipher = Rc2::new_with_eff_key_len(&md5::compute(passphrase.as_bytes()).0, 64);
+    let mut plaintext = data.to_vec();
+    for block in plaintext.chunks_exact_mut(8) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+    while plaintext.last() == Some(&0) {
+        plaintext.pop();
+    }
+    if plaintext.len() < 4 {
+        return None;
+    }
+    let text = plaintext.split_off(4);
+    let checksum = format!("{:08X}", crc32(&text));
+    if !checksum[..4].eq_ignore_ascii_case(std::str::from_utf8(&plaintext).ok()?) {
+        return None;
+    }
+    Some(text)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[test]
+fn test_decrypt_aes() {
+    use cbc::cipher::BlockEncryptMut;
+
+    let passphrase = "hunter2";
+    let mut body = AES_SIGNATURE.to_vec();
+    body.extend_from_slice(&[1; AES_SALT_LEN]);
+    body.extend_from_slice(&[2; AES_SALT_LEN]);
+    body.extend_from_slice(&[3; AES_IV_LEN]);
+    body.extend(
+        cbc::Encryptor::<Aes128>::new_from_slices(
+            &pbkdf2_key(passphrase, &[1; AES_SALT_LEN]),
+            &[3; AES_IV_LEN],
+        )
+        .unwrap()
+        .encrypt_padded_vec_mut::<Pkcs7>(b"<div>secret</div>"),
+    );
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(&pbkdf2_key(passphrase, &[2; AES_SALT_LEN])).unwrap();
+    mac.update(&body);
+    body.extend(mac.finalize().into_bytes());
+    let data = base64::encode(&body);
+
+    assert_eq!(
+        decrypt("AES", &data, passphrase).as_deref(),
+        Some("<div>secret</div>")
+    );
+    assert_eq!(decrypt("AES", &data, "wrong"), None);
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
@@ -0,0 +1,135 @@
+//! How times are written in note metadata.
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, Utc};
+use chrono_tz::Tz;
+
+/// Format of written times.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum DateFormat {
+    /// `2018-12-19 11:13:04`, which MindForger reads.
+    #[default]
+    Plain,
+    /// ISO 8601 with the offset, e.g. `2018-12-19T11:13:04+01:00`.
+    Iso8601,
+    /// RFC 3339, e.g. `2018-12-19T10:13:04Z` in UTC.
+    Rfc3339,
+    /// A `strftime` format, as chrono understands it.
+    Custom(String),
+}
+
+/// Time zone to write times in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DateZone {
+    /// The local time zone, which on Unix can be chosen with the `TZ` environment variable.
+    #[default]
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+    /// A zone of the IANA database, e.g. `Europe/Paris`, with its daylight saving time.
+    Named(Tz),
+}
+
+/// How to write times.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DateOptions {
+    pub format: DateFormat,
+    pub zone: DateZone,
+}
+
+impl DateOptions {
+    /// `time` in the chosen zone and format.
+    pub fn format(&self, time: &DateTime<Local>) -> String {
+        match self.zone {
+            DateZone::Local => self.format_in(time),
+            DateZone::Utc => self.format_in(&time.with_timezone(&Utc)),
+            DateZone::Fixed(offset) => self.format_in(&time.with_timezone(&offset)),
+            DateZone::Named(zone) => self.format_in(&time.with_timezone(&zone)),
+        }
+    }
+
+    fn format_in<Tz: chrono::TimeZone>(&self, time: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        match self.format {
+            DateFormat::Plain => time.format("%F %T").to_string(),
+            DateFormat::Iso8601 => time.format("%FT%T%:z").to_string(),
+            DateFormat::Rfc3339 => time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            DateFormat::Custom(ref format) => time.format(format).to_string(),
+        }
+    }
+}
+
+/// Parse a format: `plain`, `iso8601`, `rfc3339`, or a `strftime` format. Returns `None` for
+/// invalid `strftime` formats, which would fail when formatting.
+pub fn parse_format(format: &str) -> Option<DateFormat> {
+    match format {
+        "plain" => Some(DateFormat::Plain),
+        "iso8601" => Some(DateFormat::Iso8601),
+        "rfc3339" => Some(DateFormat::Rfc3339),
+        _ if StrftimeItems::new(format).any(|item| item == Item::Error) => None,
+        _ => Some(DateFormat::Custom(format.to_string())),
+    }
+}
+
+/// Parse a time zone: `local`, `utc`, an offset like `+05:30`, or a name like `Europe/Paris`.
+pub fn parse_zone(zone: &str) -> Option<DateZone> {
+    match zone.to_lowercase().as_str() {
+        "local" => Some(DateZone::Local),
+        "utc" | "z" => Some(DateZone::Utc),
+        _ if zone.contains('/') => zone.parse().ok().map(DateZone::Named),
+        _ => {
+            let time = format!("2000-01-01T00:00:00{}", zone);
+            let offset = DateTime::parse_from_rfc3339(&time)
+                .ok()?
+                .offset()
+                .to_owned();
+            Some(DateZone::Fixed(offset))
+        }
+    }
+}
+
+#[test]
+fn test_format() {
+    use chrono::TimeZone;
+
+    let time = Utc
+        .with_ymd_and_hms(2018, 12, 19, 10, 13, 4)
+        .unwrap()
+        .with_timezone(&Local);
+    let utc = |format| DateOptions {
+        format,
+        zone: DateZone::Utc,
+    };
+    assert_eq!(utc(DateFormat::Plain).format(&time), "2018-12-19 10:13:04");
+    assert_eq!(
+        utc(DateFormat::Iso8601).format(&time),
+        "2018-12-19T10:13:04+00:00"
+    );
+    assert_eq!(
+        utc(DateFormat::Rfc3339).format(&time),
+        "2018-12-19T10:13:04Z"
+    );
+    let options = DateOptions {
+        format: DateFormat::Custom("%d.%m.%Y %H:%M".to_string()),
+        zone: parse_zone("+05:30").unwrap(),
+    };
+    assert_eq!(options.format(&time), "19.12.2018 15:43");
+    assert_eq!(parse_format("%Q"), None);
+    assert_eq!(parse_zone("UTC"), Some(DateZone::Utc));
+    assert_eq!(parse_zone("Mars/Olympus"), None);
+    let paris = |format| DateOptions {
+        format,
+        zone: parse_zone("Europe/Paris").unwrap(),
+    };
+    assert_eq!(
+        paris(DateFormat::Iso8601).format(&time),
+        "2018-12-19T11:13:04+01:00"
+    );
+    let summer = Utc.with_ymd_and_hms(2018, 7, 1, 10, 0, 0).unwrap();
+    assert_eq!(
+        paris(DateFormat::Plain).format(&summer.with_timezone(&Local)),
+        "2018-07-01 12:00:00"
+    );
+}
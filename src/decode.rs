@@ -0,0 +1,401 @@
+//! Transcoding exports to UTF-8 before they're parsed. Older exports and some third-party tools
+//! write Latin-1, Windows-1252 or UTF-16, which is told by a byte order mark or the XML
+//! declaration. Bytes that aren't valid UTF-8 in an export said to be UTF-8 are read as
+//! Windows-1252, which is what such files usually turn out to be, or as GBK in exports by
+//! Yinxiang Biji.
+
+use crate::quirks::Producer;
+use std::char::REPLACEMENT_CHARACTER;
+use std::io::{self, Read};
+
+/// How many bytes to look at for a byte order mark, the XML declaration and the app named by
+/// `<en-export>`.
+const SNIFF_LEN: usize = 512;
+
+const BLOCK_LEN: usize = 64 * 1024;
+
+/// The characters Windows-1252 has for bytes 0x80 to 0x9f, where Latin-1 has control characters.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// GBK's characters for each lead byte from 0x81 to 0xfe and trail byte from 0x40 to 0xfe, as
+/// big-endian UTF-16, or 0 where there's none. Generated with Python's `gbk` codec.
+const GBK: &[u8; 126 * 191 * 2] = include_bytes!("gbk.bin");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Utf8,
+    /// Latin-1, which is read as its superset Windows-1252.
+    Windows1252,
+    Utf16Le,
+    Utf16Be,
+    /// GBK, or its subset GB2312. GB18030's four-byte sequences aren't decoded.
+    Gbk,
+}
+
+/// Reads an export as UTF-8, whatever its encoding. The XML declaration is rewritten to say so.
+pub struct Utf8Reader<R> {
+    inner: R,
+    encoding: Option<Encoding>,
+    /// What bytes that aren't valid UTF-8 are read as.
+    fallback: Encoding,
+    /// Read but not yet decoded, e.g. the start of a character split between reads.
+    raw: Vec<u8>,
+    decoded: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> Utf8Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Utf8Reader {
+            inner,
+            encoding: None,
+            fallback: Encoding::Windows1252,
+            raw: Vec::new(),
+            decoded: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    /// Read and decode the next block, returning false at the end.
+    fn fill(&mut self) -> io::Result<bool> {
+        self.decoded.clear();
+        self.pos = 0;
+        while self.decoded.is_empty() {
+            if self.eof {
+                return Ok(false);
+            }
+            let want = if self.encoding.is_none() {
+                SNIFF_LEN
+            } else {
+                BLOCK_LEN
+            };
+            while self.raw.len() < want && !self.eof {
+                let start = self.raw.len();
+                self.raw.resize(want, 0);
+                let n = self.inner.read(&mut self.raw[start..])?;
+                self.raw.truncate(start + n);
+                self.eof = n == 0;
+            }
+            match self.encoding {
+                Some(encoding) => self.decode(encoding),
+                None => {
+                    self.fallback = fallback(&self.raw);
+                    let encoding = sniff(&mut self.raw);
+                    self.encoding = Some(encoding);
+                    self.decode(encoding);
+                    if self.decoded.starts_with(b"<?xml") {
+                        self.decoded = declare_utf8(&self.decoded);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Move what can be decoded of `raw` to `decoded`.
+    fn decode(&mut self, encoding: Encoding) {
+        let mut decoded = String::new();
+        let used = match encoding {
+            Encoding::Utf8 => match std::str::from_utf8(&self.raw) {
+                Ok(_) => {
+                    std::mem::swap(&mut self.raw, &mut self.decoded);
+                    self.raw.clear();
+                    return;
+                }
+                Err(_) => decode_utf8(&self.raw, self.eof, self.fallback, &mut decoded),
+            },
+            Encoding::Windows1252 => {
+                decoded.extend(self.raw.iter().map(|&byte| windows_1252(byte)));
+                self.raw.len()
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                decode_utf16(&self.raw, encoding, self.eof, &mut decoded)
+            }
+            Encoding::Gbk => decode_gbk(&self.raw, self.eof, &mut decoded),
+        };
+        self.raw.drain(..used);
+        self.decoded = decoded.into_bytes();
+    }
+}
+
+impl<R: Read> Read for Utf8Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.decoded.len() && !self.fill()? {
+            return Ok(0);
+        }
+        let n = (&self.decoded[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// The encoding of an export starting with `start`, dropping any byte order mark and tidying the
+/// XML declaration.
+fn sniff(start: &mut Vec<u8>) -> Encoding {
+    let (encoding, bom_len) = match start.as_slice() {
+        [0xef, 0xbb, 0xbf, ..] => (Some(Encoding::Utf8), 3),
+        [0xff, 0xfe, ..] => (Some(Encoding::Utf16Le), 2),
+        [0xfe, 0xff, ..] => (Some(Encoding::Utf16Be), 2),
+        [b'<', 0, b'?', 0, ..] => (Some(Encoding::Utf16Le), 0),
+        [0, b'<', 0, b'?', ..] => (Some(Encoding::Utf16Be), 0),
+        _ => (None, 0),
+    };
+    start.drain(..bom_len);
+    match encoding {
+        Some(Encoding::Utf16Le | Encoding::Utf16Be) => {}
+        _ => tidy_declaration(start),
+    }
+    encoding.unwrap_or_else(|| declared(start))
+}
+
+/// Drop whitespace before an XML declaration at the start of `start` and lowercase its `<?xml`,
+/// which XML parsers reject but some apps write.
+fn tidy_declaration(start: &mut Vec<u8>) {
+    let at = match start.iter().position(|b| !b.is_ascii_whitespace()) {
+        Some(at) => at,
+        None => return,
+    };
+    let is_declaration = start[at..]
+        .get(..6)
+        .is_some_and(|prefix| prefix[..5].eq_ignore_ascii_case(b"<?xml") && prefix[5] <= b' ');
+    if is_declaration {
+        start.drain(..at);
+        start[2..5].make_ascii_lowercase();
+    }
+}
+
+/// The encoding the XML declaration at the start of `start` gives, if it's one of ours.
+fn declared(start: &[u8]) -> Encoding {
+    let declaration = match start.strip_prefix(b"<?xml") {
+        Some(rest) => &rest[..rest.iter().position(|&b| b == b'>').unwrap_or(rest.len())],
+        None => return Encoding::Utf8,
+    };
+    let declaration = String::from_utf8_lossy(declaration).to_lowercase();
+    match encoding_value(&declaration) {
+        Some("iso-8859-1" | "iso8859-1" | "latin1" | "latin-1" | "windows-1252" | "cp1252") => {
+            Encoding::Windows1252
+        }
+        Some("gbk" | "gb2312" | "gb18030" | "cp936" | "x-gbk" | "euc-cn") => Encoding::Gbk,
+        _ => Encoding::Utf8,
+    }
+}
+
+/// What bytes that aren't valid UTF-8 are read as in an export starting with `start`: GBK if
+/// the app that made it writes some content so (see `Quirks::gbk`), otherwise Windows-1252.
+fn fallback(start: &[u8]) -> Encoding {
+    let start = String::from_utf8_lossy(start);
+    let application = start
+        .find("<en-export")
+        .and_then(|at| attribute_value(&start[at..], "application"));
+    if Producer::from_application(application).quirks().gbk {
+        Encoding::Gbk
+    } else {
+        Encoding::Windows1252
+    }
+}
+
+/// The value of the `encoding` pseudo-attribute of an XML declaration.
+fn encoding_value(declaration: &str) -> Option<&str> {
+    attribute_value(declaration, "encoding")
+}
+
+/// The value of the first attribute `name` in `tag`.
+fn attribute_value<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let rest = &tag[tag.find(name)? + name.len()..];
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+    let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let value = &rest[1..];
+    Some(&value[..value.find(quote)?])
+}
+
+/// `decoded` with the encoding in its XML declaration changed to UTF-8.
+fn declare_utf8(decoded: &[u8]) -> Vec<u8> {
+    let end = match decoded.iter().position(|&b| b == b'>') {
+        Some(end) => end,
+        None => return decoded.to_vec(),
+    };
+    let declaration = String::from_utf8_lossy(&decoded[..end]);
+    if !declaration.is_ascii() {
+        return decoded.to_vec();
+    }
+    let lower = declaration.to_lowercase();
+    let value = match encoding_value(&lower) {
+        Some(value) => value,
+        None => return decoded.to_vec(),
+    };
+    // The declaration is ASCII, so lowercasing it kept the offsets.
+    let start = value.as_ptr() as usize - lower.as_ptr() as usize;
+    let mut rewritten = Vec::with_capacity(decoded.len());
+    rewritten.extend_from_slice(&declaration.as_bytes()[..start]);
+    rewritten.extend_from_slice(b"UTF-8");
+    rewritten.extend_from_slice(&decoded[start + value.len()..]);
+    rewritten
+}
+
+fn windows_1252(byte: u8) -> char {
+    match byte {
+        0x80..=0x9f => WINDOWS_1252[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+/// The character at the start of `bytes` in GBK and its length, or `None` if `bytes` ends within
+/// it. Bytes that aren't GBK are U+FFFD.
+fn gbk_char(bytes: &[u8]) -> Option<(char, usize)> {
+    match *bytes {
+        [] => None,
+        [byte @ 0..=0x7f, ..] => Some((byte as char, 1)),
+        [0x80, ..] => Some(('€', 1)),
+        [0xff, ..] | [_, 0..=0x2f, ..] | [_, 0x3a..=0x3f, ..] | [_, 0xff, ..] => {
+            Some((REPLACEMENT_CHARACTER, 1))
+        }
+        [_] => None,
+        [lead, trail @ 0x40..=0xfe, ..] => {
+            let at = (usize::from(lead - 0x81) * 191 + usize::from(trail - 0x40)) * 2;
+            let unit = u16::from_be_bytes([GBK[at], GBK[at + 1]]);
+            let c = match unit {
+                0 => REPLACEMENT_CHARACTER,
+                unit => char::from_u32(unit.into()).unwrap_or(REPLACEMENT_CHARACTER),
+            };
+            Some((c, 2))
+        }
+        // GB18030's four-byte sequences, with a digit for their second byte.
+        _ if bytes.len() < 4 => None,
+        _ => Some((REPLACEMENT_CHARACTER, 4)),
+    }
+}
+
+/// Decode `raw` as GBK into `decoded`. Returns how many bytes were used, leaving a character split
+/// at the end unless at `eof`.
+fn decode_gbk(raw: &[u8], eof: bool, decoded: &mut String) -> usize {
+    let mut used = 0;
+    while used < raw.len() {
+        match gbk_char(&raw[used..]) {
+            Some((c, len)) => {
+                decoded.push(c);
+                used += len;
+            }
+            None if eof => {
+                decoded.push(REPLACEMENT_CHARACTER);
+                return raw.len();
+            }
+            None => break,
+        }
+    }
+    used
+}
+
+/// Decode `raw` as UTF-8 into `decoded`, reading invalid bytes as `fallback`, GBK or
+/// Windows-1252. Returns how many bytes were used, leaving a character split at the end unless
+/// at `eof`.
+fn decode_utf8(raw: &[u8], eof: bool, fallback: Encoding, decoded: &mut String) -> usize {
+    let mut rest = raw;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                decoded.push_str(valid);
+                return raw.len();
+            }
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                decoded.push_str(std::str::from_utf8(valid).expect("valid up to here"));
+                if fallback == Encoding::Gbk {
+                    match gbk_char(invalid) {
+                        Some((c, len)) => {
+                            decoded.push(c);
+                            rest = &invalid[len..];
+                        }
+                        None if !eof => return raw.len() - invalid.len(),
+                        None => {
+                            decoded.push(REPLACEMENT_CHARACTER);
+                            return raw.len();
+                        }
+                    }
+                    continue;
+                }
+                let len = match err.error_len() {
+                    Some(len) => len,
+                    None if !eof => return raw.len() - invalid.len(),
+                    None => invalid.len(),
+                };
+                decoded.extend(invalid[..len].iter().map(|&byte| windows_1252(byte)));
+                rest = &invalid[len..];
+            }
+        }
+    }
+}
+
+/// Decode `raw` as UTF-16 into `decoded`. Returns how many bytes were used, leaving a character
+/// split at the end unless at `eof`.
+fn decode_utf16(raw: &[u8], encoding: Encoding, eof: bool, decoded: &mut String) -> usize {
+    let mut units: Vec<u16> = raw
+        .chunks_exact(2)
+        .map(|pair| match encoding {
+            Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+            _ => u16::from_be_bytes([pair[0], pair[1]]),
+        })
+        .collect();
+    let mut used = units.len() * 2;
+    if !eof
+        && units
+            .last()
+            .is_some_and(|&unit| (0xd800..0xdc00).contains(&unit))
+    {
+        units.pop();
+        used -= 2;
+    }
+    decoded.extend(char::decode_utf16(units).map(|c| c.unwrap_or(REPLACEMENT_CHARACTER)));
+    if eof {
+        raw.len()
+    } else {
+        used
+    }
+}
+
+#[test]
+fn test_utf8_reader() {
+    let read = |bytes: &[u8]| {
+        let mut text = String::new();
+        Utf8Reader::new(bytes).read_to_string(&mut text).unwrap();
+        text
+    };
+    let latin1 = b"<?xml version=\"1.0\" encoding='ISO-8859-1'?><a>caf\xe9 \x93q\x94</a>";
+    assert_eq!(
+        read(latin1),
+        "<?xml version=\"1.0\" encoding='UTF-8'?><a>café “q”</a>"
+    );
+    assert_eq!(
+        read(b"\xef\xbb\xbf<a>caf\xc3\xa9 caf\xe9</a>"),
+        "<a>café café</a>"
+    );
+    let utf16: Vec<u8> = "\u{feff}<?xml version=\"1.0\" encoding=\"UTF-16\"?><a>𝄞</a>"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    assert_eq!(
+        read(&utf16),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><a>𝄞</a>"
+    );
+    assert_eq!(
+        read(b"\xef\xbb\xbf\r\n  <?XML version=\"1.0\" encoding=\"latin1\"?><a>\xe9</a>"),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><a>é</a>"
+    );
+    let gbk = b"<?xml version=\"1.0\" encoding=\"GBK\"?><a>\xd6\xd0\xce\xc4 \x80</a>";
+    assert_eq!(
+        read(gbk),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><a>中文 €</a>"
+    );
+    // Yinxiang Biji's exports are UTF-8, except for content it wrote in GBK.
+    let yinxiang = b"<en-export application=\"YXBJ/Windows\"><a>\xe4\xb8\xad\xce\xc4</a>";
+    assert_eq!(
+        read(yinxiang),
+        "<en-export application=\"YXBJ/Windows\"><a>中文</a>"
+    );
+    // Not a declaration, so not the parser's problem.
+    assert_eq!(read(b" <?xml-stylesheet?><a/>"), " <?xml-stylesheet?><a/>");
+}
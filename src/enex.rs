@@ -1,21 +1,66 @@
-//! Parses a .enex Evernote export using xml-rs pull parser.
+//! Parses a .enex Evernote export with an XML pull parser, xml-rs or quick-xml (see `pull`).
 
+use crate::enml::{self, EnmlOptions};
 use crate::error::{Error, Result};
-use chrono::{DateTime, Local};
-use std::io::Read;
-use xml::reader::{EventReader, ParserConfig, XmlEvent};
+use crate::pull::{self, Pull, XmlPull};
+use crate::quirks::{Producer, Quirks};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use xml::attribute::OwnedAttribute;
+use xml::common::TextPosition;
+use xml::reader::{EventReader, XmlEvent};
 
+/// Metadata of a note from its `<note-attributes>`.
 #[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NoteAttributes {
+    pub subject_date: Option<DateTime<Local>>,
     pub author: Option<String>,
     pub source_url: Option<String>,
     pub source: Option<String>,
     pub latitude: Option<String>,
     pub longitude: Option<String>,
     pub altitude: Option<String>,
+    pub reminder_order: Option<String>,
+    pub reminder_time: Option<DateTime<Local>>,
+    pub reminder_done_time: Option<DateTime<Local>>,
+    /// Set by apps like Evernote Food or Hello whose notes have special structure, e.g.
+    /// `evernote.food.meal`. Writers may want to special-case these.
+    pub content_class: Option<String>,
+    /// Arbitrary `<application-data key=...>` entries, e.g. from third-party apps.
+    pub application_data: HashMap<String, String>,
 }
 
+/// Metadata of a resource from its `<resource-attributes>`, except the file name, which is kept
+/// on the resource.
 #[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceAttributes {
+    /// Where the resource came from, e.g. the image of a web clip.
+    pub source_url: Option<String>,
+    /// When the resource was made, e.g. a photo taken.
+    pub timestamp: Option<DateTime<Local>>,
+    pub latitude: Option<String>,
+    pub longitude: Option<String>,
+    pub altitude: Option<String>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub reco_type: Option<String>,
+    /// Evernote's flag for showing the resource as an attachment rather than inline, even if it's
+    /// an image.
+    pub attachment: bool,
+    /// Arbitrary `<application-data key=...>` entries, e.g. from third-party apps.
+    pub application_data: HashMap<String, String>,
+}
+
+/// A note of the export. The content is ENML, Evernote's dialect of XHTML.
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Note {
     pub title: Option<String>,
     pub content: Option<String>,
@@ -23,91 +68,716 @@ pub struct Note {
     pub updated: Option<DateTime<Local>>,
     pub tags: Vec<String>,
     pub attributes: NoteAttributes,
+    pub resources: Vec<Resource>,
+    /// Where the note is in the export, if the parser was asked with `EnexParser::spans`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Option<Span>,
+}
+
+/// Where a note is in the export, from the start of `<note>` to the start of `</note>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: TextPosition,
+    pub end: TextPosition,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// An attachment embedded in a note, referenced from the content by `<en-media hash=...>`.
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Resource {
+    /// Base64-encoded contents, as found in the export.
+    pub data: Option<String>,
+    /// Base64-encoded contents of `<alternate-data>`, another form of the resource that Evernote
+    /// made for some, e.g. ink notes.
+    pub alternate_data: Option<String>,
+    pub mime: Option<String>,
+    pub file_name: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// OCR'd words from `<recognition>`, keeping the most likely candidate for each.
+    pub recognition: Vec<String>,
+    pub attributes: ResourceAttributes,
+    /// The decoded contents instead of `data`, for resources over `EnexParser::memory_limit` or
+    /// with `EnexParser::decode_resources_to`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub spilled: Option<SpillFile>,
+    /// The decoded contents instead of `alternate_data`, spilled as `spilled` is.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub alternate_spilled: Option<SpillFile>,
+}
+
+/// A temporary file holding a resource's decoded contents, removed when dropped.
+#[derive(Debug, PartialEq)]
+pub struct SpillFile {
+    path: PathBuf,
+    hash: md5::Digest,
+}
+
+impl SpillFile {
+    /// Create a new file in `dir` to decode into. Its hash is set once it's written.
+    fn create(dir: &Path) -> Result<(SpillFile, File)> {
+        static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+        fs::create_dir_all(dir)?;
+        loop {
+            let path = dir.join(format!(
+                ".enex2mf-{}-{}.spill",
+                std::process::id(),
+                SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+            ));
+            // Never a file that's already there, or a link to one, as others could leave in a
+            // shared temporary directory.
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    // Owns the path from here on, so that it's removed on failure.
+                    let spilled = SpillFile {
+                        path,
+                        hash: md5::Digest([0; 16]),
+                    };
+                    return Ok((spilled, file));
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The MD5 hash of the contents.
+    pub fn hash(&self) -> md5::Digest {
+        self.hash
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl NoteAttributes {
+    /// The latitude and longitude, if both are numbers.
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        let coordinate =
+            |c: &Option<String>| c.as_deref().and_then(|c| c.trim().parse::<f64>().ok());
+        Some((coordinate(&self.latitude)?, coordinate(&self.longitude)?))
+    }
+}
+
+impl Note {
+    /// Whether the note has no title, or Evernote's placeholder for one.
+    pub fn is_untitled(&self) -> bool {
+        self.title.as_deref().is_none_or(|title| {
+            let title = title.trim().to_lowercase();
+            title.is_empty() || title == "untitled" || title == "untitled note"
+        })
+    }
+
+    /// Whether the note has no attachments, and its content is missing or shows nothing.
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty() && self.content.as_deref().is_none_or(enml::is_blank)
+    }
+
+    /// The content converted to Markdown. Notes keep their content as ENML and only convert it
+    /// when asked, so filtering notes by title, tag or date doesn't pay for the conversion.
+    pub fn content_markdown(&self, options: &EnmlOptions) -> String {
+        enml::to_markdown(self.content.as_deref().unwrap_or(""), options)
+    }
+
+    /// Start building a note, e.g. `Note::builder().title("Hello").tag("greeting").build()`.
+    pub fn builder() -> NoteBuilder {
+        NoteBuilder::default()
+    }
+}
+
+/// Builds a `Note` field by field. Unset fields are empty.
+#[derive(Debug, Default)]
+pub struct NoteBuilder {
+    note: Note,
+}
+
+impl NoteBuilder {
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.note.title = Some(title.into());
+        self
+    }
+
+    /// ENML content, i.e. an `<en-note>` element.
+    pub fn content(mut self, content: impl Into<String>) -> Self {
+        self.note.content = Some(content.into());
+        self
+    }
+
+    pub fn created(mut self, created: DateTime<Local>) -> Self {
+        self.note.created = Some(created);
+        self
+    }
+
+    pub fn updated(mut self, updated: DateTime<Local>) -> Self {
+        self.note.updated = Some(updated);
+        self
+    }
+
+    /// Add a tag; call repeatedly for several.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.note.tags.push(tag.into());
+        self
+    }
+
+    pub fn attributes(mut self, attributes: NoteAttributes) -> Self {
+        self.note.attributes = attributes;
+        self
+    }
+
+    /// Add a resource; call repeatedly for several.
+    pub fn resource(mut self, resource: Resource) -> Self {
+        self.note.resources.push(resource);
+        self
+    }
+
+    pub fn build(self) -> Note {
+        self.note
+    }
+}
+
+impl Resource {
+    /// A resource with the contents `data`, which the content references by its MD5 hash.
+    pub fn new(data: &[u8], mime: impl Into<String>) -> Self {
+        Resource {
+            data: Some(base64::encode(data)),
+            mime: Some(mime.into()),
+            ..Resource::default()
+        }
+    }
+
+    /// Decode the base64 data. The export wraps it across lines, so whitespace is ignored.
+    pub fn decode(&self) -> Result<Vec<u8>> {
+        if let Some(ref spilled) = self.spilled {
+            return Ok(fs::read(spilled.path())?);
+        }
+        let data: Vec<u8> = self
+            .data
+            .as_deref()
+            .unwrap_or("")
+            .bytes()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        Ok(base64::decode(&data)?)
+    }
+
+    /// Decode the base64 data to `writer` a piece at a time, rather than all at once like
+    /// `decode`. Returns the MD5 hash of the decoded data.
+    pub fn decode_to(&self, writer: impl Write) -> Result<md5::Digest> {
+        let mut writer = HashWriter {
+            writer,
+            hash: md5::Context::new(),
+        };
+        match self.spilled {
+            Some(ref spilled) => {
+                io::copy(&mut File::open(spilled.path())?, &mut writer)?;
+            }
+            None => decode_base64(self.data.as_deref().unwrap_or(""), &mut writer)?,
+        }
+        Ok(writer.hash.compute())
+    }
+
+    /// The MD5 hash of the decoded data, which the content references the resource by.
+    pub fn hash(&self) -> Result<md5::Digest> {
+        match self.spilled {
+            Some(ref spilled) => Ok(spilled.hash()),
+            None => self.decode_to(io::sink()),
+        }
+    }
+
+    /// The size of the decoded data, worked out from the length of the base64 rather than by
+    /// decoding it.
+    pub fn size(&self) -> Result<u64> {
+        if let Some(ref spilled) = self.spilled {
+            return Ok(fs::metadata(spilled.path())?.len());
+        }
+        let data = self.data.as_deref().unwrap_or("");
+        let len = data.bytes().filter(|b| !b.is_ascii_whitespace()).count();
+        let padding = data
+            .trim_end()
+            .bytes()
+            .rev()
+            .take_while(|&b| b == b'=')
+            .count();
+        Ok((len / 4 * 3).saturating_sub(padding) as u64)
+    }
+
+    /// Decode the base64 alternate data, if there is any.
+    pub fn decode_alternate(&self) -> Result<Option<Vec<u8>>> {
+        if let Some(ref spilled) = self.alternate_spilled {
+            return Ok(Some(fs::read(spilled.path())?));
+        }
+        match self.alternate_data {
+            Some(ref data) => {
+                let mut decoded = Vec::new();
+                decode_base64(data, &mut decoded)?;
+                Ok(Some(decoded))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Hashes what is written through it.
+struct HashWriter<W> {
+    writer: W,
+    hash: md5::Context,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.hash.consume(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// How much base64 to decode at a time.
+const PIECE: usize = 64 * 1024;
+
+/// Decodes base64 given a piece at a time, ignoring whitespace, to `writer`.
+struct Base64Decoder<W> {
+    writer: W,
+    /// Base64 not yet decoded, without whitespace.
+    pending: Vec<u8>,
+    decoded: Vec<u8>,
+}
+
+impl<W: Write> Base64Decoder<W> {
+    fn new(writer: W) -> Self {
+        Base64Decoder {
+            writer,
+            pending: Vec::with_capacity(PIECE),
+            decoded: Vec::with_capacity(PIECE / 4 * 3),
+        }
+    }
+
+    fn push(&mut self, base64: &[u8]) -> Result<()> {
+        for piece in base64.chunks(PIECE) {
+            let text = piece.iter().filter(|b| !b.is_ascii_whitespace());
+            self.pending.extend(text);
+            if self.pending.len() >= PIECE {
+                // Whole base64 quanta, leaving the rest for the next piece.
+                self.decode(self.pending.len() / 4 * 4)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode the first `len` bytes pending.
+    fn decode(&mut self, len: usize) -> Result<()> {
+        self.decoded.clear();
+        base64::decode_config_buf(&self.pending[..len], base64::STANDARD, &mut self.decoded)?;
+        self.writer.write_all(&self.decoded)?;
+        self.pending.drain(..len);
+        Ok(())
+    }
+
+    /// Decode the rest, returning the writer.
+    fn finish(mut self) -> Result<W> {
+        self.decode(self.pending.len())?;
+        Ok(self.writer)
+    }
+}
+
+/// Decode base64 `data` to `writer` a piece at a time, ignoring whitespace.
+fn decode_base64(data: &str, writer: &mut impl Write) -> Result<()> {
+    let mut decoder = Base64Decoder::new(writer);
+    decoder.push(data.as_bytes())?;
+    decoder.finish()?;
+    Ok(())
 }
 
 /// This is split from EnexParser to avoid multiple mutable borrows; see
 /// https://github.com/rust-lang/rfcs/issues/1215. It also lets us encapsulate low-level XML event
 /// parsing.
-struct EnexReader<R: Read> {
-    reader: EventReader<R>,
+struct EnexReader<P: XmlPull> {
+    reader: P,
+    /// Attributes of the last element returned by `read_start_element_until_enclosing`.
+    attributes: Vec<OwnedAttribute>,
 }
 
 /// `consume_*` methods read and ignore an event. `read_*` methods read an event and return a
 /// parsed value.
-impl<R: Read> EnexReader<R> {
-    fn consume_start_document(&mut self) -> Result<()> {
-        match self.reader.next()? {
-            XmlEvent::StartDocument { .. } => Ok(()),
-            x => Err(Error::UnexpectedEvent(
-                "expected document start".to_string(),
-                x,
-            )),
-        }
+impl<P: XmlPull> EnexReader<P> {
+    /// Where the last event read starts.
+    fn position(&self) -> TextPosition {
+        self.reader.position()
     }
 
-    fn consume_end_document(&mut self) -> Result<()> {
-        match self.reader.next()? {
-            XmlEvent::EndDocument => Ok(()),
-            x => Err(Error::UnexpectedEvent(
-                "expected document end".to_string(),
-                x,
-            )),
+    /// `Error::UnexpectedEvent` for `event`, the last one read.
+    fn unexpected(&self, context: String, event: XmlEvent) -> Error {
+        Error::UnexpectedEvent(context, event, self.position())
+    }
+
+    /// Consume the start of the document up to and including `<root>`, keeping its attributes
+    /// for `attribute`. The XML declaration, processing instructions, comments and whitespace
+    /// before the root are skipped, whichever of them there are.
+    fn consume_start_document(&mut self, root: &str) -> Result<()> {
+        loop {
+            match self.reader.next()? {
+                XmlEvent::StartDocument { .. }
+                | XmlEvent::ProcessingInstruction { .. }
+                | XmlEvent::Comment(_)
+                | XmlEvent::Whitespace(_) => {}
+                XmlEvent::StartElement {
+                    ref name,
+                    attributes,
+                    ..
+                } if name.local_name == root => {
+                    self.attributes = attributes;
+                    return Ok(());
+                }
+                x => return Err(self.unexpected(format!("expected <{}>", root), x)),
+            }
         }
     }
 
-    fn consume_start_element(&mut self, start_tag: &str) -> Result<()> {
+    fn consume_end_document(&mut self) -> Result<()> {
         match self.reader.next()? {
-            XmlEvent::StartElement { ref name, .. } if name.local_name == start_tag => Ok(()),
-            x => Err(Error::UnexpectedEvent(
-                format!("expected <{}>", start_tag),
-                x,
-            )),
+            XmlEvent::EndDocument => Ok(()),
+            x => Err(self.unexpected("expected document end".to_string(), x)),
         }
     }
 
     /// Return `Ok(Some(start_tag))` for `<start_tag>` or `Ok(None)` for `</end_tag>`.
     fn read_start_element_until_enclosing(&mut self, end_tag: &str) -> Result<Option<String>> {
         match self.reader.next()? {
-            XmlEvent::StartElement { name, .. } => Ok(Some(name.local_name)),
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                self.attributes = attributes;
+                Ok(Some(name.local_name))
+            }
             XmlEvent::EndElement { ref name, .. } if name.local_name == end_tag => Ok(None),
-            x => Err(Error::UnexpectedEvent(format!("in <{}>", end_tag), x)),
+            x => Err(self.unexpected(format!("in <{}>", end_tag), x)),
         }
     }
 
-    /// Return the text until `</end_tag>`.
+    /// Return an attribute of the last element read by `read_start_element_until_enclosing` or
+    /// `consume_start_document`.
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attr| attr.name.local_name == name)
+            .map(|attr| attr.value.as_str())
+    }
+
+    /// Return the text until `</end_tag>`, which the XML parser may split between events, e.g.
+    /// for CDATA sections, trimmed of whitespace.
     fn read_text_until_enclosing(&mut self, end_tag: &str) -> Result<Option<String>> {
-        match self.reader.next()? {
-            XmlEvent::Characters(text) => {
-                match self.read_text_until_enclosing(end_tag)? {
-                    Some(more_text) => Ok(Some(text + &more_text)), // not an expected case
-                    None => Ok(Some(text)),
+        let mut text = String::new();
+        self.reader.read_text(end_tag, &mut |more| {
+            text.push_str(more);
+            Ok(())
+        })?;
+        Ok(trimmed(text))
+    }
+
+    /// Return the base64 text until `</end_tag>`, or once it's longer than `spill_over`, a file
+    /// in `dir` that it's decoded to as the rest is read.
+    fn read_data_until_enclosing(
+        &mut self,
+        end_tag: &str,
+        spill_over: Option<usize>,
+        dir: &Path,
+    ) -> Result<(Option<String>, Option<SpillFile>)> {
+        type Spill = (SpillFile, Base64Decoder<HashWriter<BufWriter<File>>>);
+        let mut text = String::new();
+        let mut spill: Option<Spill> = None;
+        self.reader.read_text(end_tag, &mut |more| {
+            if let Some((_, ref mut decoder)) = spill {
+                return decoder.push(more.as_bytes());
+            }
+            text.push_str(more);
+            if spill_over.is_some_and(|over| text.trim().len() > over) {
+                let (spilled, file) = SpillFile::create(dir)?;
+                let mut decoder = Base64Decoder::new(HashWriter {
+                    writer: BufWriter::new(file),
+                    hash: md5::Context::new(),
+                });
+                decoder.push(std::mem::take(&mut text).as_bytes())?;
+                spill = Some((spilled, decoder));
+            }
+            Ok(())
+        })?;
+        match spill {
+            Some((mut spilled, decoder)) => {
+                let mut writer = decoder.finish()?;
+                writer.flush()?;
+                spilled.hash = writer.hash.compute();
+                Ok((None, Some(spilled)))
+            }
+            None => Ok((trimmed(text), None)),
+        }
+    }
+
+    /// Return the ENML until `</end_tag>`: the text of CDATA sections or escaped markup, or
+    /// elements, which some apps write instead. ENML escaped twice is unescaped.
+    fn read_content_until_enclosing(
+        &mut self,
+        end_tag: &str,
+        limit: Option<usize>,
+    ) -> Result<Option<String>> {
+        let mut content = String::new();
+        let mut depth = 0;
+        loop {
+            if let Some(limit) = limit.filter(|&limit| content.len() > limit) {
+                return Err(Error::LimitExceeded(format!(
+                    "content over the memory limit of {} bytes",
+                    limit
+                )));
+            }
+            match self.reader.next()? {
+                XmlEvent::Characters(text) if depth == 0 => content.push_str(&text),
+                XmlEvent::Characters(text) => content.push_str(&enml::escape(&text)),
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    content.push('<');
+                    content.push_str(&name.local_name);
+                    for attribute in attributes {
+                        let value = enml::escape(&attribute.value);
+                        let name = &attribute.name.local_name;
+                        content.push_str(&format!(" {}=\"{}\"", name, value));
+                    }
+                    content.push('>');
+                    depth += 1;
                 }
+                XmlEvent::EndElement { name } if depth > 0 => {
+                    content.push_str(&format!("</{}>", name.local_name));
+                    depth -= 1;
+                }
+                XmlEvent::EndElement { ref name, .. } if name.local_name == end_tag => break,
+                XmlEvent::Comment(_) | XmlEvent::ProcessingInstruction { .. } => {}
+                x => return Err(self.unexpected("expected content".to_string(), x)),
             }
-            XmlEvent::EndElement { ref name, .. } if name.local_name == end_tag => Ok(None),
-            x => Err(Error::UnexpectedEvent("expected text".to_string(), x)),
         }
+        if content.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(unescape_twice(content)))
     }
 
-    /// Return the parsed date until `</end_tag>`.
-    fn read_datetime_until_enclosing(&mut self, end_tag: &str) -> Result<Option<DateTime<Local>>> {
+    /// Return the parsed date until `</end_tag>`, in any of the formats of `parse_datetime`, or
+    /// as local time if `local` and it has no offset.
+    fn read_datetime_until_enclosing(
+        &mut self,
+        end_tag: &str,
+        local: bool,
+    ) -> Result<Option<DateTime<Local>>> {
         let text = self.read_text_until_enclosing(end_tag)?;
-        let text = text.as_ref().map(String::as_str).unwrap_or("");
-        // %#z https://github.com/chronotope/chrono/commit/95f6a2be1c8f7a5d8d21a78664b3708e8200bd2b
-        Ok(Some(
-            DateTime::parse_from_str(text, "%Y%m%dT%H%M%S%#z")?.with_timezone(&Local),
-        ))
+        let text = text.as_deref().unwrap_or("").trim();
+        let time = if local {
+            parse_local_datetime(text).or_else(|| parse_datetime(text))
+        } else {
+            parse_datetime(text)
+        };
+        match time {
+            Some(time) => Ok(Some(time)),
+            // Fail with the error for Evernote's own format.
+            None => Err(DateTime::parse_from_str(text, EVERNOTE_DATETIME)
+                .expect_err("tried by parse_datetime")
+                .into()),
+        }
     }
 
-    /// Skip until `</resource>`.
-    fn consume_resource(&mut self) -> Result<()> {
-        loop {
-            match self.reader.next()? {
-                XmlEvent::EndElement { ref name } if name.local_name == "resource" => break,
-                _ => {}
+    /// Return the parsed integer until `</end_tag>`.
+    fn read_u32_until_enclosing(&mut self, end_tag: &str) -> Result<Option<u32>> {
+        match self.read_text_until_enclosing(end_tag)? {
+            Some(text) => Ok(Some(text.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Skip until `</end_tag>`, including any nested elements.
+    fn consume_until_enclosing(&mut self, end_tag: &str) -> Result<()> {
+        self.reader.skip_element(end_tag)
+    }
+}
+
+/// `text` without whitespace around it, if there's any left.
+fn trimmed(text: String) -> Option<String> {
+    match text.trim() {
+        "" => None,
+        trimmed if trimmed.len() == text.len() => Some(text),
+        trimmed => Some(trimmed.to_string()),
+    }
+}
+
+/// `content` unescaped if it's ENML escaped once more than it should be, e.g. `&lt;en-note&gt;`.
+fn unescape_twice(content: String) -> String {
+    let start = content.trim_start();
+    let escaped = ["&lt;?xml", "&lt;!DOCTYPE", "&lt;en-note"]
+        .iter()
+        .any(|markup| start.starts_with(markup));
+    if !escaped || content.contains('<') {
+        return content;
+    }
+    content
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The format of times in Evernote's exports, e.g. `20181226T083916Z`.
+// %#z https://github.com/chronotope/chrono/commit/95f6a2be1c8f7a5d8d21a78664b3708e8200bd2b
+const EVERNOTE_DATETIME: &str = "%Y%m%dT%H%M%S%#z";
+
+/// Formats of times without an offset.
+const NAIVE_DATETIMES: [&str; 3] = [
+    "%Y%m%dT%H%M%S",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+];
+
+/// Parse a time as Evernote writes it, or as other apps writing exports do: RFC 3339 (e.g.
+/// `2018-12-26T08:39:16Z`), or either without an offset, which is then taken to be UTC like
+/// Evernote's.
+pub fn parse_datetime(text: &str) -> Option<DateTime<Local>> {
+    if let Ok(time) = DateTime::parse_from_str(text, EVERNOTE_DATETIME) {
+        return Some(time.with_timezone(&Local));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Local));
+    }
+    NAIVE_DATETIMES
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .map(|time| Utc.from_utc_datetime(&time).with_timezone(&Local))
+}
+
+/// Parse a time without an offset in local time, as some apps other than Evernote write them.
+fn parse_local_datetime(text: &str) -> Option<DateTime<Local>> {
+    NAIVE_DATETIMES
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+}
+
+/// Parse the recoIndex document embedded in `<recognition>`. Each `<item>` is a recognized region
+/// with alternative `<t>` readings, weighted by `w`; keep the heaviest.
+pub(crate) fn parse_recognition(xml: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut in_item = false;
+    let mut weight = None;
+    let mut best: Option<(u32, String)> = None;
+    for event in EventReader::new(xml.as_bytes()) {
+        match event? {
+            XmlEvent::StartElement { ref name, .. } if name.local_name == "item" => in_item = true,
+            XmlEvent::StartElement {
+                ref name,
+                ref attributes,
+                ..
+            } if in_item && name.local_name == "t" => {
+                weight = attributes
+                    .iter()
+                    .find(|attr| attr.name.local_name == "w")
+                    .and_then(|attr| attr.value.parse().ok())
+                    .or(Some(0));
             }
+            XmlEvent::Characters(text) => {
+                if let Some(w) = weight {
+                    if best.as_ref().is_none_or(|&(b, _)| w > b) {
+                        best = Some((w, text));
+                    }
+                }
+            }
+            XmlEvent::EndElement { ref name } if name.local_name == "t" => weight = None,
+            XmlEvent::EndElement { ref name } if name.local_name == "item" => {
+                in_item = false;
+                words.extend(best.take().map(|(_, text)| text));
+            }
+            _ => {}
         }
+    }
+    Ok(words)
+}
+
+/// Callbacks for the parts of each note, for `EnexParser::visit`. Resources are passed on as
+/// they are parsed rather than kept in the note, e.g. to write them out one at a time.
+pub trait NoteVisitor {
+    /// At `<note>`, before any of its fields.
+    fn note_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// After `<note-attributes>`.
+    fn attributes(&mut self, _attributes: &NoteAttributes) -> Result<()> {
         Ok(())
     }
+
+    /// After each `<resource>`. `note` has the fields parsed so far, which in an Evernote export
+    /// are all but the resources.
+    fn resource(&mut self, _note: &Note, _resource: Resource) -> Result<()> {
+        Ok(())
+    }
+
+    /// At `</note>`, with the whole note except its resources.
+    fn note_end(&mut self, _note: Note) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Collects a note with its resources, for iterating.
+#[derive(Default)]
+struct Collect {
+    resources: Vec<Resource>,
+    note: Option<Note>,
+}
+
+impl NoteVisitor for Collect {
+    fn resource(&mut self, _note: &Note, resource: Resource) -> Result<()> {
+        self.resources.push(resource);
+        Ok(())
+    }
+
+    fn note_end(&mut self, mut note: Note) -> Result<()> {
+        note.resources = std::mem::take(&mut self.resources);
+        self.note = Some(note);
+        Ok(())
+    }
+}
+
+/// Bounds on what an export can make the parser do, against exports made to exhaust memory or
+/// the stack. Exports of unknown origin are otherwise safe to read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    /// How deeply elements may be nested, in the export or in note content.
+    pub depth: usize,
+    /// The longest text, e.g. note content or the base64 data of an attachment, in bytes.
+    pub text_len: usize,
+    /// How many characters entities declared in the export may expand to, against "billion
+    /// laughs" exports. quick-xml doesn't expand such entities at all.
+    pub entity_expansion: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            depth: 1000,
+            text_len: 1 << 30,
+            entity_expansion: 1_000_000,
+        }
+    }
 }
 
 enum EnexParserState {
@@ -121,84 +791,354 @@ enum EnexParserState {
 /// # Example
 ///
 /// ```
-/// use crate::enex::EnexParser;
-/// let buf = b"<en-export><note><title>foo</title></note></en-export>".as_bytes();
+/// use enex2mf::EnexParser;
+/// # fn main() -> enex2mf::Result<()> {
+/// let buf: &[u8] = b"<en-export><note><title>foo</title></note></en-export>";
 /// let parser = EnexParser::new(buf);
 /// for note in parser {
 ///     println!("{:?}", note?);
 /// }
+/// # Ok(())
+/// # }
 /// ```
 pub struct EnexParser<R: Read> {
-    reader: EnexReader<R>,
+    reader: EnexReader<Pull<R>>,
     state: EnexParserState,
+    lenient: bool,
+    spans: bool,
+    memory_limit: Option<usize>,
+    /// Where to decode every resource to, if set.
+    decode_dir: Option<PathBuf>,
+    limits: Limits,
+    metadata_only: bool,
+    skip_resources: bool,
+    recover: bool,
+    /// Whether a note is being read, i.e. its `</note>` hasn't been.
+    in_note: bool,
+    export_date: Option<DateTime<Local>>,
+    application: Option<String>,
+    version: Option<String>,
+    /// The quirks of the app that made the export, once known, unless set.
+    quirks: Option<Quirks>,
+    /// Unrecognized elements skipped so far in lenient mode.
+    skipped: Vec<String>,
+    /// What was skipped or left out so far in lenient mode, and why.
+    warnings: Vec<String>,
 }
 
 impl<R: Read> EnexParser<R> {
+    /// Parse the export read from `reader`.
     pub fn new(reader: R) -> Self {
+        EnexParser::with_limits(reader, Limits::default())
+    }
+
+    /// Parse the export read from `reader`, failing with `Error::LimitExceeded` or an XML error
+    /// if it goes beyond `limits`.
+    pub fn with_limits(reader: R, limits: Limits) -> Self {
         EnexParser {
             reader: EnexReader {
-                reader: ParserConfig::new()
-                    .trim_whitespace(true)
-                    .cdata_to_characters(true)
-                    .create_reader(reader),
+                reader: pull::new(reader, &limits),
+                attributes: Vec::new(),
             },
             state: EnexParserState::Initial,
+            lenient: false,
+            spans: false,
+            memory_limit: None,
+            decode_dir: None,
+            limits,
+            metadata_only: false,
+            skip_resources: false,
+            recover: false,
+            in_note: false,
+            export_date: None,
+            application: None,
+            version: None,
+            quirks: None,
+            skipped: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// In lenient mode, unrecognized elements are skipped with a warning instead of failing with
+    /// `Error::UnexpectedElement`, and times that can't be parsed are left out. Parsing is strict
+    /// by default.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Record where each note is in the export as `Note::span`, e.g. to find a note that failed
+    /// to convert. Off by default.
+    pub fn spans(mut self, spans: bool) -> Self {
+        self.spans = spans;
+        self
+    }
+
+    /// Keep memory use per note to about `bytes`: content larger than that fails with
+    /// `Error::LimitExceeded`, and resources are decoded to temporary files once their base64
+    /// text gets longer, the rest as it's read (see `Resource::spilled`). xml-rs still reads each
+    /// text whole before passing it on; with the `quick-xml` feature, it's read in pieces.
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Decode the data of every resource to a file in `dir` as it's read, rather than keep its
+    /// base64 text (see `Resource::spilled`). With `dir` where attachments are extracted to,
+    /// they can be moved into place rather than written again.
+    pub fn decode_resources_to(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.decode_dir = Some(dir.into());
+        self
+    }
+
+    /// Skip the content of notes and the data and recognized text of resources without parsing
+    /// them, for when only titles, dates, tags and the like are needed. Notes then have no
+    /// `content`, and resources no `data`. Untitled notes keep their content, which
+    /// `ConvertOptions::prepare` takes a title from.
+    pub fn metadata_only(mut self, metadata_only: bool) -> Self {
+        self.metadata_only = metadata_only;
+        self
+    }
+
+    /// Skip `<resource>` elements without reading their data, for when only the text of notes is
+    /// needed. Notes then have no resources, and their `<en-media>` are dropped. With the
+    /// `quick-xml` feature, resources aren't even parsed.
+    pub fn skip_resources(mut self, skip_resources: bool) -> Self {
+        self.skip_resources = skip_resources;
+        self
+    }
+
+    /// After a note fails to parse, skip the rest of it, so that iterating goes on with the next
+    /// note. Otherwise iterating ends at the first error. Errors in the XML itself can't be
+    /// recovered from, and end it either way.
+    pub fn recover(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    /// Tolerate `quirks`, rather than those of the app that made the export, e.g.
+    /// `Quirks::default()` to parse it as Evernote's.
+    pub fn with_quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// Read up to the first note, so that metadata of the export is available. Iterating calls
+    /// this as needed.
+    pub fn start(&mut self) -> Result<()> {
+        if let EnexParserState::Initial = self.state {
+            self.reader.consume_start_document("en-export")?;
+            self.export_date = self
+                .reader
+                .attribute("export-date")
+                .and_then(parse_datetime);
+            self.application = self.reader.attribute("application").map(String::from);
+            self.version = self.reader.attribute("version").map(String::from);
+            if self.quirks.is_none() {
+                let producer = self.producer();
+                if producer.quirks().any() {
+                    info!("export made by {}, tolerating its quirks", producer.name());
+                }
+                self.quirks = Some(producer.quirks());
+            }
+            self.state = EnexParserState::EnExport;
+        }
+        Ok(())
+    }
+
+    /// When the export was made, if known. Only available after `start`.
+    pub fn export_date(&self) -> Option<DateTime<Local>> {
+        self.export_date
+    }
+
+    /// The app that made the export, e.g. `Evernote/Windows`. Only available after `start`.
+    pub fn application(&self) -> Option<&str> {
+        self.application.as_deref()
+    }
+
+    /// The version of the app that made the export. Only available after `start`.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// The app that made the export. Only known after `start`.
+    pub fn producer(&self) -> Producer {
+        Producer::from_application(self.application.as_deref())
+    }
+
+    /// The quirks being tolerated. Only known after `start`, unless set.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks.unwrap_or_default()
+    }
+
+    fn is_lenient(&self) -> bool {
+        self.lenient || self.quirks().lenient
+    }
+
+    /// The unrecognized elements skipped so far in lenient mode.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// What was skipped or left out so far in lenient mode: unrecognized elements and times that
+    /// couldn't be parsed.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Skip the unrecognized element `tag` if lenient, otherwise fail.
+    fn skip_unexpected(&mut self, tag: &str) -> Result<()> {
+        if !self.is_lenient() {
+            return Err(Error::UnexpectedElement(
+                tag.to_owned(),
+                self.reader.position(),
+            ));
+        }
+        warn!("skipping unexpected <{}>", tag);
+        self.skipped.push(tag.to_owned());
+        self.warnings.push(format!("skipped unexpected <{}>", tag));
+        self.reader.consume_until_enclosing(tag)
+    }
+
+    /// Read the time until `</tag>`. In lenient mode, a time that can't be parsed is left out
+    /// with a warning, otherwise it fails.
+    fn read_datetime(&mut self, tag: &str) -> Result<Option<DateTime<Local>>> {
+        let position = self.reader.position();
+        let local = self.quirks().local_times;
+        match self.reader.read_datetime_until_enclosing(tag, local) {
+            Err(Error::Chrono(err)) if self.is_lenient() => {
+                warn!("{}: leaving out <{}>: {}", position, tag, err);
+                let warning = format!("{}: left out <{}>: {}", position, tag, err);
+                self.warnings.push(warning);
+                Ok(None)
+            }
+            result => result,
         }
     }
 
-    /// The main logic starts here. For ergonomics we return a Result<Option<Note>> here instead of
-    /// the Option<Result<Note>> required by Iterator::next.
+    /// Parse the rest of the export, passing each note to `visitor` piece by piece instead of
+    /// collecting it into a `Note`.
+    pub fn visit(&mut self, visitor: &mut impl NoteVisitor) -> Result<()> {
+        while self.visit_next(visitor)? {}
+        Ok(())
+    }
+
+    /// For ergonomics we return a Result<Option<Note>> here instead of the Option<Result<Note>>
+    /// required by Iterator::next.
     fn next_helper(&mut self) -> Result<Option<Note>> {
+        let mut collect = Collect::default();
+        self.visit_next(&mut collect)?;
+        Ok(collect.note)
+    }
+
+    /// The main logic starts here. Parse the next note into `visitor`, returning whether there
+    /// was one.
+    fn visit_next(&mut self, visitor: &mut impl NoteVisitor) -> Result<bool> {
         loop {
             match self.state {
-                EnexParserState::Initial => {
-                    self.reader.consume_start_document()?;
-                    self.reader.consume_start_element("en-export")?;
-                    self.state = EnexParserState::EnExport;
-                }
+                EnexParserState::Initial => self.start()?,
                 EnexParserState::EnExport => {
-                    return match self
+                    match self
                         .reader
                         .read_start_element_until_enclosing("en-export")?
-                        .as_ref()
-                        .map(String::as_str)
+                        .as_deref()
                     {
-                        Some("note") => Ok(Some(self.read_note()?)),
-                        Some(tag) => Err(Error::UnexpectedElement(tag.to_owned())),
+                        Some("note") => {
+                            if let Err(err) = self.read_note(visitor) {
+                                self.recover_from(&err)?;
+                                return Err(err);
+                            }
+                            return Ok(true);
+                        }
+                        Some(tag) => self.skip_unexpected(tag)?,
                         None => {
                             self.reader.consume_end_document()?;
                             self.state = EnexParserState::Done;
-                            Ok(None)
+                            return Ok(false);
                         }
-                    };
+                    }
+                }
+                EnexParserState::Done => return Ok(false),
+            }
+        }
+    }
+
+    /// After `err` reading a note, skip to its end if recovering, or stop.
+    fn recover_from(&mut self, err: &Error) -> Result<()> {
+        let broken = match err {
+            Error::Io(_) | Error::Xml(_) => true,
+            #[cfg(feature = "quick-xml")]
+            Error::QuickXml(_) => true,
+            _ => false,
+        };
+        if !self.recover || broken {
+            self.state = EnexParserState::Done;
+        } else if self.in_note {
+            // Notes don't nest, so the next `</note>` ends this one.
+            loop {
+                match self.reader.reader.next()? {
+                    XmlEvent::EndElement { ref name } if name.local_name == "note" => break,
+                    XmlEvent::EndDocument => {
+                        self.state = EnexParserState::Done;
+                        break;
+                    }
+                    _ => {}
                 }
-                EnexParserState::Done => return Ok(None),
             }
+            self.in_note = false;
         }
+        Ok(())
     }
 
-    fn read_note(&mut self) -> Result<Note> {
+    fn read_note(&mut self, visitor: &mut impl NoteVisitor) -> Result<()> {
+        visitor.note_start()?;
+        self.in_note = true;
+        let start = self.reader.position();
         let mut note = Note::default();
         while let Some(tag) = self
             .reader
             .read_start_element_until_enclosing("note")?
-            .as_ref()
-            .map(String::as_str)
+            .as_deref()
         {
             match tag {
                 "title" => note.title = self.reader.read_text_until_enclosing(tag)?,
-                "content" => note.content = self.reader.read_text_until_enclosing(tag)?,
-                "created" => note.created = self.reader.read_datetime_until_enclosing(tag)?,
-                "updated" => note.updated = self.reader.read_datetime_until_enclosing(tag)?,
+                "content" if self.metadata_only && !note.is_untitled() => {
+                    self.reader.consume_until_enclosing(tag)?
+                }
+                "content" => {
+                    let quirks = self.quirks();
+                    note.content = self
+                        .reader
+                        .read_content_until_enclosing(tag, self.memory_limit)?
+                        .map(|content| quirks.content(content));
+                    let depth = note.content.as_deref().map_or(0, enml::nesting_depth);
+                    if depth > self.limits.depth {
+                        return Err(Error::LimitExceeded(format!(
+                            "content nested {} deep",
+                            depth
+                        )));
+                    }
+                }
+                "created" => note.created = self.read_datetime(tag)?,
+                "updated" => note.updated = self.read_datetime(tag)?,
                 "tag" => note
                     .tags
                     .extend(self.reader.read_text_until_enclosing(tag)?),
-                "note-attributes" => note.attributes = self.read_note_attributes()?,
-                "resource" => self.reader.consume_resource()?,
-                _ => return Err(Error::UnexpectedElement(tag.to_owned())),
+                "note-attributes" => {
+                    note.attributes = self.read_note_attributes()?;
+                    visitor.attributes(&note.attributes)?;
+                }
+                "resource" if self.skip_resources => self.reader.consume_until_enclosing(tag)?,
+                "resource" => visitor.resource(&note, self.read_resource()?)?,
+                _ => self.skip_unexpected(tag)?,
             }
         }
-        Ok(note)
+        self.in_note = false;
+        if self.spans {
+            let end = self.reader.position();
+            note.span = Some(Span { start, end });
+        }
+        visitor.note_end(note)
     }
 
     fn read_note_attributes(&mut self) -> Result<NoteAttributes> {
@@ -206,21 +1146,122 @@ impl<R: Read> EnexParser<R> {
         while let Some(tag) = self
             .reader
             .read_start_element_until_enclosing("note-attributes")?
-            .as_ref()
-            .map(String::as_str)
+            .as_deref()
         {
             match tag {
+                "subject-date" => attrs.subject_date = self.read_datetime(tag)?,
                 "author" => attrs.author = self.reader.read_text_until_enclosing(tag)?,
                 "source" => attrs.source = self.reader.read_text_until_enclosing(tag)?,
                 "source-url" => attrs.source_url = self.reader.read_text_until_enclosing(tag)?,
                 "latitude" => attrs.latitude = self.reader.read_text_until_enclosing(tag)?,
                 "longitude" => attrs.longitude = self.reader.read_text_until_enclosing(tag)?,
                 "altitude" => attrs.altitude = self.reader.read_text_until_enclosing(tag)?,
-                _ => return Err(Error::UnexpectedElement(tag.to_owned())),
+                "reminder-order" => {
+                    attrs.reminder_order = self.reader.read_text_until_enclosing(tag)?
+                }
+                "reminder-time" => attrs.reminder_time = self.read_datetime(tag)?,
+                "reminder-done-time" => attrs.reminder_done_time = self.read_datetime(tag)?,
+                "content-class" => {
+                    attrs.content_class = self.reader.read_text_until_enclosing(tag)?
+                }
+                "application-data" => {
+                    let key = self.reader.attribute("key").unwrap_or("").to_owned();
+                    let value = self.reader.read_text_until_enclosing(tag)?;
+                    attrs
+                        .application_data
+                        .insert(key, value.unwrap_or_default());
+                }
+                _ => self.skip_unexpected(tag)?,
             }
         }
         Ok(attrs)
     }
+
+    fn read_resource(&mut self) -> Result<Resource> {
+        let mut resource = Resource::default();
+        while let Some(tag) = self
+            .reader
+            .read_start_element_until_enclosing("resource")?
+            .as_deref()
+        {
+            match tag {
+                "data" | "alternate-data" | "recognition" if self.metadata_only => {
+                    self.reader.consume_until_enclosing(tag)?
+                }
+                "data" | "alternate-data" => {
+                    let (spill_over, dir) = match self.decode_dir {
+                        Some(ref dir) => (Some(0), dir.clone()),
+                        None => (self.memory_limit, std::env::temp_dir()),
+                    };
+                    let (data, spilled) = self
+                        .reader
+                        .read_data_until_enclosing(tag, spill_over, &dir)?;
+                    if tag == "data" {
+                        resource.data = data;
+                        resource.spilled = spilled;
+                    } else {
+                        resource.alternate_data = data;
+                        resource.alternate_spilled = spilled;
+                    }
+                }
+                "mime" => resource.mime = self.reader.read_text_until_enclosing(tag)?,
+                "width" => resource.width = self.reader.read_u32_until_enclosing(tag)?,
+                "height" => resource.height = self.reader.read_u32_until_enclosing(tag)?,
+                "resource-attributes" => {
+                    let (file_name, attributes) = self.read_resource_attributes()?;
+                    resource.file_name = file_name;
+                    resource.attributes = attributes;
+                }
+                "recognition" => {
+                    if let Some(xml) = self.reader.read_text_until_enclosing(tag)? {
+                        resource.recognition = parse_recognition(&xml)?;
+                    }
+                }
+                "duration" => self.reader.consume_until_enclosing(tag)?,
+                _ => self.skip_unexpected(tag)?,
+            }
+        }
+        self.quirks().resource(&mut resource);
+        Ok(resource)
+    }
+
+    /// Read the file name and the other attributes of a resource.
+    fn read_resource_attributes(&mut self) -> Result<(Option<String>, ResourceAttributes)> {
+        let mut file_name = None;
+        let mut attrs = ResourceAttributes::default();
+        while let Some(tag) = self
+            .reader
+            .read_start_element_until_enclosing("resource-attributes")?
+            .as_deref()
+        {
+            match tag {
+                "file-name" => file_name = self.reader.read_text_until_enclosing(tag)?,
+                "source-url" => attrs.source_url = self.reader.read_text_until_enclosing(tag)?,
+                "timestamp" => attrs.timestamp = self.read_datetime(tag)?,
+                "latitude" => attrs.latitude = self.reader.read_text_until_enclosing(tag)?,
+                "longitude" => attrs.longitude = self.reader.read_text_until_enclosing(tag)?,
+                "altitude" => attrs.altitude = self.reader.read_text_until_enclosing(tag)?,
+                "camera-make" => attrs.camera_make = self.reader.read_text_until_enclosing(tag)?,
+                "camera-model" => {
+                    attrs.camera_model = self.reader.read_text_until_enclosing(tag)?
+                }
+                "reco-type" => attrs.reco_type = self.reader.read_text_until_enclosing(tag)?,
+                "attachment" => {
+                    let text = self.reader.read_text_until_enclosing(tag)?;
+                    attrs.attachment = text.is_some_and(|text| text.trim() == "true");
+                }
+                "application-data" => {
+                    let key = self.reader.attribute("key").unwrap_or("").to_owned();
+                    let value = self.reader.read_text_until_enclosing(tag)?;
+                    attrs
+                        .application_data
+                        .insert(key, value.unwrap_or_default());
+                }
+                _ => self.skip_unexpected(tag)?,
+            }
+        }
+        Ok((file_name, attrs))
+    }
 }
 
 impl<R: Read> Iterator for EnexParser<R> {
@@ -242,8 +1283,459 @@ fn test_simple() {
 <!DOCTYPE en-export SYSTEM "http://xml.evernote.com/pub/evernote-export2.dtd">
 <en-export export-date="20181226T083916Z" application="Evernote/Windows" version="6.x">
 <note><title>foo</title></note>
-</en-export>"#.as_bytes();
+</en-export>"#
+        .as_bytes();
 
     let notes: Vec<Note> = EnexParser::new(buf).map(|x| x.unwrap()).collect();
-    assert_eq!(notes, vec![Note { title: Some("foo".to_string()), .. Note::default() }])
+    assert_eq!(
+        notes,
+        vec![Note {
+            title: Some("foo".to_string()),
+            ..Note::default()
+        }]
+    )
+}
+
+#[test]
+fn test_content() {
+    let content = |xml: &str| {
+        let enex = format!(
+            "<en-export><note><content>{}</content></note></en-export>",
+            xml
+        );
+        let note = EnexParser::new(enex.as_bytes()).next().unwrap().unwrap();
+        note.content.unwrap()
+    };
+    assert_eq!(
+        content("<![CDATA[<en-note>a]]> b</en-note>]]>"),
+        "<en-note>a]]> b</en-note>"
+    );
+    assert_eq!(
+        content("<en-note><div class=\"x\">a &lt;b&gt;</div></en-note>"),
+        "<en-note><div class=\"x\">a &lt;b&gt;</div></en-note>"
+    );
+    assert_eq!(
+        content("&amp;lt;en-note&amp;gt;a &amp;amp;amp; b&amp;lt;/en-note&amp;gt;"),
+        "<en-note>a &amp; b</en-note>"
+    );
+}
+
+#[test]
+fn test_resource() {
+    let buf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<en-export>
+<note><title>foo</title><resource>
+<data encoding="base64">
+aGVsbG8=
+</data>
+<mime>image/png</mime><width>640</width><height>480</height>
+<recognition><![CDATA[<recoIndex><item><t>hi</t></item></recoIndex>]]></recognition>
+<resource-attributes><source-url>x</source-url><file-name>hello.png</file-name>
+<timestamp>20181226T083916Z</timestamp><camera-make>Apple</camera-make>
+<attachment>true</attachment></resource-attributes>
+<alternate-data encoding="base64">aGk=</alternate-data>
+</resource></note>
+</en-export>"#
+        .as_bytes();
+
+    let notes: Vec<Note> = EnexParser::new(buf).map(|x| x.unwrap()).collect();
+    assert_eq!(
+        notes[0].resources,
+        vec![Resource {
+            data: Some("aGVsbG8=".to_string()),
+            mime: Some("image/png".to_string()),
+            file_name: Some("hello.png".to_string()),
+            width: Some(640),
+            height: Some(480),
+            recognition: vec!["hi".to_string()],
+            attributes: ResourceAttributes {
+                source_url: Some("x".to_string()),
+                timestamp: parse_datetime("20181226T083916Z"),
+                camera_make: Some("Apple".to_string()),
+                attachment: true,
+                ..ResourceAttributes::default()
+            },
+            alternate_data: Some("aGk=".to_string()),
+            spilled: None,
+            alternate_spilled: None,
+        }]
+    );
+    assert_eq!(
+        notes[0].resources[0].decode_alternate().unwrap(),
+        Some(b"hi".to_vec())
+    );
+}
+
+#[test]
+fn test_recognition() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<recoIndex docType="picture" objType="image">
+<item x="1" y="2" w="3" h="4"><t w="31">he1lo</t><t w="87">hello</t></item>
+<item x="5" y="6" w="7" h="8"><t w="50">world</t></item>
+<object type="face"><t w="20">ignored</t></object>
+</recoIndex>"#;
+    assert_eq!(parse_recognition(xml).unwrap(), vec!["hello", "world"]);
+}
+
+#[test]
+fn test_application_data() {
+    let buf = r#"<en-export><note><note-attributes>
+<application-data key="com.example.app">42</application-data>
+<application-data key="empty"></application-data>
+<subject-date>20181219T101304Z</subject-date><content-class>evernote.food.meal</content-class>
+</note-attributes></note></en-export>"#
+        .as_bytes();
+
+    let notes: Vec<Note> = EnexParser::new(buf).map(|x| x.unwrap()).collect();
+    let data = &notes[0].attributes.application_data;
+    assert_eq!(data.get("com.example.app").map(String::as_str), Some("42"));
+    assert_eq!(data.get("empty").map(String::as_str), Some(""));
+    let attributes = &notes[0].attributes;
+    assert_eq!(
+        attributes.content_class.as_deref(),
+        Some("evernote.food.meal")
+    );
+    let subject_date = attributes.subject_date.map(|date| date.timestamp());
+    assert_eq!(subject_date, Some(1_545_214_384));
+}
+
+#[test]
+fn test_reminders() {
+    let buf = r#"<en-export><note><note-attributes>
+<reminder-order>1545214384000</reminder-order><reminder-time>20181220T090000Z</reminder-time>
+<reminder-done-time>20181220T101500Z</reminder-done-time>
+</note-attributes></note></en-export>"#;
+
+    let note = EnexParser::new(buf.as_bytes()).next().unwrap().unwrap();
+    let attributes = &note.attributes;
+    assert_eq!(attributes.reminder_order.as_deref(), Some("1545214384000"));
+    let time = attributes.reminder_time.map(|time| time.timestamp());
+    assert_eq!(time, Some(1_545_296_400));
+    let done = attributes.reminder_done_time.map(|time| time.timestamp());
+    assert_eq!(done, Some(1_545_300_900));
+}
+
+#[test]
+fn test_lenient() {
+    let buf =
+        r#"<en-export><note><title>foo</title><future><x/></future></note><other/></en-export>"#;
+
+    let strict: Vec<Result<Note>> = EnexParser::new(buf.as_bytes()).collect();
+    match strict[0] {
+        Err(Error::UnexpectedElement(ref tag, pos)) => {
+            assert_eq!(tag, "future");
+            assert_eq!(pos.to_string(), "1:36");
+        }
+        ref x => panic!("expected UnexpectedElement, got {:?}", x),
+    }
+
+    let notes: Vec<Note> = EnexParser::new(buf.as_bytes())
+        .lenient(true)
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(
+        notes,
+        vec![Note {
+            title: Some("foo".to_string()),
+            ..Note::default()
+        }]
+    )
+}
+
+#[test]
+fn test_spans() {
+    let buf = "<en-export>\n<note><title>foo</title>\n</note>\n</en-export>".as_bytes();
+    let note = EnexParser::new(buf).spans(true).next().unwrap().unwrap();
+    assert_eq!(note.span.unwrap().to_string(), "2:1-3:1");
+}
+
+#[test]
+fn test_producer_quirks() {
+    let buf = r#"<en-export application="Apple Notes"><note><title>foo</title>
+<created>2018-12-26 08:39:16</created><folder>Notes</folder></note></en-export>"#;
+    let mut parser = EnexParser::new(buf.as_bytes());
+    let note = parser.next().unwrap().unwrap();
+    assert_eq!(parser.producer(), Producer::AppleNotes);
+    assert_eq!(
+        note.created.map(|time| time.naive_local().to_string()),
+        Some("2018-12-26 08:39:16".to_string())
+    );
+    assert_eq!(parser.skipped(), ["folder"]);
+
+    let mut parser = EnexParser::new(buf.as_bytes()).with_quirks(Quirks::default());
+    assert!(parser.next().unwrap().is_err());
+}
+
+#[test]
+fn test_parse_datetime() {
+    let utc = |text| parse_datetime(text).map(|time| time.naive_utc().to_string());
+    let expected = Some("2018-12-26 08:39:16".to_string());
+    assert_eq!(utc("20181226T083916Z"), expected);
+    assert_eq!(utc("20181226T093916+0100"), expected);
+    assert_eq!(utc("2018-12-26T08:39:16Z"), expected);
+    assert_eq!(utc("20181226T083916"), expected);
+    assert_eq!(utc("2018-12-26 08:39:16"), expected);
+    assert_eq!(utc("26/12/2018"), None);
+
+    let buf = "<en-export><note><title>t</title><created>soon</created></note></en-export>";
+    assert!(EnexParser::new(buf.as_bytes()).next().unwrap().is_err());
+    let note = EnexParser::new(buf.as_bytes())
+        .lenient(true)
+        .next()
+        .unwrap();
+    assert_eq!(note.unwrap().created, None);
+}
+
+#[test]
+fn test_export_date() {
+    let buf = r#"<en-export export-date="20181226T083916Z" application="Evernote/Windows" version="6.x"></en-export>"#.as_bytes();
+    let mut parser = EnexParser::new(buf);
+    parser.start().unwrap();
+    assert_eq!(
+        parser
+            .export_date()
+            .map(|date| date.naive_utc().to_string()),
+        Some("2018-12-26 08:39:16".to_string())
+    );
+    assert_eq!(parser.application(), Some("Evernote/Windows"));
+    assert_eq!(parser.version(), Some("6.x"));
+    assert!(parser.next().is_none());
+}
+
+#[test]
+fn test_prolog() {
+    let prologs = [
+        "\u{feff}<?xml version=\"1.0\"?>",
+        "\u{feff}\n",
+        "\r\n  <?XML version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<?xml-stylesheet href=\"a.css\"?><!-- exported -->\n<!DOCTYPE en-export>",
+    ];
+    for prolog in &prologs {
+        let buf = format!(
+            "{}<en-export><note><title>t</title></note></en-export>",
+            prolog
+        );
+        let note = EnexParser::new(buf.as_bytes()).next().unwrap().unwrap();
+        assert_eq!(note.title.as_deref(), Some("t"), "{:?}", prolog);
+    }
+}
+
+#[test]
+fn test_visit() {
+    #[derive(Default)]
+    struct Sizes(Vec<(String, usize)>);
+
+    impl NoteVisitor for Sizes {
+        fn resource(&mut self, note: &Note, resource: Resource) -> Result<()> {
+            let title = note.title.clone().unwrap_or_default();
+            self.0.push((title, resource.decode()?.len()));
+            Ok(())
+        }
+    }
+
+    let buf = r#"<en-export><note><title>foo</title>
+        <resource><data>aGVsbG8=</data></resource><resource><data>aGk=</data></resource>
+        </note><note><title>bar</title></note></en-export>"#
+        .as_bytes();
+    let mut sizes = Sizes::default();
+    EnexParser::new(buf).visit(&mut sizes).unwrap();
+    assert_eq!(
+        sizes.0,
+        vec![("foo".to_string(), 5), ("foo".to_string(), 2)]
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let buf = r#"<en-export><note><title>foo</title><created>20181219T111304Z</created>
+        <tag>a</tag><resource><data>aGVsbG8=</data><mime>image/png</mime></resource>
+        </note></en-export>"#
+        .as_bytes();
+    let note = EnexParser::new(buf).next().unwrap().unwrap();
+    let json = serde_json::to_string(&note).unwrap();
+    let parsed: Note = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, note);
+}
+
+#[test]
+fn test_note_builder() {
+    let note = Note::builder()
+        .title("foo")
+        .tag("a")
+        .tag("b")
+        .resource(Resource::new(b"hello", "image/png"))
+        .build();
+    assert_eq!(note.title.as_deref(), Some("foo"));
+    assert_eq!(note.tags, vec!["a", "b"]);
+    assert_eq!(note.content, None);
+    assert_eq!(note.resources[0].decode().unwrap(), b"hello");
+
+    let note = Note::builder()
+        .content("<en-note><b>hi</b></en-note>")
+        .build();
+    assert_eq!(note.content_markdown(&EnmlOptions::default()), "**hi**");
+}
+
+#[test]
+fn test_decode_to() {
+    let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+    let mut resource = Resource::new(&data, "application/pdf");
+    // Wrapped like in an export.
+    let base64 = resource.data.take().unwrap();
+    let lines: Vec<&str> = base64
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap())
+        .collect();
+    resource.data = Some(lines.join("\n"));
+
+    let mut decoded = Vec::new();
+    let hash = resource.decode_to(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+    assert_eq!(hash, md5::compute(&data));
+}
+
+#[test]
+fn test_metadata_only() {
+    let buf = r#"<en-export><note><title>foo</title><content><![CDATA[<en-note/>]]></content>
+        <tag>a</tag><resource><data>aGVsbG8=</data><mime>image/png</mime></resource>
+        </note></en-export>"#;
+    let note = EnexParser::new(buf.as_bytes())
+        .metadata_only(true)
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(note.title.as_deref(), Some("foo"));
+    assert_eq!(note.content, None);
+    assert_eq!(note.tags, vec!["a"]);
+    assert_eq!(note.resources[0].data, None);
+    assert_eq!(note.resources[0].mime.as_deref(), Some("image/png"));
+}
+
+#[test]
+fn test_skip_resources() {
+    let buf = r#"<en-export><note><title>foo</title><content><![CDATA[<en-note/>]]></content>
+        <resource><data>aGVsbG8=</data><mime>image/png</mime></resource><tag>a</tag>
+        </note></en-export>"#;
+    let note = EnexParser::new(buf.as_bytes())
+        .skip_resources(true)
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(note.content.as_deref(), Some("<en-note/>"));
+    assert_eq!(note.tags, vec!["a"]);
+    assert!(note.resources.is_empty());
+}
+
+#[test]
+fn test_limits() {
+    let parse = |enex: &str, limits| {
+        let enex = format!("<en-export><note>{}</note></en-export>", enex);
+        EnexParser::with_limits(enex.as_bytes(), limits)
+            .next()
+            .unwrap()
+    };
+    let limits = Limits {
+        depth: 3,
+        ..Limits::default()
+    };
+    assert!(parse("<title>a</title>", limits).is_ok());
+    let attributes = "<note-attributes><author>a</author></note-attributes>";
+    assert!(matches!(
+        parse(attributes, limits),
+        Err(Error::LimitExceeded(_))
+    ));
+    let content = "<content><![CDATA[<en-note><div><b><i>a</i></b></div></en-note>]]></content>";
+    assert!(matches!(
+        parse(content, limits),
+        Err(Error::LimitExceeded(_))
+    ));
+    let limits = Limits {
+        text_len: 4,
+        ..Limits::default()
+    };
+    assert!(parse("<title>abcd</title>", limits).is_ok());
+    assert!(parse("<title>abcdefgh</title>", limits).is_err());
+    // quick-xml skips without reading the text, where xml-rs still reads it.
+    #[cfg(feature = "quick-xml")]
+    {
+        let enex = "<en-export><note><title>t</title><resource><data>abcdefgh</data>\
+            </resource><tag>a</tag></note></en-export>";
+        let note = EnexParser::with_limits(enex.as_bytes(), limits)
+            .metadata_only(true)
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(note.tags, vec!["a"]);
+    }
+}
+
+#[test]
+fn test_memory_limit() {
+    let buf = r#"<en-export><note><title>foo</title><content>hi</content>
+        <resource><data>aGVsbG8=</data><alternate-data>d29ybGQ=</alternate-data></resource>
+        <resource><data>aGk=</data></resource>
+        </note></en-export>"#;
+    let note = EnexParser::new(buf.as_bytes())
+        .memory_limit(4)
+        .next()
+        .unwrap()
+        .unwrap();
+    let spilled = note.resources[0]
+        .spilled
+        .as_ref()
+        .unwrap()
+        .path()
+        .to_owned();
+    assert_eq!(fs::read(&spilled).unwrap(), b"hello");
+    assert_eq!(note.resources[0].data, None);
+    assert_eq!(note.resources[0].decode().unwrap(), b"hello");
+    assert_eq!(note.resources[0].size().unwrap(), 5);
+    assert_eq!(note.resources[1].size().unwrap(), 2);
+    assert_eq!(note.resources[0].alternate_data, None);
+    assert_eq!(
+        note.resources[0].decode_alternate().unwrap().as_deref(),
+        Some(&b"world"[..])
+    );
+    assert_eq!(note.resources[1].data.as_deref(), Some("aGk="));
+    drop(note);
+    assert!(!spilled.exists());
+
+    let mut parser = EnexParser::new(buf.as_bytes()).memory_limit(1);
+    assert!(matches!(parser.next(), Some(Err(Error::LimitExceeded(_)))));
+
+    // Stopped as soon as the content grows past the limit, not once it's read whole.
+    let buf = format!(
+        "<en-export><note><content><en-note>{}</en-note></content></note></en-export>",
+        "<div>hi</div>".repeat(1000)
+    );
+    let mut parser = EnexParser::new(buf.as_bytes()).memory_limit(100);
+    match parser.next() {
+        Some(Err(Error::LimitExceeded(message))) => assert!(message.contains("100 bytes")),
+        x => panic!(
+            "expected LimitExceeded, got {:?}",
+            x.map(|note| note.is_ok())
+        ),
+    }
+
+    // Decoded in pieces across lines, as it's read.
+    let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+    let base64 = base64::encode(&data);
+    let lines: Vec<&str> = base64
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap())
+        .collect();
+    let buf = format!(
+        "<en-export><note><resource><data>\n{}\n</data></resource></note></en-export>",
+        lines.join("\n")
+    );
+    let note = EnexParser::new(buf.as_bytes())
+        .memory_limit(1000)
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(note.resources[0].spilled.is_some());
+    assert_eq!(note.resources[0].decode().unwrap(), data);
 }
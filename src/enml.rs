@@ -0,0 +1,812 @@
+//! Handlers for Evernote-specific ENML elements, plugged into html2md's conversion.
+
+use crate::code::DivHandlerFactory;
+use crate::crypt;
+use crate::links::{LinkHandlerFactory, NoteLinks};
+use crate::lists::{ListHandlerFactory, ListItemHandlerFactory, Lists};
+use crate::sanitize::{inner_html, sanitize, VOID_ELEMENTS};
+use crate::tables::TableHandlerFactory;
+use html2md::common::get_tag_attr;
+use html2md::{parse_html_custom, NodeData};
+pub use html2md::{Handle, StructuredPrinter, TagHandler, TagHandlerFactory};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Where an `<en-media>` element should point in the converted note.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Media {
+    /// Link target, e.g. a path relative to the output. Not URL-encoded.
+    pub target: String,
+    pub name: String,
+    pub mime: Option<String>,
+    /// Size of the file in bytes, if known.
+    pub size: Option<u64>,
+    /// OCR'd text of the resource, if any.
+    pub recognition: Option<String>,
+    /// Evernote shows it as an attachment, so it's linked to even if it's an image.
+    pub attachment: bool,
+    /// Width of the image in pixels, if known.
+    pub width: Option<u32>,
+}
+
+impl Media {
+    /// Whether to show it as an image, rather than link to it.
+    pub(crate) fn is_image(&self) -> bool {
+        !self.attachment
+            && self
+                .mime
+                .as_deref()
+                .is_some_and(|mime| mime.starts_with("image/"))
+    }
+
+    /// What kind of file this is and how big, to follow a link to it, e.g. `(PDF, 1.2 MB)`.
+    pub(crate) fn annotation(&self) -> Option<String> {
+        let kind = self.mime.as_deref().map(|mime| match mime {
+            "application/pdf" => "PDF",
+            "application/msword"
+            | "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+                "Word document"
+            }
+            "application/vnd.ms-excel"
+            | "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => "spreadsheet",
+            "application/vnd.ms-powerpoint"
+            | "application/vnd.openxmlformats-officedocument.presentationml.presentation" => {
+                "presentation"
+            }
+            "application/zip" => "ZIP archive",
+            _ if mime.starts_with("audio/") => "audio",
+            _ if mime.starts_with("video/") => "video",
+            _ if mime.starts_with("text/") => "text",
+            _ => mime,
+        });
+        let details: Vec<String> = kind
+            .map(String::from)
+            .into_iter()
+            .chain(self.size.map(human_size))
+            .collect();
+        if details.is_empty() {
+            None
+        } else {
+            Some(format!("({})", details.join(", ")))
+        }
+    }
+}
+
+/// `bytes` in the largest unit it's at least one of, e.g. `1.2 MB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// What to do with recognized text of media.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrMode {
+    #[default]
+    Off,
+    /// A paragraph below the media.
+    Text,
+    /// A hidden HTML comment after the media, still searchable in the Markdown source.
+    Comment,
+}
+
+/// How to mark highlighted text, which Evernote stores as a `<span>` with a background color.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightMode {
+    /// Plain text, dropping the highlight.
+    #[default]
+    Off,
+    /// `==text==`, as in Obsidian and other Markdown extensions.
+    Equals,
+    /// `<mark>text</mark>`, for Markdown renderers that allow HTML.
+    Mark,
+    /// `**text**`, for formats without highlighting.
+    Bold,
+}
+
+/// What to write as the body of a note.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BodyMode {
+    #[default]
+    Markdown,
+    /// The cleaned-up HTML within `<en-note>`, with attachments as `<img>`s and links, for
+    /// archiving without the losses of converting to Markdown.
+    RawHtml,
+}
+
+/// Custom conversion of elements, by tag name, run in place of the built-in handlers. Handlers are
+/// html2md's, re-exported from this module.
+///
+/// ```
+/// use enex2mf::enml::{Handle, Hooks, StructuredPrinter, TagHandler, TagHandlerFactory};
+/// use enex2mf::{enml_to_markdown, EnmlOptions};
+///
+/// struct Redact;
+///
+/// impl TagHandler for Redact {
+///     fn handle(&mut self, _tag: &Handle, printer: &mut StructuredPrinter) {
+///         printer.append_str("[redacted]");
+///     }
+///     fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+///     fn skip_descendants(&self) -> bool {
+///         true
+///     }
+/// }
+///
+/// struct RedactFactory;
+///
+/// impl TagHandlerFactory for RedactFactory {
+///     fn instantiate(&self) -> Box<dyn TagHandler> {
+///         Box::new(Redact)
+///     }
+/// }
+///
+/// let mut hooks = Hooks::default();
+/// hooks.register("en-crypt", RedactFactory);
+/// let options = EnmlOptions { hooks, ..EnmlOptions::default() };
+/// let enml = "<en-note>PIN: <en-crypt>c2VjcmV0</en-crypt></en-note>";
+/// assert_eq!(enml_to_markdown(enml, &options), "PIN: [redacted]");
+/// ```
+#[derive(Clone, Default)]
+pub struct Hooks(HashMap<String, Arc<dyn TagHandlerFactory + Send + Sync>>);
+
+impl Hooks {
+    /// Convert `<tag>` elements with handlers from `factory`, instead of the built-in ones.
+    pub fn register(&mut self, tag: &str, factory: impl TagHandlerFactory + Send + Sync + 'static) {
+        self.0.insert(tag.to_string(), Arc::new(factory));
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Shares a registered factory with the handlers of each conversion.
+struct HookFactory(Arc<dyn TagHandlerFactory + Send + Sync>);
+
+impl TagHandlerFactory for HookFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        self.0.instantiate()
+    }
+}
+
+/// How to convert ENML.
+#[derive(Clone, Debug, Default)]
+pub struct EnmlOptions {
+    /// Resources of the note being converted, keyed by the MD5 hash used in `<en-media hash=...>`.
+    pub media: HashMap<String, Media>,
+    pub ocr: OcrMode,
+    pub highlight: HighlightMode,
+    pub body: BodyMode,
+    /// Passphrase for `<en-crypt>` sections; without it they are marked as encrypted.
+    pub passphrase: Option<String>,
+    /// Embed media with Obsidian's `![[target]]` instead of Markdown images, link to other media
+    /// with `[[target]]` and link notes with `[[target]]`.
+    pub wiki_links: bool,
+    /// Where other notes are, to rewrite Evernote's links to them. They are left as is without.
+    pub note_links: Option<NoteLinks>,
+    /// Show images wider than this many pixels at this width, as Obsidian's `![[target|width]]`
+    /// or else an HTML `<img>`.
+    pub max_image_width: Option<u32>,
+    pub hooks: Hooks,
+}
+
+/// Convert ENML `content` to Markdown. The content can come from anywhere, e.g. the Evernote API,
+/// not only a parsed export. Also exported as `enex2mf::enml_to_markdown`.
+///
+/// ```
+/// use enex2mf::{enml_to_markdown, EnmlOptions};
+/// let enml = "<en-note><div><en-todo checked=\"true\"/>done</div></en-note>";
+/// assert_eq!(enml_to_markdown(enml, &EnmlOptions::default()), "- [x] done");
+/// ```
+///
+/// With `BodyMode::RawHtml`, the content is only cleaned up, staying HTML.
+pub fn to_markdown(content: &str, options: &EnmlOptions) -> String {
+    if options.body == BodyMode::RawHtml {
+        return inner_html(content, &options.media);
+    }
+    let md = parse_html_custom(&sanitize(content), &handlers(options));
+    md.trim().replace("\\-", "-")
+}
+
+/// `text` escaped for ENML, in text or attribute values.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `text` escaped for the text of a Markdown link or image, where `[` and `]` would end it.
+pub(crate) fn link_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if let '\\' | '[' | ']' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `target` with the characters that would end a Markdown link target, stop it being read as
+/// one or start a fragment, percent-encoded.
+pub(crate) fn link_target(target: &str) -> String {
+    let mut encoded = String::with_capacity(target.len());
+    for c in target.chars() {
+        match c {
+            ' ' | '#' | '(' | ')' | '<' | '>' => encoded.push_str(&format!("%{:02X}", c as u32)),
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// `alias` for a wiki link, with the `[`, `]` and `|` that would end it replaced.
+pub(crate) fn wiki_alias(alias: &str) -> String {
+    alias
+        .chars()
+        .map(|c| match c {
+            '[' => '(',
+            ']' => ')',
+            '|' => '-',
+            c => c,
+        })
+        .collect()
+}
+
+/// Whether `target` can be the target of a wiki link. Obsidian reads `#` and `^` in one as a
+/// heading or block, and `[`, `]` and `|` end it.
+pub(crate) fn is_wiki_target(target: &str) -> bool {
+    !target.contains(['#', '^', '[', ']', '|'])
+}
+
+/// How deeply the elements of `content` are nested, to reject content that would take too deep a
+/// recursion to convert.
+pub fn nesting_depth(content: &str) -> usize {
+    let mut depth: usize = 0;
+    let mut deepest = 0;
+    let mut rest = content;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        if tag.starts_with('/') {
+            depth = depth.saturating_sub(1);
+        } else if !tag.starts_with(['!', '?']) && !tag.ends_with('/') {
+            let name = tag.split(|c: char| c.is_whitespace()).next().unwrap_or("");
+            if !VOID_ELEMENTS
+                .iter()
+                .any(|void| void.eq_ignore_ascii_case(name))
+            {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+        }
+        rest = &rest[end..];
+    }
+    deepest
+}
+
+/// The hashes that the `<en-media>` elements of ENML `content` refer to, lowercase and in order.
+pub fn media_hashes(content: &str) -> Vec<String> {
+    let mut hashes = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<en-media") {
+        rest = &rest[start + "<en-media".len()..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+        let value = tag
+            .match_indices("hash=")
+            .find(|&(i, _)| tag[..i].ends_with(char::is_whitespace))
+            .map(|(i, _)| &tag[i + "hash=".len()..]);
+        if let Some(value) = value {
+            let quote = value.chars().next().filter(|&c| c == '"' || c == '\'');
+            let hash = match quote {
+                Some(quote) => value[1..].split(quote).next(),
+                None => value.split(char::is_whitespace).next(),
+            };
+            hashes.extend(hash.map(str::to_lowercase));
+        }
+    }
+    hashes
+}
+
+/// Elements that show something without any text.
+const TEXTLESS_ELEMENTS: [&str; 5] = ["en-media", "en-crypt", "en-todo", "img", "hr"];
+
+/// Whether ENML `content` shows nothing: only whitespace, non-breaking spaces and elements like
+/// the empty `<div><br/></div>` Evernote leaves in notes that were cleared.
+pub fn is_blank(content: &str) -> bool {
+    let mut rest = content;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '\u{200b}');
+        if rest.is_empty() {
+            return true;
+        }
+        if let Some(tag) = rest.strip_prefix('<') {
+            let end = match tag.find('>') {
+                Some(end) => end,
+                None => return false,
+            };
+            let name = tag[..end]
+                .trim_start_matches('/')
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or("")
+                .to_lowercase();
+            if TEXTLESS_ELEMENTS.contains(&name.as_str()) {
+                return false;
+            }
+            rest = &tag[end + 1..];
+        } else if let Some(entity) = rest.strip_prefix('&') {
+            let end = match entity.find(';') {
+                Some(end) => end,
+                None => return false,
+            };
+            if !matches!(&entity[..end], "nbsp" | "#160" | "#xa0" | "#xA0") {
+                return false;
+            }
+            rest = &entity[end + 1..];
+        } else {
+            return false;
+        }
+    }
+}
+
+/// Build the custom tag handlers to pass to `html2md::parse_html_custom`.
+pub fn handlers(options: &EnmlOptions) -> HashMap<String, Box<dyn TagHandlerFactory>> {
+    let options = Rc::new(options.clone());
+    let mut handlers: HashMap<String, Box<dyn TagHandlerFactory>> = HashMap::new();
+    handlers.insert(
+        "en-media".to_string(),
+        Box::new(MediaHandlerFactory {
+            options: options.clone(),
+        }),
+    );
+    handlers.insert(
+        "en-crypt".to_string(),
+        Box::new(CryptHandlerFactory {
+            options: options.clone(),
+        }),
+    );
+    handlers.insert(
+        "table".to_string(),
+        Box::new(TableHandlerFactory {
+            options: options.clone(),
+        }),
+    );
+    handlers.insert("en-todo".to_string(), Box::new(TodoHandlerFactory));
+    handlers.insert(
+        "span".to_string(),
+        Box::new(HighlightHandlerFactory {
+            mode: options.highlight,
+        }),
+    );
+    handlers.insert("div".to_string(), Box::new(DivHandlerFactory));
+    let lists = Lists::default();
+    for tag in ["ul", "ol", "menu"] {
+        let lists = lists.clone();
+        handlers.insert(tag.to_string(), Box::new(ListHandlerFactory { lists }));
+    }
+    handlers.insert("li".to_string(), Box::new(ListItemHandlerFactory { lists }));
+    handlers.insert(
+        "a".to_string(),
+        Box::new(LinkHandlerFactory {
+            options: options.clone(),
+        }),
+    );
+    for (tag, factory) in &options.hooks.0 {
+        handlers.insert(tag.clone(), Box::new(HookFactory(factory.clone())));
+    }
+    handlers
+}
+
+/// Concatenate the text children of `tag`.
+fn text_content(tag: &Handle) -> String {
+    let mut text = String::new();
+    for child in tag.children.borrow().iter() {
+        if let NodeData::Text { ref contents } = child.data {
+            text.push_str(&contents.borrow());
+        }
+    }
+    text
+}
+
+struct MediaHandlerFactory {
+    options: Rc<EnmlOptions>,
+}
+
+impl TagHandlerFactory for MediaHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(MediaHandler {
+            options: self.options.clone(),
+        })
+    }
+}
+
+struct CryptHandlerFactory {
+    options: Rc<EnmlOptions>,
+}
+
+impl TagHandlerFactory for CryptHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(CryptHandler {
+            options: self.options.clone(),
+        })
+    }
+}
+
+/// Replaces `<en-media>` with a Markdown image or link. Media missing from the options (e.g. when
+/// attachments aren't being extracted) is dropped.
+struct MediaHandler {
+    options: Rc<EnmlOptions>,
+}
+
+impl TagHandler for MediaHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        let media = match get_tag_attr(tag, "hash").and_then(|hash| self.options.media.get(&hash)) {
+            Some(media) => media,
+            None => return,
+        };
+        // Other targets are linked in Markdown, which Obsidian reads too.
+        let wiki_link = self.options.wiki_links
+            && !media.target.starts_with("data:")
+            && is_wiki_target(&media.target);
+        // Evernote keeps the width an image was resized to in the note, else it's the image's.
+        let width = get_tag_attr(tag, "width")
+            .and_then(|width| width.trim_end_matches("px").parse().ok())
+            .or(media.width);
+        let sized = match (width, self.options.max_image_width) {
+            (Some(width), Some(max)) if width > max => Some(max),
+            _ => None,
+        };
+        if media.is_image() {
+            let target = link_target(&media.target);
+            match sized {
+                Some(width) if wiki_link => {
+                    printer.append_str(&format!("![[{}|{}]]", media.target, width))
+                }
+                // Obsidian's own Markdown for sized images.
+                Some(width) if self.options.wiki_links => {
+                    let name = link_text(&wiki_alias(&media.name));
+                    printer.append_str(&format!("![{}|{}]({})", name, width, target))
+                }
+                Some(width) => printer.append_str(&format!(
+                    "<img src=\"{}\" alt=\"{}\" width=\"{}\">",
+                    escape(&target),
+                    escape(&media.name),
+                    width
+                )),
+                None if wiki_link => printer.append_str(&format!("![[{}]]", media.target)),
+                None => {
+                    let name = link_text(&media.name);
+                    printer.append_str(&format!("![{}]({})", name, target))
+                }
+            }
+        } else {
+            if wiki_link && media.target == media.name {
+                printer.append_str(&format!("[[{}]]", media.target));
+            } else if wiki_link {
+                let alias = wiki_alias(&media.name);
+                printer.append_str(&format!("[[{}|{}]]", media.target, alias));
+            } else {
+                let target = link_target(&media.target);
+                printer.append_str(&format!("[{}]({})", link_text(&media.name), target));
+            }
+            if let Some(annotation) = media.annotation() {
+                printer.append_str(&format!(" {}", annotation));
+            }
+        }
+
+        if let Some(ref text) = media.recognition {
+            match self.options.ocr {
+                OcrMode::Off => {}
+                OcrMode::Text => printer.append_str(&format!("\n\n{}\n\n", text)),
+                OcrMode::Comment => {
+                    printer.append_str(&format!(" <!-- OCR: {} -->", text.replace("--", "- -")))
+                }
+            }
+        }
+    }
+
+    fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+}
+
+struct TodoHandlerFactory;
+
+impl TagHandlerFactory for TodoHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(TodoHandler)
+    }
+}
+
+/// Turns `<en-todo checked=...>` checkboxes into GFM task list items.
+struct TodoHandler;
+
+impl TagHandler for TodoHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        let checkbox = match get_tag_attr(tag, "checked").as_deref() {
+            Some("true") => "[x] ",
+            _ => "[ ] ",
+        };
+        // Already a list item, e.g. <li><en-todo/>...</li>.
+        if printer.parent_chain.last().map(String::as_str) == Some("li") {
+            printer.append_str(checkbox);
+            return;
+        }
+        // Keep consecutive checkboxes (usually one per <div>) in a single tight list.
+        let previous = printer.data.trim_end_matches('\n');
+        if previous.len() < printer.data.len()
+            && previous
+                .rsplit('\n')
+                .next()
+                .is_some_and(|line| line.starts_with("- ["))
+        {
+            printer.data.truncate(previous.len() + 1);
+        } else if !printer.data.is_empty() && !printer.data.ends_with('\n') {
+            printer.insert_newline();
+        }
+        printer.append_str("- ");
+        printer.append_str(checkbox);
+    }
+
+    fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+}
+
+struct HighlightHandlerFactory {
+    mode: HighlightMode,
+}
+
+impl TagHandlerFactory for HighlightHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(HighlightHandler {
+            mode: self.mode,
+            start: None,
+        })
+    }
+}
+
+/// Marks the text of a highlighted `<span>` per `HighlightMode`.
+struct HighlightHandler {
+    mode: HighlightMode,
+    /// Where the text starts, if highlighted.
+    start: Option<usize>,
+}
+
+/// Whether a `style` attribute highlights text: Evernote's own `--en-highlight`, or a background
+/// color that isn't the page's.
+fn is_highlight(style: &str) -> bool {
+    style.split(';').any(|declaration| {
+        let (property, value) = match declaration.split_once(':') {
+            Some((property, value)) => (property.trim(), value.trim().to_lowercase()),
+            None => return false,
+        };
+        match property {
+            "--en-highlight" => true,
+            "background-color" | "background" => !matches!(
+                value.replace(' ', "").as_str(),
+                "" | "transparent"
+                    | "inherit"
+                    | "initial"
+                    | "white"
+                    | "#fff"
+                    | "#ffffff"
+                    | "rgb(255,255,255)"
+                    | "rgba(0,0,0,0)"
+            ),
+            _ => false,
+        }
+    })
+}
+
+impl TagHandler for HighlightHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        let highlighted = get_tag_attr(tag, "style").is_some_and(|style| is_highlight(&style));
+        if highlighted && self.mode != HighlightMode::Off {
+            self.start = Some(printer.data.len());
+        }
+    }
+
+    fn after_handle(&mut self, printer: &mut StructuredPrinter) {
+        let start = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+        let (open, close) = match self.mode {
+            HighlightMode::Off => return,
+            HighlightMode::Equals => ("==", "=="),
+            HighlightMode::Mark => ("<mark>", "</mark>"),
+            HighlightMode::Bold => ("**", "**"),
+        };
+        // Markers must be next to the text, not spaces.
+        let text = &printer.data[start..];
+        if text.trim().is_empty() {
+            return;
+        }
+        let leading = text.len() - text.trim_start().len();
+        let trailing = text.len() - text.trim_end().len();
+        let end = printer.data.len() - trailing;
+        printer.insert_str(end, close);
+        printer.insert_str(start + leading, open);
+    }
+}
+
+/// Replaces `<en-crypt>` with its decrypted contents, or a marker if it can't be decrypted.
+struct CryptHandler {
+    options: Rc<EnmlOptions>,
+}
+
+impl TagHandler for CryptHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        // RC2 is the default per the ENML DTD.
+        let cipher = get_tag_attr(tag, "cipher").unwrap_or_else(|| "RC2".to_string());
+        let decrypted = self
+            .options
+            .passphrase
+            .as_deref()
+            .and_then(|passphrase| crypt::decrypt(&cipher, &text_content(tag), passphrase));
+        match decrypted {
+            Some(enml) => printer.append_str(&parse_html_custom(&enml, &handlers(&self.options))),
+            None => {
+                if self.options.passphrase.is_some() {
+                    warn!("couldn't decrypt {} section", cipher);
+                }
+                let hint = get_tag_attr(tag, "hint")
+                    .map(|hint| format!(" (hint: {})", hint))
+                    .unwrap_or_default();
+                printer.append_str(&format!("**[Encrypted content{}]**", hint));
+            }
+        }
+    }
+
+    fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+
+    fn skip_descendants(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_media() {
+    let mut options = EnmlOptions::default();
+    options.media.insert(
+        "abc".to_string(),
+        Media {
+            target: "files/abc.png".to_string(),
+            name: "cat.png".to_string(),
+            mime: Some("image/png".to_string()),
+            size: Some(2048),
+            recognition: Some("meow".to_string()),
+            attachment: false,
+            width: Some(1200),
+        },
+    );
+    options.media.insert(
+        "def".to_string(),
+        Media {
+            target: "files/def.pdf".to_string(),
+            name: "Q3 report.pdf".to_string(),
+            mime: Some("application/pdf".to_string()),
+            size: Some(1_300_000),
+            recognition: None,
+            attachment: false,
+            width: None,
+        },
+    );
+    let content = r#"<en-note><div>see <en-media hash="abc" type="image/png"/></div></en-note>"#;
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "see ![cat.png](files/abc.png)");
+
+    options.ocr = OcrMode::Comment;
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "see ![cat.png](files/abc.png) <!-- OCR: meow -->");
+
+    let content = r#"<en-note><en-media hash="def" type="application/pdf"/></en-note>"#;
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[Q3 report.pdf](files/def.pdf) (PDF, 1.2 MB)");
+    options.wiki_links = true;
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[[files/def.pdf|Q3 report.pdf]] (PDF, 1.2 MB)");
+    options.media.get_mut("def").unwrap().target = "Q3 report.pdf".to_string();
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[[Q3 report.pdf]] (PDF, 1.2 MB)");
+
+    // Brackets in the name would end the link text, and parentheses the target.
+    options.wiki_links = false;
+    let def = options.media.get_mut("def").unwrap();
+    def.name = "x]y(1).pdf".to_string();
+    def.target = "att/x]y(1).pdf".to_string();
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[x\\]y(1).pdf](att/x]y%281%29.pdf) (PDF, 1.2 MB)");
+    options.wiki_links = true;
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[x\\]y(1).pdf](att/x]y%281%29.pdf) (PDF, 1.2 MB)");
+    options.media.get_mut("def").unwrap().target = "att/def.pdf".to_string();
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[[att/def.pdf|x)y(1).pdf]] (PDF, 1.2 MB)");
+
+    // Evernote's attachment flag links to images rather than showing them.
+    options.wiki_links = false;
+    options.ocr = OcrMode::default();
+    options.media.get_mut("abc").unwrap().attachment = true;
+    let content = r#"<en-note><en-media hash="abc" type="image/png"/></en-note>"#;
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[cat.png](files/abc.png) (image/png, 2.0 KB)");
+
+    // Images wider than the maximum are sized, by the width in the note if it has one.
+    options.media.get_mut("abc").unwrap().attachment = false;
+    options.max_image_width = Some(600);
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(
+        md,
+        "<img src=\"files/abc.png\" alt=\"cat.png\" width=\"600\">"
+    );
+    options.wiki_links = true;
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "![[files/abc.png|600]]");
+    let content = r#"<en-note><en-media hash="abc" type="image/png" width="400px"/></en-note>"#;
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "![[files/abc.png]]");
+}
+
+#[test]
+fn test_highlight() {
+    let content = "<en-note><div>a <span style=\"--en-highlight:yellow;background-color: #ffef9e;\">\
+        key point </span>and <span style=\"background-color: rgb(255, 255, 255);\">b</span></div></en-note>";
+    let mut options = EnmlOptions::default();
+    assert_eq!(to_markdown(content, &options), "a key point and b");
+    options.highlight = HighlightMode::Equals;
+    assert_eq!(to_markdown(content, &options), "a ==key point== and b");
+    options.highlight = HighlightMode::Mark;
+    assert_eq!(
+        to_markdown(content, &options),
+        "a <mark>key point</mark> and b"
+    );
+}
+
+#[test]
+fn test_is_blank() {
+    let boilerplate = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <!DOCTYPE en-note SYSTEM \"http://xml.evernote.com/pub/enml2.dtd\">\n";
+    assert!(is_blank(""));
+    assert!(is_blank(&format!(
+        "{}<en-note><div><br/></div>&nbsp;\u{a0}</en-note>",
+        boilerplate
+    )));
+    assert!(!is_blank("<en-note><div>a</div></en-note>"));
+    assert!(!is_blank("<en-note><en-todo/></en-note>"));
+    assert!(!is_blank("<en-note>&amp;</en-note>"));
+}
+
+#[test]
+fn test_nesting_depth() {
+    let content =
+        "<?xml version=\"1.0\"?><en-note><div><br><b>a</b><en-media/></div><p/></en-note>";
+    assert_eq!(nesting_depth(content), 3);
+    assert_eq!(nesting_depth(&"<div>".repeat(1000)), 1000);
+}
+
+#[test]
+fn test_todo() {
+    let content =
+        r#"<en-note><div><en-todo checked="true"/>milk</div><div><en-todo/>eggs</div></en-note>"#;
+    let md = html2md::parse_html_custom(content, &handlers(&EnmlOptions::default()));
+    assert_eq!(md, "- [x] milk\n- [ ] eggs");
+}
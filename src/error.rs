@@ -1,10 +1,28 @@
+//! The errors of parsing and converting exports.
+
+use xml::common::TextPosition;
+
+/// Anything that can go wrong reading an export or writing the converted notes.
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
     Xml(xml::reader::Error),
+    #[cfg(feature = "quick-xml")]
+    QuickXml(quick_xml::Error),
     Chrono(chrono::format::ParseError),
-    UnexpectedElement(String),
-    UnexpectedEvent(String, xml::reader::XmlEvent),
+    ParseInt(std::num::ParseIntError),
+    Base64(base64::DecodeError),
+    Toml(toml::de::Error),
+    /// An element the parser doesn't know, and where it starts in the export.
+    UnexpectedElement(String, TextPosition),
+    /// The export is larger or more deeply nested than the parser was asked to allow.
+    LimitExceeded(String),
+    /// The output format can't be written this way, e.g. a format that needs a directory.
+    Unsupported(String),
+    UnexpectedEvent(String, xml::reader::XmlEvent, TextPosition),
+    /// A request to the Evernote API failed, or its reply couldn't be read.
+    #[cfg(feature = "evernote")]
+    Api(String),
 }
 
 impl std::fmt::Display for Error {
@@ -12,21 +30,43 @@ impl std::fmt::Display for Error {
         match self {
             Error::Io(e) => e.fmt(f),
             Error::Xml(e) => e.fmt(f),
+            #[cfg(feature = "quick-xml")]
+            Error::QuickXml(e) => e.fmt(f),
             Error::Chrono(e) => e.fmt(f),
-            Error::UnexpectedElement(s) => f.write_fmt(format_args!("Unexpected <{}>", s)),
-            Error::UnexpectedEvent(s, e) => f.write_fmt(format_args!("Unexpected {:?}, {}", e, s)),
+            Error::ParseInt(e) => e.fmt(f),
+            Error::Base64(e) => e.fmt(f),
+            Error::Toml(e) => e.fmt(f),
+            Error::UnexpectedElement(s, pos) => {
+                f.write_fmt(format_args!("{}: Unexpected <{}>", pos, s))
+            }
+            Error::LimitExceeded(s) => f.write_fmt(format_args!("Over the limit: {}", s)),
+            Error::Unsupported(s) => f.write_str(s),
+            Error::UnexpectedEvent(s, e, pos) => {
+                f.write_fmt(format_args!("{}: Unexpected {:?}, {}", pos, e, s))
+            }
+            #[cfg(feature = "evernote")]
+            Error::Api(s) => f.write_fmt(format_args!("Evernote API: {}", s)),
         }
     }
 }
 
 impl std::error::Error for Error {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Error::Io(e) => e.description(),
-            Error::Xml(e) => e.description(),
-            Error::Chrono(e) => e.description(),
-            Error::UnexpectedElement(_) => "Unexpected element",
-            Error::UnexpectedEvent(_, _) => "Unexpected event",
+            Error::Io(e) => Some(e),
+            Error::Xml(e) => Some(e),
+            #[cfg(feature = "quick-xml")]
+            Error::QuickXml(e) => Some(e),
+            Error::Chrono(e) => Some(e),
+            Error::ParseInt(e) => Some(e),
+            Error::Base64(e) => Some(e),
+            Error::Toml(e) => Some(e),
+            Error::UnexpectedElement(_, _) => None,
+            Error::LimitExceeded(_) => None,
+            Error::Unsupported(_) => None,
+            Error::UnexpectedEvent(_, _, _) => None,
+            #[cfg(feature = "evernote")]
+            Error::Api(_) => None,
         }
     }
 }
@@ -43,10 +83,36 @@ impl From<xml::reader::Error> for Error {
     }
 }
 
+#[cfg(feature = "quick-xml")]
+impl From<quick_xml::Error> for Error {
+    fn from(e: quick_xml::Error) -> Error {
+        Error::QuickXml(e)
+    }
+}
+
 impl From<chrono::format::ParseError> for Error {
     fn from(e: chrono::format::ParseError) -> Error {
         Error::Chrono(e)
     }
 }
 
+impl From<std::num::ParseIntError> for Error {
+    fn from(e: std::num::ParseIntError) -> Error {
+        Error::ParseInt(e)
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Error {
+        Error::Base64(e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Error {
+        Error::Toml(e)
+    }
+}
+
+/// Result with this crate's [`Error`].
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,524 @@
+//! Fetching notes from the Evernote API, or Yinxiang's, instead of reading an export, e.g. for
+//! scheduled backups. Notes come back as the parser returns them, ENML content and base64
+//! resources, so the conversion goes on as for an export.
+//!
+//! The API is Thrift over HTTPS. Requests are sent with the `curl` command, so that no HTTP or TLS
+//! library needs building; a `Transport` can send them another way. Authenticate with a developer
+//! token or an OAuth access token.
+
+use crate::enex::{parse_recognition, Note, NoteAttributes, Resource};
+use crate::error::{Error, Result};
+use crate::thrift::{read_reply, Call, Struct, Value};
+use chrono::{DateTime, Local, TimeZone};
+use log::{debug, warn};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+/// Evernote's service, for `Client::connect`.
+pub const EVERNOTE_HOST: &str = "www.evernote.com";
+/// Yinxiang Biji, Evernote's service in China.
+pub const YINXIANG_HOST: &str = "app.yinxiang.com";
+/// Evernote's sandbox, for trying things out with a sandbox account.
+pub const SANDBOX_HOST: &str = "sandbox.evernote.com";
+
+/// How many notes to list at a time, the most the API returns.
+const PAGE_SIZE: i32 = 250;
+
+/// `EDAMErrorCode` of `EDAMSystemException` when requests come too fast.
+const RATE_LIMIT_REACHED: i64 = 19;
+
+/// Names of `EDAMErrorCode`s, from 1.
+const ERROR_CODES: &[&str] = &[
+    "UNKNOWN",
+    "BAD_DATA_FORMAT",
+    "PERMISSION_DENIED",
+    "INTERNAL_ERROR",
+    "DATA_REQUIRED",
+    "LIMIT_REACHED",
+    "QUOTA_REACHED",
+    "INVALID_AUTH",
+    "AUTH_EXPIRED",
+    "DATA_CONFLICT",
+    "ENML_VALIDATION",
+    "SHARD_UNAVAILABLE",
+    "LEN_TOO_SHORT",
+    "LEN_TOO_LONG",
+    "TOO_FEW",
+    "TOO_MANY",
+    "UNSUPPORTED_OPERATION",
+    "TAKEN_DOWN",
+    "RATE_LIMIT_REACHED",
+];
+
+/// Sends Thrift requests over HTTP.
+pub trait Transport {
+    /// POST `body` to `url`, returning the body of the response.
+    fn post(&mut self, url: &str, body: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Sends requests with the `curl` command.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Curl;
+
+impl Transport for Curl {
+    fn post(&mut self, url: &str, body: &[u8]) -> Result<Vec<u8>> {
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--data-binary", "@-"])
+            .args(["--header", "Content-Type: application/x-thrift"])
+            .args(["--header", "Accept: application/x-thrift"])
+            .args([
+                "--user-agent",
+                concat!("enex2mf/", env!("CARGO_PKG_VERSION")),
+            ])
+            .arg(url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Api(format!("couldn't run curl: {}", e)))?;
+        // The token goes in the body rather than the arguments, where other users could see it.
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(body)?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::Api(message.trim().to_string()));
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// A notebook of the account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notebook {
+    pub guid: String,
+    pub name: String,
+}
+
+/// A connection to the note store of an account.
+pub struct Client<T: Transport = Curl> {
+    transport: T,
+    token: String,
+    note_store_url: String,
+    seq: i32,
+    /// Names of the account's tags by guid, listed when first needed.
+    tags: Option<HashMap<String, String>>,
+    received: Rc<Cell<u64>>,
+    /// Leave out the data and recognition of resources.
+    metadata_only: bool,
+}
+
+impl Client<Curl> {
+    /// Connect to the account of `token` on `host`, e.g. `EVERNOTE_HOST`.
+    pub fn connect(host: &str, token: &str) -> Result<Self> {
+        Client::with_transport(Curl, host, token)
+    }
+}
+
+impl<T: Transport> Client<T> {
+    /// Connect to the account of `token` on `host`, sending requests with `transport`.
+    pub fn with_transport(transport: T, host: &str, token: &str) -> Result<Self> {
+        let mut client = Client {
+            transport,
+            token: token.to_string(),
+            note_store_url: String::new(),
+            seq: 0,
+            tags: None,
+            received: Rc::new(Cell::new(0)),
+            metadata_only: false,
+        };
+        let user_store_url = format!("https://{}/edam/user", host);
+        let call = client.call("getNoteStoreUrl").string(1, token);
+        let reply = client.send(&user_store_url, "getNoteStoreUrl", call)?;
+        client.note_store_url = reply
+            .string(0)
+            .ok_or_else(|| Error::Api("no note store URL".to_string()))?;
+        Ok(client)
+    }
+
+    /// How many bytes have been received so far, counting up as notes are fetched.
+    pub fn received(&self) -> Rc<Cell<u64>> {
+        self.received.clone()
+    }
+
+    /// Fetch notes without the data of their resources, e.g. to list them quickly. The content is
+    /// still fetched, to title untitled notes by.
+    pub fn metadata_only(mut self, metadata_only: bool) -> Self {
+        self.metadata_only = metadata_only;
+        self
+    }
+
+    /// Start a call to `name`, authenticated by the token as its first argument.
+    fn call(&mut self, name: &str) -> Call {
+        self.seq += 1;
+        Call::new(name, self.seq)
+    }
+
+    /// Send `call` to `url`, returning the result struct. Waits as long as the API asks when
+    /// requests come too fast.
+    fn send(&mut self, url: &str, name: &str, call: Call) -> Result<Struct> {
+        let body = call.finish();
+        loop {
+            let reply = self.transport.post(url, &body)?;
+            self.received.set(self.received.get() + reply.len() as u64);
+            let result = read_reply(&reply, name)?;
+            if let Some(exception) = result.get_struct(2) {
+                if exception.int(1) == Some(RATE_LIMIT_REACHED) {
+                    let seconds = exception.int(3).unwrap_or(60).max(1) as u64;
+                    warn!("Evernote asks to wait {} seconds between requests", seconds);
+                    thread::sleep(Duration::from_secs(seconds));
+                    continue;
+                }
+            }
+            return check(result, name);
+        }
+    }
+
+    fn send_note_store(&mut self, name: &str, call: Call) -> Result<Struct> {
+        let url = self.note_store_url.clone();
+        self.send(&url, name, call)
+    }
+
+    pub fn notebooks(&mut self) -> Result<Vec<Notebook>> {
+        let call = self.call("listNotebooks").string(1, &self.token.clone());
+        let reply = self.send_note_store("listNotebooks", call)?;
+        Ok(reply
+            .list(0)
+            .iter()
+            .filter_map(Value::as_struct)
+            .map(|notebook| Notebook {
+                guid: notebook.string(1).unwrap_or_default(),
+                name: notebook.string(2).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// The notebook named `name`, ignoring case.
+    pub fn notebook(&mut self, name: &str) -> Result<Notebook> {
+        self.notebooks()?
+            .into_iter()
+            .find(|notebook| notebook.name.to_lowercase() == name.to_lowercase())
+            .ok_or_else(|| Error::Api(format!("no notebook named {}", name)))
+    }
+
+    /// The guids of the notes in `notebook`, oldest first.
+    pub fn note_guids(&mut self, notebook: &Notebook) -> Result<Vec<String>> {
+        let mut guids = Vec::new();
+        loop {
+            let token = self.token.clone();
+            let call = self
+                .call("findNotesMetadata")
+                .string(1, &token)
+                // NoteFilter: by creation, ascending, in the notebook.
+                .begin(2)
+                .i32(1, 1)
+                .bool(2, true)
+                .string(4, &notebook.guid)
+                .end()
+                .i32(3, guids.len() as i32)
+                .i32(4, PAGE_SIZE)
+                .begin(5)
+                .end();
+            let reply = self.send_note_store("findNotesMetadata", call)?;
+            let list = reply.get_struct(0).cloned().unwrap_or_default();
+            let page: Vec<String> = list
+                .list(3)
+                .iter()
+                .filter_map(|note| note.as_struct()?.string(1))
+                .collect();
+            let total = list.int(2).unwrap_or(0) as usize;
+            if page.is_empty() {
+                return Ok(guids);
+            }
+            guids.extend(page);
+            if guids.len() >= total {
+                return Ok(guids);
+            }
+        }
+    }
+
+    /// The note `guid`, with its content, tags, resources and recognition.
+    pub fn note(&mut self, guid: &str) -> Result<Note> {
+        let token = self.token.clone();
+        let call = self
+            .call("getNote")
+            .string(1, &token)
+            .string(2, guid)
+            .bool(3, true)
+            .bool(4, !self.metadata_only)
+            .bool(5, !self.metadata_only)
+            .bool(6, false);
+        let reply = self.send_note_store("getNote", call)?;
+        let edam = reply
+            .get_struct(0)
+            .ok_or_else(|| Error::Api(format!("no note {}", guid)))?;
+        let mut note = Note::builder().build();
+        note.title = edam.string(2);
+        note.content = edam.string(3);
+        note.created = edam.int(6).and_then(time);
+        note.updated = edam.int(7).and_then(time);
+        for tag_guid in edam.list(12).iter().filter_map(Value::as_string) {
+            note.tags.extend(self.tag_name(&tag_guid)?);
+        }
+        if let Some(attributes) = edam.get_struct(14) {
+            note.attributes = note_attributes(attributes);
+            // Only the keys come with the note.
+            let keys = attributes.get_struct(23).map(|data| data.list(1));
+            if keys.is_some_and(|keys| !keys.is_empty()) {
+                note.attributes.application_data = self.application_data(guid)?;
+            }
+        }
+        for resource in edam.list(13).iter().filter_map(Value::as_struct) {
+            note.resources.push(resource_of(resource)?);
+        }
+        debug!("fetched {}", note.title.as_deref().unwrap_or("untitled"));
+        Ok(note)
+    }
+
+    /// The notes of `notebook`, fetched one at a time as they're iterated.
+    pub fn notes(mut self, notebook: &Notebook) -> Result<impl Iterator<Item = Result<Note>>> {
+        let guids = self.note_guids(notebook)?;
+        Ok(guids.into_iter().map(move |guid| self.note(&guid)))
+    }
+
+    fn tag_name(&mut self, guid: &str) -> Result<Option<String>> {
+        if self.tags.is_none() {
+            let call = self.call("listTags").string(1, &self.token.clone());
+            let reply = self.send_note_store("listTags", call)?;
+            let tags = reply
+                .list(0)
+                .iter()
+                .filter_map(Value::as_struct)
+                .filter_map(|tag| Some((tag.string(1)?, tag.string(2)?)))
+                .collect();
+            self.tags = Some(tags);
+        }
+        Ok(self.tags.as_ref().and_then(|tags| tags.get(guid)).cloned())
+    }
+
+    fn application_data(&mut self, guid: &str) -> Result<HashMap<String, String>> {
+        let token = self.token.clone();
+        let call = self
+            .call("getNoteApplicationData")
+            .string(1, &token)
+            .string(2, guid);
+        let reply = self.send_note_store("getNoteApplicationData", call)?;
+        let map = reply.get_struct(0).cloned().unwrap_or_default();
+        Ok(map
+            .map(2)
+            .iter()
+            .filter_map(|(key, value)| Some((key.as_string()?, value.as_string()?)))
+            .collect())
+    }
+}
+
+/// The return value of `result`, or the exception the call `name` threw as an error.
+fn check(result: Struct, name: &str) -> Result<Struct> {
+    if let Some(exception) = result.get_struct(1).or_else(|| result.get_struct(2)) {
+        let code = exception.int(1).unwrap_or(0);
+        let code = match usize::try_from(code) {
+            Ok(code @ 1..=19) => ERROR_CODES[code - 1].to_string(),
+            _ => code.to_string(),
+        };
+        let detail = exception.string(2).unwrap_or_default();
+        return Err(Error::Api(format!("{} failed: {} {}", name, code, detail)));
+    }
+    if let Some(not_found) = result.get_struct(3) {
+        let what = not_found.string(1).unwrap_or_default();
+        return Err(Error::Api(format!("{} failed: {} not found", name, what)));
+    }
+    Ok(result)
+}
+
+/// A time in milliseconds since the epoch, as the API has them.
+fn time(millis: i64) -> Option<DateTime<Local>> {
+    Local.timestamp_millis_opt(millis).single()
+}
+
+fn note_attributes(attributes: &Struct) -> NoteAttributes {
+    let number = |id| attributes.double(id).map(|n| n.to_string());
+    NoteAttributes {
+        subject_date: attributes.int(1).and_then(time),
+        latitude: number(10),
+        longitude: number(11),
+        altitude: number(12),
+        author: attributes.string(13),
+        source: attributes.string(14),
+        source_url: attributes.string(15),
+        reminder_order: attributes.int(18).map(|order| order.to_string()),
+        reminder_done_time: attributes.int(19).and_then(time),
+        reminder_time: attributes.int(20).and_then(time),
+        content_class: attributes.string(22),
+        ..NoteAttributes::default()
+    }
+}
+
+fn resource_of(edam: &Struct) -> Result<Resource> {
+    let body = |id| edam.get_struct(id).and_then(|data| data.binary(3));
+    let mut resource = Resource {
+        data: body(3).map(base64::encode),
+        mime: edam.string(4),
+        ..Resource::default()
+    };
+    resource.width = edam.int(5).and_then(|width| u32::try_from(width).ok());
+    resource.height = edam.int(6).and_then(|height| u32::try_from(height).ok());
+    if let Some(recognition) = body(9) {
+        resource.recognition = parse_recognition(&String::from_utf8_lossy(recognition))?;
+    }
+    resource.file_name = edam
+        .get_struct(11)
+        .and_then(|attributes| attributes.string(10));
+    Ok(resource)
+}
+
+#[test]
+fn test_client() {
+    use crate::thrift::Struct;
+    use std::collections::BTreeMap;
+    use std::convert::TryInto;
+
+    /// Replies to each call by name, recording the URLs called.
+    struct Fake(Vec<String>);
+
+    fn string(s: &str) -> Value {
+        Value::Binary(s.as_bytes().to_vec())
+    }
+
+    fn fields(fields: Vec<(i16, Value)>) -> Value {
+        Value::Struct(Struct(fields.into_iter().collect::<BTreeMap<_, _>>()))
+    }
+
+    /// A reply to `name` returning `value`, in the binary protocol.
+    fn reply(name: &str, value: Value) -> Vec<u8> {
+        fn write(value: &Value, out: &mut Vec<u8>) {
+            match value {
+                Value::Bool(b) => out.push(*b as u8),
+                Value::Byte(n) => out.extend(n.to_be_bytes()),
+                Value::I16(n) => out.extend(n.to_be_bytes()),
+                Value::I32(n) => out.extend(n.to_be_bytes()),
+                Value::I64(n) => out.extend(n.to_be_bytes()),
+                Value::Double(n) => out.extend(n.to_bits().to_be_bytes()),
+                Value::Binary(bytes) => {
+                    out.extend((bytes.len() as i32).to_be_bytes());
+                    out.extend(bytes);
+                }
+                Value::Struct(s) => {
+                    for (id, value) in &s.0 {
+                        out.push(type_of(value));
+                        out.extend(id.to_be_bytes());
+                        write(value, out);
+                    }
+                    out.push(0);
+                }
+                Value::List(items) => {
+                    out.push(items.first().map_or(12, type_of));
+                    out.extend((items.len() as i32).to_be_bytes());
+                    for item in items {
+                        write(item, out);
+                    }
+                }
+                Value::Map(pairs) => {
+                    let (key, value) = pairs
+                        .first()
+                        .map_or((11, 11), |(key, value)| (type_of(key), type_of(value)));
+                    out.extend([key, value]);
+                    out.extend((pairs.len() as i32).to_be_bytes());
+                    for (key, value) in pairs {
+                        write(key, out);
+                        write(value, out);
+                    }
+                }
+            }
+        }
+        fn type_of(value: &Value) -> u8 {
+            match value {
+                Value::Bool(_) => 2,
+                Value::Byte(_) => 3,
+                Value::Double(_) => 4,
+                Value::I16(_) => 6,
+                Value::I32(_) => 8,
+                Value::I64(_) => 10,
+                Value::Binary(_) => 11,
+                Value::Struct(_) => 12,
+                Value::Map(_) => 13,
+                Value::List(_) => 15,
+            }
+        }
+        let mut out = 0x8001_0002u32.to_be_bytes().to_vec();
+        write(&string(name), &mut out);
+        out.extend(1i32.to_be_bytes());
+        write(&fields(vec![(0, value)]), &mut out);
+        out
+    }
+
+    impl Transport for Fake {
+        fn post(&mut self, url: &str, body: &[u8]) -> Result<Vec<u8>> {
+            self.0.push(url.to_string());
+            let len = i32::from_be_bytes(body[4..8].try_into().unwrap()) as usize;
+            let name = std::str::from_utf8(&body[8..8 + len]).unwrap();
+            let value = match name {
+                "getNoteStoreUrl" => string("https://x/shard/s1/notestore"),
+                "listNotebooks" => Value::List(vec![fields(vec![
+                    (1, string("nb1")),
+                    (2, string("Recipes")),
+                ])]),
+                "findNotesMetadata" => fields(vec![
+                    (2, Value::I32(1)),
+                    (3, Value::List(vec![fields(vec![(1, string("n1"))])])),
+                ]),
+                "listTags" => {
+                    Value::List(vec![fields(vec![(1, string("t1")), (2, string("food"))])])
+                }
+                "getNote" => fields(vec![
+                    (2, string("Soup")),
+                    (3, string("<en-note>hot</en-note>")),
+                    (6, Value::I64(1_545_214_384_000)),
+                    (12, Value::List(vec![string("t1")])),
+                    (
+                        13,
+                        Value::List(vec![fields(vec![
+                            (3, fields(vec![(3, string("GIF89a"))])),
+                            (4, string("image/gif")),
+                            (11, fields(vec![(10, string("soup.gif"))])),
+                        ])]),
+                    ),
+                    (
+                        14,
+                        fields(vec![(10, Value::Double(52.52)), (13, string("Ann"))]),
+                    ),
+                ]),
+                _ => panic!("unexpected call {}", name),
+            };
+            Ok(reply(name, value))
+        }
+    }
+
+    let mut client = Client::with_transport(Fake(Vec::new()), EVERNOTE_HOST, "token").unwrap();
+    let notebook = client.notebook("recipes").unwrap();
+    let notes: Vec<Note> = client
+        .notes(&notebook)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(notes.len(), 1);
+    let note = &notes[0];
+    assert_eq!(note.title.as_deref(), Some("Soup"));
+    assert_eq!(note.tags, ["food"]);
+    assert_eq!(
+        note.created.map(|time| time.timestamp()),
+        Some(1_545_214_384)
+    );
+    assert_eq!(note.attributes.latitude.as_deref(), Some("52.52"));
+    assert_eq!(note.attributes.author.as_deref(), Some("Ann"));
+    assert_eq!(note.resources[0].decode().unwrap(), b"GIF89a");
+    assert_eq!(note.resources[0].file_name.as_deref(), Some("soup.gif"));
+}
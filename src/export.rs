@@ -0,0 +1,269 @@
+//! Writing notes as an ENEX export, which Evernote and the apps reading its exports import. Notes
+//! read back from Markdown by `outline` go through here, as can any others.
+
+use crate::enex::{Note, NoteAttributes, Resource, ResourceAttributes};
+use crate::enml::{self, EnmlOptions};
+use crate::error::Result;
+use crate::writer::{ExportInfo, NoteWriter};
+use chrono::{DateTime, Local, Utc};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// What goes before the `<en-note>` of note content, which Evernote expects.
+const ENML_PROLOG: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\
+    <!DOCTYPE en-note SYSTEM \"http://xml.evernote.com/pub/enml2.dtd\">";
+
+/// A time as the export has them, e.g. `20181226T083916Z`.
+fn timestamp(time: &DateTime<Local>) -> String {
+    time.with_timezone(&Utc)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+/// `text` in a CDATA section, splitting it where it contains `]]>` as Evernote does.
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// Writes notes to an ENEX export. Notebooks aren't kept apart, since an export has none.
+pub struct EnexWriter<W: Write> {
+    writer: W,
+    started: bool,
+    now: DateTime<Local>,
+}
+
+impl<W: Write> EnexWriter<W> {
+    pub fn new(writer: W) -> Self {
+        EnexWriter {
+            writer,
+            started: false,
+            now: Local::now(),
+        }
+    }
+
+    /// Write the start of the export, if it isn't written yet.
+    fn start(&mut self) -> Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        writeln!(self.writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            self.writer,
+            "<!DOCTYPE en-export SYSTEM \"http://xml.evernote.com/pub/evernote-export3.dtd\">"
+        )?;
+        writeln!(
+            self.writer,
+            "<en-export export-date=\"{}\" application=\"enex2mf\" version=\"{}\">",
+            timestamp(&self.now),
+            env!("CARGO_PKG_VERSION")
+        )?;
+        Ok(())
+    }
+
+    /// Write `<element>` with `text`, if there is any.
+    fn element(&mut self, element: &str, text: Option<&str>) -> Result<()> {
+        if let Some(text) = text {
+            writeln!(
+                self.writer,
+                "<{0}>{1}</{0}>",
+                element,
+                enml::escape(text.trim())
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_attributes(&mut self, attributes: &NoteAttributes) -> Result<()> {
+        if *attributes == NoteAttributes::default() {
+            return Ok(());
+        }
+        writeln!(self.writer, "<note-attributes>")?;
+        let time = |time: &Option<DateTime<Local>>| time.as_ref().map(timestamp);
+        self.element("subject-date", time(&attributes.subject_date).as_deref())?;
+        self.element("latitude", attributes.latitude.as_deref())?;
+        self.element("longitude", attributes.longitude.as_deref())?;
+        self.element("altitude", attributes.altitude.as_deref())?;
+        self.element("author", attributes.author.as_deref())?;
+        self.element("source", attributes.source.as_deref())?;
+        self.element("source-url", attributes.source_url.as_deref())?;
+        self.element("reminder-order", attributes.reminder_order.as_deref())?;
+        self.element("reminder-time", time(&attributes.reminder_time).as_deref())?;
+        self.element(
+            "reminder-done-time",
+            time(&attributes.reminder_done_time).as_deref(),
+        )?;
+        self.element("content-class", attributes.content_class.as_deref())?;
+        self.write_application_data(&attributes.application_data)?;
+        writeln!(self.writer, "</note-attributes>")?;
+        Ok(())
+    }
+
+    fn write_resource(&mut self, resource: &Resource) -> Result<()> {
+        writeln!(self.writer, "<resource>")?;
+        let data = match (&resource.spilled, &resource.data) {
+            (None, Some(data)) => data.trim().to_string(),
+            _ => base64::encode(&resource.decode()?),
+        };
+        writeln!(self.writer, "<data encoding=\"base64\">{}</data>", data)?;
+        let mime = resource
+            .mime
+            .as_deref()
+            .unwrap_or("application/octet-stream");
+        self.element("mime", Some(mime))?;
+        self.element("width", resource.width.map(|w| w.to_string()).as_deref())?;
+        self.element("height", resource.height.map(|h| h.to_string()).as_deref())?;
+        let attributes = &resource.attributes;
+        if resource.file_name.is_some() || *attributes != ResourceAttributes::default() {
+            writeln!(self.writer, "<resource-attributes>")?;
+            self.element("source-url", attributes.source_url.as_deref())?;
+            let timestamp = attributes.timestamp.as_ref().map(timestamp);
+            self.element("timestamp", timestamp.as_deref())?;
+            self.element("latitude", attributes.latitude.as_deref())?;
+            self.element("longitude", attributes.longitude.as_deref())?;
+            self.element("altitude", attributes.altitude.as_deref())?;
+            self.element("camera-make", attributes.camera_make.as_deref())?;
+            self.element("camera-model", attributes.camera_model.as_deref())?;
+            self.element("reco-type", attributes.reco_type.as_deref())?;
+            self.element("file-name", resource.file_name.as_deref())?;
+            if attributes.attachment {
+                self.element("attachment", Some("true"))?;
+            }
+            self.write_application_data(&attributes.application_data)?;
+            writeln!(self.writer, "</resource-attributes>")?;
+        }
+        let alternate = match (&resource.alternate_spilled, &resource.alternate_data) {
+            (None, Some(data)) => Some(data.trim().to_string()),
+            _ => resource
+                .decode_alternate()?
+                .map(|data| base64::encode(&data)),
+        };
+        if let Some(data) = alternate {
+            writeln!(
+                self.writer,
+                "<alternate-data encoding=\"base64\">{}</alternate-data>",
+                data
+            )?;
+        }
+        writeln!(self.writer, "</resource>")?;
+        Ok(())
+    }
+
+    /// Write `<application-data>` entries, sorted so that the output is the same every time.
+    fn write_application_data(&mut self, data: &HashMap<String, String>) -> Result<()> {
+        let mut data: Vec<_> = data.iter().collect();
+        data.sort();
+        for (key, value) in data {
+            writeln!(
+                self.writer,
+                "<application-data key=\"{}\">{}</application-data>",
+                enml::escape(key),
+                enml::escape(value)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> NoteWriter for EnexWriter<W> {
+    fn begin(&mut self, _export: &ExportInfo) -> Result<()> {
+        self.start()
+    }
+
+    /// Write `note` as it is; its content is already ENML, so `enml` doesn't apply.
+    fn write_note(&mut self, note: &Note, _enml: &EnmlOptions) -> Result<()> {
+        self.start()?;
+        writeln!(self.writer, "<note>")?;
+        // Evernote requires a title, which may be no longer than 255 characters.
+        let title = note
+            .title
+            .as_deref()
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+            .unwrap_or("Untitled");
+        let title: String = title.chars().take(255).collect();
+        self.element("title", Some(&title))?;
+        let content = note.content.as_deref().unwrap_or("<en-note/>");
+        let content = if content.trim_start().starts_with("<?xml") {
+            content.to_string()
+        } else {
+            format!("{}{}", ENML_PROLOG, content)
+        };
+        writeln!(self.writer, "<content>{}</content>", cdata(&content))?;
+        self.element("created", note.created.as_ref().map(timestamp).as_deref())?;
+        self.element("updated", note.updated.as_ref().map(timestamp).as_deref())?;
+        for tag in &note.tags {
+            self.element("tag", Some(tag))?;
+        }
+        self.write_attributes(&note.attributes)?;
+        for resource in &note.resources {
+            self.write_resource(resource)?;
+        }
+        writeln!(self.writer, "</note>")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.start()?;
+        writeln!(self.writer, "</en-export>")?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[test]
+fn test_enex_writer() {
+    use crate::enex::{EnexParser, Limits};
+    use crate::validate::validate;
+    use chrono::TimeZone;
+
+    let created = Utc
+        .with_ymd_and_hms(2018, 12, 19, 10, 13, 4)
+        .unwrap()
+        .with_timezone(&Local);
+    let mut resource = Resource::new(b"GIF89a", "image/gif");
+    resource.file_name = Some("a & b.gif".to_string());
+    resource.attributes.attachment = true;
+    let mut note = Note::builder()
+        .title("Hello <world>")
+        .content(
+            "<en-note><div>]]> &amp; <en-media hash=\"x\" type=\"image/gif\"/></div></en-note>",
+        )
+        .created(created)
+        .tag("a")
+        .tag("b")
+        .resource(resource)
+        .build();
+    note.attributes.source_url = Some("http://x.com/?a=1&b=2".to_string());
+    let mut out = Vec::new();
+    let mut writer = EnexWriter::new(&mut out);
+    writer.begin(&ExportInfo::default()).unwrap();
+    writer.write_note(&note, &EnmlOptions::default()).unwrap();
+    writer
+        .write_note(&Note::default(), &EnmlOptions::default())
+        .unwrap();
+    writer.finish().unwrap();
+
+    assert_eq!(validate(&out[..], &Limits::default()).unwrap(), []);
+    let notes: Vec<Note> = EnexParser::new(&out[..]).map(Result::unwrap).collect();
+    assert_eq!(notes.len(), 2);
+    assert_eq!(notes[0].title, note.title);
+    assert!(notes[0]
+        .content
+        .as_deref()
+        .unwrap()
+        .ends_with(note.content.as_deref().unwrap()));
+    assert_eq!(notes[0].created, note.created);
+    assert_eq!(notes[0].tags, note.tags);
+    assert_eq!(notes[0].attributes, note.attributes);
+    assert_eq!(notes[0].resources[0].decode().unwrap(), b"GIF89a");
+    assert_eq!(notes[0].resources[0].file_name, note.resources[0].file_name);
+    assert_eq!(
+        notes[0].resources[0].attributes,
+        note.resources[0].attributes
+    );
+    assert_eq!(notes[1].title.as_deref(), Some("Untitled"));
+}
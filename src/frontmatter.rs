@@ -0,0 +1,144 @@
+//! YAML frontmatter for notes written to their own Markdown files, which most Markdown editors
+//! and static site generators read.
+
+use crate::dates::{DateFormat, DateOptions};
+use crate::enex::Note;
+use crate::error::Result;
+use crate::mindforger::MetadataOptions;
+use serde::Deserialize;
+use std::io::Write;
+
+/// Where to put the metadata of notes written to their own files.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataStyle {
+    /// As the format has it, e.g. MindForger's HTML comment.
+    #[default]
+    Native,
+    /// In YAML frontmatter instead.
+    Frontmatter,
+    /// In both.
+    Both,
+}
+
+impl MetadataStyle {
+    pub fn native(self) -> bool {
+        self != MetadataStyle::Frontmatter
+    }
+
+    pub fn frontmatter(self) -> bool {
+        self != MetadataStyle::Native
+    }
+}
+
+/// Quote a YAML string.
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Write the title and the other fields of `note` as YAML frontmatter.
+pub fn write_frontmatter<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    options: &MetadataOptions,
+) -> Result<()> {
+    writeln!(writer, "---")?;
+    let title = note.title.as_deref().unwrap_or("untitled");
+    writeln!(writer, "title: {}", quote(title))?;
+    write_fields(writer, note, options, |tag| tag.to_string())?;
+    writeln!(writer, "---\n")?;
+    Ok(())
+}
+
+/// Write the dates, tags, source, author, location and content class of `note` as lines of
+/// frontmatter, with the author and location as `options` says and tags as `tag` writes them.
+/// Times are ISO 8601, unless `options.dates` has another format.
+pub fn write_fields<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    options: &MetadataOptions,
+    tag: impl Fn(&str) -> String,
+) -> Result<()> {
+    let dates = match options.dates.format {
+        DateFormat::Plain => DateOptions {
+            format: DateFormat::Iso8601,
+            ..options.dates.clone()
+        },
+        _ => options.dates.clone(),
+    };
+    if let Some(ref created) = note.created {
+        writeln!(writer, "created: {}", quote(&dates.format(created)))?;
+    }
+    if let Some(ref updated) = note.updated {
+        writeln!(writer, "updated: {}", quote(&dates.format(updated)))?;
+    }
+    if !note.tags.is_empty() {
+        writeln!(writer, "tags:")?;
+        for name in &note.tags {
+            writeln!(writer, "  - {}", quote(&tag(name)))?;
+        }
+    }
+    let attributes = &note.attributes;
+    if let Some(ref source_url) = attributes.source_url {
+        writeln!(writer, "source: {}", quote(source_url))?;
+    }
+    if let Some(author) = attributes.author.as_ref().filter(|_| options.author) {
+        writeln!(writer, "author: {}", quote(author))?;
+    }
+    let coordinates = attributes
+        .coordinates()
+        .filter(|_| options.location.is_some());
+    if let Some((latitude, longitude)) = coordinates {
+        writeln!(writer, "location: [{}, {}]", latitude, longitude)?;
+    }
+    if let Some(ref class) = attributes.content_class {
+        writeln!(writer, "content-class: {}", quote(class))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_frontmatter() {
+    use crate::dates::DateZone;
+    use crate::mindforger::LocationLink;
+    use chrono::{Local, TimeZone, Utc};
+
+    let mut note = Note::builder()
+        .title("Say \"hi\"")
+        .created(
+            Utc.with_ymd_and_hms(2018, 12, 19, 10, 13, 4)
+                .unwrap()
+                .with_timezone(&Local),
+        )
+        .tag("a b")
+        .build();
+    note.attributes.author = Some("Ann".to_string());
+    note.attributes.latitude = Some("52.52".to_string());
+    note.attributes.longitude = Some("13.40".to_string());
+    note.attributes.content_class = Some("evernote.food.meal".to_string());
+    let mut options = MetadataOptions {
+        dates: DateOptions {
+            zone: DateZone::Utc,
+            ..DateOptions::default()
+        },
+        author: true,
+        location: Some(LocationLink::Osm),
+        ..MetadataOptions::default()
+    };
+    let mut out = Vec::new();
+    write_frontmatter(&mut out, &note, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "---\ntitle: \"Say \\\"hi\\\"\"\ncreated: \"2018-12-19T10:13:04+00:00\"\n\
+         tags:\n  - \"a b\"\nauthor: \"Ann\"\nlocation: [52.52, 13.4]\n\
+         content-class: \"evernote.food.meal\"\n---\n\n"
+    );
+
+    // The author and location only when asked for.
+    options.author = false;
+    options.location = None;
+    let mut out = Vec::new();
+    write_frontmatter(&mut out, &note, &options).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(!out.contains("author:") && !out.contains("location:"));
+}
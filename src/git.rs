@@ -0,0 +1,110 @@
+//! Committing the converted files to the git repository they were written into, with
+//! `--git-commit`, so repeated migrations leave a history of what changed.
+
+use enex2mf::Summary;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Stage `paths` and commit them, leaving anything else already staged alone. Paths outside the
+/// repository of the first are left out, e.g. attachments written elsewhere. Returns whether there
+/// was anything to commit.
+pub fn commit(paths: &[&Path], message: &str) -> io::Result<bool> {
+    let first = paths.first().expect("at least one path");
+    let dir = if first.is_dir() {
+        first
+    } else {
+        match first.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        }
+    };
+    let top = git(dir, &["rev-parse", "--show-toplevel"], &[]).map_err(|_| {
+        let message = format!("{} isn't in a git repository", first.display());
+        io::Error::new(io::ErrorKind::NotFound, message)
+    })?;
+    let top = PathBuf::from(top.trim()).canonicalize()?;
+    let paths: Vec<PathBuf> = paths
+        .iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .filter(|path| path.starts_with(&top))
+        .collect();
+    if paths.is_empty() {
+        return Ok(false);
+    }
+
+    git(&top, &["add", "--all"], &paths)?;
+    if git(&top, &["diff", "--cached", "--name-only"], &paths)?.is_empty() {
+        return Ok(false);
+    }
+    git(&top, &["commit", "--quiet", "--message", message], &paths)?;
+    Ok(true)
+}
+
+/// Run git in `dir` with `args`, limited to `paths` if any, returning its output, or its error
+/// message as an error if it fails.
+fn git(dir: &Path, args: &[&str], paths: &[PathBuf]) -> io::Result<String> {
+    let mut command = Command::new("git");
+    command.current_dir(dir).args(args);
+    if !paths.is_empty() {
+        command.arg("--").args(paths);
+    }
+    let output = command.output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(io::Error::other(format!("git: {}", message)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A commit message saying which notebooks were converted, with how many notes each and when
+/// they were exported.
+pub fn message(summary: &Summary) -> String {
+    let subject = match summary.notebooks[..] {
+        [ref notebook] => format!("Convert {} from Evernote", notebook.name),
+        ref notebooks => format!("Convert {} notebooks from Evernote", notebooks.len()),
+    };
+    let mut message = format!("{}\n\n", subject);
+    for notebook in &summary.notebooks {
+        let notes = match notebook.notes {
+            1 => "1 note".to_string(),
+            n => format!("{} notes", n),
+        };
+        let exported = match notebook.export_date {
+            Some(date) => format!("exported {}", date.format("%F %T")),
+            None => "export date unknown".to_string(),
+        };
+        message += &format!("{}: {}, {}\n", notebook.name, notes, exported);
+    }
+    message += concat!(
+        "\nConverted with enex2mf ",
+        env!("CARGO_PKG_VERSION"),
+        ".\n"
+    );
+    message
+}
+
+#[test]
+fn test_message() {
+    use chrono::{Local, TimeZone};
+    use enex2mf::NotebookSummary;
+
+    let notebook = |name: &str, notes| NotebookSummary {
+        name: name.to_string(),
+        export_date: None,
+        notes,
+    };
+    let mut summary = Summary {
+        notebooks: vec![notebook("Recipes", 1)],
+        ..Summary::default()
+    };
+    assert!(message(&summary).starts_with("Convert Recipes from Evernote\n\nRecipes: 1 note,"));
+
+    summary.notebooks.push(NotebookSummary {
+        export_date: Local.with_ymd_and_hms(2018, 12, 26, 8, 39, 16).single(),
+        ..notebook("Travel", 3)
+    });
+    let message = message(&summary);
+    assert!(message.starts_with("Convert 2 notebooks from Evernote\n"));
+    assert!(message.contains("\nTravel: 3 notes, exported 2018-12-26 08:39:16\n"));
+}
@@ -0,0 +1,129 @@
+//! Standalone HTML pages for browsing an archive without MindForger. Notes are converted to
+//! Markdown as for the other formats, then rendered with pulldown-cmark.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::writer::{ExportInfo, NoteWriter};
+use pulldown_cmark::{html, Parser};
+use std::io::Write;
+
+const STYLE: &str = "body { max-width: 50em; margin: 2em auto; padding: 0 1em; \
+                     font-family: sans-serif; line-height: 1.5 } \
+                     img { max-width: 100% } \
+                     .meta { color: #666; font-size: 90% } \
+                     article + article { border-top: 1px solid #ccc; margin-top: 2em }";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write the start of a page titled `title`, up to the opening `<body>`.
+pub fn write_header<W: Write + ?Sized>(writer: &mut W, title: &str) -> Result<()> {
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{}</title>", escape(title))?;
+    writeln!(writer, "<style>{}</style>", STYLE)?;
+    writeln!(writer, "</head>\n<body>")?;
+    Ok(())
+}
+
+/// Close the elements opened by `write_header`.
+pub fn write_footer<W: Write + ?Sized>(writer: &mut W) -> Result<()> {
+    writeln!(writer, "</body>\n</html>")?;
+    Ok(())
+}
+
+/// Write `note` as an `<article>`, to go between `write_header` and `write_footer`.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    let title = note.title.as_deref().unwrap_or("untitled");
+    writeln!(writer, "<article>\n<h1>{}</h1>", escape(title))?;
+
+    let mut meta = Vec::new();
+    if let Some(ref created) = note.created {
+        meta.push(format!("Created {}", created.format("%F %R")));
+    }
+    if let Some(ref updated) = note.updated {
+        meta.push(format!("updated {}", updated.format("%F %R")));
+    }
+    if !note.tags.is_empty() {
+        meta.push(format!("tags: {}", escape(&note.tags.join(", "))));
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        let source_url = escape(source_url);
+        meta.push(format!("from <a href=\"{0}\">{0}</a>", source_url));
+    }
+    if !meta.is_empty() {
+        writeln!(writer, "<p class=\"meta\">{}</p>", meta.join(" · "))?;
+    }
+
+    let markdown = note.content_markdown(enml);
+    let mut content = String::new();
+    html::push_html(&mut content, Parser::new(&markdown));
+    write!(writer, "{}", content)?;
+    writeln!(writer, "</article>")?;
+    Ok(())
+}
+
+/// Write `note` as a page of its own.
+pub fn write_page<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    write_header(writer, note.title.as_deref().unwrap_or("untitled"))?;
+    write_note(writer, note, enml)?;
+    write_footer(writer)
+}
+
+/// Writes a notebook as a single page, with an article per note.
+pub struct PageWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PageWriter<W> {
+    /// Write the page to `writer`.
+    pub fn new(writer: W) -> Self {
+        PageWriter { writer }
+    }
+}
+
+impl<W: Write> NoteWriter for PageWriter<W> {
+    fn begin(&mut self, export: &ExportInfo) -> Result<()> {
+        write_header(&mut self.writer, &export.name)
+    }
+
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        write_note(&mut self.writer, note, enml)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        write_footer(&mut self.writer)
+    }
+}
+
+#[test]
+fn test_write_note() {
+    let note = Note {
+        title: Some("<Hello>".to_string()),
+        content: Some("<en-note><div>a <b>world</b></div></en-note>".to_string()),
+        ..Note::default()
+    };
+    let mut out = Vec::new();
+    write_note(&mut out, &note, &EnmlOptions::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "<article>\n<h1>&lt;Hello&gt;</h1>\n<p>a <strong>world</strong></p>\n</article>\n"
+    );
+}
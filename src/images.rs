@@ -0,0 +1,155 @@
+//! Shrinking extracted images, to keep the converted notes small: photos are downscaled to fit a
+//! size and BMPs, which few apps show, converted to PNG. Needs the `images` feature; without it,
+//! images are extracted as they are.
+
+use crate::enex::Resource;
+use crate::error::Result;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Shrinks images to fit in `max_size` pixels each way, keeping count of the bytes saved by all
+/// the threads extracting them.
+#[derive(Clone, Debug)]
+pub struct Shrink {
+    pub max_size: u32,
+    images: Arc<AtomicU64>,
+    saved: Arc<AtomicU64>,
+}
+
+/// An image as shrunk.
+pub struct Shrunk {
+    pub data: Vec<u8>,
+    pub mime: &'static str,
+    pub width: u32,
+    /// The size of the image before, in bytes.
+    original_size: u64,
+}
+
+impl Shrink {
+    pub fn new(max_size: u32) -> Self {
+        Shrink {
+            max_size,
+            images: Arc::default(),
+            saved: Arc::default(),
+        }
+    }
+
+    /// How many images were shrunk and the bytes that saved, of those written.
+    pub fn saved(&self) -> (u64, u64) {
+        (
+            self.images.load(Ordering::Relaxed),
+            self.saved.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Write `shrunk` to `writer`, counting what that saved.
+    pub fn write(&self, shrunk: &Shrunk, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&shrunk.data)?;
+        let saved = shrunk
+            .original_size
+            .saturating_sub(shrunk.data.len() as u64);
+        self.images.fetch_add(1, Ordering::Relaxed);
+        self.saved.fetch_add(saved, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// `resource` shrunk, if it's an image that's too big or that few apps show. Images that
+    /// can't be decoded, or that wouldn't be smaller, are left as they are.
+    #[cfg(feature = "images")]
+    pub fn shrink(&self, resource: &Resource) -> Result<Option<Shrunk>> {
+        use image::imageops::FilterType;
+        use image::{ImageFormat, ImageOutputFormat};
+        use log::{debug, warn};
+        use std::io::Cursor;
+
+        // GIFs are left alone, as only their first frame would be kept.
+        let (format, output, mime) = match resource.mime.as_deref() {
+            Some("image/jpeg") | Some("image/jpg") | Some("image/pjpeg") => {
+                (ImageFormat::Jpeg, ImageOutputFormat::Jpeg(85), "image/jpeg")
+            }
+            Some("image/png") => (ImageFormat::Png, ImageOutputFormat::Png, "image/png"),
+            Some("image/bmp") => (ImageFormat::Bmp, ImageOutputFormat::Png, "image/png"),
+            _ => return Ok(None),
+        };
+        // Images that say they fit already aren't decoded.
+        let fits = |size: Option<u32>| size.is_some_and(|size| size <= self.max_size);
+        if format != ImageFormat::Bmp && fits(resource.width) && fits(resource.height) {
+            return Ok(None);
+        }
+
+        let data = resource.decode()?;
+        let mut image = match image::load_from_memory_with_format(&data, format) {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("couldn't shrink {}: {}", resource_name(resource), e);
+                return Ok(None);
+            }
+        };
+        let resized = image.width() > self.max_size || image.height() > self.max_size;
+        if resized {
+            image = image.resize(self.max_size, self.max_size, FilterType::Lanczos3);
+        } else if format != ImageFormat::Bmp {
+            return Ok(None);
+        }
+        let mut shrunk = Vec::new();
+        if let Err(e) = image.write_to(&mut Cursor::new(&mut shrunk), output) {
+            warn!("couldn't shrink {}: {}", resource_name(resource), e);
+            return Ok(None);
+        }
+        if shrunk.len() >= data.len() && format != ImageFormat::Bmp {
+            return Ok(None);
+        }
+        debug!(
+            "shrank {} from {} to {} bytes",
+            resource_name(resource),
+            data.len(),
+            shrunk.len()
+        );
+        Ok(Some(Shrunk {
+            data: shrunk,
+            mime,
+            width: image.width(),
+            original_size: data.len() as u64,
+        }))
+    }
+
+    /// Without the `images` feature, nothing is shrunk.
+    #[cfg(not(feature = "images"))]
+    pub fn shrink(&self, _resource: &Resource) -> Result<Option<Shrunk>> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "images")]
+fn resource_name(resource: &Resource) -> &str {
+    resource.file_name.as_deref().unwrap_or("an image")
+}
+
+#[cfg(feature = "images")]
+#[test]
+fn test_shrink() {
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+    use std::io::Cursor;
+
+    let mut bmp = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::new(40, 20))
+        .write_to(&mut Cursor::new(&mut bmp), ImageOutputFormat::Bmp)
+        .unwrap();
+    let resource = Resource::new(&bmp, "image/bmp");
+    let shrink = Shrink::new(10);
+    let shrunk = shrink.shrink(&resource).unwrap().unwrap();
+    assert_eq!((shrunk.mime, shrunk.width), ("image/png", 10));
+    let mut written = Vec::new();
+    shrink.write(&shrunk, &mut written).unwrap();
+    assert!(written.starts_with(b"\x89PNG"));
+    assert_eq!(shrink.saved(), (1, (bmp.len() - written.len()) as u64));
+
+    // Small enough already.
+    let small = Resource {
+        width: Some(8),
+        height: Some(8),
+        ..Resource::new(b"not decoded", "image/png")
+    };
+    assert!(shrink.shrink(&small).unwrap().is_none());
+}
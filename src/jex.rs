@@ -0,0 +1,361 @@
+//! Joplin JEX export: a tar of serialized Joplin items (notebook, notes, resources and tags) plus
+//! the resource files.
+//!
+//! Each item is a Markdown file named by its id: the title, a blank line, the body, a blank line,
+//! then `key: value` properties ending with `type_`. Ids are 32 hex digits; we derive them from
+//! MD5 hashes so repeated exports produce the same ids.
+
+use crate::attachments;
+use crate::enex::Note;
+use crate::enml::{EnmlOptions, Media};
+use crate::error::Result;
+use crate::writer::{ExportInfo, NoteWriter};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use std::collections::HashSet;
+use std::io::Write;
+
+const TYPE_NOTE: u32 = 1;
+const TYPE_FOLDER: u32 = 2;
+const TYPE_RESOURCE: u32 = 4;
+const TYPE_TAG: u32 = 5;
+const TYPE_NOTE_TAG: u32 = 6;
+
+fn id(kind: &str, key: &str) -> String {
+    format!("{:x}", md5::compute(format!("{}:{}", kind, key)))
+}
+
+fn timestamp<Tz: TimeZone>(time: &DateTime<Tz>) -> String {
+    time.with_timezone(&Utc)
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+/// Joplin properties are single-line.
+fn escape(value: &str) -> String {
+    value.replace('\r', "").replace('\n', "\\n")
+}
+
+/// Writes notes to a JEX archive.
+pub struct JexWriter<W: Write> {
+    builder: tar::Builder<W>,
+    folder_id: String,
+    /// Ids of tags and resources already written, since they are shared between notes.
+    written: HashSet<String>,
+    /// Number of notes so far, to give notes with the same title distinct ids.
+    count: usize,
+    now: DateTime<Local>,
+}
+
+impl<W: Write> JexWriter<W> {
+    /// Start an export. Call `begin` before writing notes.
+    pub fn new(writer: W) -> Self {
+        JexWriter {
+            builder: tar::Builder::new(writer),
+            folder_id: String::new(),
+            written: HashSet::new(),
+            count: 0,
+            now: Local::now(),
+        }
+    }
+
+    fn append_file(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.now.timestamp() as u64);
+        self.builder.append_data(&mut header, path, data)?;
+        Ok(())
+    }
+
+    fn append_item(
+        &mut self,
+        title: &str,
+        body: &str,
+        properties: &[(&str, &str)],
+        item_type: u32,
+    ) -> Result<()> {
+        let mut item = String::new();
+        if !title.is_empty() {
+            item.push_str(&escape(title));
+            item.push_str("\n\n");
+        }
+        if !body.is_empty() {
+            item.push_str(body);
+            item.push_str("\n\n");
+        }
+        for (key, value) in properties {
+            item.push_str(&format!("{}: {}\n", key, escape(value)));
+        }
+        item.push_str(&format!("type_: {}", item_type));
+
+        let id = properties
+            .iter()
+            .find(|&&(key, _)| key == "id")
+            .map_or("", |&(_, value)| value);
+        self.append_file(&format!("{}.md", id), item.as_bytes())
+    }
+
+    /// Write the resources of `note` that haven't been written yet, returning the media to link
+    /// from its content. Joplin links resources as `:/id`; we use the MD5 hash as the id.
+    fn write_resources(&mut self, note: &Note) -> Result<Vec<(String, Media)>> {
+        let now = timestamp(&self.now);
+        let mut media = Vec::new();
+        for resource in &note.resources {
+            let data = resource.decode()?;
+            let hash = format!("{:x}", md5::compute(&data));
+            let mime = resource
+                .mime
+                .as_deref()
+                .unwrap_or("application/octet-stream");
+            let extension = attachments::extension(mime).unwrap_or("");
+            let name = resource.file_name.clone().unwrap_or_else(|| hash.clone());
+
+            if self.written.insert(hash.clone()) {
+                let file = if extension.is_empty() {
+                    format!("resources/{}", hash)
+                } else {
+                    format!("resources/{}.{}", hash, extension)
+                };
+                self.append_file(&file, &data)?;
+                let size = data.len().to_string();
+                self.append_item(
+                    &name,
+                    "",
+                    &[
+                        ("id", hash.as_str()),
+                        ("mime", mime),
+                        ("filename", &name),
+                        ("created_time", &now),
+                        ("updated_time", &now),
+                        ("user_created_time", &now),
+                        ("user_updated_time", &now),
+                        ("file_extension", extension),
+                        ("encryption_cipher_text", ""),
+                        ("encryption_applied", "0"),
+                        ("encryption_blob_encrypted", "0"),
+                        ("size", &size),
+                        ("is_shared", "0"),
+                    ],
+                    TYPE_RESOURCE,
+                )?;
+            }
+
+            media.push((
+                hash.clone(),
+                Media {
+                    target: format!(":/{}", hash),
+                    name,
+                    mime: resource.mime.clone(),
+                    size: Some(data.len() as u64),
+                    recognition: attachments::recognition(resource),
+                    attachment: resource.attributes.attachment,
+                    width: resource.width,
+                },
+            ));
+        }
+        Ok(media)
+    }
+}
+
+impl<W: Write> NoteWriter for JexWriter<W> {
+    /// Add a notebook, which the following notes go into.
+    fn begin(&mut self, export: &ExportInfo) -> Result<()> {
+        let name = export.name.as_str();
+        self.folder_id = id("folder", name);
+        let now = timestamp(&self.now);
+        let folder_id = self.folder_id.clone();
+        self.append_item(
+            name,
+            "",
+            &[
+                ("id", folder_id.as_str()),
+                ("created_time", &now),
+                ("updated_time", &now),
+                ("user_created_time", &now),
+                ("user_updated_time", &now),
+                ("encryption_cipher_text", ""),
+                ("encryption_applied", "0"),
+                ("parent_id", ""),
+                ("is_shared", "0"),
+            ],
+            TYPE_FOLDER,
+        )
+    }
+
+    /// Write `note` and its resources and tags, linking the resources from the content.
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        self.count += 1;
+        let title = note.title.as_deref().unwrap_or("untitled");
+        let created = note.created.as_ref().map(timestamp).unwrap_or_default();
+        let updated = note
+            .updated
+            .as_ref()
+            .or(note.created.as_ref())
+            .map(timestamp)
+            .unwrap_or_default();
+        let folder_id = self.folder_id.clone();
+        let note_id = id("note", &format!("{}:{}:{}", folder_id, self.count, title));
+
+        let mut enml = enml.clone();
+        enml.media.extend(self.write_resources(note)?);
+        let body = note.content_markdown(&enml);
+
+        let attributes = &note.attributes;
+        self.append_item(
+            title,
+            &body,
+            &[
+                ("id", note_id.as_str()),
+                ("parent_id", &folder_id),
+                ("created_time", &created),
+                ("updated_time", &updated),
+                ("is_conflict", "0"),
+                ("latitude", attributes.latitude.as_deref().unwrap_or("0")),
+                ("longitude", attributes.longitude.as_deref().unwrap_or("0")),
+                ("altitude", attributes.altitude.as_deref().unwrap_or("0")),
+                ("author", attributes.author.as_deref().unwrap_or("")),
+                ("source_url", attributes.source_url.as_deref().unwrap_or("")),
+                ("is_todo", "0"),
+                ("todo_due", "0"),
+                ("todo_completed", "0"),
+                ("source", "evernote"),
+                ("source_application", "enex2mf"),
+                ("application_data", ""),
+                ("order", "0"),
+                ("user_created_time", &created),
+                ("user_updated_time", &updated),
+                ("encryption_cipher_text", ""),
+                ("encryption_applied", "0"),
+                ("markup_language", "1"),
+                ("is_shared", "0"),
+            ],
+            TYPE_NOTE,
+        )?;
+
+        for tag in &note.tags {
+            let tag_id = id("tag", tag);
+            let now = timestamp(&self.now);
+            if self.written.insert(tag_id.clone()) {
+                self.append_item(
+                    tag,
+                    "",
+                    &[
+                        ("id", tag_id.as_str()),
+                        ("created_time", &now),
+                        ("updated_time", &now),
+                        ("user_created_time", &now),
+                        ("user_updated_time", &now),
+                        ("encryption_cipher_text", ""),
+                        ("encryption_applied", "0"),
+                        ("is_shared", "0"),
+                        ("parent_id", ""),
+                    ],
+                    TYPE_TAG,
+                )?;
+            }
+            let note_tag_id = id("note_tag", &format!("{}:{}", note_id, tag_id));
+            self.append_item(
+                "",
+                "",
+                &[
+                    ("id", note_tag_id.as_str()),
+                    ("note_id", &note_id),
+                    ("tag_id", &tag_id),
+                    ("created_time", &now),
+                    ("updated_time", &now),
+                    ("user_created_time", &now),
+                    ("user_updated_time", &now),
+                    ("encryption_cipher_text", ""),
+                    ("encryption_applied", "0"),
+                    ("is_shared", "0"),
+                ],
+                TYPE_NOTE_TAG,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write out the items so far. The archive ends on `finish`.
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.builder.get_mut().flush()?)
+    }
+
+    /// Write the end of the archive.
+    fn finish(&mut self) -> Result<()> {
+        self.builder.finish()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_jex_writer() {
+    use crate::enex::Resource;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    let hash = "1ac2109d47dbc72551f71df89d01ed18";
+    let content = format!(
+        "<en-note>Stir<en-media hash=\"{}\" type=\"image/gif\"/></en-note>",
+        hash
+    );
+    let soup = Note::builder()
+        .title("Soup")
+        .tag("food")
+        .content(&content)
+        .resource(Resource::new(b"GIF89a", "image/gif"))
+        .build();
+    let stew = Note::builder()
+        .title("Stew")
+        .tag("food")
+        .content(&content)
+        .resource(Resource::new(b"GIF89a", "image/gif"))
+        .build();
+    let mut writer = JexWriter::new(Vec::new());
+    let export = ExportInfo {
+        name: "Recipes".to_string(),
+        ..ExportInfo::default()
+    };
+    writer.begin(&export).unwrap();
+    writer.write_note(&soup, &EnmlOptions::default()).unwrap();
+    writer.write_note(&stew, &EnmlOptions::default()).unwrap();
+    writer.finish().unwrap();
+    let tar = writer.builder.into_inner().unwrap();
+
+    let mut files = HashMap::new();
+    for entry in tar::Archive::new(tar.as_slice()).entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        files.insert(path, data);
+    }
+    let items: Vec<String> = files
+        .iter()
+        .filter(|(path, _)| path.ends_with(".md"))
+        .map(|(_, data)| String::from_utf8(data.clone()).unwrap())
+        .collect();
+    let of_type = |item_type: u32| {
+        let type_line = format!("\ntype_: {}", item_type);
+        items
+            .iter()
+            .filter(|item| item.ends_with(&type_line))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(files[&format!("resources/{}.gif", hash)], b"GIF89a");
+    let folder = of_type(TYPE_FOLDER);
+    assert_eq!(folder.len(), 1);
+    assert!(folder[0].starts_with("Recipes\n\n"));
+    let notes = of_type(TYPE_NOTE);
+    assert_eq!(notes.len(), 2);
+    let soup = notes.iter().find(|item| item.starts_with("Soup")).unwrap();
+    assert!(soup.contains(&format!("](:/{})", hash)));
+    assert!(soup.contains(&format!("\nparent_id: {}\n", id("folder", "Recipes"))));
+    let resources = of_type(TYPE_RESOURCE);
+    assert_eq!(resources.len(), 1);
+    assert!(resources[0].contains(&format!("\nid: {}\nmime: image/gif\n", hash)));
+    let tags = of_type(TYPE_TAG);
+    assert_eq!(tags.len(), 1);
+    assert!(tags[0].starts_with("food\n\n"));
+    assert_eq!(of_type(TYPE_NOTE_TAG).len(), 2);
+}
@@ -0,0 +1,108 @@
+//! Newline-delimited JSON output: one object per note, for piping into jq and other tools.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use chrono::{DateTime, Local};
+use serde_json::{json, Map, Value};
+use std::io::Write;
+
+fn date(date: &Option<DateTime<Local>>) -> Value {
+    date.as_ref()
+        .map_or(Value::Null, |date| date.to_rfc3339().into())
+}
+
+/// Attributes that are set, so notes without them stay short.
+fn attributes(note: &Note) -> Value {
+    let attributes = &note.attributes;
+    let mut object = Map::new();
+    let strings = [
+        ("author", &attributes.author),
+        ("source_url", &attributes.source_url),
+        ("source", &attributes.source),
+        ("latitude", &attributes.latitude),
+        ("longitude", &attributes.longitude),
+        ("altitude", &attributes.altitude),
+        ("reminder_order", &attributes.reminder_order),
+        ("content_class", &attributes.content_class),
+    ];
+    for (key, value) in strings.iter() {
+        if let Some(value) = value {
+            object.insert(key.to_string(), value.as_str().into());
+        }
+    }
+    let dates = [
+        ("subject_date", &attributes.subject_date),
+        ("reminder_time", &attributes.reminder_time),
+        ("reminder_done_time", &attributes.reminder_done_time),
+    ];
+    for (key, value) in dates.iter() {
+        if value.is_some() {
+            object.insert(key.to_string(), date(value));
+        }
+    }
+    if !attributes.application_data.is_empty() {
+        object.insert(
+            "application_data".to_string(),
+            json!(attributes.application_data),
+        );
+    }
+    Value::Object(object)
+}
+
+/// Metadata of the resources of `note`; the data itself is left out.
+fn resources(note: &Note) -> Result<Value> {
+    let mut resources = Vec::new();
+    for resource in &note.resources {
+        let data = resource.decode()?;
+        resources.push(json!({
+            "hash": format!("{:x}", md5::compute(&data)),
+            "size": data.len(),
+            "mime": resource.mime,
+            "file_name": resource.file_name,
+            "source_url": resource.attributes.source_url,
+            "attachment": resource.attributes.attachment,
+            "width": resource.width,
+            "height": resource.height,
+        }));
+    }
+    Ok(Value::Array(resources))
+}
+
+/// Write `note` as a single line of JSON.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    let content = note.content_markdown(enml);
+    let value = json!({
+        "title": note.title,
+        "created": date(&note.created),
+        "updated": date(&note.updated),
+        "tags": note.tags,
+        "attributes": attributes(note),
+        "content": content,
+        "resources": resources(note)?,
+    });
+    writeln!(writer, "{}", value)?;
+    Ok(())
+}
+
+#[test]
+fn test_write_note() {
+    let note = Note {
+        title: Some("Hello".to_string()),
+        content: Some("<en-note><div>world</div></en-note>".to_string()),
+        tags: vec!["a".to_string()],
+        ..Note::default()
+    };
+    let mut out = Vec::new();
+    write_note(&mut out, &note, &EnmlOptions::default()).unwrap();
+    let value: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(value["title"], "Hello");
+    assert_eq!(value["content"], "world");
+    assert_eq!(value["tags"], json!(["a"]));
+    assert_eq!(value["attributes"], json!({}));
+    assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 1);
+}
@@ -0,0 +1,100 @@
+//! Convert Evernote exports to Markdown for MindForger and other note apps.
+//!
+//! [`EnexParser`] reads the notes of a `.enex` export. [`Note::content_markdown`] converts their
+//! content, and the format modules write them, e.g. [`mindforger::write_note`]. Each format also
+//! has a [`NoteWriter`], to choose the format at runtime. Attachments are written with
+//! [`attachments`]. The `enex2mf` binary is a command line around these.
+//!
+//! [`outline::read_notes`] reads Markdown back into notes, which [`export::EnexWriter`] writes
+//! as an export, as the `mf2enex` binary does.
+//!
+//! ```
+//! use enex2mf::enml::EnmlOptions;
+//! use enex2mf::mindforger::{self, MetadataOptions};
+//! use enex2mf::EnexParser;
+//!
+//! # fn main() -> enex2mf::Result<()> {
+//! let enex: &[u8] = b"<en-export><note><title>Hello</title>\
+//!     <content><![CDATA[<en-note><b>world</b></en-note>]]></content></note></en-export>";
+//! let mut markdown = Vec::new();
+//! for note in EnexParser::new(enex) {
+//!     let note = note?;
+//!     let enml = EnmlOptions::default();
+//!     mindforger::write_note(&mut markdown, &note, &enml, &MetadataOptions::default())?;
+//! }
+//! assert!(String::from_utf8(markdown).unwrap().contains("**world**"));
+//! # Ok(())
+//! # }
+//! ```
+
+// Some unwrapping of Option<String> with default values is awkward:
+//     opt_string.as_ref().map_or("default", String::as_str);
+// https://github.com/rust-lang/rust/issues/50264 will allow:
+//     opt_string.deref().unwrap_or("untitled");
+//
+// August is a plaintext alternative to html2md. https://gitlab.com/alantrick/august/
+// `text` renders plain text from the converted Markdown instead, so content is converted once.
+
+// UnexpectedEvent carries a whole XmlEvent; errors are rare enough that the size doesn't matter.
+#![allow(clippy::result_large_err)]
+
+pub mod asciidoc;
+#[cfg(feature = "tokio")]
+pub mod async_enex;
+pub mod attachments;
+pub mod bear;
+mod cdata;
+mod code;
+pub mod convert;
+mod crypt;
+pub mod dates;
+mod decode;
+pub mod enex;
+pub mod enml;
+pub mod error;
+#[cfg(feature = "evernote")]
+pub mod evernote;
+pub mod export;
+pub mod frontmatter;
+pub mod html;
+pub mod images;
+pub mod jex;
+pub mod json;
+pub mod links;
+mod lists;
+pub mod logseq;
+pub mod markdown;
+pub mod mindforger;
+pub mod naming;
+pub mod notion;
+pub mod obsidian;
+pub mod org;
+pub mod outline;
+// Shared with the binaries rather than part of the API.
+#[doc(hidden)]
+pub mod output;
+mod pull;
+#[cfg(feature = "quick-xml")]
+mod quick;
+pub mod quirks;
+mod render;
+pub mod sanitize;
+pub mod standardnotes;
+mod tables;
+pub mod tags;
+pub mod text;
+#[cfg(feature = "evernote")]
+mod thrift;
+pub mod tiddlywiki;
+pub mod validate;
+pub mod writer;
+pub mod zim;
+mod zip;
+
+#[cfg(feature = "tokio")]
+pub use crate::async_enex::AsyncEnexParser;
+pub use crate::convert::{convert, ConvertOptions, NotebookSummary, Summary};
+pub use crate::enex::{EnexParser, Limits, Note, NoteVisitor, Resource, Span};
+pub use crate::enml::{to_markdown as enml_to_markdown, EnmlOptions};
+pub use crate::error::{Error, Result};
+pub use crate::writer::{EnmlFn, ExportInfo, Format, NoteWriter};
@@ -0,0 +1,186 @@
+//! Points Evernote's links between notes (`evernote:///view/...`) at the converted notes.
+//!
+//! Exports don't include the GUIDs these links are made of, so notes are found by the link text,
+//! which Evernote sets to the title of the linked note.
+
+use crate::attachments::relative_path;
+use crate::enml::{is_wiki_target, link_target, wiki_alias, EnmlOptions};
+use html2md::anchors::AnchorHandler;
+use html2md::common::get_tag_attr;
+use html2md::{Handle, NodeData, StructuredPrinter, TagHandler, TagHandlerFactory};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/// Where the converted notes are, by title.
+#[derive(Debug, Default)]
+pub struct NoteIndex {
+    paths: HashMap<String, PathBuf>,
+    /// For links whose text was retyped in another case.
+    lowercase: HashMap<String, PathBuf>,
+    unresolved: Mutex<BTreeSet<String>>,
+}
+
+impl NoteIndex {
+    /// Add the note titled `title`, written to `path`. Of notes with the same title, links go to
+    /// the first.
+    pub fn insert(&mut self, title: &str, path: PathBuf) {
+        self.lowercase
+            .entry(title.to_lowercase())
+            .or_insert_with(|| path.clone());
+        self.paths.entry(title.to_string()).or_insert(path);
+    }
+
+    /// Where the note titled `title` is.
+    pub fn get(&self, title: &str) -> Option<&Path> {
+        self.paths
+            .get(title)
+            .or_else(|| self.lowercase.get(&title.to_lowercase()))
+            .map(PathBuf::as_path)
+    }
+
+    /// Titles of linked notes that weren't found, so far.
+    pub fn unresolved(&self) -> Vec<String> {
+        let unresolved = self.unresolved.lock().unwrap_or_else(|e| e.into_inner());
+        unresolved.iter().cloned().collect()
+    }
+}
+
+/// How to link to other notes from the note being converted.
+#[derive(Clone, Debug)]
+pub struct NoteLinks {
+    pub index: Arc<NoteIndex>,
+    /// Directory that links are relative to, usually the one the note is written to.
+    pub relative_to: PathBuf,
+}
+
+/// Whether `url` links to a note in Evernote, e.g. from "Copy Note Link".
+pub fn is_note_link(url: &str) -> bool {
+    url.starts_with("evernote:///view/")
+        || (url.starts_with("https://www.evernote.com/shard/") && url.contains("/nl/"))
+}
+
+pub struct LinkHandlerFactory {
+    pub options: Rc<EnmlOptions>,
+}
+
+impl TagHandlerFactory for LinkHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(LinkHandler {
+            options: self.options.clone(),
+            start: 0,
+            target: None,
+            anchor: AnchorHandler::default(),
+        })
+    }
+}
+
+/// Replaces links to notes that were converted with links to their files. Other links, including
+/// those to notes that weren't, are left to html2md.
+struct LinkHandler {
+    options: Rc<EnmlOptions>,
+    start: usize,
+    /// Where the linked note is, if it was found.
+    target: Option<PathBuf>,
+    anchor: AnchorHandler,
+}
+
+impl TagHandler for LinkHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        let href = get_tag_attr(tag, "href").unwrap_or_default();
+        let links = match self.options.note_links {
+            Some(ref links) if is_note_link(&href) => links,
+            _ => return self.anchor.handle(tag, printer),
+        };
+        let mut title = String::new();
+        text(tag, &mut title);
+        let title = title.trim();
+        match links.index.get(title) {
+            Some(path) => {
+                self.target = Some(relative_path(path, &links.relative_to).unwrap_or(path.into()));
+                self.start = printer.data.len();
+            }
+            None => {
+                let mut unresolved = links
+                    .index
+                    .unresolved
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                unresolved.insert(title.to_string());
+                self.anchor.handle(tag, printer);
+            }
+        }
+    }
+
+    fn after_handle(&mut self, printer: &mut StructuredPrinter) {
+        let target = match self.target.take() {
+            Some(target) => target,
+            None => return self.anchor.after_handle(printer),
+        };
+        let stem = target.with_extension("");
+        let stem = stem.to_string_lossy();
+        if self.options.wiki_links && is_wiki_target(&stem) {
+            let text = wiki_alias(&printer.data.split_off(self.start));
+            if text == stem {
+                printer.append_str(&format!("[[{}]]", text));
+            } else {
+                printer.append_str(&format!("[[{}|{}]]", stem, text));
+            }
+        } else {
+            let target = link_target(&target.to_string_lossy());
+            printer.insert_str(self.start, "[");
+            printer.append_str(&format!("]({})", target));
+        }
+    }
+}
+
+/// Append all the text within `node` to `text`.
+fn text(node: &Handle, text: &mut String) {
+    for child in node.children.borrow().iter() {
+        match child.data {
+            NodeData::Text { ref contents } => text.push_str(&contents.borrow()),
+            _ => self::text(child, text),
+        }
+    }
+}
+
+#[test]
+fn test_note_links() {
+    use crate::enml::handlers;
+
+    let mut index = NoteIndex::default();
+    index.insert("Other note", PathBuf::from("out/nb/Other note.md"));
+    let mut options = EnmlOptions {
+        note_links: Some(NoteLinks {
+            index: Arc::new(index),
+            relative_to: PathBuf::from("out/nb"),
+        }),
+        ..EnmlOptions::default()
+    };
+    let content = "<en-note>See <a href=\"evernote:///view/1/s1/abc/abc/\"><span>other note</span></a>, \
+        <a href=\"evernote:///view/1/s1/def/def/\">Gone</a> and <a href=\"https://example.com\">x</a></en-note>";
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(
+        md,
+        "See [other note](Other%20note.md), [Gone](evernote:///view/1/s1/def/def/) and \
+         [x](https://example.com)"
+    );
+    let index = &options.note_links.as_ref().unwrap().index;
+    assert_eq!(index.unresolved(), ["Gone"]);
+
+    options.wiki_links = true;
+    let content = "<en-note><a href=\"evernote:///view/1/s1/abc/abc/\">Other note</a></en-note>";
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[[Other note]]");
+
+    // Wiki links can't have `]` or `|` in their text, nor `#` in their target.
+    let mut index = NoteIndex::default();
+    index.insert("[1] | x", PathBuf::from("out/nb/One x.md"));
+    index.insert("C# notes", PathBuf::from("out/nb/C# notes.md"));
+    options.note_links.as_mut().unwrap().index = Arc::new(index);
+    let content = "<en-note><a href=\"evernote:///view/1/s1/abc/abc/\">[1] | x</a> \
+        <a href=\"evernote:///view/1/s1/abc/abc/\">C# notes</a></en-note>";
+    let md = html2md::parse_html_custom(content, &handlers(&options));
+    assert_eq!(md, "[[One x|(1) - x]] [C# notes](C%23%20notes.md)");
+}
@@ -0,0 +1,195 @@
+//! Converts ENML lists to Markdown lists, in place of html2md's conversion, which loses nesting
+//! in the lists Evernote writes and restarts numbering.
+//!
+//! Evernote nests a list directly in its parent list rather than in an item, e.g.
+//! `<ul><li>a</li><ul><li>b</li></ul></ul>`, so such lists are indented under the item before
+//! them. Ordered lists keep their `start` and items their `value`. Items are kept tight, even
+//! though Evernote wraps each in a `<div>`.
+
+use html2md::common::get_tag_attr;
+use html2md::{Handle, NodeData, StructuredPrinter, TagHandler, TagHandlerFactory};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A list being converted.
+struct List {
+    ordered: bool,
+    /// Number of the next item.
+    next: i64,
+    /// Width of the last item's marker, which its content and any sublists are indented by.
+    indent: usize,
+}
+
+/// The lists being converted, innermost last, shared by the handlers of one conversion.
+#[derive(Clone, Default)]
+pub struct Lists(Rc<RefCell<Vec<List>>>);
+
+pub struct ListHandlerFactory {
+    pub lists: Lists,
+}
+
+impl TagHandlerFactory for ListHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(ListHandler {
+            lists: self.lists.clone(),
+            start: 0,
+        })
+    }
+}
+
+pub struct ListItemHandlerFactory {
+    pub lists: Lists,
+}
+
+impl TagHandlerFactory for ListItemHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(ListItemHandler {
+            lists: self.lists.clone(),
+            start: None,
+        })
+    }
+}
+
+fn is_list(tag: &str) -> bool {
+    matches!(tag, "ul" | "ol" | "menu")
+}
+
+/// Handles `<ul>`, `<ol>` and `<menu>`.
+struct ListHandler {
+    lists: Lists,
+    start: usize,
+}
+
+impl TagHandler for ListHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        let ordered =
+            matches!(tag.data, NodeData::Element { ref name, .. } if &*name.local == "ol");
+        let next = get_tag_attr(tag, "start")
+            .and_then(|start| start.trim().parse().ok())
+            .unwrap_or(1);
+        let mut lists = self.lists.0.borrow_mut();
+        if lists.is_empty() {
+            printer.insert_newline();
+            printer.insert_newline();
+        } else {
+            // Right below the item's text, even if that was a paragraph.
+            let end = printer.data.trim_end_matches('\n').len();
+            printer.data.truncate(end);
+            printer.insert_newline();
+        }
+        lists.push(List {
+            ordered,
+            next,
+            indent: 0,
+        });
+        self.start = printer.data.len();
+    }
+
+    fn after_handle(&mut self, printer: &mut StructuredPrinter) {
+        let mut lists = self.lists.0.borrow_mut();
+        lists.pop();
+        let nested_in_list = printer
+            .parent_chain
+            .last()
+            .is_some_and(|parent| is_list(parent));
+        match lists.last() {
+            Some(parent) if nested_in_list => {
+                // Belongs to the item before it, or failing that, to the start of the list.
+                let indent = if parent.indent > 0 { parent.indent } else { 2 };
+                indent_lines(printer, self.start, indent);
+            }
+            _ => {}
+        }
+        if lists.is_empty() {
+            printer.insert_newline();
+            printer.insert_newline();
+        } else if !printer.data.ends_with('\n') {
+            printer.insert_newline();
+        }
+    }
+}
+
+/// Handles `<li>`.
+struct ListItemHandler {
+    lists: Lists,
+    /// Where the content starts and how far it's indented, unless outside a list.
+    start: Option<(usize, usize)>,
+}
+
+impl TagHandler for ListItemHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        let mut lists = self.lists.0.borrow_mut();
+        let list = match lists.last_mut() {
+            Some(list) => list,
+            None => return,
+        };
+        let marker = if list.ordered {
+            if let Some(value) = get_tag_attr(tag, "value").and_then(|v| v.trim().parse().ok()) {
+                list.next = value;
+            }
+            list.next += 1;
+            format!("{}. ", list.next - 1)
+        } else {
+            "* ".to_string()
+        };
+        list.indent = marker.len();
+        if !printer.data.is_empty() && !printer.data.ends_with('\n') {
+            printer.insert_newline();
+        }
+        printer.append_str(&marker);
+        self.start = Some((printer.data.len(), marker.len()));
+    }
+
+    fn after_handle(&mut self, printer: &mut StructuredPrinter) {
+        let (start, indent) = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+        // The content of a <div> or <p> comes with blank lines around it.
+        let leading = printer.data[start..].len() - printer.data[start..].trim_start().len();
+        printer.data.replace_range(start..start + leading, "");
+        let content_end = printer.data.trim_end().len().max(start);
+        printer.data.truncate(content_end);
+        indent_lines(printer, start, indent);
+        printer.insert_newline();
+    }
+}
+
+/// Indent the lines of `printer` after `start` by `indent` spaces, except blank ones.
+fn indent_lines(printer: &mut StructuredPrinter, start: usize, indent: usize) {
+    let text = &printer.data[start..];
+    let mut indented = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            indented.push('\n');
+            if !line.trim().is_empty() {
+                indented.push_str(&" ".repeat(indent));
+            }
+        } else if start > 0 && printer.data[..start].ends_with('\n') && !line.is_empty() {
+            indented.push_str(&" ".repeat(indent));
+        }
+        indented.push_str(line);
+    }
+    printer.data.replace_range(start.., &indented);
+}
+
+#[test]
+fn test_lists() {
+    use crate::enml::{handlers, EnmlOptions};
+
+    let content = "<en-note><div>Intro</div>\
+        <ul><li><div>one</div></li><li><div>two</div>\
+        <ul><li><div>two a</div><ol start=\"3\"><li>x</li><li>y<ul><li>z</li></ul></li></ol></li>\
+        <li>two b</li></ul></li><li>three</li></ul>\
+        <ul><li>Evernote</li><ul><li>nested in ul</li></ul><li>back</li></ul>\
+        <ol><li>first</li><ol><li>inner</li></ol><li>second</li></ol><div>After</div></en-note>";
+    let md = html2md::parse_html_custom(content, &handlers(&EnmlOptions::default()));
+    assert_eq!(
+        md,
+        "Intro\n\n\
+         * one\n* two\n  * two a\n    3. x\n    4. y\n       * z\n  * two b\n* three\n\n\
+         * Evernote\n  * nested in ul\n* back\n\n\
+         1. first\n   1. inner\n2. second\n\n\
+         After"
+    );
+}
@@ -0,0 +1,156 @@
+//! Logseq graph output: a page per note in `pages/`, with attachments in `assets/`. Logseq is an
+//! outliner, so each paragraph becomes a top-level block.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use std::io::Write;
+
+/// Pages go in this folder of the graph.
+pub const PAGES_DIR: &str = "pages";
+/// Attachments go in this folder of the graph.
+pub const ASSETS_DIR: &str = "assets";
+
+/// The indentation, marker and text of a list item's line, e.g. `  1. text`.
+fn item(line: &str) -> Option<(usize, &str, &str)> {
+    let text = line.trim_start();
+    let indent = line.len() - text.len();
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let marker = match text[digits..].chars().next() {
+        Some('.' | ')') if digits > 0 => digits + 1,
+        Some('-' | '*' | '+') if digits == 0 => 1,
+        _ => return None,
+    };
+    match text[marker..].strip_prefix(' ') {
+        Some(rest) => Some((indent, &text[..marker], rest)),
+        None if text.len() == marker => Some((indent, &text[..marker], "")),
+        None => None,
+    }
+}
+
+/// Whether `block` is a list: it starts with an item, and its other lines are items or
+/// indented.
+fn is_list(block: &str) -> bool {
+    block.lines().enumerate().all(|(i, line)| match item(line) {
+        Some((indent, _, _)) => i > 0 || indent == 0,
+        None => i > 0 && line.starts_with(' '),
+    })
+}
+
+/// A list as Logseq blocks: items bulleted with `-` and indented two spaces a level. Numbered
+/// items keep their numbering as Logseq's `logseq.order-list-type` property.
+fn to_blocks(list: &str) -> String {
+    // The indentation of the markers of the items we're in, and where their text starts.
+    let mut items: Vec<(usize, usize)> = Vec::new();
+    let mut blocks = Vec::new();
+    for line in list.lines() {
+        if let Some((indent, marker, text)) = item(line) {
+            while items.last().is_some_and(|&(outer, _)| outer >= indent) {
+                items.pop();
+            }
+            let prefix = "  ".repeat(items.len());
+            blocks.push(format!("{}- {}", prefix, text));
+            if marker.ends_with(['.', ')']) {
+                blocks.push(format!("{}  logseq.order-list-type:: number", prefix));
+            }
+            items.push((indent, indent + marker.len() + 1));
+        } else {
+            let text_start = items.last().map_or(0, |&(_, start)| start);
+            let spaces = line.len() - line.trim_start_matches(' ').len();
+            let prefix = "  ".repeat(items.len());
+            blocks.push(format!("{}{}", prefix, &line[spaces.min(text_start)..]));
+        }
+    }
+    blocks.join("\n")
+}
+
+/// Turn Markdown into Logseq blocks: each paragraph (or other block separated by blank lines) is
+/// a bullet, with its continuation lines indented. Lists are blocks already, once bulleted
+/// with `-`.
+pub fn to_outline(markdown: &str) -> String {
+    // Blank lines inside fenced code don't separate blocks.
+    let mut blocks: Vec<String> = Vec::new();
+    let mut block = String::new();
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if line.trim().is_empty() && !in_fence {
+            if !block.is_empty() {
+                blocks.push(block);
+                block = String::new();
+            }
+            continue;
+        }
+        if !block.is_empty() {
+            block.push('\n');
+        }
+        block.push_str(line);
+    }
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+
+    let mut outline = Vec::new();
+    for block in blocks {
+        if is_list(&block) {
+            outline.push(to_blocks(&block));
+        } else {
+            outline.push(format!("- {}", block.replace('\n', "\n  ")));
+        }
+    }
+    outline.join("\n")
+}
+
+/// Write a single note as a Logseq page, with its metadata as page properties.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    if let Some(ref title) = note.title {
+        writeln!(writer, "title:: {}", title)?;
+    }
+    if !note.tags.is_empty() {
+        writeln!(writer, "tags:: {}", note.tags.join(", "))?;
+    }
+    if let Some(ref created) = note.created {
+        writeln!(writer, "created:: {}", created.format("%F"))?;
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        writeln!(writer, "source:: {}", source_url)?;
+    }
+    writeln!(writer)?;
+
+    let content = note.content_markdown(enml);
+    writeln!(writer, "{}", to_outline(&content))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_to_outline() {
+    let markdown = "Hello\nworld\n\n- a\n  - b\n\n```\nx\n\ny\n```";
+    assert_eq!(
+        to_outline(markdown),
+        "- Hello\n  world\n- a\n  - b\n- ```\n  x\n  \n  y\n  ```"
+    );
+
+    let note = Note::builder()
+        .content(
+            "<en-note><ol><li>first</li><li>second</li><ul><li>kept</li></ul></ol>\
+             <div>*not a list</div></en-note>",
+        )
+        .build();
+    let markdown = note.content_markdown(&EnmlOptions::default());
+    assert_eq!(
+        to_outline(&markdown),
+        "- first\n  logseq.order-list-type:: number\n\
+         - second\n  logseq.order-list-type:: number\n  - kept\n- \\*not a list"
+    );
+    assert_eq!(
+        to_outline("* a\n  continued\n    + b\n10) c"),
+        "- a\n  continued\n  - b\n- c\n  logseq.order-list-type:: number"
+    );
+}
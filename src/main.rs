@@ -1,54 +1,63 @@
-// Some unwrapping of Option<String> with default values is awkward:
-//     opt_string.as_ref().map_or("default", String::as_str);
-// https://github.com/rust-lang/rust/issues/50264 will allow:
-//     opt_string.deref().unwrap_or("untitled");
-//
-// August is a plaintext alternative to html2md. https://gitlab.com/alantrick/august/
-
-mod enex;
-mod error;
-
-use crate::enex::{EnexParser, Note};
-use crate::error::Result;
-use html2md::parse_html;
-use pulldown_cmark::{html, Parser};
-use std::ffi::OsStr;
-use std::fs::File;
-use std::io::{stdout, BufReader, Write};
-use std::path::Path;
-use std::str;
-
-/// Write a single note in MindForger-compatible Markdown.
-fn write_as_mf<W: Write>(writer: &mut W, note: &Note) -> Result<()> {
-    let title = note.title.as_ref().map_or("untitled", String::as_str);
-    write!(writer, "# {} <!-- Metadata: type: Note; ", title)?;
-    if !note.tags.is_empty() {
-        write!(writer, "tags: {}; ", note.tags.join(","))?;
-    }
-    if let Some(ref created) = note.created {
-        write!(writer, "created: {}; ", created.format("%F %T"))?;
-    }
-    // Awkward to avoid moving refs.
-    if let Some(modified) = note.updated.as_ref().or_else(|| note.created.as_ref()) {
-        write!(writer, "modified: {}; ", modified.format("%F %T"))?;
-    }
-    writeln!(writer, "-->\n")?;
-    if let Some(ref from) = note.attributes.source_url {
-        writeln!(writer, "From {}\n", from)?;
-    }
+// UnexpectedEvent carries a whole XmlEvent; errors are rare enough that the size doesn't matter.
+#![allow(clippy::result_large_err)]
 
-    let content_md = parse_html(&note.content.as_ref().map_or("", String::as_str));
-    writeln!(writer, "{}", content_md.trim().replace("\\-", "-"))?;
-    writeln!(writer)?;
+mod config;
+mod git;
+mod mapped;
+mod progress;
 
-    Ok(())
-}
+use crate::config::Config;
+use crate::mapped::MappedFile;
+use crate::progress::{CountingReader, Progress};
+use chrono::{DateTime, Local};
+use clap::{crate_version, value_t, App, Arg, ArgGroup, ArgMatches, ErrorKind};
+use enex2mf::attachments::{
+    AlternateData, AttachmentArchive, AttachmentLayout, AttachmentNames, AttachmentNaming,
+    AttachmentOptions,
+};
+use enex2mf::convert::{self, MissingDates, OnError};
+use enex2mf::dates::{self, DateOptions};
+use enex2mf::enml::{self, BodyMode, EnmlOptions, HighlightMode, Hooks, OcrMode};
+#[cfg(feature = "evernote")]
+use enex2mf::evernote;
+use enex2mf::frontmatter::MetadataStyle;
+#[cfg(feature = "images")]
+use enex2mf::images::Shrink;
+use enex2mf::links::NoteIndex;
+use enex2mf::mindforger::{self, LocationLink, MetadataOptions, TagLevels};
+use enex2mf::naming::{self, Duplicates, NameStyle, UniqueNames};
+use enex2mf::output::SafeFile;
+use enex2mf::sanitize;
+use enex2mf::tags::TagMap;
+use enex2mf::validate;
+use enex2mf::writer::{NoteFiles, FORMATS};
+use enex2mf::zim::ZimWriter;
+use enex2mf::Result;
+use enex2mf::{asciidoc, bear, html, logseq, markdown, obsidian, org, text, tiddlywiki};
+use enex2mf::{ConvertOptions, EnexParser, Error, ExportInfo, Format, Limits, Note, NoteWriter};
+use enex2mf::{NotebookSummary, Summary};
+use log::{debug, error, info, warn, Level, LevelFilter};
+use pulldown_cmark::Parser;
+use rayon::prelude::*;
+use regex::Regex;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, stdin, stdout, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 // TODO this is only for development
-fn write_sxs<W: Write>(
+#[allow(dead_code)]
+fn write_sxs<W: Write + ?Sized>(
     writer: &mut W,
     notes: Vec<Note>,
-) -> std::result::Result<(), Box<std::error::Error>> {
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
     writeln!(writer, "<meta charset=utf-8><style>.html, .md {{ display: inline-block; width: 49%; margin: 0; vertical-align: top; overflow-x: hidden }} x.md {{ font-size: 130% }}</style>")?;
     writeln!(writer, "<br>")?;
     for note in notes {
@@ -56,45 +65,1794 @@ fn write_sxs<W: Write>(
             writer,
             "<div class=html><h1>{}</h1>{}</div>",
             note.title.as_ref().map_or("untitled", String::as_str),
-            note.content.as_ref().map_or("", String::as_str)
+            sanitize::sanitize(note.content.as_ref().map_or("", String::as_str))
         )?;
-        // Some web clip notes have unterminated <div>
-        for _ in 0..30 {
-            write!(writer, "</div>")?;
-        }
         // writeln!(writer, "<pre class=md>")?;
         let mut md = Vec::new();
-        write_as_mf(&mut md, &note)?;
+        let metadata = MetadataOptions::default();
+        mindforger::write_note(&mut md, &note, &EnmlOptions::default(), &metadata)?;
         let mut md_html = String::new();
-        html::push_html(&mut md_html, Parser::new(str::from_utf8(&md)?));
+        pulldown_cmark::html::push_html(&mut md_html, Parser::new(str::from_utf8(&md)?));
         writeln!(writer, "<div class=md>{}</div>", md_html)?;
         // writeln!(writer, "</pre>")?;
     }
     Ok(())
 }
 
-fn main() -> std::result::Result<(), Box<std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let input_path = match &args[..] {
-        [_, input_path] => input_path,
-        _ => panic!("Usage: enex2mf input.enex"),
+struct Options {
+    /// Format, attachments, filtering and the other options shared with the library.
+    convert: ConvertOptions,
+    /// File to write to instead of stdout, for formats that write a single stream.
+    output: Option<PathBuf>,
+    /// Overwrite `output` if it exists.
+    force: bool,
+    output_dir: Option<PathBuf>,
+    /// MindForger repository to write outlines into.
+    mf_repo: Option<PathBuf>,
+    /// Commit what was written to its git repository.
+    git_commit: bool,
+    /// How to name the files written to `output_dir`.
+    file_names: NameStyle,
+    /// How to tell apart files of notes with the same title.
+    duplicates: Duplicates,
+    /// How many times `--verbose` was given.
+    verbose: u64,
+    /// Treat all inputs as a single notebook.
+    merge: bool,
+    /// List the notes instead of converting them.
+    dry_run: bool,
+    /// Check the inputs against the DTD instead of converting them.
+    validate: bool,
+    /// Write out the output after each note rather than when the buffer is full.
+    flush_each_note: bool,
+    /// Read input files through a memory map.
+    mmap: bool,
+    /// How many threads to convert with, or the number of CPUs.
+    jobs: Option<usize>,
+    /// Don't show progress or warnings.
+    quiet: bool,
+    /// Warnings from reading and converting notes so far, for the summary.
+    warnings: Mutex<Vec<String>>,
+    /// Where to fetch `evernote:` inputs from.
+    #[cfg(feature = "evernote")]
+    evernote_host: String,
+    #[cfg(feature = "evernote")]
+    evernote_token: Option<String>,
+}
+
+impl Options {
+    /// Options from the command line, falling back to `config` for those not given.
+    fn from_matches(matches: &ArgMatches, config: Config) -> Options {
+        let verbose = matches.occurrences_of("verbose");
+        let mf_repo = match matches.value_of_os("mf-repo") {
+            Some(dir) => Some(PathBuf::from(dir)),
+            None if matches.is_present("output") || matches.is_present("output-dir") => None,
+            // The config's repository is only for MindForger outlines.
+            None if matches.is_present("format") => None,
+            None if config
+                .format
+                .is_some_and(|format| format != Format::MindForger) =>
+            {
+                None
+            }
+            None => config.mf_repo.clone(),
+        };
+        let file_names = match matches.value_of("file-names") {
+            Some("preserve-spaces") => NameStyle::PreserveSpaces,
+            Some("ascii") => NameStyle::Ascii,
+            Some("kebab") => NameStyle::Kebab,
+            _ => config.file_names.unwrap_or_default(),
+        };
+        let convert = ConvertOptions {
+            format: match matches.value_of("format") {
+                _ if mf_repo.is_some() => Format::MindForger,
+                Some(format) => Format::from_name(format).unwrap_or_default(),
+                None => config.format.unwrap_or_default(),
+            },
+            name: matches.value_of("name").map(String::from),
+            metadata: MetadataOptions {
+                reminders: matches.is_present("reminders") || config.reminders,
+                application_data: matches.is_present("application-data") || config.application_data,
+                // Set by `main`, which can report errors.
+                dates: DateOptions::default(),
+                style: match matches.value_of("metadata") {
+                    Some("native") => MetadataStyle::Native,
+                    Some("frontmatter") => MetadataStyle::Frontmatter,
+                    Some("both") => MetadataStyle::Both,
+                    _ => config.metadata.unwrap_or_default(),
+                },
+                importance: tag_levels(matches, "importance", &config.importance),
+                urgency: tag_levels(matches, "urgency", &config.urgency),
+                author: matches.is_present("author") || config.author,
+                location: match matches.value_of("location") {
+                    Some("osm") => Some(LocationLink::Osm),
+                    Some("geo") => Some(LocationLink::Geo),
+                    _ => config.location,
+                },
+            },
+            attachments: AttachmentOptions {
+                dir: matches
+                    .value_of_os("attachments")
+                    .map(PathBuf::from)
+                    .or(config.attachments),
+                inline_images: matches.is_present("inline-images") || config.inline_images,
+                links_relative_to: None,
+                alternate_data: match matches.value_of("alternate-data") {
+                    Some("prefer") => AlternateData::Prefer,
+                    Some("both") => AlternateData::Both,
+                    Some(_) => AlternateData::Ignore,
+                    None => config.alternate_data.unwrap_or_default(),
+                },
+                names: match matches.value_of("attachment-names") {
+                    Some("original") => Some(AttachmentNames::new(file_names)),
+                    Some(_) => None,
+                    None => match config.attachment_names.unwrap_or_default() {
+                        AttachmentNaming::Original => Some(AttachmentNames::new(file_names)),
+                        AttachmentNaming::Hash => None,
+                    },
+                },
+                #[cfg(feature = "images")]
+                shrink: value_t!(matches, "shrink-images", u32)
+                    .ok()
+                    .or(config.shrink_images)
+                    .map(Shrink::new),
+                #[cfg(not(feature = "images"))]
+                shrink: None,
+                archive: matches
+                    .value_of_os("attachments-zip")
+                    .map(PathBuf::from)
+                    .or(config.attachments_zip)
+                    .map(AttachmentArchive::new),
+                layout: match matches.value_of("attachment-layout") {
+                    Some("shared") => AttachmentLayout::Shared,
+                    Some("per-note") => AttachmentLayout::PerNote,
+                    Some("date") => AttachmentLayout::Date,
+                    _ => config.attachment_layout.unwrap_or_default(),
+                },
+            },
+            ocr: match matches.value_of("ocr") {
+                Some("text") => OcrMode::Text,
+                Some("comment") => OcrMode::Comment,
+                _ => config.ocr.unwrap_or_default(),
+            },
+            highlight: match matches.value_of("highlight") {
+                Some("off") => Some(HighlightMode::Off),
+                Some("equals") => Some(HighlightMode::Equals),
+                Some("mark") => Some(HighlightMode::Mark),
+                Some("bold") => Some(HighlightMode::Bold),
+                _ => config.highlight,
+            },
+            body: match matches.value_of("body") {
+                Some("markdown") => BodyMode::Markdown,
+                Some("raw-html") => BodyMode::RawHtml,
+                _ => config.body.unwrap_or_default(),
+            },
+            passphrase: matches.value_of("encrypt-passphrase").map(String::from),
+            lenient: matches.is_present("lenient") || config.lenient,
+            no_quirks: matches.is_present("no-quirks") || config.no_quirks,
+            memory_limit: matches
+                .value_of("memory-limit")
+                .map(|mb| megabytes(mb).expect("validated by clap")),
+            skip_resources: matches.is_present("skip-resources") || config.skip_resources,
+            verify_hashes: matches.is_present("verify-hashes") || config.verify_hashes,
+            limits: limits(matches),
+            tags: matches
+                .values_of("tag")
+                .map(|tags| tags.map(String::from).collect())
+                .unwrap_or(config.tags),
+            // Compiled by `main`, which can report errors.
+            title_match: None,
+            title_exclude: None,
+            // Read by `main`, which can report errors.
+            tag_map: TagMap::default(),
+            tag_separators: matches
+                .value_of("tag-hierarchy")
+                .or(config.tag_hierarchy.as_deref())
+                .map(|separators| separators.chars().collect())
+                .unwrap_or_default(),
+            // Set once the output is known, by `index_notes`.
+            note_index: None,
+            hooks: Hooks::default(),
+            // Set by `main`, which can report errors.
+            missing_dates: MissingDates::default(),
+            on_error: match matches.value_of("on-error") {
+                Some("fail") => OnError::Fail,
+                Some("skip") => OnError::Skip,
+                Some("stub") => OnError::Stub,
+                _ => config.on_error.unwrap_or_default(),
+            },
+            // An empty placeholder leaves empty notes blank.
+            empty_placeholder: Some(
+                matches
+                    .value_of("empty-placeholder")
+                    .map(String::from)
+                    .or(config.empty_placeholder)
+                    .unwrap_or_else(|| convert::EMPTY_PLACEHOLDER.to_string()),
+            )
+            .filter(|text| !text.is_empty()),
+            max_image_width: value_t!(matches, "max-image-width", u32)
+                .ok()
+                .or(config.max_image_width),
+        };
+        Options {
+            convert,
+            output: matches.value_of_os("output").map(PathBuf::from),
+            force: matches.is_present("force"),
+            output_dir: match matches.value_of_os("output-dir") {
+                Some(dir) => Some(PathBuf::from(dir)),
+                None if matches.is_present("output") => None,
+                None if mf_repo.is_some() => None,
+                None => config.output_dir,
+            },
+            mf_repo,
+            git_commit: matches.is_present("git-commit") || config.git_commit,
+            file_names,
+            duplicates: match matches.value_of("duplicates") {
+                Some("counter") => Duplicates::Counter,
+                Some("date") => Duplicates::Date,
+                Some("hash") => Duplicates::Hash,
+                _ => config.duplicates.unwrap_or_default(),
+            },
+            verbose,
+            merge: matches.is_present("merge") || config.merge,
+            dry_run: matches.is_present("dry-run"),
+            validate: matches.is_present("validate"),
+            flush_each_note: matches.value_of("flush") == Some("note"),
+            mmap: matches.is_present("mmap"),
+            jobs: value_t!(matches, "jobs", usize).ok(),
+            quiet: matches.is_present("quiet") || (config.quiet && verbose == 0),
+            warnings: Mutex::default(),
+            #[cfg(feature = "evernote")]
+            evernote_host: match matches
+                .value_of("evernote-host")
+                .or(config.evernote_host.as_deref())
+            {
+                Some("evernote") | None => evernote::EVERNOTE_HOST.to_string(),
+                Some("yinxiang") => evernote::YINXIANG_HOST.to_string(),
+                Some("sandbox") => evernote::SANDBOX_HOST.to_string(),
+                Some(host) => host.to_string(),
+            },
+            #[cfg(feature = "evernote")]
+            evernote_token: matches
+                .value_of("evernote-token")
+                .map(String::from)
+                .or(config.evernote_token),
+        }
+    }
+
+    /// Add `warnings` to those for the summary.
+    fn warn(&self, warnings: impl IntoIterator<Item = String>) {
+        let mut all = self.warnings.lock().unwrap_or_else(|e| e.into_inner());
+        all.extend(warnings);
+    }
+
+    /// What to log on stderr: warnings by default, more with `-v`/`-vv`, only errors with `-q`.
+    fn log_level(&self) -> LevelFilter {
+        match self.verbose {
+            _ if self.quiet => LevelFilter::Error,
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    /// Whether to show a progress line for each input: not with `--verbose` or `--quiet`, or when
+    /// stderr isn't a terminal.
+    fn show_progress(&self) -> bool {
+        self.log_level() == LevelFilter::Warn && io::stderr().is_terminal()
+    }
+
+    /// Names for note files, none taken yet.
+    fn names(&self) -> UniqueNames {
+        UniqueNames::new(self.file_names)
+            .duplicates(self.duplicates)
+            .underscores(self.convert.format == Format::Zim)
+            .wiki_links(self.convert.format == Format::Obsidian)
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("enex2mf")
+        .version(crate_version!())
+        .about("Converts Evernote exports to Markdown for MindForger and other note apps.")
+        .arg(
+            Arg::with_name("input")
+                .required(true)
+                .multiple(true)
+                .value_name("INPUT.enex")
+                .help("Evernote exports to convert, one per notebook, or - for stdin"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .short("f")
+                .value_name("FORMAT")
+                .possible_values(FORMATS)
+                .help("Output format [default: mindforger]"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .value_name("FILE")
+                .conflicts_with("output-dir")
+                .help("Write to FILE instead of stdout"),
+        )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .requires("destination")
+                .help("Overwrite the --output file, or outlines in the --mf-repo, if they exist"),
+        )
+        .arg(
+            Arg::with_name("output-dir")
+                .long("output-dir")
+                .value_name("DIR")
+                .help("Write each note to its own file in DIR (the vault or graph for obsidian and logseq)"),
+        )
+        .arg(
+            Arg::with_name("mf-repo")
+                .long("mf-repo")
+                .value_name("DIR")
+                .conflicts_with("output-dir")
+                .help("Write each notebook as an outline into the MindForger repository DIR, with its attachments"),
+        )
+        .group(ArgGroup::with_name("destination").args(&["output", "mf-repo"]))
+        .arg(
+            Arg::with_name("git-commit")
+                .long("git-commit")
+                .help("Commit the files written to the git repository they're in, saying what was converted"),
+        )
+        .arg(
+            Arg::with_name("file-names")
+                .long("file-names")
+                .value_name("STYLE")
+                .possible_values(&["preserve-spaces", "ascii", "kebab"])
+                .help("Name files after titles as they are, in ASCII, or as lowercase-words [default: preserve-spaces]"),
+        )
+        .arg(
+            Arg::with_name("duplicates")
+                .long("duplicates")
+                .value_name("SUFFIX")
+                .possible_values(&["counter", "date", "hash"])
+                .help("Tell apart files of notes with the same title by a counter, the creation date or a short hash [default: counter]"),
+        )
+        .arg(
+            Arg::with_name("attachments")
+                .long("attachments")
+                .value_name("DIR")
+                .help("Extract attachments into DIR and link to them"),
+        )
+        .arg(
+            Arg::with_name("attachments-zip")
+                .long("attachments-zip")
+                .value_name("FILE")
+                .help("Collect attachments in the zip archive FILE, with a manifest, rather than extract them"),
+        )
+        .arg(
+            Arg::with_name("inline-images")
+                .long("inline-images")
+                .help("Embed images as data: URIs"),
+        )
+        .arg(
+            Arg::with_name("alternate-data")
+                .long("alternate-data")
+                .value_name("MODE")
+                .possible_values(&["ignore", "prefer", "both"])
+                .help("Extract the alternate form some attachments have instead of them, or as well"),
+        )
+        .arg(
+            Arg::with_name("attachment-names")
+                .long("attachment-names")
+                .value_name("NAMES")
+                .possible_values(&["hash", "original"])
+                .help("Name extracted attachments by hash, or after their file name or note [default: hash]"),
+        )
+        .arg(
+            Arg::with_name("attachment-layout")
+                .long("attachment-layout")
+                .value_name("LAYOUT")
+                .possible_values(&["shared", "per-note", "date"])
+                .help("Extract attachments into one directory, a folder per note or folders by date [default: shared]"),
+        )
+        .arg(
+            Arg::with_name("max-image-width")
+                .long("max-image-width")
+                .value_name("PX")
+                .validator(|n| n.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Show images wider than PX pixels at that width"),
+        )
+        .arg(
+            Arg::with_name("ocr")
+                .long("ocr")
+                .value_name("MODE")
+                .possible_values(&["text", "comment"])
+                .help("Include text recognized in images, as a paragraph or a hidden comment"),
+        )
+        .arg(
+            Arg::with_name("highlight")
+                .long("highlight")
+                .value_name("MODE")
+                .possible_values(&["off", "equals", "mark", "bold"])
+                .help("Mark highlighted text as ==text==, <mark>text</mark> or **text** [default: per format]"),
+        )
+        .arg(
+            Arg::with_name("body")
+                .long("body")
+                .value_name("MODE")
+                .possible_values(&["markdown", "raw-html"])
+                .help("Convert note content to Markdown, or keep it as cleaned-up HTML [default: markdown]"),
+        )
+        .arg(
+            Arg::with_name("metadata")
+                .long("metadata")
+                .value_name("STYLE")
+                .possible_values(&["native", "frontmatter", "both"])
+                .help("Write note metadata as the format does, as YAML frontmatter, or both [default: native]"),
+        )
+        .arg(
+            Arg::with_name("missing-dates")
+                .long("missing-dates")
+                .value_name("DATE")
+                .help("Date notes without a creation or update time: keep them undated, or use the export date (or else the file's modification time), the file's modification time (file) or a DATE like 2018-12-19 [default: export]"),
+        )
+        .arg(
+            Arg::with_name("date-format")
+                .long("date-format")
+                .value_name("FORMAT")
+                .help("Write times in metadata as plain, iso8601, rfc3339 or a strftime FORMAT [default: plain, which MindForger reads]"),
+        )
+        .arg(
+            Arg::with_name("timezone")
+                .long("timezone")
+                .value_name("ZONE")
+                .help("Write times in metadata in ZONE: local, utc, an offset like +05:30 or a name like Europe/Paris [default: local]"),
+        )
+        .arg(
+            Arg::with_name("importance")
+                .long("importance")
+                .value_name("TAG=LEVEL")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(tag_level)
+                .help("Give notes tagged TAG an importance of LEVEL out of 5 in MindForger; may be repeated"),
+        )
+        .arg(
+            Arg::with_name("urgency")
+                .long("urgency")
+                .value_name("TAG=LEVEL")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(tag_level)
+                .help("Give notes tagged TAG an urgency of LEVEL out of 5 in MindForger; may be repeated"),
+        )
+        .arg(
+            Arg::with_name("author")
+                .long("author")
+                .help("Include the author in note metadata"),
+        )
+        .arg(
+            Arg::with_name("location")
+                .long("location")
+                .value_name("LINK")
+                .possible_values(&["osm", "geo"])
+                .help("Link where notes were taken to OpenStreetMap or as a geo: URI, below the title"),
+        )
+        .arg(
+            Arg::with_name("reminders")
+                .long("reminders")
+                .help("Include reminder times in note metadata"),
+        )
+        .arg(
+            Arg::with_name("application-data")
+                .long("application-data")
+                .help("Include data attached by other apps in note metadata"),
+        )
+        .arg(
+            Arg::with_name("lenient")
+                .long("lenient")
+                .help("Skip unknown elements and invalid times with a warning instead of failing"),
+        )
+        .arg(
+            Arg::with_name("no-quirks")
+                .long("no-quirks")
+                .help("Read exports from apps other than Evernote as strictly as Evernote's"),
+        )
+        .arg(
+            Arg::with_name("flush")
+                .long("flush")
+                .value_name("WHEN")
+                .possible_values(&["end", "note"])
+                .help("Write out the output after each note, or only as the buffer fills [default: end]"),
+        )
+        .arg(
+            Arg::with_name("mmap")
+                .long("mmap")
+                .help("Read input files through a memory map, which is faster for large files"),
+        )
+        .arg(
+            Arg::with_name("on-error")
+                .long("on-error")
+                .value_name("ACTION")
+                .possible_values(&["fail", "skip", "stub"])
+                .help("When a note can't be read, stop, skip it, or write a note saying why in its place, exiting with status 2 at the end [default: fail]"),
+        )
+        .arg(
+            Arg::with_name("empty-placeholder")
+                .long("empty-placeholder")
+                .value_name("TEXT")
+                .help(
+                    "Write TEXT as the body of notes with no content, or nothing if it's empty \
+                     [default: This note is empty.]",
+                ),
+        )
+        .arg(
+            Arg::with_name("skip-resources")
+                .long("skip-resources")
+                .conflicts_with_all(&["attachments", "attachments-zip", "inline-images"])
+                .help("Skip attachments without reading their data, converting only text"),
+        )
+        .arg(
+            Arg::with_name("verify-hashes")
+                .long("verify-hashes")
+                .conflicts_with("skip-resources")
+                .help("Warn about attachments whose MD5 hash doesn't match what notes refer to"),
+        )
+        .arg(
+            Arg::with_name("memory-limit")
+                .long("memory-limit")
+                .value_name("MB")
+                .validator(|mb| megabytes(&mb).map(|_| ()))
+                .help("Fail on note content larger than MB, and keep larger attachments on disk"),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .value_name("N")
+                .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Fail on elements nested deeper than N, in the export or note content [default: 1000]"),
+        )
+        .arg(
+            Arg::with_name("max-text-size")
+                .long("max-text-size")
+                .value_name("MB")
+                .validator(|mb| megabytes(&mb).map(|_| ()))
+                .help("Fail on text, e.g. an attachment's data, larger than MB [default: 1024]"),
+        )
+        .arg(
+            Arg::with_name("max-entity-expansion")
+                .long("max-entity-expansion")
+                .value_name("N")
+                .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Fail on entities declared in the export expanding to more than N characters [default: 1000000]"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .long("jobs")
+                .short("j")
+                .value_name("N")
+                .validator(|n| match n.parse::<usize>() {
+                    Ok(0) => Err("must be at least 1".to_string()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.to_string()),
+                })
+                .help("Convert with N threads [default: the number of CPUs]"),
+        )
+        .arg(
+            Arg::with_name("encrypt-passphrase")
+                .long("encrypt-passphrase")
+                .value_name("PASSPHRASE")
+                .help("Decrypt encrypted sections with PASSPHRASE"),
+        )
+        .arg(
+            Arg::with_name("merge")
+                .long("merge")
+                .help("Combine all inputs into a single notebook"),
+        )
+        .arg(
+            Arg::with_name("name")
+                .long("name")
+                .value_name("NAME")
+                .help("Name of the notebook, when there is only one"),
+        )
+        .arg(
+            Arg::with_name("tag")
+                .long("tag")
+                .value_name("TAG")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only convert notes tagged TAG; may be repeated to allow several tags"),
+        )
+        .arg(
+            Arg::with_name("title-match")
+                .long("title-match")
+                .value_name("REGEX")
+                .help("Only convert notes whose titles match REGEX"),
+        )
+        .arg(
+            Arg::with_name("title-exclude")
+                .long("title-exclude")
+                .value_name("REGEX")
+                .help("Don't convert notes whose titles match REGEX"),
+        )
+        .arg(
+            Arg::with_name("tag-map")
+                .long("tag-map")
+                .value_name("FILE")
+                .help("Rename, merge or drop tags as the TOML file FILE says"),
+        )
+        .arg(
+            Arg::with_name("tag-hierarchy")
+                .long("tag-hierarchy")
+                .value_name("SEPARATORS")
+                .help("Treat tags split by any of SEPARATORS, e.g. / or :, as nested and write them as a/b"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read default options from FILE instead of enex2mf.toml"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .short("n")
+                .help("List the notes that would be converted without writing anything"),
+        )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .help(
+                    "Check the inputs against the rules of Evernote's DTD and list what breaks \
+                     them, without converting; exits with status 2 if anything does",
+                ),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .long("verbose")
+                .short("v")
+                .multiple(true)
+                .help("Report each note as it is converted; twice for timing and other details"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .conflicts_with("verbose")
+                .help("Don't show progress or warnings"),
+        )
+        .args(&evernote_args())
+        .args(&images_args())
+}
+
+/// The options for fetching notes from the Evernote API, with the `evernote` feature.
+#[cfg(feature = "evernote")]
+fn evernote_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("evernote-token")
+            .long("evernote-token")
+            .value_name("TOKEN")
+            .env("EVERNOTE_TOKEN")
+            .hide_env_values(true)
+            .help(
+                "Fetch the inputs evernote:NOTEBOOK, or evernote: for all notebooks, from the \
+                 Evernote API with this developer or OAuth token",
+            ),
+        Arg::with_name("evernote-host")
+            .long("evernote-host")
+            .value_name("HOST")
+            .help("Evernote's service to fetch from: evernote, yinxiang, sandbox or a host name"),
+    ]
+}
+
+#[cfg(not(feature = "evernote"))]
+fn evernote_args() -> Vec<Arg<'static, 'static>> {
+    Vec::new()
+}
+
+#[cfg(feature = "images")]
+fn images_args() -> Vec<Arg<'static, 'static>> {
+    vec![Arg::with_name("shrink-images")
+        .long("shrink-images")
+        .value_name("PX")
+        .validator(|n| match n.parse::<u32>() {
+            Ok(0) => Err("must be at least 1".to_string()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        })
+        .help("Downscale extracted images to fit in PX pixels, and convert BMPs to PNG")]
+}
+
+#[cfg(not(feature = "images"))]
+fn images_args() -> Vec<Arg<'static, 'static>> {
+    Vec::new()
+}
+
+fn main() {
+    let matches = app().get_matches();
+    let config_path = matches.value_of_os("config").map(Path::new);
+    let config = match config::load(config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            let path = config_path.unwrap_or_else(|| Path::new(config::DEFAULT_PATH));
+            let message = format!("{}: {}", path.display(), err);
+            clap::Error::with_description(&message, ErrorKind::InvalidValue).exit()
+        }
+    };
+    let tag_map = matches
+        .value_of_os("tag-map")
+        .map(PathBuf::from)
+        .or_else(|| config.tag_map.clone());
+    let title_match = matches
+        .value_of("title-match")
+        .map(String::from)
+        .or_else(|| config.title_match.clone());
+    let title_exclude = matches
+        .value_of("title-exclude")
+        .map(String::from)
+        .or_else(|| config.title_exclude.clone());
+    let missing_dates = matches
+        .value_of("missing-dates")
+        .map(String::from)
+        .or_else(|| config.missing_dates.clone());
+    let date_format = matches
+        .value_of("date-format")
+        .map(String::from)
+        .or_else(|| config.date_format.clone());
+    let timezone = matches
+        .value_of("timezone")
+        .map(String::from)
+        .or_else(|| config.timezone.clone());
+    let mut options = Options::from_matches(&matches, config);
+    if let Some(missing) = missing_dates {
+        options.convert.missing_dates = match MissingDates::parse(&missing) {
+            Some(missing) => missing,
+            None => {
+                let message = format!("Invalid date {}", missing);
+                clap::Error::with_description(&message, ErrorKind::InvalidValue).exit()
+            }
+        };
+    }
+    if let Some(format) = date_format {
+        options.convert.metadata.dates.format = match dates::parse_format(&format) {
+            Some(format) => format,
+            None => {
+                let message = format!("Invalid date format {}", format);
+                clap::Error::with_description(&message, ErrorKind::InvalidValue).exit()
+            }
+        };
+    }
+    if let Some(zone) = timezone {
+        options.convert.metadata.dates.zone = match dates::parse_zone(&zone) {
+            Some(zone) => zone,
+            None => {
+                let message = format!("Unknown time zone {}", zone);
+                clap::Error::with_description(&message, ErrorKind::InvalidValue).exit()
+            }
+        };
+    }
+    let title_regex = |pattern: String| match Regex::new(&pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            let message = format!("Invalid title pattern {}: {}", pattern, err);
+            clap::Error::with_description(&message, ErrorKind::InvalidValue).exit()
+        }
+    };
+    options.convert.title_match = title_match.map(title_regex);
+    options.convert.title_exclude = title_exclude.map(title_regex);
+    if let Some(path) = tag_map {
+        options.convert.tag_map = match TagMap::load(&path) {
+            Ok(tag_map) => tag_map,
+            Err(err) => {
+                let message = format!("{}: {}", path.display(), err);
+                clap::Error::with_description(&message, ErrorKind::InvalidValue).exit()
+            }
+        };
+    }
+    init_logging(options.log_level());
+    if let Some(jobs) = options.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .expect("the thread pool is only built once");
+    }
+    let inputs: Vec<String> = matches
+        .values_of("input")
+        .expect("input is required")
+        .map(String::from)
+        .collect();
+    let inputs = expand_globs(inputs);
+    #[cfg(feature = "evernote")]
+    let inputs = evernote_inputs(inputs, &options);
+    let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+    if options.validate {
+        match validate(&inputs, &options.convert.limits) {
+            Ok(0) => return,
+            Ok(_) => process::exit(2),
+            Err(err) => {
+                eprintln!("enex2mf: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+    if matches!(
+        options.convert.format,
+        Format::Obsidian | Format::Logseq | Format::Bear | Format::Zim
+    ) && options.output_dir.is_none()
+    {
+        let message = "--output-dir is required for this format";
+        clap::Error::with_description(message, ErrorKind::MissingRequiredArgument).exit()
+    }
+    let single_document = matches!(
+        options.convert.format,
+        Format::Html | Format::Org | Format::AsciiDoc
+    );
+    if inputs.len() > 1 && !options.merge && options.output_dir.is_none() && single_document {
+        let message = "Multiple inputs need --merge or --output-dir for this format";
+        clap::Error::with_description(message, ErrorKind::ArgumentConflict).exit()
+    }
+    // Checked here, since a conflict in clap would reach --output through their group.
+    if matches.is_present("mf-repo") && matches.is_present("format") {
+        let message = "--mf-repo only writes MindForger outlines, not another --format";
+        clap::Error::with_description(message, ErrorKind::ArgumentConflict).exit()
+    }
+    if let Some(ref repo) = options.mf_repo {
+        let empty = repo
+            .read_dir()
+            .map_or(true, |mut entries| entries.next().is_none());
+        if !repo.join(MEMORY_DIR).is_dir() && !empty {
+            let message = format!("{} isn't a MindForger repository", repo.display());
+            clap::Error::with_description(&message, ErrorKind::InvalidValue).exit()
+        }
+    }
+    if let Some(ref path) = options.output {
+        if path.exists() && !options.force {
+            let message = format!("{} exists; use --force to overwrite it", path.display());
+            clap::Error::with_description(&message, ErrorKind::ArgumentConflict).exit()
+        }
+    }
+    let written = options.output.is_some() || options.output_dir.is_some();
+    if options.git_commit && !written && options.mf_repo.is_none() {
+        let message = "--git-commit needs --output, --output-dir or --mf-repo";
+        clap::Error::with_description(message, ErrorKind::MissingRequiredArgument).exit()
+    }
+    options.convert.note_index = index_notes(&inputs, &options).map(Arc::new);
+    let result = run(&inputs, &options).and_then(|summary| {
+        if let Some(ref archive) = options.convert.attachments.archive {
+            let files = archive.finish()?;
+            info!(
+                "archived {} in {}",
+                plural(files, "file"),
+                archive.path().display()
+            );
+        }
+        if options.git_commit && !options.dry_run {
+            commit(&summary, &options)?;
+        }
+        Ok(summary)
+    });
+    match result {
+        // The rest of the pipeline stopped reading, e.g. `enex2mf - | head`.
+        Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(err) => {
+            eprintln!("enex2mf: {}", err);
+            process::exit(1);
+        }
+        // Some notes weren't converted, with `--on-error skip` or `stub`.
+        Ok(summary) if !summary.failed.is_empty() => process::exit(2),
+        Ok(_) => {}
+    }
+}
+
+/// Log to stderr at `verbosity`, or as configured by `RUST_LOG` if set. Dependencies only log
+/// warnings, since html5ever is chatty at debug level.
+fn init_logging(verbosity: LevelFilter) {
+    let mut builder = env_logger::Builder::new();
+    builder
+        .filter_level(verbosity.min(LevelFilter::Warn))
+        .filter_module(module_path!(), verbosity)
+        .format(|buf, record| {
+            let level = match record.level() {
+                Level::Warn => "warning".to_string(),
+                level => level.to_string().to_lowercase(),
+            };
+            writeln!(buf, "{}: {}", level, record.args())
+        });
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    builder.init();
+}
+
+/// `inputs` with patterns like `*.enex` replaced by the files they match, in order of their
+/// paths, for shells that leave them to the program. Existing files, invalid patterns and
+/// patterns matching nothing are kept as they are.
+fn expand_globs(inputs: Vec<String>) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for input in inputs {
+        let paths = match glob::glob(&input) {
+            Ok(paths) if input.contains(['*', '?', '[']) && !Path::new(&input).exists() => paths,
+            _ => {
+                expanded.push(input);
+                continue;
+            }
+        };
+        let matches: Vec<String> = paths
+            .filter_map(|path| path.ok())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        if matches.is_empty() {
+            expanded.push(input);
+        } else {
+            expanded.extend(matches);
+        }
+    }
+    expanded
+}
+
+/// Inputs naming a notebook to fetch from the Evernote API, or all of them if nothing follows.
+#[cfg(feature = "evernote")]
+const EVERNOTE_PREFIX: &str = "evernote:";
+
+/// `inputs` with `evernote:` replaced by each notebook of the account. Exits if there are
+/// notebooks to fetch but no token, or the notebooks can't be listed.
+#[cfg(feature = "evernote")]
+fn evernote_inputs(inputs: Vec<String>, options: &Options) -> Vec<String> {
+    if !inputs
+        .iter()
+        .any(|input| input.starts_with(EVERNOTE_PREFIX))
+    {
+        return inputs;
+    }
+    let token = match options.evernote_token {
+        Some(ref token) => token,
+        None => {
+            let message = "--evernote-token is required to fetch evernote: inputs";
+            clap::Error::with_description(message, ErrorKind::MissingRequiredArgument).exit()
+        }
+    };
+    if !inputs.iter().any(|input| input == EVERNOTE_PREFIX) {
+        return inputs;
+    }
+    let notebooks = evernote::Client::connect(&options.evernote_host, token)
+        .and_then(|mut client| client.notebooks());
+    let notebooks = match notebooks {
+        Ok(notebooks) => notebooks,
+        Err(err) => {
+            eprintln!("enex2mf: {}", err);
+            process::exit(1);
+        }
+    };
+    let mut expanded = Vec::new();
+    for input in inputs {
+        if input == EVERNOTE_PREFIX {
+            let names = notebooks.iter().map(|notebook| &notebook.name);
+            expanded.extend(names.map(|name| format!("{}{}", EVERNOTE_PREFIX, name)));
+        } else {
+            expanded.push(input);
+        }
+    }
+    expanded
+}
+
+type Notes<'a> = Box<dyn Iterator<Item = Result<Note>> + 'a>;
+
+/// The notes of an input, or of all inputs with `--merge`.
+struct Notebook<'a> {
+    info: ExportInfo,
+    notes: Notes<'a>,
+}
+
+/// Open an input, `-` meaning stdin. The notebook is named after the file, or for stdin after
+/// the export date. Progress is shown on stderr if `show_progress`.
+fn open<'a>(input_path: &str, options: &'a Options, show_progress: bool) -> Result<Notebook<'a>> {
+    #[cfg(feature = "evernote")]
+    if let Some(name) = input_path.strip_prefix(EVERNOTE_PREFIX) {
+        return open_evernote(name, options, show_progress);
+    }
+    if input_path == "-" {
+        let (reader, read) = CountingReader::new(stdin());
+        let mut parser = options
+            .convert
+            .parser(BufReader::new(reader))
+            .metadata_only(options.dry_run);
+        parser.start()?;
+        let info = ExportInfo::from_parser(&parser);
+        let progress = Progress::new("stdin", read, None);
+        return Ok(Notebook {
+            info: info.clone(),
+            notes: notes(
+                parsed(parser, options),
+                info,
+                progress,
+                show_progress,
+                options,
+            ),
+        });
+    }
+
+    match File::open(input_path) {
+        Ok(file) => {
+            let total = file.metadata().ok().map(|metadata| metadata.len());
+            let file_modified = file_modified(&file);
+            let file: Box<dyn Read> = if options.mmap {
+                Box::new(MappedFile::new(&file)?)
+            } else {
+                Box::new(BufReader::new(file))
+            };
+            let (reader, read) = CountingReader::new(file);
+            let mut parser = options
+                .convert
+                .parser(reader)
+                .metadata_only(options.dry_run);
+            parser.start()?;
+            let info = ExportInfo {
+                name: notebook_name(input_path),
+                file_modified,
+                ..ExportInfo::from_parser(&parser)
+            };
+            let progress = Progress::new(input_path, read, total);
+            Ok(Notebook {
+                info: info.clone(),
+                notes: notes(
+                    parsed(parser, options),
+                    info,
+                    progress,
+                    show_progress,
+                    options,
+                ),
+            })
+        }
+        Err(err) => {
+            let message = format!("Can't open {}: {}", input_path, err);
+            clap::Error::with_description(&message, ErrorKind::Io).exit()
+        }
+    }
+}
+
+/// Fetch the notebook `name` from the Evernote API, dated now as if it were exported now.
+#[cfg(feature = "evernote")]
+fn open_evernote<'a>(
+    name: &str,
+    options: &'a Options,
+    show_progress: bool,
+) -> Result<Notebook<'a>> {
+    let token = options.evernote_token.as_deref().expect("checked by main");
+    let mut client =
+        evernote::Client::connect(&options.evernote_host, token)?.metadata_only(options.dry_run);
+    let notebook = client.notebook(name)?;
+    let info = ExportInfo {
+        name: notebook.name.clone(),
+        application: Some(options.evernote_host.clone()),
+        ..ExportInfo::from_date(Some(Local::now()))
+    };
+    let progress = Progress::new(&notebook.name, client.received(), None);
+    Ok(Notebook {
+        info: info.clone(),
+        notes: notes(
+            client.notes(&notebook)?,
+            info,
+            progress,
+            show_progress,
+            options,
+        ),
+    })
+}
+
+/// When `file` was last modified, for `--missing-dates file`.
+fn file_modified(file: &File) -> Option<DateTime<Local>> {
+    let modified = file.metadata().and_then(|metadata| metadata.modified());
+    modified.ok().map(DateTime::from)
+}
+
+/// The `--max-*` limits, or the defaults for those not given.
+fn limits(matches: &ArgMatches) -> Limits {
+    let defaults = Limits::default();
+    Limits {
+        depth: value_t!(matches, "max-depth", usize).unwrap_or(defaults.depth),
+        text_len: matches
+            .value_of("max-text-size")
+            .map_or(defaults.text_len, |mb| {
+                megabytes(mb).expect("validated by clap")
+            }),
+        entity_expansion: value_t!(matches, "max-entity-expansion", usize)
+            .unwrap_or(defaults.entity_expansion),
+    }
+}
+
+/// The `--importance` or `--urgency` rules named `name`, after those in the config.
+fn tag_levels(matches: &ArgMatches, name: &str, config: &HashMap<String, u8>) -> TagLevels {
+    let mut levels = TagLevels::default();
+    for (tag, &level) in config {
+        levels.insert(tag, level);
+    }
+    for rule in matches.values_of(name).into_iter().flatten() {
+        let (tag, level) = TagLevels::parse_rule(rule).expect("validated by clap");
+        levels.insert(tag, level);
+    }
+    levels
+}
+
+/// The number of bytes in `mb` megabytes.
+fn megabytes(mb: &str) -> std::result::Result<usize, String> {
+    let mb = mb.parse::<usize>().map_err(|e| e.to_string())?;
+    mb.checked_mul(1 << 20)
+        .ok_or_else(|| format!("{} MB is more than can be addressed", mb))
+}
+
+fn tag_level(rule: String) -> std::result::Result<(), String> {
+    match TagLevels::parse_rule(&rule) {
+        Some(_) => Ok(()),
+        None => Err("expected TAG=LEVEL, with LEVEL from 0 to 5".to_string()),
+    }
+}
+
+/// Whether each input is written to its own subdirectory of `--output-dir`.
+fn subdirs(inputs: &[&str], options: &Options) -> bool {
+    // Notebooks get their own directories, except in Logseq where all pages go together, and in
+    // Zim where they're namespaces of one notebook.
+    inputs.len() > 1
+        && !options.merge
+        && !matches!(options.convert.format, Format::Logseq | Format::Zim)
+}
+
+/// Where each note will be written with `--output-dir`, so that links between notes can point at
+/// their files. The inputs are read twice for this, so not stdin.
+fn index_notes(inputs: &[&str], options: &Options) -> Option<NoteIndex> {
+    let format = options.convert.format;
+    let dir = match options.output_dir {
+        // Zim links between pages by name, not file.
+        Some(ref dir) if format.writes_files() && format != Format::Zim && !options.dry_run => dir,
+        _ => return None,
+    };
+    if inputs.contains(&"-") {
+        return None;
+    }
+    let subdirs = subdirs(inputs, options);
+    let mut index = NoteIndex::default();
+    let mut names = options.names();
+    for input_path in inputs {
+        let notes_dir = if subdirs {
+            names = options.names();
+            notes_dir(
+                &dir.join(naming::slugify(
+                    &notebook_name(input_path),
+                    options.file_names,
+                )),
+                format,
+            )
+        } else {
+            notes_dir(dir, format)
+        };
+        // Errors are reported when converting.
+        let file = match File::open(input_path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        let file_modified = file_modified(&file);
+        let mut parser = options
+            .convert
+            .parser(BufReader::new(file))
+            .metadata_only(true);
+        if parser.start().is_err() {
+            continue;
+        }
+        let export = ExportInfo {
+            file_modified,
+            ..ExportInfo::from_parser(&parser)
+        };
+        for mut note in parser.filter_map(Result::ok) {
+            if options.convert.includes(&note) {
+                options.convert.prepare(&mut note, &export);
+                let name = names.note_file_name(&note, format.extension());
+                let title = note.title.as_deref().unwrap_or("untitled");
+                index.insert(title, notes_dir.join(name));
+            }
+        }
+    }
+    Some(index)
+}
+
+fn run(inputs: &[&str], options: &Options) -> Result<Summary> {
+    if let Some(ref dir) = options.output_dir {
+        if subdirs(inputs, options) && options.convert.format.writes_files() && !options.dry_run {
+            let mut summary = write_dirs(inputs, dir, options)?;
+            report(&mut summary, options);
+            return Ok(summary);
+        }
+    }
+
+    let mut notebooks = Vec::new();
+    for input_path in inputs {
+        notebooks.push(open(input_path, options, options.show_progress())?);
+    }
+    if options.merge {
+        let info = notebooks[0].info.clone();
+        let notes = notebooks.into_iter().flat_map(|notebook| notebook.notes);
+        notebooks = vec![Notebook {
+            info,
+            notes: Box::new(notes),
+        }];
+    }
+    if let (Some(name), [notebook]) = (&options.convert.name, &mut notebooks[..]) {
+        notebook.info.name = name.clone();
+    }
+
+    if options.dry_run {
+        dry_run(notebooks, options)?;
+        return Ok(Summary::default());
+    }
+
+    if let Some(ref repo) = options.mf_repo {
+        let mut summary = write_repo(notebooks, repo, options)?;
+        report(&mut summary, options);
+        return Ok(summary);
+    }
+    let mut summary = match options.output_dir {
+        Some(ref dir) if options.convert.format.writes_files() => {
+            let mut names = options.names();
+            let mut summary = Summary::default();
+            for notebook in notebooks {
+                if options.convert.format == Format::Zim {
+                    names = options.names();
+                }
+                summary.merge(write_dir(notebook, dir, &mut names, options)?);
+            }
+            summary
+        }
+        _ => match options.output {
+            Some(ref path) => {
+                let mut file = SafeFile::create(path)?;
+                let attachments = options.convert.stream_attachments();
+                let summary = write_stream(&mut file, notebooks, options, &attachments)?;
+                file.commit()?;
+                summary
+            }
+            None => {
+                let attachments = options.convert.stream_attachments();
+                write_stream(&mut stdout().lock(), notebooks, options, &attachments)?
+            }
+        },
     };
+    report(&mut summary, options);
+    Ok(summary)
+}
+
+/// Write each input to its own subdirectory of `dir`, converting several inputs at once. Their
+/// progress lines would overwrite each other, so only the combined summary is shown.
+fn write_dirs(inputs: &[&str], dir: &Path, options: &Options) -> Result<Summary> {
+    let summaries = inputs
+        .par_iter()
+        .map(|input_path| {
+            let notebook = open(input_path, options, false)?;
+            let name = notebook.info.name.clone();
+            let dir = dir.join(naming::slugify(&name, options.file_names));
+            let summary = write_dir(notebook, &dir, &mut options.names(), options)?;
+            info!("{}: {}", name, plural(summary.notes, "note"));
+            Ok(summary)
+        })
+        .collect::<Result<Vec<Summary>>>()?;
+    let mut summary = Summary::default();
+    for other in summaries {
+        summary.merge(other);
+    }
+    Ok(summary)
+}
 
-    let file = File::open(input_path)?;
-    let file = BufReader::new(file);
-    let parser = EnexParser::new(file);
+/// Report what was converted, where progress is shown or with `--verbose`, adding the links to
+/// notes that weren't converted to the warnings of `summary`.
+fn report(summary: &mut Summary, options: &Options) {
+    let mut line = format!(
+        "converted {} with {}",
+        plural(summary.notes, "note"),
+        plural(summary.attachments, "attachment")
+    );
+    if let Some(ref shrink) = options.convert.attachments.shrink {
+        let (images, saved) = shrink.saved();
+        if images > 0 {
+            line.push_str(&format!(
+                ", shrinking {} by {}",
+                plural(images as usize, "image"),
+                enml::human_size(saved)
+            ));
+        }
+    }
+    if options.show_progress() {
+        eprintln!("{}", line);
+    } else {
+        info!("{}", line);
+    }
+    if !summary.empty.is_empty() {
+        warn!(
+            "{} had no content: {}",
+            plural(summary.empty.len(), "note"),
+            summary.empty.join(", ")
+        );
+    }
+    if !summary.failed.is_empty() {
+        error!("{} couldn't be read", plural(summary.failed.len(), "note"));
+    }
+    if let Some(unresolved) = options.convert.unresolved_links() {
+        warn!("{}", unresolved);
+        summary.warnings.push(unresolved);
+    }
+}
+
+/// Commit the files written to their git repository, with `--git-commit`.
+fn commit(summary: &Summary, options: &Options) -> Result<()> {
+    let mut paths = Vec::new();
+    paths.extend(options.mf_repo.as_deref().map(|repo| repo.join(MEMORY_DIR)));
+    paths.extend(options.output_dir.clone());
+    paths.extend(options.output.clone());
+    paths.extend(options.convert.attachments.dir.clone());
+    if let Some(ref archive) = options.convert.attachments.archive {
+        paths.push(archive.path().to_owned());
+    }
+    let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    if git::commit(&paths, &git::message(summary))? {
+        info!("committed {}", plural(summary.notes, "note"));
+    } else {
+        info!("nothing changed to commit");
+    }
+    Ok(())
+}
+
+/// `n` and `noun`, with an "s" unless there's one.
+fn plural(n: usize, noun: &str) -> String {
+    match n {
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
+
+/// List the notes that would be converted, and the files they would be written to.
+fn dry_run(notebooks: Vec<Notebook>, options: &Options) -> Result<()> {
+    let writer = &mut stdout();
+    let files = options.output_dir.is_some() && options.convert.format.writes_files();
+    let mut names = options.names();
+    for notebook in notebooks {
+        writeln!(writer, "{}", notebook.info.name)?;
+        if options.convert.format != Format::Logseq {
+            names = options.names();
+        }
+        for note in notebook.notes {
+            let note = note?;
+            let title = note.title.as_deref().unwrap_or("untitled");
+            let date = note
+                .created
+                .map(|date| date.format("%F").to_string())
+                .unwrap_or_else(|| "-".repeat(10));
+            write!(writer, "  {}  {}", date, title)?;
+            if !note.tags.is_empty() {
+                write!(writer, "  [{}]", note.tags.join(", "))?;
+            }
+            match note.resources.len() {
+                0 => {}
+                1 => write!(writer, "  1 attachment")?,
+                n => write!(writer, "  {} attachments", n)?,
+            }
+            if files {
+                write!(
+                    writer,
+                    "  -> {}",
+                    names.note_file_name(&note, options.convert.format.extension())
+                )?;
+            }
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
 
+/// List where `inputs` break the rules of Evernote's DTD, returning how many violations there are.
+fn validate(inputs: &[&str], limits: &Limits) -> Result<usize> {
     let writer = &mut stdout();
+    let mut count = 0;
+    for &input_path in inputs {
+        let violations = if input_path == "-" {
+            validate::validate(BufReader::new(stdin()), limits)?
+        } else {
+            match File::open(input_path) {
+                Ok(file) => validate::validate(BufReader::new(file), limits)?,
+                Err(err) => {
+                    let message = format!("Can't open {}: {}", input_path, err);
+                    clap::Error::with_description(&message, ErrorKind::Io).exit()
+                }
+            }
+        };
+        for violation in &violations {
+            writeln!(writer, "{}:{}", input_path, violation)?;
+        }
+        info!("{}: {}", input_path, plural(violations.len(), "violation"));
+        count += violations.len();
+    }
+    Ok(count)
+}
+
+/// Write `notebooks` as a single stream, to stdout or `--output`.
+fn write_stream(
+    writer: &mut dyn Write,
+    notebooks: Vec<Notebook>,
+    options: &Options,
+    attachments: &AttachmentOptions,
+) -> Result<Summary> {
+    let mut writer = BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, writer);
+    let mut backend = options
+        .convert
+        .format
+        .stream_writer(&mut writer, options.convert.metadata.clone())
+        .expect("--output-dir is required");
+    let summary = write_notebooks(backend.as_mut(), notebooks, options, attachments)?;
+    drop(backend);
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// Write each notebook as an outline in the `memory` directory of the MindForger repository
+/// `repo`, creating the repository if there's none. Outlines are numbered rather than overwrite
+/// existing files, unless `--force` is given. Attachments go in a directory named after the
+/// outline, unless `--attachments` puts them elsewhere.
+fn write_repo(notebooks: Vec<Notebook>, repo: &Path, options: &Options) -> Result<Summary> {
+    let memory = repo.join(MEMORY_DIR);
+    if !memory.is_dir() {
+        for dir in REPO_DIRS {
+            fs::create_dir_all(repo.join(dir))?;
+        }
+    }
+    let mut names = options.names();
+    let mut summary = Summary::default();
+    for notebook in notebooks {
+        let mut file_name = names.file_name(&notebook.info.name, "md");
+        while memory.join(&file_name).exists() && !options.force {
+            file_name = names.file_name(&notebook.info.name, "md");
+        }
+        let path = memory.join(&file_name);
+        let stem = file_name.trim_end_matches(".md");
+        let attachments = AttachmentOptions {
+            dir: options
+                .convert
+                .attachments
+                .dir
+                .clone()
+                .or_else(|| Some(memory.join(stem))),
+            links_relative_to: Some(memory.clone()),
+            ..options.convert.attachments.clone()
+        };
+        let mut file = SafeFile::create(&path)?;
+        summary.merge(write_stream(
+            &mut file,
+            vec![notebook],
+            options,
+            &attachments,
+        )?);
+        file.commit()?;
+        info!("wrote {}", path.display());
+    }
+    Ok(summary)
+}
+
+/// Where the note files of a notebook written to `dir` go.
+fn notes_dir(dir: &Path, format: Format) -> PathBuf {
+    match format {
+        Format::Logseq => dir.join(logseq::PAGES_DIR),
+        _ => dir.to_owned(),
+    }
+}
+
+/// Write `notebook` to files in `dir`, one per note. Links to attachments are relative to the
+/// note files unless the format says otherwise, so they may go up into a sibling directory.
+fn write_dir(
+    notebook: Notebook,
+    dir: &Path,
+    names: &mut UniqueNames,
+    options: &Options,
+) -> Result<Summary> {
+    let (notes_dir, attachments) = match options.convert.format {
+        Format::Obsidian => {
+            let vault = options.output_dir.as_deref().unwrap_or(dir);
+            let attachments = AttachmentOptions {
+                dir: Some(vault.join(obsidian::ATTACHMENTS_DIR)),
+                // Obsidian resolves links from the root of the vault.
+                links_relative_to: Some(vault.to_owned()),
+                ..options.convert.attachments.clone()
+            };
+            (dir.to_owned(), attachments)
+        }
+        Format::Logseq => {
+            let attachments = AttachmentOptions {
+                dir: Some(dir.join(logseq::ASSETS_DIR)),
+                links_relative_to: None,
+                ..options.convert.attachments.clone()
+            };
+            (notes_dir(dir, Format::Logseq), attachments)
+        }
+        Format::Bear => {
+            // Bear imports images from files, not `data:` URIs.
+            let attachments = AttachmentOptions {
+                dir: Some(dir.join(bear::ASSETS_DIR)),
+                inline_images: false,
+                links_relative_to: None,
+                ..options.convert.attachments.clone()
+            };
+            (dir.to_owned(), attachments)
+        }
+        // Attachments go in each page's folder, which `ZimWriter` extracts them to.
+        Format::Zim => (dir.to_owned(), AttachmentOptions::default()),
+        _ => (dir.to_owned(), options.convert.attachments.clone()),
+    };
+    let attachments = AttachmentOptions {
+        links_relative_to: Some(
+            attachments
+                .links_relative_to
+                .clone()
+                .unwrap_or_else(|| notes_dir.clone()),
+        ),
+        ..attachments
+    };
+
+    let extension = options.convert.format.extension();
+    let mut backend: Box<dyn NoteWriter + '_> = match options.convert.format {
+        Format::MindForger => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            |file, note, enml| mindforger::write_note(file, note, enml, &options.convert.metadata),
+        )),
+        Format::Markdown => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            |file, note, enml| markdown::write_file(file, note, enml, &options.convert.metadata),
+        )),
+        Format::Obsidian => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            |file, note, enml| obsidian::write_note(file, note, enml, &options.convert.metadata),
+        )),
+        Format::Logseq => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            logseq::write_note,
+        )),
+        Format::Bear => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            bear::write_note,
+        )),
+        Format::Html => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            html::write_page,
+        )),
+        Format::TiddlyWiki => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            tiddlywiki::write_tid,
+        )),
+        Format::AsciiDoc => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            asciidoc::write_document,
+        )),
+        Format::Org => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            org::write_note,
+        )),
+        Format::Text => Box::new(NoteFiles::new(
+            &notes_dir,
+            extension,
+            names,
+            text::write_note,
+        )),
+        Format::Zim => Box::new(ZimWriter::new(
+            &notes_dir,
+            names,
+            options.convert.attachments.clone(),
+        )),
+        Format::Json | Format::Jex | Format::Notion | Format::StandardNotes => {
+            unreachable!("written as a single stream")
+        }
+    };
+    let summary = write_notebooks(backend.as_mut(), vec![notebook], options, &attachments)?;
+    drop(backend);
+    for (title, name) in names.take_renamed() {
+        info!("duplicate title {:?} written to {}", title, name);
+    }
+    Ok(summary)
+}
+
+/// The notes to convert, reported on stderr: each title if `--verbose` (with where it is in the
+/// export and the time taken to parse it at `-vv`), otherwise a progress line if `show_progress`.
+fn notes<'a>(
+    mut parsed: impl Iterator<Item = Result<Note>> + 'a,
+    export: ExportInfo,
+    mut progress: Progress,
+    show_progress: bool,
+    options: &'a Options,
+) -> Notes<'a> {
+    let timed = iter::from_fn(move || {
+        let start = Instant::now();
+        let note = parsed.next()?;
+        if let Ok(ref note) = note {
+            let title = note.title.as_deref().unwrap_or("untitled");
+            let span = note.span.map(|span| span.to_string()).unwrap_or_default();
+            debug!("parsed {} at {} in {:?}", title, span, start.elapsed());
+        }
+        Some(note)
+    });
+    let notes = timed
+        .filter(move |note| {
+            note.as_ref()
+                .map_or(true, |note| options.convert.includes(note))
+        })
+        .map(move |note| {
+            note.map(|mut note| {
+                options.convert.prepare(&mut note, &export);
+                note
+            })
+        })
+        .inspect(move |note| {
+            if let Ok(note) = note {
+                info!("{}", note.title.as_deref().unwrap_or("untitled"));
+                if show_progress {
+                    progress.note();
+                }
+            }
+        });
+    Box::new(notes)
+}
+
+/// The notes `parser` reads, with spans, passing its warnings on to `options` once it's done.
+fn parsed<'a, R: Read + 'a>(
+    parser: EnexParser<R>,
+    options: &'a Options,
+) -> impl Iterator<Item = Result<Note>> + 'a {
+    let mut parser = parser.spans(true);
+    iter::from_fn(move || {
+        let note = parser.next();
+        if note.is_none() {
+            options.warn(parser.warnings().iter().cloned());
+        }
+        note
+    })
+    .fuse()
+}
+
+/// Notebooks are named after the export file.
+fn notebook_name(input_path: &str) -> String {
     let notebook_name = Path::new(input_path)
         .file_stem()
         .map(OsStr::to_string_lossy);
     // Is it possible to get the &str from the Cow instead of Cow'ing the default value?
-    let notebook_name = notebook_name.unwrap_or_else(|| "unknown".into());
-    writeln!(writer, "# {} <!-- Metadata: type: Outline; created: 2018-12-19 11:13:04; reads: 9; read: 2018-12-19 17:39:29; revision: 9; modified: 2018-12-19 17:39:29; importance: 0/5; urgency: 0/5; -->", notebook_name)?;
-    // TODO dev only. write_sxs(writer, notes)?;
-    for note in parser {
-        write_as_mf(writer, &note?)?;
+    notebook_name.unwrap_or("unknown".into()).into_owned()
+}
+
+/// The directory of a MindForger repository that outlines go in.
+const MEMORY_DIR: &str = "memory";
+
+/// The directories MindForger creates for a new repository.
+const REPO_DIRS: &[&str] = &[MEMORY_DIR, "limbo", "stencils/outlines", "stencils/notes"];
+
+/// How many notes to convert at a time, in parallel when written to a file each.
+const BATCH_SIZE: usize = 64;
+
+/// Bytes of output to buffer before writing, unless flushing after each note.
+const OUTPUT_BUFFER_SIZE: usize = 1 << 16;
+
+/// Write `notebooks` with `backend`, extracting or inlining attachments per `attachments`.
+fn write_notebooks(
+    backend: &mut dyn NoteWriter,
+    notebooks: Vec<Notebook>,
+    options: &Options,
+    attachments: &AttachmentOptions,
+) -> Result<Summary> {
+    let enml = |note: &Note| {
+        options.warn(options.convert.verify(note)?);
+        options.convert.enml_options(note, attachments)
+    };
+    // With a memory limit, only hold one note at a time.
+    let batch_size = if options.convert.memory_limit.is_some() || options.flush_each_note {
+        1
+    } else {
+        BATCH_SIZE
+    };
+    let mut summary = Summary::default();
+    for notebook in notebooks {
+        backend.begin(&notebook.info)?;
+        let mut notes = notebook.notes;
+        let written = summary.notes;
+        // Notes that fail have no title to go by, so they're counted.
+        let mut position = 0;
+        loop {
+            // Notes parsed before an error are still written.
+            let mut batch = Vec::with_capacity(batch_size);
+            let mut error = None;
+            for note in notes.by_ref().take(batch_size) {
+                position += 1;
+                match (note, options.convert.on_error) {
+                    (Ok(mut note), _) => {
+                        if options.convert.fill_empty(&mut note) {
+                            let title = note.title.as_deref().unwrap_or("untitled");
+                            summary.empty.push(title.to_string());
+                        }
+                        batch.push(note);
+                    }
+                    (Err(e), OnError::Fail) => {
+                        error = Some(e);
+                        break;
+                    }
+                    (Err(e), on_error) => {
+                        let failure = format!("{}, note {}: {}", notebook.info.name, position, e);
+                        error!("{}", failure);
+                        summary.failed.push(failure);
+                        if on_error == OnError::Stub {
+                            batch.push(convert::stub_note(&e));
+                        }
+                    }
+                }
+            }
+            if batch.is_empty() && error.is_none() {
+                break;
+            }
+            backend.write_notes(&batch, &enml)?;
+            summary.notes += batch.len();
+            summary.attachments += batch.iter().map(|note| note.resources.len()).sum::<usize>();
+            if options.flush_each_note {
+                backend.flush()?;
+            }
+            if let Some(e) = error {
+                return Err(e);
+            }
+        }
+        summary.notebooks.push(NotebookSummary {
+            name: notebook.info.name,
+            export_date: notebook.info.export_date,
+            notes: summary.notes - written,
+        });
     }
+    backend.finish()?;
+    let mut warnings = options.warnings.lock().unwrap_or_else(|e| e.into_inner());
+    summary.warnings.append(&mut warnings);
+    Ok(summary)
+}
 
-    Ok(())
+#[test]
+fn test_expand_globs() {
+    let dir = env::temp_dir().join(format!("enex2mf-glob-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["b.enex", "a.enex", "c.txt"] {
+        File::create(dir.join(name)).unwrap();
+    }
+    let pattern = dir.join("*.enex").to_string_lossy().into_owned();
+    let inputs = vec![
+        pattern,
+        "-".to_string(),
+        "none*.enex".to_string(),
+        "[".to_string(),
+    ];
+    let expected = vec![
+        dir.join("a.enex").to_string_lossy().into_owned(),
+        dir.join("b.enex").to_string_lossy().into_owned(),
+        "-".to_string(),
+        "none*.enex".to_string(),
+        "[".to_string(),
+    ];
+    assert_eq!(expand_globs(inputs), expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_megabytes() {
+    assert_eq!(megabytes("2"), Ok(2 << 20));
+    assert!(megabytes("17592186044416").is_err());
+    assert!(megabytes("-1").is_err());
 }
@@ -0,0 +1,56 @@
+//! Reading an input through a memory map, with `--mmap`, to save copying it through read calls.
+
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+
+pub struct MappedFile {
+    map: Mmap,
+    pos: usize,
+}
+
+impl MappedFile {
+    /// Map all of `file`, which shouldn't change while it's read.
+    pub fn new(file: &File) -> io::Result<MappedFile> {
+        // Safety: the file is only read, and another process truncating it while mapped would at
+        // worst crash the conversion, as reading a file that's being overwritten garbles it.
+        let map = unsafe { Mmap::map(file)? };
+        Ok(MappedFile { map, pos: 0 })
+    }
+}
+
+impl Read for MappedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (&self.map[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl BufRead for MappedFile {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(&self.map[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.map.len());
+    }
+}
+
+#[test]
+fn test_mapped_file() {
+    use std::io::Write;
+
+    let path = std::env::temp_dir().join(format!("enex2mf-mapped-{}", std::process::id()));
+    File::create(&path)
+        .unwrap()
+        .write_all(b"hello world")
+        .unwrap();
+    let mut mapped = MappedFile::new(&File::open(&path).unwrap()).unwrap();
+    let mut hello = [0; 5];
+    mapped.read_exact(&mut hello).unwrap();
+    assert_eq!(&hello, b"hello");
+    mapped.consume(1);
+    assert_eq!(mapped.fill_buf().unwrap(), b"world");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,110 @@
+//! Plain Markdown output, for editors other than MindForger: the metadata is a normal line of
+//! text instead of an HTML comment.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::frontmatter::write_frontmatter;
+use crate::mindforger::MetadataOptions;
+use std::io::Write;
+
+/// The dates, tags and source of `note` as a line of text, or `None` if it has none of them.
+/// `options` can add the author and location.
+fn metadata(note: &Note, options: &MetadataOptions) -> Option<String> {
+    let mut fields = Vec::new();
+    if let Some(ref created) = note.created {
+        fields.push(format!("Created: {}", options.dates.format(created)));
+    }
+    if let Some(ref updated) = note.updated {
+        fields.push(format!("Updated: {}", options.dates.format(updated)));
+    }
+    if !note.tags.is_empty() {
+        fields.push(format!("Tags: {}", note.tags.join(", ")));
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        fields.push(format!("Source: <{}>", source_url));
+    }
+    if let Some(author) = note.attributes.author.as_ref().filter(|_| options.author) {
+        fields.push(format!("Author: {}", author));
+    }
+    if let Some(location) = options.location.and_then(|link| link.link(note)) {
+        fields.push(format!("Location: {}", location));
+    }
+    if fields.is_empty() {
+        None
+    } else {
+        Some(fields.join(" · "))
+    }
+}
+
+/// Write a single note as CommonMark, with its title as a heading.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    write_body(writer, note, enml, Some(&MetadataOptions::default()))
+}
+
+/// Write a note to its own file, with its metadata as `options.style` says.
+pub fn write_file<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+    options: &MetadataOptions,
+) -> Result<()> {
+    if options.style.frontmatter() {
+        write_frontmatter(writer, note, options)?;
+    }
+    let options = Some(options).filter(|options| options.style.native());
+    write_body(writer, note, enml, options)
+}
+
+fn write_body<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+    options: Option<&MetadataOptions>,
+) -> Result<()> {
+    let title = note.title.as_deref().unwrap_or("untitled");
+    writeln!(writer, "# {}\n", title)?;
+    if let Some(metadata) = options.and_then(|options| metadata(note, options)) {
+        writeln!(writer, "*{}*\n", metadata)?;
+    }
+
+    let content = note.content_markdown(enml);
+    if !content.is_empty() {
+        writeln!(writer, "{}\n", content)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_metadata() {
+    use crate::dates::{DateFormat, DateOptions, DateZone};
+    use chrono::{Local, TimeZone, Utc};
+
+    let note = Note::builder()
+        .title("Hi")
+        .created(
+            Utc.with_ymd_and_hms(2018, 12, 19, 10, 13, 4)
+                .unwrap()
+                .with_timezone(&Local),
+        )
+        .tag("a")
+        .build();
+    let options = MetadataOptions {
+        dates: DateOptions {
+            format: DateFormat::Rfc3339,
+            zone: DateZone::Utc,
+        },
+        ..MetadataOptions::default()
+    };
+    let mut out = Vec::new();
+    write_file(&mut out, &note, &EnmlOptions::default(), &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "# Hi\n\n*Created: 2018-12-19T10:13:04Z · Tags: a*\n\n"
+    );
+}
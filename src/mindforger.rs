@@ -0,0 +1,313 @@
+//! MindForger output: a notebook is an outline, a Markdown file whose headings are the notes. Each
+//! heading carries the note's metadata in an HTML comment, which MindForger parses.
+
+use crate::dates::DateOptions;
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::frontmatter::{write_frontmatter, MetadataStyle};
+use crate::writer::{ExportInfo, NoteWriter};
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Optional metadata to include in the heading comments.
+#[derive(Clone, Debug, Default)]
+pub struct MetadataOptions {
+    /// Reminder and reminder-done times.
+    pub reminders: bool,
+    /// Key/value pairs other apps attached to the note.
+    pub application_data: bool,
+    /// How to write times. MindForger only reads them as dates in the default format.
+    pub dates: DateOptions,
+    /// Whether notes written to their own files have YAML frontmatter, and the HTML comment.
+    pub style: MetadataStyle,
+    /// Importance of notes with certain tags.
+    pub importance: TagLevels,
+    /// Urgency of notes with certain tags.
+    pub urgency: TagLevels,
+    /// The author.
+    pub author: bool,
+    /// Where the note was taken, as a link below the heading.
+    pub location: Option<LocationLink>,
+}
+
+impl MetadataOptions {
+    /// `time` as `dates` says, without `;`, which ends a field of the comment.
+    fn date(&self, time: &DateTime<Local>) -> String {
+        self.dates.format(time).replace(';', ",")
+    }
+}
+
+/// What the location of a note links to.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LocationLink {
+    /// A map on OpenStreetMap.
+    Osm,
+    /// A `geo:` URI, which phones open in their map app.
+    Geo,
+}
+
+impl LocationLink {
+    /// The location of `note` as a Markdown link, if it has one.
+    pub fn link(self, note: &Note) -> Option<String> {
+        let (latitude, longitude) = note.attributes.coordinates()?;
+        let url = match self {
+            LocationLink::Osm => format!(
+                "https://www.openstreetmap.org/?mlat={0}&mlon={1}#map=15/{0}/{1}",
+                latitude, longitude
+            ),
+            LocationLink::Geo => format!("geo:{},{}", latitude, longitude),
+        };
+        Some(format!("[{}, {}]({})", latitude, longitude, url))
+    }
+}
+
+/// Levels of 0 to 5, as MindForger shows importance and urgency, given to notes by their tags.
+#[derive(Clone, Debug, Default)]
+pub struct TagLevels(HashMap<String, u8>);
+
+impl TagLevels {
+    /// Give notes tagged `tag`, ignoring case, at least `level`, up to 5.
+    pub fn insert(&mut self, tag: &str, level: u8) {
+        self.0.insert(tag.to_lowercase(), level.min(5));
+    }
+
+    /// Parse a rule like `urgent=5`.
+    pub fn parse_rule(rule: &str) -> Option<(&str, u8)> {
+        let (tag, level) = rule.rsplit_once('=')?;
+        match level.trim().parse() {
+            Ok(level) if level <= 5 && !tag.trim().is_empty() => Some((tag.trim(), level)),
+            _ => None,
+        }
+    }
+
+    /// The highest level of `tags`, or `None` if no rule matches them.
+    fn level(&self, tags: &[String]) -> Option<u8> {
+        tags.iter()
+            .filter_map(|tag| self.0.get(&tag.to_lowercase()))
+            .max()
+            .copied()
+    }
+}
+
+/// Write the heading of the outline for `export`, which the notes follow. The outline was
+/// created and last modified when the export was made, and is described by the app that made it.
+pub fn write_outline_header<W: Write + ?Sized>(
+    writer: &mut W,
+    export: &ExportInfo,
+    options: &MetadataOptions,
+) -> Result<()> {
+    write!(writer, "# {} <!-- Metadata: type: Outline; ", export.name)?;
+    if let Some(ref date) = export.export_date {
+        let date = options.date(date);
+        write!(
+            writer,
+            "created: {0}; reads: 0; read: {0}; revision: 0; modified: {0}; ",
+            date
+        )?;
+    }
+    writeln!(writer, "importance: 0/5; urgency: 0/5; -->")?;
+    if let Some(ref application) = export.application {
+        match export.version {
+            Some(ref version) => writeln!(writer, "\nExported by {} {}.\n", application, version)?,
+            None => writeln!(writer, "\nExported by {}.\n", application)?,
+        }
+    }
+    Ok(())
+}
+
+/// Write a single note in MindForger-compatible Markdown.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+    options: &MetadataOptions,
+) -> Result<()> {
+    if options.style.frontmatter() {
+        write_frontmatter(writer, note, options)?;
+    }
+    let title = note.title.as_ref().map_or("untitled", String::as_str);
+    write!(writer, "# {}", title)?;
+    if options.style.native() {
+        write_comment(writer, note, options)?;
+    } else {
+        writeln!(writer, "\n")?;
+    }
+    if let Some(ref from) = note.attributes.source_url {
+        writeln!(writer, "From {}\n", from)?;
+    }
+    if let Some(location) = options.location.and_then(|link| link.link(note)) {
+        writeln!(writer, "Location: {}\n", location)?;
+    }
+
+    let content_md = note.content_markdown(enml);
+    if !content_md.is_empty() {
+        writeln!(writer, "{}", content_md)?;
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+/// Write the metadata comment that follows the heading of `note`.
+fn write_comment<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    options: &MetadataOptions,
+) -> Result<()> {
+    write!(writer, " <!-- Metadata: type: Note; ")?;
+    if !note.tags.is_empty() {
+        write!(writer, "tags: {}; ", note.tags.join(","))?;
+    }
+    if let Some(ref created) = note.created {
+        write!(writer, "created: {}; ", options.date(created))?;
+    }
+    // Awkward to avoid moving refs.
+    if let Some(modified) = note.updated.as_ref().or(note.created.as_ref()) {
+        write!(writer, "modified: {}; ", options.date(modified))?;
+    }
+    if let Some(importance) = options.importance.level(&note.tags) {
+        write!(writer, "importance: {}/5; ", importance)?;
+    }
+    if let Some(urgency) = options.urgency.level(&note.tags) {
+        write!(writer, "urgency: {}/5; ", urgency)?;
+    }
+    if let Some(ref class) = note.attributes.content_class {
+        write!(writer, "content-class: {}; ", field(class))?;
+    }
+    if let Some(author) = note.attributes.author.as_ref().filter(|_| options.author) {
+        write!(writer, "author: {}; ", field(author))?;
+    }
+    if options.reminders {
+        if let Some(ref reminder) = note.attributes.reminder_time {
+            write!(writer, "reminder: {}; ", options.date(reminder))?;
+        }
+        if let Some(ref done) = note.attributes.reminder_done_time {
+            write!(writer, "reminder-done: {}; ", options.date(done))?;
+        }
+    }
+    if options.application_data {
+        let mut data: Vec<_> = note.attributes.application_data.iter().collect();
+        data.sort();
+        for (key, value) in data {
+            write!(writer, "{}: {}; ", key, field(value))?;
+        }
+    }
+    writeln!(writer, "-->\n")?;
+    Ok(())
+}
+
+/// `value` without `;`, which ends a field, or `--`, which ends the comment.
+fn field(value: &str) -> String {
+    value.replace(';', ",").replace("--", "- -")
+}
+
+/// Writes each notebook as an outline.
+pub struct OutlineWriter<W: Write> {
+    writer: W,
+    metadata: MetadataOptions,
+}
+
+impl<W: Write> OutlineWriter<W> {
+    /// Write outlines to `writer`, including `metadata` in the note headings. Notes in an outline
+    /// can't have frontmatter, so they always have the comment.
+    pub fn new(writer: W, metadata: MetadataOptions) -> Self {
+        let metadata = MetadataOptions {
+            style: MetadataStyle::Native,
+            ..metadata
+        };
+        OutlineWriter { writer, metadata }
+    }
+}
+
+impl<W: Write> NoteWriter for OutlineWriter<W> {
+    fn begin(&mut self, export: &ExportInfo) -> Result<()> {
+        write_outline_header(&mut self.writer, export, &self.metadata)
+    }
+
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        write_note(&mut self.writer, note, enml, &self.metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[test]
+fn test_metadata() {
+    use crate::dates::DateZone;
+    use chrono::{TimeZone, Utc};
+
+    let mut urgency = TagLevels::default();
+    let (tag, level) = TagLevels::parse_rule("Urgent=5").unwrap();
+    urgency.insert(tag, level);
+    urgency.insert("soon", 3);
+    assert_eq!(TagLevels::parse_rule("urgent=6"), None);
+    assert_eq!(TagLevels::parse_rule("=2"), None);
+
+    let mut note = Note::builder()
+        .title("Pay rent")
+        .tag("soon")
+        .tag("urgent")
+        .build();
+    note.attributes.content_class = Some("evernote.food.meal".to_string());
+    let options = MetadataOptions {
+        urgency,
+        ..MetadataOptions::default()
+    };
+    let mut out = Vec::new();
+    write_comment(&mut out, &note, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        " <!-- Metadata: type: Note; tags: soon,urgent; urgency: 5/5; \
+         content-class: evernote.food.meal; -->\n\n"
+    );
+
+    note.attributes.latitude = Some("52.5".to_string());
+    note.attributes.longitude = Some(" 13.4".to_string());
+    assert_eq!(
+        LocationLink::Geo.link(&note).unwrap(),
+        "[52.5, 13.4](geo:52.5,13.4)"
+    );
+
+    note.attributes.reminder_time =
+        Some(Utc.with_ymd_and_hms(2018, 12, 20, 9, 0, 0).unwrap().into());
+    note.attributes.reminder_done_time = Some(
+        Utc.with_ymd_and_hms(2018, 12, 20, 10, 15, 0)
+            .unwrap()
+            .into(),
+    );
+    let reminders = MetadataOptions {
+        reminders: true,
+        dates: DateOptions {
+            zone: DateZone::Utc,
+            ..DateOptions::default()
+        },
+        ..MetadataOptions::default()
+    };
+    let mut out = Vec::new();
+    write_comment(&mut out, &note, &reminders).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .ends_with("reminder: 2018-12-20 09:00:00; reminder-done: 2018-12-20 10:15:00; -->\n\n"));
+    let mut out = Vec::new();
+    write_comment(&mut out, &note, &options).unwrap();
+    assert!(!String::from_utf8(out).unwrap().contains("reminder"));
+
+    let export = ExportInfo {
+        name: "Inbox".to_string(),
+        application: Some("Evernote".to_string()),
+        ..ExportInfo::default()
+    };
+    let mut out = Vec::new();
+    write_outline_header(&mut out, &export, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "# Inbox <!-- Metadata: type: Outline; importance: 0/5; urgency: 0/5; -->\n\n\
+         Exported by Evernote.\n\n"
+    );
+}
@@ -0,0 +1,330 @@
+//! File names for notes written one per file.
+
+use crate::enex::Note;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// Longest file name stem, in bytes, leaving room for a counter and an extension within the
+/// 255 bytes most file systems allow.
+const MAX_STEM_LEN: usize = 200;
+
+/// How to turn titles into file names.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NameStyle {
+    /// The title as is, with only reserved characters replaced.
+    #[default]
+    PreserveSpaces,
+    /// Like `PreserveSpaces`, with accents dropped and other non-ASCII characters replaced.
+    Ascii,
+    /// Lowercase ASCII words joined by hyphens, e.g. `meeting-1-2-draft`.
+    Kebab,
+}
+
+/// What to add to the names of notes with the same title, to tell them apart. Notes whose names
+/// still clash get a counter too.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Duplicates {
+    /// `Note 2`, `Note 3`, ...
+    #[default]
+    Counter,
+    /// The date the note was created, e.g. `Note 2020-01-31`.
+    Date,
+    /// A short hash of the note's title and dates, which stays the same between conversions.
+    Hash,
+}
+
+/// Replace characters that are reserved in file names on common platforms.
+pub fn sanitize(title: &str) -> String {
+    slugify(title, NameStyle::PreserveSpaces)
+}
+
+/// A file name stem for `title` in `style`, safe on Windows, macOS and Linux alike.
+pub fn slugify(title: &str, style: NameStyle) -> String {
+    let name: String = match style {
+        NameStyle::PreserveSpaces => title.chars().map(replace_reserved).collect(),
+        NameStyle::Ascii => to_ascii(title).chars().map(replace_reserved).collect(),
+        NameStyle::Kebab => to_ascii(title)
+            .to_lowercase()
+            .replace('\'', "")
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join("-"),
+    };
+    let mut name = trim(&name);
+    if name.len() > MAX_STEM_LEN {
+        let mut end = MAX_STEM_LEN;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = trim(&name[..end]);
+    }
+    if name.is_empty() {
+        return "untitled".to_string();
+    }
+    // Windows reserves device names, even with an extension.
+    let device = name.split('.').next().unwrap_or("").to_uppercase();
+    let reserved = matches!(device.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || (device.len() == 4
+            && (device.starts_with("COM") || device.starts_with("LPT"))
+            && device.ends_with(|c: char| c.is_ascii_digit() && c != '0'));
+    if reserved {
+        name.insert(device.len(), '_');
+    }
+    name.to_string()
+}
+
+fn replace_reserved(c: char) -> char {
+    match c {
+        '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+        c if c.is_control() => '-',
+        c => c,
+    }
+}
+
+/// Leading dots hide files, and Windows drops trailing dots and spaces.
+fn trim(name: &str) -> String {
+    name.trim_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string()
+}
+
+/// `text` with accented Latin letters and typographic punctuation replaced by their closest
+/// ASCII, and other non-ASCII characters by `-`.
+fn to_ascii(text: &str) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for c in text.chars() {
+        let replacement = match c {
+            c if c.is_ascii() => {
+                ascii.push(c);
+                continue;
+            }
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+            'æ' => "ae",
+            'Æ' => "AE",
+            'ç' | 'ć' | 'č' => "c",
+            'Ç' | 'Ć' | 'Č' => "C",
+            'ď' | 'đ' | 'ð' => "d",
+            'Ď' | 'Đ' | 'Ð' => "D",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+            'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ė' | 'Ę' | 'Ě' => "E",
+            'ğ' => "g",
+            'Ğ' => "G",
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'ı' => "i",
+            'Ì' | 'Í' | 'Î' | 'Ï' | 'Ī' | 'İ' => "I",
+            'ł' | 'ľ' => "l",
+            'Ł' | 'Ľ' => "L",
+            'ñ' | 'ń' | 'ň' => "n",
+            'Ñ' | 'Ń' | 'Ň' => "N",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+            'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ő' => "O",
+            'œ' => "oe",
+            'Œ' => "OE",
+            'ř' => "r",
+            'Ř' => "R",
+            'ś' | 'š' | 'ş' => "s",
+            'Ś' | 'Š' | 'Ş' => "S",
+            'ß' => "ss",
+            'ť' | 'ţ' => "t",
+            'Ť' | 'Ţ' => "T",
+            'þ' => "th",
+            'Þ' => "TH",
+            'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => "u",
+            'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ū' | 'Ů' | 'Ű' => "U",
+            'ý' | 'ÿ' => "y",
+            'Ý' | 'Ÿ' => "Y",
+            'ź' | 'ż' | 'ž' => "z",
+            'Ź' | 'Ż' | 'Ž' => "Z",
+            '‘' | '’' => "'",
+            '“' | '”' => "\"",
+            '–' | '—' => "-",
+            '…' => "...",
+            '\u{a0}' => " ",
+            _ => "-",
+        };
+        ascii.push_str(replacement);
+    }
+    ascii
+}
+
+/// Hands out file names that are unique (case-insensitively, for macOS and Windows) by suffixing
+/// duplicates with a counter, or as `Duplicates` says for notes.
+#[derive(Debug, Default)]
+pub struct UniqueNames {
+    style: NameStyle,
+    duplicates: Duplicates,
+    /// Whether spaces are replaced by underscores.
+    underscores: bool,
+    /// Whether names are kept to what wiki links can refer to.
+    wiki_links: bool,
+    taken: HashSet<String>,
+    /// Titles given a name other than their own, and the name.
+    renamed: Vec<(String, String)>,
+}
+
+impl UniqueNames {
+    /// Names in `style`.
+    pub fn new(style: NameStyle) -> Self {
+        UniqueNames {
+            style,
+            ..UniqueNames::default()
+        }
+    }
+
+    /// Tell notes with the same title apart as `duplicates` says.
+    pub fn duplicates(mut self, duplicates: Duplicates) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Replace spaces by underscores, as Zim names files after pages. Names are unique after
+    /// the replacement, so `a b` and `a_b` don't share a file.
+    pub fn underscores(mut self, underscores: bool) -> Self {
+        self.underscores = underscores;
+        self
+    }
+
+    /// Replace `#`, `^`, `[` and `]` too, so that wiki links can refer to the files by name, as
+    /// Obsidian links them.
+    pub fn wiki_links(mut self, wiki_links: bool) -> Self {
+        self.wiki_links = wiki_links;
+        self
+    }
+
+    /// Return a unique file name for `title`, with `extension` appended.
+    pub fn file_name(&mut self, title: &str, extension: &str) -> String {
+        let stem = self.stem(title);
+        self.unique(&stem, extension)
+    }
+
+    /// Return a unique file name for `note`, after its title.
+    pub fn note_file_name(&mut self, note: &Note, extension: &str) -> String {
+        let title = note.title.as_deref().unwrap_or("untitled");
+        let stem = self.stem(title);
+        let name = format!("{}.{}", stem, extension);
+        if self.taken.insert(name.to_lowercase()) {
+            return name;
+        }
+        let suffix = match self.duplicates {
+            Duplicates::Counter => None,
+            Duplicates::Date => note.created.map(|created| created.format("%F").to_string()),
+            Duplicates::Hash => {
+                let key = format!("{}\n{:?}\n{:?}", title, note.created, note.updated);
+                Some(format!("{:x}", md5::compute(key))[..7].to_string())
+            }
+        };
+        let name = match suffix {
+            Some(suffix) => self.unique(
+                &format!("{}{}{}", stem, self.separator(), suffix),
+                extension,
+            ),
+            None => self.unique(&stem, extension),
+        };
+        self.renamed.push((title.to_string(), name.clone()));
+        name
+    }
+
+    /// The titles that were given names other than their own since the last call, and the names,
+    /// to report where notes with duplicate titles went.
+    pub fn take_renamed(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.renamed)
+    }
+
+    /// `stem` with `extension`, if any, followed by a counter if it's taken.
+    fn unique(&mut self, stem: &str, extension: &str) -> String {
+        let dot = if extension.is_empty() { "" } else { "." };
+        let mut name = format!("{}{}{}", stem, dot, extension);
+        let mut counter = 1;
+        while !self.taken.insert(name.to_lowercase()) {
+            counter += 1;
+            let separator = self.separator();
+            name = format!("{}{}{}{}{}", stem, separator, counter, dot, extension);
+        }
+        name
+    }
+
+    fn stem(&self, title: &str) -> String {
+        let mut stem = slugify(title, self.style);
+        if self.underscores {
+            stem = stem.replace(' ', "_");
+        }
+        if self.wiki_links {
+            stem = stem.replace(['#', '^', '[', ']'], "-");
+        }
+        stem
+    }
+
+    fn separator(&self) -> &'static str {
+        if self.style == NameStyle::Kebab {
+            "-"
+        } else if self.underscores {
+            "_"
+        } else {
+            " "
+        }
+    }
+}
+
+#[test]
+fn test_unique_names() {
+    let mut names = UniqueNames::default();
+    assert_eq!(names.file_name("a/b: c?", "md"), "a-b- c-.md");
+    assert_eq!(names.file_name("Note", "md"), "Note.md");
+    assert_eq!(names.file_name("note", "md"), "note 2.md");
+    assert_eq!(names.file_name("..", "md"), "untitled.md");
+    assert_eq!(names.file_name("con", "md"), "con_.md");
+    assert_eq!(names.file_name(&"é".repeat(150), "md").len(), 200 + 3);
+
+    let title = "Meeting: 1/2 \u{201c}draft\u{201d}? Café";
+    let mut names = UniqueNames::new(NameStyle::Ascii);
+    assert_eq!(
+        names.file_name(title, "md"),
+        "Meeting- 1-2 -draft-- Cafe.md"
+    );
+    let mut names = UniqueNames::new(NameStyle::Kebab);
+    assert_eq!(names.file_name(title, "md"), "meeting-1-2-draft-cafe.md");
+    assert_eq!(names.file_name(title, "md"), "meeting-1-2-draft-cafe-2.md");
+    assert_eq!(names.file_name("Don't 東京", "md"), "dont.md");
+
+    let mut names = UniqueNames::default().underscores(true);
+    assert_eq!(names.file_name("Soup hot", "txt"), "Soup_hot.txt");
+    assert_eq!(names.file_name("Soup_hot", "txt"), "Soup_hot_2.txt");
+
+    let mut names = UniqueNames::default().wiki_links(true);
+    assert_eq!(
+        names.file_name("C# [draft] ^1 |", "md"),
+        "C- -draft- -1 -.md"
+    );
+}
+
+#[test]
+fn test_duplicates() {
+    use chrono::{Local, TimeZone};
+
+    let note = |day| {
+        Note::builder()
+            .title("Untitled")
+            .created(Local.with_ymd_and_hms(2020, 1, day, 12, 0, 0).unwrap())
+            .build()
+    };
+    let mut names = UniqueNames::default().duplicates(Duplicates::Date);
+    assert_eq!(names.note_file_name(&note(1), "md"), "Untitled.md");
+    assert_eq!(
+        names.note_file_name(&note(2), "md"),
+        "Untitled 2020-01-02.md"
+    );
+    assert_eq!(
+        names.note_file_name(&note(2), "md"),
+        "Untitled 2020-01-02 2.md"
+    );
+
+    let mut names = UniqueNames::new(NameStyle::Kebab).duplicates(Duplicates::Hash);
+    assert_eq!(names.note_file_name(&note(1), "md"), "untitled.md");
+    let name = names.note_file_name(&note(2), "md");
+    assert!(name.starts_with("untitled-") && name.len() == "untitled-1234567.md".len());
+    let mut again = UniqueNames::new(NameStyle::Kebab).duplicates(Duplicates::Hash);
+    again.note_file_name(&note(1), "md");
+    assert_eq!(again.note_file_name(&note(2), "md"), name);
+}
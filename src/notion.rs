@@ -0,0 +1,225 @@
+//! Notion's Markdown & CSV import: a zip with, for each notebook, a CSV listing its notes, which
+//! Notion makes a database, and a folder with a Markdown page per note. Each page's attachments
+//! are in a folder named after it, as Notion's own exports have them.
+
+use crate::attachments;
+use crate::enex::Note;
+use crate::enml::{EnmlOptions, Media};
+use crate::error::Result;
+use crate::naming::{NameStyle, UniqueNames};
+use crate::writer::{ExportInfo, NoteWriter};
+use crate::zip::ZipWriter;
+use chrono::{DateTime, Local};
+use std::io::Write;
+use std::path::Path;
+
+/// The columns of the CSV, which become the properties of the database.
+const COLUMNS: &str = "Name,Created,Updated,Tags,URL";
+
+/// Times as Notion writes them, e.g. `December 19, 2018 11:13 AM`.
+fn time(time: &DateTime<Local>) -> String {
+    time.format("%B %-d, %Y %-I:%M %p").to_string()
+}
+
+/// `value` as a field of a CSV, quoted if it needs to be.
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes notes to a zip for Notion to import.
+pub struct NotionWriter<W: Write> {
+    zip: ZipWriter<W>,
+    /// The folder of the notebook being written, named after it.
+    folder: String,
+    /// A line of the CSV per note written to the notebook.
+    rows: Vec<String>,
+    /// Names of pages in the notebook's folder.
+    pages: UniqueNames,
+    notebooks: UniqueNames,
+}
+
+impl<W: Write> NotionWriter<W> {
+    /// Start an export. Call `begin` before writing notes.
+    pub fn new(writer: W) -> Self {
+        NotionWriter {
+            zip: ZipWriter::new(writer),
+            folder: String::new(),
+            rows: Vec::new(),
+            pages: UniqueNames::new(NameStyle::PreserveSpaces),
+            notebooks: UniqueNames::new(NameStyle::PreserveSpaces),
+        }
+    }
+
+    /// Write the CSV of the notebook written so far, if any.
+    fn end_notebook(&mut self) -> Result<()> {
+        if self.folder.is_empty() {
+            return Ok(());
+        }
+        let mut csv = format!("{}\n", COLUMNS);
+        for row in self.rows.drain(..) {
+            csv.push_str(&row);
+            csv.push('\n');
+        }
+        // A byte order mark, so that spreadsheets read it as UTF-8.
+        let csv = format!("\u{feff}{}", csv);
+        self.zip
+            .add(&format!("{}.csv", self.folder), csv.as_bytes())
+    }
+
+    /// Add the resources of `note` to the folder `dir` next to its page, returning the media to
+    /// link from the page.
+    fn write_resources(&mut self, note: &Note, dir: &str) -> Result<Vec<(String, Media)>> {
+        let mut names = UniqueNames::new(NameStyle::PreserveSpaces);
+        let mut media = Vec::new();
+        for resource in &note.resources {
+            let data = resource.decode()?;
+            let hash = format!("{:x}", md5::compute(&data));
+            if media.iter().any(|(written, _)| *written == hash) {
+                continue;
+            }
+            let extension = resource
+                .mime
+                .as_deref()
+                .and_then(attachments::extension)
+                .unwrap_or("");
+            let original = resource.file_name.clone();
+            let (stem, extension) = match original.as_deref().map(Path::new) {
+                Some(path) => (
+                    path.file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    path.extension()
+                        .map_or(extension.to_string(), |e| e.to_string_lossy().into_owned()),
+                ),
+                None => (hash.clone(), extension.to_string()),
+            };
+            let name = names.file_name(&stem, &extension);
+            let name = name.trim_end_matches('.').to_string();
+            self.zip
+                .add(&format!("{}/{}/{}", self.folder, dir, name), &data)?;
+            media.push((
+                hash,
+                Media {
+                    target: format!("{}/{}", dir, name),
+                    name: original.unwrap_or(name),
+                    mime: resource.mime.clone(),
+                    size: Some(data.len() as u64),
+                    recognition: attachments::recognition(resource),
+                    attachment: resource.attributes.attachment,
+                    width: resource.width,
+                },
+            ));
+        }
+        Ok(media)
+    }
+}
+
+impl<W: Write> NoteWriter for NotionWriter<W> {
+    /// Start a notebook: a folder of pages, and the CSV listing them.
+    fn begin(&mut self, export: &ExportInfo) -> Result<()> {
+        self.end_notebook()?;
+        let name = self.notebooks.file_name(&export.name, "csv");
+        self.folder = name.trim_end_matches(".csv").to_string();
+        self.pages = UniqueNames::new(NameStyle::PreserveSpaces);
+        Ok(())
+    }
+
+    /// Write `note` as a page, with its properties below the title as Notion exports them, and
+    /// add it to the CSV.
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        let title = note.title.as_deref().unwrap_or("untitled");
+        let file_name = self.pages.note_file_name(note, "md");
+        let stem = file_name.trim_end_matches(".md").to_string();
+
+        let mut enml = enml.clone();
+        enml.media.extend(self.write_resources(note, &stem)?);
+        let created = note.created.as_ref().map(time).unwrap_or_default();
+        let updated = note.updated.as_ref().map(time).unwrap_or_default();
+        let tags = note.tags.join(", ");
+        let url = note.attributes.source_url.as_deref().unwrap_or("");
+
+        let mut page = format!("# {}\n\n", title);
+        for (name, value) in [
+            ("Created", &created),
+            ("Updated", &updated),
+            ("Tags", &tags),
+        ] {
+            if !value.is_empty() {
+                page.push_str(&format!("{}: {}\n", name, value));
+            }
+        }
+        if !url.is_empty() {
+            page.push_str(&format!("URL: {}\n", url));
+        }
+        if !page.ends_with("\n\n") {
+            page.push('\n');
+        }
+        let content = note.content_markdown(&enml);
+        if !content.is_empty() {
+            page.push_str(&content);
+            page.push('\n');
+        }
+        self.zip
+            .add(&format!("{}/{}", self.folder, file_name), page.as_bytes())?;
+
+        let row = [title, &created, &updated, &tags, url];
+        let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        self.rows.push(row.join(","));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.zip.get_mut().flush()?)
+    }
+
+    /// Write the last CSV and the end of the archive.
+    fn finish(&mut self) -> Result<()> {
+        self.end_notebook()?;
+        self.zip.finish()
+    }
+}
+
+#[test]
+fn test_notion_writer() {
+    use crate::enex::Resource;
+
+    let note = Note::builder()
+        .title("Soup, hot")
+        .tag("food")
+        .content(
+            "<en-note><div>Stir</div>\
+             <en-media hash=\"1ac2109d47dbc72551f71df89d01ed18\" type=\"image/gif\"/></en-note>",
+        )
+        .resource(Resource {
+            file_name: Some("pot.gif".to_string()),
+            ..Resource::new(b"GIF89a", "image/gif")
+        })
+        .build();
+    let mut zip = Vec::new();
+    let mut writer = NotionWriter::new(&mut zip);
+    writer
+        .begin(&ExportInfo {
+            name: "Recipes".to_string(),
+            ..ExportInfo::default()
+        })
+        .unwrap();
+    writer.write_note(&note, &EnmlOptions::default()).unwrap();
+    writer.finish().unwrap();
+    drop(writer);
+
+    // The files are stored, so their names and contents can be found as they are.
+    let text = String::from_utf8_lossy(&zip);
+    assert!(text.contains(
+        "Recipes/Soup, hot.md# Soup, hot\n\nTags: food\n\nStir\n\n![pot.gif](Soup,%20hot/pot.gif)"
+    ));
+    assert!(text.contains("Recipes/Soup, hot/pot.gifGIF89a"));
+    assert!(
+        text.contains("Recipes.csv\u{feff}Name,Created,Updated,Tags,URL\n\"Soup, hot\",,,food,\n")
+    );
+    assert_eq!(&zip[zip.len() - 22..zip.len() - 18], b"PK\x05\x06");
+}
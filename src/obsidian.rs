@@ -0,0 +1,85 @@
+//! Obsidian vault output: one Markdown file per note, with YAML frontmatter for metadata.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::frontmatter::write_fields;
+use crate::mindforger::MetadataOptions;
+use std::io::Write;
+
+/// Attachments go in this folder of the vault.
+pub const ATTACHMENTS_DIR: &str = "attachments";
+
+/// Write a single note in Obsidian-flavored Markdown, with its metadata as `options` says. The
+/// title is the file name, so it isn't repeated in the body, and notes without metadata have no
+/// frontmatter.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+    options: &MetadataOptions,
+) -> Result<()> {
+    let mut fields = Vec::new();
+    // Obsidian tags can't contain spaces.
+    write_fields(&mut fields, note, options, |tag| tag.replace(' ', "-"))?;
+    if !fields.is_empty() {
+        writeln!(writer, "---")?;
+        writer.write_all(&fields)?;
+        writeln!(writer, "---\n")?;
+    }
+
+    let content = note.content_markdown(enml);
+    writeln!(writer, "{}", content)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_write_note() {
+    use crate::attachments::{self, AttachmentOptions};
+    use crate::enex::Resource;
+
+    let vault = std::env::temp_dir().join(format!("enex2mf-obsidian-{}", std::process::id()));
+    let gif = "1ac2109d47dbc72551f71df89d01ed18";
+    let pdf = "914240125319291c7cb7e712e419b254";
+    let mut plan = Resource::new(b"%PDF-1.4", "application/pdf");
+    plan.file_name = Some("plan.pdf".to_string());
+    let note = Note::builder()
+        .title("Kitchen")
+        .tag("home work")
+        .content(format!(
+            "<en-note><div>Tiles</div><en-media hash=\"{}\" type=\"image/gif\"/>\
+             <en-media hash=\"{}\" type=\"application/pdf\"/></en-note>",
+            gif, pdf
+        ))
+        .resource(Resource::new(b"GIF89a", "image/gif"))
+        .resource(plan)
+        .build();
+    let options = AttachmentOptions {
+        dir: Some(vault.join(ATTACHMENTS_DIR)),
+        links_relative_to: Some(vault.clone()),
+        ..AttachmentOptions::default()
+    };
+    let enml = EnmlOptions {
+        wiki_links: true,
+        media: attachments::media(&note, &options).unwrap(),
+        ..EnmlOptions::default()
+    };
+    let mut out = Vec::new();
+    write_note(&mut out, &note, &enml, &MetadataOptions::default()).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("---\ntags:\n  - \"home-work\"\n---\n\nTiles\n"));
+
+    assert!(out.contains(&format!("![[attachments/{}.gif]]", gif)));
+    assert!(out.contains(&format!("[[attachments/{}.pdf|plan.pdf]]", pdf)));
+    assert!(vault
+        .join(ATTACHMENTS_DIR)
+        .join(format!("{}.pdf", pdf))
+        .exists());
+    std::fs::remove_dir_all(&vault).unwrap();
+
+    let bare = Note::builder().content("<en-note>Hi</en-note>").build();
+    let mut out = Vec::new();
+    write_note(&mut out, &bare, &enml, &MetadataOptions::default()).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "Hi\n");
+}
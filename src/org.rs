@@ -0,0 +1,204 @@
+//! Emacs Org mode output, a heading per note in a file per notebook.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::render::{end_pipe_table, render, start_pipe_table, Markup};
+use crate::writer::{ExportInfo, NoteWriter};
+use chrono::{DateTime, TimeZone};
+use pulldown_cmark::Tag;
+use std::fmt::Display;
+use std::io::Write;
+
+/// An inactive Org timestamp, which doesn't show up in the agenda.
+fn timestamp<Tz: TimeZone>(time: &DateTime<Tz>) -> String
+where
+    Tz::Offset: Display,
+{
+    time.format("[%Y-%m-%d %a %H:%M]").to_string()
+}
+
+/// Org tags may only contain letters, numbers, `_`, `@`, `#` and `%`.
+fn tag(tag: &str) -> String {
+    tag.chars()
+        .map(|c| match c {
+            '@' | '#' | '%' => c,
+            c if c.is_alphanumeric() => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Relative paths need a `file:` prefix to be recognized as links.
+fn link_target(url: &str) -> String {
+    if url.contains(':') {
+        url.to_string()
+    } else {
+        format!("file:{}", url.replace("%20", " "))
+    }
+}
+
+/// Write a single note as a top-level Org heading with a property drawer for its metadata.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    let title = note.title.as_deref().unwrap_or("untitled");
+    write!(writer, "* {}", title)?;
+    if !note.tags.is_empty() {
+        let tags: Vec<_> = note.tags.iter().map(|t| tag(t)).collect();
+        write!(writer, " :{}:", tags.join(":"))?;
+    }
+    writeln!(writer)?;
+
+    writeln!(writer, ":PROPERTIES:")?;
+    if let Some(ref created) = note.created {
+        writeln!(writer, ":CREATED: {}", timestamp(created))?;
+    }
+    if let Some(ref updated) = note.updated {
+        writeln!(writer, ":UPDATED: {}", timestamp(updated))?;
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        writeln!(writer, ":SOURCE_URL: {}", source_url)?;
+    }
+    writeln!(writer, ":END:\n")?;
+
+    let content = note.content_markdown(enml);
+    let content = to_org(&content);
+    if !content.is_empty() {
+        writeln!(writer, "{}\n", content)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a notebook as a single Org file, with a top-level heading per note.
+pub struct OrgWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> OrgWriter<W> {
+    /// Write the file to `writer`.
+    pub fn new(writer: W) -> Self {
+        OrgWriter { writer }
+    }
+}
+
+impl<W: Write> NoteWriter for OrgWriter<W> {
+    fn begin(&mut self, export: &ExportInfo) -> Result<()> {
+        writeln!(self.writer, "#+TITLE: {}\n", export.name)?;
+        Ok(())
+    }
+
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        write_note(&mut self.writer, note, enml)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Render Markdown as the body of a top-level Org heading, so headings are demoted one level.
+pub fn to_org(markdown: &str) -> String {
+    render(markdown, &mut Org::default())
+}
+
+#[derive(Default)]
+struct Org {
+    in_image: bool,
+    columns: usize,
+}
+
+impl Markup for Org {
+    fn start(&mut self, out: &mut String, tag: Tag<'_>) {
+        match tag {
+            Tag::Rule => out.push_str("-----\n\n"),
+            Tag::Header(level) => {
+                out.push_str(&"*".repeat(level as usize + 1));
+                out.push(' ');
+            }
+            Tag::BlockQuote => out.push_str("#+BEGIN_QUOTE\n"),
+            Tag::CodeBlock(ref lang) if lang.is_empty() => out.push_str("#+BEGIN_EXAMPLE\n"),
+            Tag::CodeBlock(lang) => out.push_str(&format!("#+BEGIN_SRC {}\n", lang)),
+            Tag::Emphasis => out.push('/'),
+            Tag::Strong => out.push('*'),
+            Tag::Code => out.push('~'),
+            Tag::Link(url, _) => out.push_str(&format!("[[{}][", link_target(&url))),
+            Tag::Image(url, _) => {
+                out.push_str(&format!("[[{}]]", link_target(&url)));
+                self.in_image = true;
+            }
+            tag => start_pipe_table(out, &tag, &mut self.columns),
+        }
+    }
+
+    fn end(&mut self, out: &mut String, tag: Tag<'_>) {
+        match tag {
+            Tag::Header(_) => out.push_str("\n\n"),
+            Tag::BlockQuote => out.push_str("#+END_QUOTE\n\n"),
+            Tag::CodeBlock(ref lang) if lang.is_empty() => out.push_str("#+END_EXAMPLE\n\n"),
+            Tag::CodeBlock(_) => out.push_str("#+END_SRC\n\n"),
+            Tag::Emphasis => out.push('/'),
+            Tag::Strong => out.push('*'),
+            Tag::Code => out.push('~'),
+            Tag::Link(_, _) => out.push_str("]]"),
+            Tag::Image(_, _) => self.in_image = false,
+            tag => end_pipe_table(out, &tag, self.columns),
+        }
+    }
+
+    fn item(&mut self, depth: usize, number: Option<usize>) -> String {
+        let indent = "  ".repeat(depth - 1);
+        match number {
+            Some(number) => format!("{}{}. ", indent, number),
+            None => format!("{}- ", indent),
+        }
+    }
+
+    fn text(&mut self, out: &mut String, text: &str) {
+        if self.in_image {
+            return;
+        }
+        // Task list items, which Org writes with an uppercase X.
+        match text.strip_prefix("[x] ") {
+            Some(rest) if out.ends_with("- ") => {
+                out.push_str("[X] ");
+                out.push_str(rest);
+            }
+            _ => out.push_str(text),
+        }
+    }
+
+    fn html(&mut self, out: &mut String, html: &str) {
+        out.push_str("#+BEGIN_EXPORT html\n");
+        out.push_str(html);
+        out.push_str("#+END_EXPORT\n\n");
+    }
+
+    fn inline_html(&mut self, out: &mut String, html: &str) {
+        out.push_str(&format!("@@html:{}@@", html));
+    }
+
+    fn footnote_reference(&mut self, out: &mut String, name: &str) {
+        out.push_str(&format!("[fn:{}]", name));
+    }
+
+    fn hard_break(&mut self, out: &mut String) {
+        out.push_str("\\\\\n");
+    }
+}
+
+#[test]
+fn test_to_org() {
+    let markdown = "## Shopping\n\n- [x] **milk**\n- [ ] eggs\n\n\
+                    See [site](http://x.com) and ![cat.png](files/my%20cat.png)\n\n\
+                    | a | b |\n| --- | --- |\n| 1 | 2 |";
+    assert_eq!(
+        to_org(markdown),
+        "*** Shopping\n\n- [X] *milk*\n- [ ] eggs\n\n\
+         See [[http://x.com][site]] and [[file:files/my cat.png]]\n\n\
+         | a | b |\n|---|---|\n| 1 | 2 |"
+    );
+}
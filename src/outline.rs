@@ -0,0 +1,515 @@
+//! Reading Markdown back into notes, to write them as an ENEX export with `export`: outlines
+//! written for MindForger, notes written to their own files with frontmatter or a metadata line,
+//! or Markdown written by hand. Metadata goes back where the conversion took it from, and the
+//! content is rendered as ENML, with the local images and files it links to as resources.
+
+use crate::attachments;
+use crate::enex::{Note, Resource};
+use crate::error::Result;
+use crate::sanitize;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use log::warn;
+use pulldown_cmark::{html, Options, Parser};
+use std::fs;
+use std::path::Path;
+
+/// Metadata MindForger keeps for itself, which has no place in an export.
+const MINDFORGER_FIELDS: &[&str] = &[
+    "type",
+    "reads",
+    "read",
+    "revision",
+    "importance",
+    "urgency",
+    "progress",
+    "links",
+    "scope",
+    "deadline",
+];
+
+/// The `key: value` fields of a MindForger metadata comment.
+type Fields = Vec<(String, String)>;
+
+/// A heading and the lines up to the next one.
+struct Section<'a> {
+    /// The heading line, if the section has one; text before the first heading has none.
+    heading: Option<&'a str>,
+    title: String,
+    /// The fields of the heading's MindForger metadata comment.
+    metadata: Option<Fields>,
+    body: Vec<&'a str>,
+}
+
+/// Read the notes of `markdown`, the file `name` in `dir`, which links to attachments are
+/// relative to. A file with MindForger metadata comments has a note for each heading with one,
+/// and other headings are part of the notes. Other files are a single note, titled by their
+/// frontmatter, a heading starting them, or `name`.
+pub fn read_notes(markdown: &str, name: &str, dir: &Path) -> Result<Vec<Note>> {
+    let (frontmatter, markdown) = split_frontmatter(markdown);
+    let sections = sections(markdown);
+    let is_outline = sections.iter().any(|section| section.metadata.is_some());
+
+    let mut notes: Vec<(Note, Vec<&str>)> = Vec::new();
+    for section in sections {
+        match section.metadata {
+            Some(ref fields) if field(fields, "type") == Some("Outline") => {}
+            Some(ref fields) => {
+                let mut note = Note::builder().title(section.title.as_str()).build();
+                apply_comment(&mut note, fields);
+                notes.push((note, section.body));
+            }
+            None => match notes.last_mut() {
+                Some((_, body)) => {
+                    body.extend(section.heading);
+                    body.extend(section.body);
+                }
+                // What an outline has before its first note is the outline's own.
+                None if is_outline => {}
+                None => {
+                    let mut note = Note::default();
+                    let mut body = section.body;
+                    // A heading starting the file is its title, unless it's only a section.
+                    match section.heading {
+                        Some(heading) if heading.starts_with("# ") => {
+                            note.title = Some(section.title);
+                        }
+                        Some(heading) => body.insert(0, heading),
+                        None => {}
+                    }
+                    notes.push((note, body));
+                }
+            },
+        }
+    }
+    if !is_outline && notes.is_empty() {
+        notes.push((Note::default(), Vec::new()));
+    }
+    if let (Some(frontmatter), Some((note, _))) = (frontmatter, notes.first_mut()) {
+        apply_frontmatter(note, frontmatter);
+    }
+
+    let mut read = Vec::with_capacity(notes.len());
+    for (mut note, body) in notes {
+        if note.title.is_none() {
+            note.title = Some(name.to_string());
+        }
+        let body = take_metadata_lines(&mut note, &body);
+        note.content = Some(to_enml(&body.join("\n"), dir, &mut note.resources));
+        read.push(note);
+    }
+    Ok(read)
+}
+
+/// Render `markdown` as the ENML content of a note, adding the local files and `data:` URIs it
+/// links to or shows to `resources`.
+pub fn to_enml(markdown: &str, dir: &Path, resources: &mut Vec<Resource>) -> String {
+    let mut html = String::new();
+    html::push_html(&mut html, Parser::new_ext(markdown, Options::ENABLE_TABLES));
+    let mut hashes = Vec::new();
+    sanitize::to_enml(&html, |target, name| {
+        let resource = resource(target, name, dir)?;
+        let data = match resource.decode() {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("couldn't read {}: {}", target, e);
+                return None;
+            }
+        };
+        let hash = format!("{:x}", md5::compute(&data));
+        let mime = resource.mime.clone().unwrap_or_default();
+        if !hashes.contains(&hash) {
+            hashes.push(hash.clone());
+            resources.push(resource);
+        }
+        Some((hash, mime))
+    })
+}
+
+/// `target` with the characters the conversion percent-encodes in link targets decoded.
+fn unescape_target(target: &str) -> String {
+    target
+        .replace("%20", " ")
+        .replace("%23", "#")
+        .replace("%28", "(")
+        .replace("%29", ")")
+        .replace("%3C", "<")
+        .replace("%3E", ">")
+}
+
+/// The resource a link or image with `target` and `name` refers to: a `data:` URI, or a file
+/// relative to `dir` other than a note.
+fn resource(target: &str, name: &str, dir: &Path) -> Option<Resource> {
+    let file_name = Some(name.to_string()).filter(|name| !name.is_empty());
+    if let Some(uri) = target.strip_prefix("data:") {
+        let (header, data) = uri.split_once(',')?;
+        let mime = header.strip_suffix(";base64")?;
+        return Some(Resource {
+            data: Some(data.to_string()),
+            mime: Some(mime.to_string()).filter(|mime| !mime.is_empty()),
+            file_name,
+            ..Resource::default()
+        });
+    }
+    let target = target.strip_prefix("file://").unwrap_or(target);
+    // Other URLs, but not Windows paths like `C:\`.
+    let scheme = target.split_once(':').map_or("", |(scheme, _)| scheme);
+    if scheme.len() > 1 || target.starts_with('#') {
+        return None;
+    }
+    let path = dir.join(unescape_target(target));
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if extension == "md" || extension == "markdown" || !path.is_file() {
+        return None;
+    }
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("couldn't read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let mime = attachments::mime_type(&extension).unwrap_or("application/octet-stream");
+    let mut resource = Resource::new(&data, mime);
+    resource.file_name =
+        file_name.or_else(|| Some(path.file_name()?.to_string_lossy().into_owned()));
+    Some(resource)
+}
+
+/// Split YAML frontmatter from the start of `markdown`.
+fn split_frontmatter(markdown: &str) -> (Option<&str>, &str) {
+    let rest = match markdown.strip_prefix("---\n") {
+        Some(rest) => rest,
+        None => return (None, markdown),
+    };
+    match rest.find("\n---\n") {
+        Some(end) => (Some(&rest[..end]), &rest[end + "\n---\n".len()..]),
+        None => (None, markdown),
+    }
+}
+
+/// Split `markdown` at its headings, other than lines like them in code blocks.
+fn sections(markdown: &str) -> Vec<Section<'_>> {
+    let mut sections = vec![Section {
+        heading: None,
+        title: String::new(),
+        metadata: None,
+        body: Vec::new(),
+    }];
+    let mut fence: Option<&str> = None;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        match fence {
+            Some(marker) if trimmed.starts_with(marker) => fence = None,
+            Some(_) => {}
+            None if trimmed.starts_with("```") => fence = Some("```"),
+            None if trimmed.starts_with("~~~") => fence = Some("~~~"),
+            None => {
+                if let Some((title, metadata)) = heading(line) {
+                    sections.push(Section {
+                        heading: Some(line),
+                        title,
+                        metadata,
+                        body: Vec::new(),
+                    });
+                    continue;
+                }
+            }
+        }
+        sections
+            .last_mut()
+            .expect("starts with a section")
+            .body
+            .push(line);
+    }
+    if sections[0].body.iter().all(|line| line.trim().is_empty()) {
+        sections.remove(0);
+    }
+    sections
+}
+
+/// The title and the fields of the metadata comment of a heading `line`, if it is one.
+fn heading(line: &str) -> Option<(String, Option<Fields>)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    let (title, metadata) = match rest.find("<!-- Metadata:") {
+        Some(at) => {
+            let comment = &rest[at + "<!-- Metadata:".len()..];
+            let comment = comment.trim_end().trim_end_matches("-->");
+            let fields = comment
+                .split(';')
+                .filter_map(|field| field.split_once(':'))
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .collect();
+            (&rest[..at], Some(fields))
+        }
+        None => (rest, None),
+    };
+    Some((title.trim().to_string(), metadata))
+}
+
+fn field<'a>(fields: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Set the metadata of `note` from the fields of its MindForger metadata comment. Fields other
+/// than MindForger's and those the conversion writes are application data.
+fn apply_comment(note: &mut Note, fields: &[(String, String)]) {
+    for (key, value) in fields {
+        match key.as_str() {
+            "tags" => note.tags = list(value, ','),
+            "created" => note.created = parse_time(value),
+            "modified" => note.updated = parse_time(value),
+            "author" => note.attributes.author = Some(value.clone()),
+            "content-class" => note.attributes.content_class = Some(value.clone()),
+            "reminder" => note.attributes.reminder_time = parse_time(value),
+            "reminder-done" => note.attributes.reminder_done_time = parse_time(value),
+            key if MINDFORGER_FIELDS.contains(&key) => {}
+            key => {
+                note.attributes
+                    .application_data
+                    .insert(key.to_string(), value.clone());
+            }
+        }
+    }
+}
+
+/// Set the metadata of `note` from YAML frontmatter as the conversion writes it.
+fn apply_frontmatter(note: &mut Note, frontmatter: &str) {
+    let mut lines = frontmatter.lines().peekable();
+    while let Some(line) = lines.next() {
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) if !key.starts_with(' ') => (key.trim(), value.trim()),
+            _ => continue,
+        };
+        let text = unquote(value);
+        match key {
+            "title" => note.title = Some(text),
+            "created" => note.created = parse_time(&text).or(note.created),
+            "updated" => note.updated = parse_time(&text).or(note.updated),
+            "source" => note.attributes.source_url = Some(text),
+            "author" => note.attributes.author = Some(text),
+            "content-class" => note.attributes.content_class = Some(text),
+            "location" => set_location(note, value),
+            "tags" if value.starts_with('[') => {
+                let items = value.trim_start_matches('[').trim_end_matches(']');
+                note.tags = list(items, ',').iter().map(|tag| unquote(tag)).collect();
+            }
+            "tags" => {
+                note.tags.clear();
+                while let Some(item) = lines.peek().and_then(|line| line.trim().strip_prefix("- "))
+                {
+                    note.tags.push(unquote(item.trim()));
+                    lines.next();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Set the metadata the lines starting `body` have, as the MindForger and Markdown formats write
+/// them below the title, returning the rest.
+fn take_metadata_lines<'a>(note: &mut Note, body: &[&'a str]) -> Vec<&'a str> {
+    let mut lines = body
+        .iter()
+        .copied()
+        .skip_while(|line| line.trim().is_empty());
+    let mut rest = Vec::with_capacity(body.len());
+    while let Some(line) = lines.next() {
+        if let Some(url) = line.strip_prefix("From ").filter(|url| !url.contains(' ')) {
+            note.attributes.source_url = Some(url.to_string());
+        } else if let Some(location) = line.strip_prefix("Location: ") {
+            set_location(note, location);
+        } else if !metadata_line(note, line) {
+            rest.push(line);
+            break;
+        }
+        // The blank line after it.
+        lines.next();
+    }
+    rest.extend(lines);
+    rest
+}
+
+/// Set the metadata of `note` from the line the Markdown format writes below the title, e.g.
+/// `*Created: 2018-12-19 11:13 · Tags: a, b*`, keeping the times and tags frontmatter gave. Returns
+/// whether `line` was one.
+fn metadata_line(note: &mut Note, line: &str) -> bool {
+    let fields = match line
+        .strip_prefix('*')
+        .and_then(|line| line.strip_suffix('*'))
+    {
+        Some(fields) => fields,
+        None => return false,
+    };
+    let fields: Option<Vec<(&str, &str)>> = fields
+        .split(" · ")
+        .map(|field| field.split_once(": "))
+        .collect();
+    let known = ["Created", "Updated", "Tags", "Source", "Author", "Location"];
+    match fields {
+        Some(ref fields) if fields.iter().all(|(key, _)| known.contains(key)) => {}
+        _ => return false,
+    }
+    for (key, value) in fields.unwrap_or_default() {
+        match key {
+            // Frontmatter has them to the second.
+            "Created" => note.created = note.created.or_else(|| parse_time(value)),
+            "Updated" => note.updated = note.updated.or_else(|| parse_time(value)),
+            "Tags" if note.tags.is_empty() => note.tags = list(value, ','),
+            "Tags" => {}
+            "Source" => {
+                let url = value.trim_start_matches('<').trim_end_matches('>');
+                note.attributes.source_url = Some(url.to_string());
+            }
+            "Author" => note.attributes.author = Some(value.to_string()),
+            _ => set_location(note, value),
+        }
+    }
+    true
+}
+
+/// Set the coordinates of `note` from a location like `[52.52, 13.4]`, followed by a link to it
+/// or not.
+fn set_location(note: &mut Note, location: &str) {
+    let coordinates = location
+        .strip_prefix('[')
+        .and_then(|rest| rest.split(']').next())
+        .and_then(|coordinates| coordinates.split_once(','));
+    if let Some((latitude, longitude)) = coordinates {
+        note.attributes.latitude = Some(latitude.trim().to_string());
+        note.attributes.longitude = Some(longitude.trim().to_string());
+    }
+}
+
+/// The items of a `separator`-separated list, trimmed, without empty ones.
+fn list(text: &str, separator: char) -> Vec<String> {
+    text.split(separator)
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// A YAML string, quoted by `frontmatter::quote` or otherwise.
+fn unquote(value: &str) -> String {
+    if let Some(quoted) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        let mut unquoted = String::with_capacity(quoted.len());
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unquoted.extend(chars.next()),
+                c => unquoted.push(c),
+            }
+        }
+        return unquoted;
+    }
+    match value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+    {
+        Some(quoted) => quoted.replace("''", "'"),
+        None => value.to_string(),
+    }
+}
+
+/// Parse a time as the conversion writes them: with an offset, or in local time without one.
+fn parse_time(text: &str) -> Option<DateTime<Local>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Local));
+    }
+    ["%F %T", "%F %R", "%FT%T"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+}
+
+#[test]
+fn test_read_notes() {
+    let dir = std::env::temp_dir().join(format!("enex2mf-outline-{}", std::process::id()));
+    fs::create_dir_all(dir.join("files")).unwrap();
+    fs::write(dir.join("files/a b.png"), b"PNG").unwrap();
+
+    let outline = "# Notebook <!-- Metadata: type: Outline; created: 2018-12-26 09:39:16; -->\n\n\
+        Exported by Evernote/Windows 6.x.\n\n\
+        # First <!-- Metadata: type: Note; tags: a,b; created: 2018-12-19 11:13:04; \
+        modified: 2018-12-20 12:00:00; importance: 0/5; content-class: evernote.food.meal; author: Ann; app: x; -->\n\n\
+        From http://x.com\n\n\
+        Location: [52.52, 13.4](geo:52.52,13.4)\n\n\
+        Some **bold** text.\n\n## Section\n\n```\n# not a heading\n```\n\n\
+        - [x] done\n- [ ] not yet\n\n![cat.png](files/a%20b.png)\n\n\
+        # Second <!-- Metadata: type: Note; -->\n\nSee [site](http://x.com).\n";
+    let notes = read_notes(outline, "notes", &dir).unwrap();
+    assert_eq!(notes.len(), 2);
+    let first = &notes[0];
+    assert_eq!(first.title.as_deref(), Some("First"));
+    assert_eq!(first.tags, ["a", "b"]);
+    assert_eq!(
+        first.created.map(|time| time.naive_local().to_string()),
+        Some("2018-12-19 11:13:04".to_string())
+    );
+    assert!(first.updated > first.created);
+    assert_eq!(first.attributes.author.as_deref(), Some("Ann"));
+    assert_eq!(
+        first.attributes.content_class.as_deref(),
+        Some("evernote.food.meal")
+    );
+    assert_eq!(first.attributes.source_url.as_deref(), Some("http://x.com"));
+    assert_eq!(first.attributes.coordinates(), Some((52.52, 13.4)));
+    assert_eq!(first.attributes.application_data["app"], "x");
+    assert_eq!(
+        first.content.as_deref(),
+        Some(
+            "<en-note><p>Some <strong>bold</strong> text.</p>\n<h2>Section</h2>\n\
+             <pre><code># not a heading\n</code></pre>\n<ul>\n\
+             <li><en-todo checked=\"true\"/>done</li>\n\
+             <li><en-todo checked=\"false\"/>not yet</li>\n</ul>\n\
+             <p><en-media hash=\"55505ba281b015ec31f03ccb151b2a34\" type=\"image/png\"/></p>\n\
+             </en-note>"
+        )
+    );
+    assert_eq!(first.resources[0].decode().unwrap(), b"PNG");
+    assert_eq!(first.resources[0].file_name.as_deref(), Some("cat.png"));
+    assert_eq!(
+        notes[1].content.as_deref(),
+        Some("<en-note><p>See <a href=\"http://x.com\">site</a>.</p>\n</en-note>")
+    );
+
+    let file = "---\ntitle: \"Say \\\"hi\\\"\"\ntags:\n  - \"a b\"\n---\n\n# Say \"hi\"\n\n\
+        *Created: 2018-12-19 11:13 · Source: <http://x.com>*\n\nText\n";
+    let notes = read_notes(file, "hi", &dir).unwrap();
+    assert_eq!(notes[0].title.as_deref(), Some("Say \"hi\""));
+    assert_eq!(notes[0].tags, ["a b"]);
+    assert!(notes[0].created.is_some());
+    assert_eq!(
+        notes[0].attributes.source_url.as_deref(),
+        Some("http://x.com")
+    );
+    assert_eq!(
+        notes[0].content.as_deref(),
+        Some("<en-note><p>Text</p>\n</en-note>")
+    );
+    let notes = read_notes("Just *text*\n\n## Part\n", "plain", &dir).unwrap();
+    assert_eq!(notes[0].title.as_deref(), Some("plain"));
+    assert_eq!(
+        notes[0].content.as_deref(),
+        Some("<en-note><p>Just <em>text</em></p>\n<h2>Part</h2>\n</en-note>")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,72 @@
+//! Writing the output file so that a failed conversion doesn't leave a partial file behind.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes to a temporary file next to `path`, which replaces `path` on `commit`. If dropped
+/// without committing, the temporary file is removed.
+pub struct SafeFile {
+    file: Option<File>,
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl SafeFile {
+    pub fn create(path: &Path) -> io::Result<SafeFile> {
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(".tmp");
+        let temp = path.with_file_name(format!(".{}", name.to_string_lossy()));
+        Ok(SafeFile {
+            file: Some(File::create(&temp)?),
+            temp,
+            path: path.to_owned(),
+        })
+    }
+
+    /// Move the finished file into place.
+    pub fn commit(mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+        }
+        fs::rename(&self.temp, &self.path)
+    }
+}
+
+impl Write for SafeFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("not committed").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("not committed").flush()
+    }
+}
+
+impl Drop for SafeFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+#[test]
+fn test_safe_file() {
+    let dir = std::env::temp_dir().join(format!("enex2mf-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("out.md");
+
+    let mut file = SafeFile::create(&path).unwrap();
+    write!(file, "partial").unwrap();
+    drop(file);
+    assert!(!path.exists());
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+    let mut file = SafeFile::create(&path).unwrap();
+    write!(file, "done").unwrap();
+    file.commit().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "done");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
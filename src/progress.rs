@@ -0,0 +1,104 @@
+//! Progress reporting on stderr, so long conversions don't look hung.
+
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counts the bytes read through it.
+pub struct CountingReader<R: Read> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> CountingReader<R> {
+    /// Wrap `inner`, returning the reader and a handle to its count.
+    pub fn new(inner: R) -> (Self, Rc<Cell<u64>>) {
+        let count = Rc::new(Cell::new(0));
+        let reader = CountingReader {
+            inner,
+            count: count.clone(),
+        };
+        (reader, count)
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// A status line for one input: bytes read (out of `total`, if known) and notes converted. The
+/// line is finished when this is dropped.
+pub struct Progress {
+    name: String,
+    read: Rc<Cell<u64>>,
+    total: Option<u64>,
+    notes: usize,
+    last: Option<Instant>,
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / 1_000_000.0
+}
+
+impl Progress {
+    pub fn new(name: &str, read: Rc<Cell<u64>>, total: Option<u64>) -> Self {
+        Progress {
+            name: name.to_string(),
+            read,
+            total,
+            notes: 0,
+            last: None,
+        }
+    }
+
+    /// Count a note, updating the line at most every `INTERVAL`.
+    pub fn note(&mut self) {
+        self.notes += 1;
+        if self.last.is_none_or(|last| last.elapsed() >= INTERVAL) {
+            self.print();
+            self.last = Some(Instant::now());
+        }
+    }
+
+    fn print(&self) {
+        let read = megabytes(self.read.get());
+        let size = match self.total {
+            Some(total) => format!("{:.1} / {:.1} MB", read, megabytes(total)),
+            None => format!("{:.1} MB", read),
+        };
+        let notes = if self.notes == 1 { "note" } else { "notes" };
+        let mut stderr = io::stderr();
+        let _ = write!(
+            stderr,
+            "\r{}: {}, {} {}\x1b[K",
+            self.name, size, self.notes, notes
+        );
+        let _ = stderr.flush();
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.last.is_some() {
+            self.print();
+            eprintln!();
+        }
+    }
+}
+
+#[test]
+fn test_counting_reader() {
+    let (mut reader, count) = CountingReader::new(&b"hello world"[..]);
+    let mut buf = [0; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(count.get(), 5);
+    reader.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(count.get(), 11);
+}
@@ -0,0 +1,154 @@
+//! The XML pull parser under `EnexParser`: xml-rs by default, or quick-xml with the `quick-xml`
+//! feature. Either way the parser sees xml-rs events, so the choice doesn't change its API.
+
+use crate::cdata::CdataRepair;
+use crate::decode::Utf8Reader;
+use crate::enex::Limits;
+use crate::error::{Error, Result};
+use std::io::Read;
+use xml::common::{Position, TextPosition};
+use xml::reader::{EventReader, XmlEvent};
+
+/// A source of XML events, trimmed of whitespace and with CDATA reported as characters.
+pub trait XmlPull {
+    fn next(&mut self) -> Result<XmlEvent>;
+
+    /// Where the last event read starts.
+    fn position(&self) -> TextPosition;
+
+    /// Skip until `</end_tag>`, including any nested elements.
+    fn skip_element(&mut self, end_tag: &str) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                XmlEvent::StartElement { .. } => depth += 1,
+                XmlEvent::EndElement { ref name } if depth == 0 && name.local_name == end_tag => {
+                    break
+                }
+                XmlEvent::EndElement { .. } => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass the text until `</end_tag>` to `text` as it's read, skipping comments and processing
+    /// instructions. The text may come in pieces, with whitespace around them, and borrowed from
+    /// the parser's buffer rather than allocated where the parser allows, e.g. to decode the
+    /// base64 data of a large attachment without holding it.
+    fn read_text(&mut self, end_tag: &str, text: &mut dyn FnMut(&str) -> Result<()>) -> Result<()> {
+        loop {
+            match self.next()? {
+                XmlEvent::Characters(more) => text(&more)?,
+                XmlEvent::Comment(_) | XmlEvent::ProcessingInstruction { .. } => {}
+                XmlEvent::EndElement { ref name } if name.local_name == end_tag => return Ok(()),
+                x => {
+                    let context = "expected text".to_string();
+                    return Err(Error::UnexpectedEvent(context, x, self.position()));
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> XmlPull for EventReader<R> {
+    fn next(&mut self) -> Result<XmlEvent> {
+        Ok(EventReader::next(self)?)
+    }
+
+    fn position(&self) -> TextPosition {
+        Position::position(self)
+    }
+}
+
+/// Fails with `Error::LimitExceeded` on elements nested deeper or text longer than `Limits`
+/// allow.
+pub struct Bounded<P> {
+    inner: P,
+    limits: Limits,
+    depth: usize,
+}
+
+impl<P: XmlPull> XmlPull for Bounded<P> {
+    fn next(&mut self) -> Result<XmlEvent> {
+        let event = self.inner.next()?;
+        match event {
+            XmlEvent::StartElement { .. } => {
+                self.depth += 1;
+                if self.depth > self.limits.depth {
+                    let message = format!("elements nested {} deep", self.depth);
+                    return Err(Error::LimitExceeded(message));
+                }
+            }
+            XmlEvent::EndElement { .. } => self.depth -= 1,
+            XmlEvent::Characters(ref text) if text.len() > self.limits.text_len => {
+                let message = format!("text of {} bytes", text.len());
+                return Err(Error::LimitExceeded(message));
+            }
+            _ => {}
+        }
+        Ok(event)
+    }
+
+    fn position(&self) -> TextPosition {
+        self.inner.position()
+    }
+
+    /// With the inner parser's own skipping, so that what's skipped isn't bounded.
+    fn skip_element(&mut self, end_tag: &str) -> Result<()> {
+        self.inner.skip_element(end_tag)?;
+        // The start tag was counted by `next`.
+        self.depth -= 1;
+        Ok(())
+    }
+
+    /// With the inner parser's own reading, failing once the text gets longer than `Limits`
+    /// allow.
+    fn read_text(&mut self, end_tag: &str, text: &mut dyn FnMut(&str) -> Result<()>) -> Result<()> {
+        let text_len = self.limits.text_len;
+        let mut len = 0;
+        self.inner.read_text(end_tag, &mut |more| {
+            len += more.len();
+            if len > text_len {
+                let message = format!("text of over {} bytes", text_len);
+                return Err(Error::LimitExceeded(message));
+            }
+            text(more)
+        })?;
+        self.depth -= 1;
+        Ok(())
+    }
+}
+
+/// The pull parser selected by features.
+#[cfg(not(feature = "quick-xml"))]
+pub type Pull<R> = Bounded<EventReader<CdataRepair<Utf8Reader<R>>>>;
+#[cfg(feature = "quick-xml")]
+pub type Pull<R> = Bounded<crate::quick::QuickReader<CdataRepair<Utf8Reader<R>>>>;
+
+/// Pull events from `reader`, transcoded to UTF-8 and with its CDATA sections repaired, with the
+/// parser selected by features.
+pub fn new<R: Read>(reader: R, limits: &Limits) -> Pull<R> {
+    let reader = CdataRepair::new(Utf8Reader::new(reader));
+    Bounded {
+        inner: parser(reader, limits),
+        limits: *limits,
+        depth: 0,
+    }
+}
+
+// Both fail on longer text before building it up, where `Bounded` can only fail after.
+#[cfg(not(feature = "quick-xml"))]
+fn parser<R: Read>(reader: R, limits: &Limits) -> EventReader<R> {
+    xml::reader::ParserConfig::new()
+        .trim_whitespace(true)
+        .cdata_to_characters(true)
+        .max_entity_expansion_length(limits.entity_expansion)
+        .max_data_length(limits.text_len)
+        .create_reader(reader)
+}
+
+#[cfg(feature = "quick-xml")]
+fn parser<R: Read>(reader: R, limits: &Limits) -> crate::quick::QuickReader<R> {
+    crate::quick::QuickReader::new(reader, limits)
+}
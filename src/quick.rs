@@ -0,0 +1,419 @@
+//! A faster pull parser built on quick-xml, used instead of xml-rs with the `quick-xml` feature.
+//!
+//! Markup is read into one reused buffer and translated to the xml-rs events the rest of the
+//! parser expects. Text is read straight from the input's buffer: `read_text` passes it on in
+//! pieces borrowed from there, without allocating, which matters for the base64 data of large
+//! attachments, and `next` builds it up only to the `Limits::text_len`.
+
+use crate::enex::Limits;
+use crate::error::{Error, Result};
+use crate::pull::XmlPull;
+use quick_xml::encoding::EncodingError;
+use quick_xml::escape::EscapeError;
+use quick_xml::events::Event;
+use quick_xml::name::QName;
+use quick_xml::Reader;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use xml::attribute::OwnedAttribute;
+use xml::common::{TextPosition, XmlVersion};
+use xml::name::OwnedName;
+use xml::namespace::Namespace;
+use xml::reader::XmlEvent;
+
+/// The longest entity reference to look for the end of, e.g. `&#x1F600;`.
+const MAX_ENTITY_LEN: usize = 16;
+
+/// Reads `XmlEvent`s like xml-rs' `EventReader` configured to trim whitespace and report CDATA as
+/// characters.
+pub struct QuickReader<R: Read> {
+    reader: Reader<Lines<BufReader<R>>>,
+    buf: Vec<u8>,
+    started: bool,
+    /// Events read ahead: the markup after text, and the end of an empty element, e.g. `<br/>`.
+    pending: VecDeque<XmlEvent>,
+    /// The longest text `next` builds up, in bytes.
+    text_len: usize,
+}
+
+impl<R: Read> QuickReader<R> {
+    pub fn new(reader: R, limits: &Limits) -> Self {
+        let mut reader = Reader::from_reader(Lines::new(BufReader::new(reader)));
+        // Text is read and trimmed here, and empty elements expanded.
+        reader.config_mut().trim_text(false);
+        QuickReader {
+            reader,
+            buf: Vec::new(),
+            started: false,
+            pending: VecDeque::new(),
+            text_len: limits.text_len,
+        }
+    }
+
+    /// Pass the text up to the next markup to `text` in pieces, as it is buffered from the input
+    /// rather than built up. Entity references are unescaped.
+    fn read_text_pieces(&mut self, text: &mut dyn FnMut(&str) -> Result<()>) -> Result<()> {
+        let mut stream = self.reader.stream();
+        // A character or entity reference split between reads of the input.
+        let mut split: Vec<u8> = Vec::new();
+        loop {
+            let buf = stream.fill_buf()?;
+            if buf.is_empty() {
+                if split.is_empty() {
+                    return Ok(());
+                }
+                return Err(incomplete(&split));
+            }
+            let (consumed, done) = if split.is_empty() {
+                let end = buf.iter().position(|&b| b == b'<').unwrap_or(buf.len());
+                let consumed = match buf[..end].iter().position(|&b| b == b'&') {
+                    Some(0) => match buf[..end].iter().position(|&b| b == b';') {
+                        Some(semicolon) => {
+                            text(&unescape(&buf[..=semicolon])?)?;
+                            semicolon + 1
+                        }
+                        None if end < buf.len() => return Err(incomplete(&buf[..end])),
+                        None => {
+                            split.extend_from_slice(&buf[..end]);
+                            end
+                        }
+                    },
+                    Some(amp) => {
+                        text(utf8(&buf[..amp])?)?;
+                        amp
+                    }
+                    None => match std::str::from_utf8(&buf[..end]) {
+                        Ok(piece) => {
+                            text(piece)?;
+                            end
+                        }
+                        // A character split at the end of the buffer.
+                        Err(err) if err.error_len().is_none() => {
+                            let valid = err.valid_up_to();
+                            text(utf8(&buf[..valid])?)?;
+                            split.extend_from_slice(&buf[valid..end]);
+                            end
+                        }
+                        Err(err) => return Err(encoding_error(err)),
+                    },
+                };
+                (consumed, consumed == end && end < buf.len())
+            } else if split[0] == b'&' {
+                let (consumed, semicolon) = match buf.iter().position(|&b| b == b';') {
+                    Some(semicolon) => (semicolon + 1, true),
+                    None => (buf.len(), false),
+                };
+                split.extend_from_slice(&buf[..consumed]);
+                if split.len() > MAX_ENTITY_LEN || split.contains(&b'<') {
+                    return Err(incomplete(&split));
+                }
+                if semicolon {
+                    text(&unescape(&split)?)?;
+                    split.clear();
+                }
+                (consumed, false)
+            } else {
+                split.push(buf[0]);
+                match std::str::from_utf8(&split) {
+                    Ok(piece) => {
+                        text(piece)?;
+                        split.clear();
+                    }
+                    Err(err) if err.error_len().is_none() => {}
+                    Err(err) => return Err(encoding_error(err)),
+                }
+                (1, false)
+            };
+            stream.consume(consumed);
+            if done {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str> {
+    std::str::from_utf8(bytes).map_err(encoding_error)
+}
+
+fn encoding_error(err: std::str::Utf8Error) -> Error {
+    quick_xml::Error::from(EncodingError::from(err)).into()
+}
+
+fn unescape(reference: &[u8]) -> Result<String> {
+    let reference = utf8(reference)?;
+    let unescaped = quick_xml::escape::unescape(reference).map_err(quick_xml::Error::from)?;
+    Ok(unescaped.into_owned())
+}
+
+/// The error for an entity reference without its `;`, or a character cut off, at `split`.
+fn incomplete(split: &[u8]) -> Error {
+    match std::str::from_utf8(split) {
+        Ok(_) => {
+            let err = EscapeError::UnterminatedEntity(0..split.len());
+            quick_xml::Error::from(err).into()
+        }
+        Err(err) => encoding_error(err),
+    }
+}
+
+/// `event` as xml-rs reports it, or `None` for those it's configured to leave out. Empty elements
+/// are reported as their start.
+fn translate(event: Event) -> Result<Option<XmlEvent>> {
+    let name = |name: &[u8]| OwnedName::local(String::from_utf8_lossy(name));
+    let event = match event {
+        Event::Start(e) | Event::Empty(e) => {
+            let mut attributes = Vec::new();
+            for attr in e.attributes() {
+                let attr = attr.map_err(quick_xml::Error::from)?;
+                attributes.push(OwnedAttribute::new(
+                    name(attr.key.local_name().as_ref()),
+                    attr.unescape_value()?,
+                ));
+            }
+            XmlEvent::StartElement {
+                name: name(e.local_name().as_ref()),
+                attributes,
+                namespace: Namespace::empty(),
+            }
+        }
+        Event::End(e) => XmlEvent::EndElement {
+            name: name(e.local_name().as_ref()),
+        },
+        Event::Text(e) => match e.unescape()?.trim() {
+            "" => return Ok(None),
+            text => XmlEvent::Characters(text.to_string()),
+        },
+        Event::CData(e) => {
+            XmlEvent::Characters(e.decode().map_err(quick_xml::Error::from)?.into_owned())
+        }
+        Event::Eof => XmlEvent::EndDocument,
+        Event::Decl(_) | Event::PI(_) | Event::DocType(_) | Event::Comment(_) => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+impl<R: Read> XmlPull for QuickReader<R> {
+    fn next(&mut self) -> Result<XmlEvent> {
+        // xml-rs starts every document this way, declaration or not.
+        if !self.started {
+            self.started = true;
+            return Ok(XmlEvent::StartDocument {
+                version: XmlVersion::Version10,
+                encoding: "UTF-8".to_string(),
+                standalone: None,
+            });
+        }
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            let mut text = String::new();
+            let text_len = self.text_len;
+            self.read_text_pieces(&mut |piece| {
+                if text.len() + piece.len() > text_len {
+                    let message = format!("text of over {} bytes", text_len);
+                    return Err(Error::LimitExceeded(message));
+                }
+                text.push_str(piece);
+                Ok(())
+            })?;
+            self.buf.clear();
+            let event = self.reader.read_event_into(&mut self.buf)?;
+            let empty = matches!(event, Event::Empty(_));
+            let event = translate(event)?;
+            if empty {
+                if let Some(XmlEvent::StartElement { ref name, .. }) = event {
+                    let name = name.clone();
+                    self.pending.push_back(XmlEvent::EndElement { name });
+                }
+            }
+            let trimmed = text.trim();
+            match event {
+                _ if !trimmed.is_empty() => {
+                    let characters = XmlEvent::Characters(trimmed.to_string());
+                    if let Some(event) = event {
+                        self.pending.push_front(event);
+                    }
+                    return Ok(characters);
+                }
+                Some(event) => return Ok(event),
+                None => {}
+            }
+        }
+    }
+
+    /// Where the last element read starts.
+    fn position(&self) -> TextPosition {
+        self.reader.get_ref().markup
+    }
+
+    /// Without looking at what's skipped, which is much faster for large text.
+    fn skip_element(&mut self, end_tag: &str) -> Result<()> {
+        // The end of an empty element.
+        if self.pending.pop_front().is_some() {
+            return Ok(());
+        }
+        self.buf.clear();
+        self.reader
+            .read_to_end_into(QName(end_tag.as_bytes()), &mut self.buf)?;
+        Ok(())
+    }
+
+    /// In pieces borrowed from the input's buffer, however long the text.
+    fn read_text(&mut self, end_tag: &str, text: &mut dyn FnMut(&str) -> Result<()>) -> Result<()> {
+        // The end of an empty element.
+        if self.pending.pop_front().is_some() {
+            return Ok(());
+        }
+        loop {
+            self.read_text_pieces(text)?;
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::End(ref e) if e.local_name().as_ref() == end_tag.as_bytes() => return Ok(()),
+                Event::CData(e) => text(&e.decode().map_err(quick_xml::Error::from)?)?,
+                event => {
+                    if let Some(event) = translate(event)? {
+                        let context = "expected text".to_string();
+                        return Err(Error::UnexpectedEvent(context, event, self.position()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tracks the line and column of the input as quick-xml consumes it.
+struct Lines<R> {
+    inner: R,
+    at: TextPosition,
+    /// Where the last `<` consumed is.
+    markup: TextPosition,
+}
+
+impl<R> Lines<R> {
+    fn new(inner: R) -> Self {
+        Lines {
+            inner,
+            at: TextPosition::new(),
+            markup: TextPosition::new(),
+        }
+    }
+}
+
+fn advance(at: &mut TextPosition, markup: &mut TextPosition, bytes: &[u8]) {
+    for &b in bytes {
+        match b {
+            b'\n' => {
+                at.row += 1;
+                at.column = 0;
+            }
+            b'<' => {
+                *markup = *at;
+                at.column += 1;
+            }
+            _ => at.column += 1,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Lines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        advance(&mut self.at, &mut self.markup, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Lines<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The bytes are still buffered, so this doesn't read.
+        if let Ok(buf) = self.inner.fill_buf() {
+            advance(&mut self.at, &mut self.markup, &buf[..amt]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+#[test]
+fn test_same_events() {
+    use xml::reader::ParserConfig;
+
+    let xml = r#"<?xml version="1.0"?><!DOCTYPE en-export>
+        <en-export a="1 &amp; 2"><note><title>x &lt; y</title><empty/>
+        <content><![CDATA[<en-note/>]]></content></note></en-export>"#;
+    let mut expected = ParserConfig::new()
+        .trim_whitespace(true)
+        .cdata_to_characters(true)
+        .create_reader(xml.as_bytes());
+    let mut quick = QuickReader::new(xml.as_bytes(), &Limits::default());
+    loop {
+        let event = quick.next().unwrap();
+        let expected = expected.next().unwrap();
+        match (&event, &expected) {
+            (XmlEvent::StartDocument { .. }, XmlEvent::StartDocument { .. }) => {}
+            (
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                },
+                XmlEvent::StartElement {
+                    name: n,
+                    attributes: a,
+                    ..
+                },
+            ) => assert_eq!((name, attributes), (n, a)),
+            _ => assert_eq!(event, expected),
+        }
+        if event == XmlEvent::EndDocument {
+            break;
+        }
+    }
+}
+
+#[test]
+fn test_text_pieces() {
+    // Longer than the input's buffer, so that characters and references are split between reads.
+    let escaped = "caf\u{e9} &amp; &#x1F600; ".repeat(1000);
+    let expected = "caf\u{e9} & \u{1F600} ".repeat(1000);
+    let xml = format!("<a><b>{}</b><c/><b>  x &lt; y  </b></a>", escaped);
+
+    let mut quick = QuickReader::new(xml.as_bytes(), &Limits::default());
+    quick.next().unwrap();
+    quick.next().unwrap();
+    quick.next().unwrap();
+    let mut pieces = 0;
+    let mut read = String::new();
+    quick
+        .read_text("b", &mut |piece| {
+            pieces += 1;
+            read.push_str(piece);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(read, expected);
+    assert!(pieces > 1);
+    assert!(matches!(
+        quick.next().unwrap(),
+        XmlEvent::StartElement { .. }
+    ));
+    quick.read_text("c", &mut |_| panic!("empty")).unwrap();
+    quick.next().unwrap();
+    assert_eq!(
+        quick.next().unwrap(),
+        XmlEvent::Characters("x < y".to_string())
+    );
+
+    let limits = Limits {
+        text_len: 100,
+        ..Limits::default()
+    };
+    let mut quick = QuickReader::new(xml.as_bytes(), &limits);
+    quick.next().unwrap();
+    quick.next().unwrap();
+    quick.next().unwrap();
+    assert!(matches!(quick.next(), Err(Error::LimitExceeded(_))));
+}
@@ -0,0 +1,172 @@
+//! Exports made by apps other than Evernote, which deviate from its exports in ways that are
+//! tolerated once the app is known from `<en-export application=...>`.
+
+use crate::attachments;
+use crate::enex::Resource;
+use std::path::Path;
+
+/// An app that writes ENEX exports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Producer {
+    /// Evernote, the reference for the rest.
+    Evernote,
+    /// Yinxiang Biji (印象笔记), Evernote's Chinese edition.
+    Yinxiang,
+    AppleNotes,
+    Nimbus,
+    Notability,
+    /// Any other app, or an export that doesn't say.
+    Other,
+}
+
+impl Producer {
+    /// The app named by `<en-export application=...>`, e.g. `Evernote/Windows`.
+    pub fn from_application(application: Option<&str>) -> Producer {
+        let application = match application {
+            Some(application) => application.to_lowercase(),
+            None => return Producer::Other,
+        };
+        if application.contains("yinxiang")
+            || application.starts_with("yxbj")
+            || application.contains("印象笔记")
+        {
+            Producer::Yinxiang
+        } else if application.contains("evernote") {
+            Producer::Evernote
+        } else if application.contains("apple") || application.starts_with("notes") {
+            Producer::AppleNotes
+        } else if application.contains("nimbus") {
+            Producer::Nimbus
+        } else if application.contains("notability") {
+            Producer::Notability
+        } else {
+            Producer::Other
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Producer::Evernote => "Evernote",
+            Producer::Yinxiang => "Yinxiang Biji",
+            Producer::AppleNotes => "Apple Notes",
+            Producer::Nimbus => "Nimbus Note",
+            Producer::Notability => "Notability",
+            Producer::Other => "an unknown app",
+        }
+    }
+
+    /// How the app's exports deviate from Evernote's.
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Producer::Evernote | Producer::Other => Quirks::default(),
+            // Attributes of Yinxiang's own, and content in GBK in exports otherwise UTF-8.
+            Producer::Yinxiang => Quirks {
+                lenient: true,
+                gbk: true,
+                ..Quirks::default()
+            },
+            // Times are written without an offset in local time, and elements of Apple's own.
+            Producer::AppleNotes => Quirks {
+                lenient: true,
+                local_times: true,
+                guess_mime: true,
+                ..Quirks::default()
+            },
+            // Times like `2018-12-26 08:39:16` in local time, and attributes of Nimbus's own.
+            Producer::Nimbus => Quirks {
+                lenient: true,
+                local_times: true,
+                ..Quirks::default()
+            },
+            // Attachments without a MIME type, and referenced by hashes in capitals.
+            Producer::Notability => Quirks {
+                lenient: true,
+                guess_mime: true,
+                lowercase_hashes: true,
+                ..Quirks::default()
+            },
+        }
+    }
+}
+
+/// Deviations from Evernote's exports to tolerate. None are by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    /// Parse as in lenient mode (see `EnexParser::lenient`).
+    pub lenient: bool,
+    /// Times without an offset are local, rather than UTC like Evernote's.
+    pub local_times: bool,
+    /// Resources without a `<mime>` get one from the extension of their file name.
+    pub guess_mime: bool,
+    /// `<en-media>` hashes may be in capitals, where Evernote's are lowercase.
+    pub lowercase_hashes: bool,
+    /// Bytes that aren't UTF-8 are GBK rather than Windows-1252. Only the app named in the
+    /// export says so, as exports are decoded before they're parsed.
+    pub gbk: bool,
+}
+
+impl Quirks {
+    /// Whether there's anything to tolerate.
+    pub fn any(self) -> bool {
+        self != Quirks::default()
+    }
+
+    /// Fix up a resource as read from the export.
+    pub fn resource(self, resource: &mut Resource) {
+        if self.guess_mime && resource.mime.is_none() {
+            resource.mime = resource
+                .file_name
+                .as_deref()
+                .and_then(|name| Path::new(name).extension())
+                .and_then(|extension| {
+                    attachments::mime_type(&extension.to_string_lossy().to_lowercase())
+                })
+                .map(String::from);
+        }
+    }
+
+    /// Fix up note content as read from the export.
+    pub fn content(self, content: String) -> String {
+        if !self.lowercase_hashes {
+            return content;
+        }
+        let mut fixed = String::with_capacity(content.len());
+        let mut rest = &content[..];
+        while let Some(start) = rest.find("hash=\"") {
+            let (before, after) = rest.split_at(start + "hash=\"".len());
+            let end = after.find('"').unwrap_or(after.len());
+            fixed.push_str(before);
+            fixed.push_str(&after[..end].to_lowercase());
+            rest = &after[end..];
+        }
+        fixed.push_str(rest);
+        fixed
+    }
+}
+
+#[test]
+fn test_quirks() {
+    assert_eq!(
+        Producer::from_application(Some("Evernote/Windows")),
+        Producer::Evernote
+    );
+    assert_eq!(
+        Producer::from_application(Some("YXBJ/Windows")),
+        Producer::Yinxiang
+    );
+    let notability = Producer::from_application(Some("Notability"));
+    assert_eq!(notability, Producer::Notability);
+    assert!(!Producer::from_application(None).quirks().any());
+
+    let quirks = notability.quirks();
+    assert_eq!(
+        quirks.content("<en-media hash=\"AB12\" type=\"image/png\"/>".to_string()),
+        "<en-media hash=\"ab12\" type=\"image/png\"/>"
+    );
+    let mut resource = Resource {
+        file_name: Some("Scan.PDF".to_string()),
+        ..Resource::default()
+    };
+    quirks.resource(&mut resource);
+    assert_eq!(resource.mime.as_deref(), Some("application/pdf"));
+}
@@ -0,0 +1,104 @@
+//! Renders Markdown in the markup of formats that aren't Markdown. Note content is converted to
+//! Markdown as for the other formats, then rendered from the Markdown events: [`render`] walks
+//! the events, numbering and nesting lists, and a [`Markup`] writes each piece in its syntax.
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// How a format writes the pieces of Markdown.
+pub trait Markup {
+    /// Write the start of `tag`, which is neither a list nor an item.
+    fn start(&mut self, out: &mut String, tag: Tag<'_>);
+
+    /// Write the end of `tag`, which is neither a list, an item nor a paragraph.
+    fn end(&mut self, out: &mut String, tag: Tag<'_>);
+
+    /// The marker of an item of a list nested `depth` deep, from 1, with the item's number if
+    /// the list is numbered.
+    fn item(&mut self, depth: usize, number: Option<usize>) -> String;
+
+    fn text(&mut self, out: &mut String, text: &str) {
+        out.push_str(text);
+    }
+
+    fn html(&mut self, out: &mut String, html: &str);
+
+    fn inline_html(&mut self, out: &mut String, html: &str);
+
+    fn footnote_reference(&mut self, out: &mut String, name: &str);
+
+    fn hard_break(&mut self, out: &mut String) {
+        out.push('\n');
+    }
+}
+
+/// Render `markdown`, GitHub tables included, with `markup`. Items are a line each and
+/// paragraphs end with a blank line; trailing whitespace is trimmed.
+pub fn render(markdown: &str, markup: &mut impl Markup) -> String {
+    let mut out = String::new();
+    // Numbering of the lists we're in, or `None` for bullet lists.
+    let mut lists: Vec<Option<usize>> = Vec::new();
+    for event in Parser::new_ext(markdown, Options::ENABLE_TABLES) {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                if !out.is_empty() && !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                lists.push(start);
+            }
+            Event::Start(Tag::Item) => {
+                let number = lists.last().copied().flatten();
+                out.push_str(&markup.item(lists.len(), number));
+                if let Some(Some(number)) = lists.last_mut() {
+                    *number += 1;
+                }
+            }
+            Event::Start(tag) => markup.start(&mut out, tag),
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    out.push('\n');
+                }
+            }
+            Event::End(Tag::Item) => {
+                let len = out.trim_end_matches('\n').len();
+                out.truncate(len);
+                out.push('\n');
+            }
+            Event::End(Tag::Paragraph) => out.push_str("\n\n"),
+            Event::End(tag) => markup.end(&mut out, tag),
+            Event::Text(text) => markup.text(&mut out, &text),
+            Event::Html(html) => markup.html(&mut out, &html),
+            Event::InlineHtml(html) => markup.inline_html(&mut out, &html),
+            Event::FootnoteReference(name) => markup.footnote_reference(&mut out, &name),
+            Event::SoftBreak => out.push('\n'),
+            Event::HardBreak => markup.hard_break(&mut out),
+        }
+    }
+    let len = out.trim_end().len();
+    out.truncate(len);
+    out
+}
+
+/// A table in pipes, as Org and Zim write them, with a rule of `columns` cells below the head.
+/// Cells keep the spaces around their text, as the parser leaves them.
+pub fn start_pipe_table(out: &mut String, tag: &Tag<'_>, columns: &mut usize) {
+    match tag {
+        Tag::Table(alignments) => *columns = alignments.len(),
+        Tag::TableHead | Tag::TableRow => out.push('|'),
+        _ => {}
+    }
+}
+
+/// The end of a part of a table started by [`start_pipe_table`].
+pub fn end_pipe_table(out: &mut String, tag: &Tag<'_>, columns: usize) {
+    match tag {
+        Tag::TableCell => out.push('|'),
+        Tag::TableHead => {
+            out.push_str("\n|");
+            out.push_str(&"---|".repeat(columns));
+            out.push('\n');
+        }
+        Tag::TableRow | Tag::Table(_) => out.push('\n'),
+        _ => {}
+    }
+}
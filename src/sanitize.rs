@@ -0,0 +1,595 @@
+//! Cleans up ENML before it's converted. Web clips and syncing leave notes full of inline styles,
+//! attribute-less `<span>`s and `<div>`s wrapping nothing but another `<div>`, which only add
+//! noise to the converted note.
+//!
+//! ENML is XHTML, but it's parsed as HTML like a browser would, which balances unterminated
+//! elements of malformed web clips. Only self-closing elements, which HTML reads as opening ones,
+//! need repairing first.
+//!
+//! Going the other way, `to_enml` turns HTML into ENML, keeping only what Evernote accepts.
+
+use crate::enml::{self, Media};
+use html2md::common::get_tag_attr;
+use html5ever::driver::ParseOpts;
+use html5ever::serialize::{serialize, SerializeOpts, TraversalScope};
+use html5ever::tendril::TendrilSink;
+use html5ever::{namespace_url, ns, parse_document, Attribute, LocalName, QualName};
+use markup5ever_rcdom::{Handle, Node, NodeData, RcDom, SerializableHandle};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Style properties that are kept: those the conversion looks at, e.g. for code blocks and
+/// highlights, and plain formatting.
+const KEPT_STYLES: &[&str] = &[
+    "-en-codeblock",
+    "--en-highlight",
+    "background-color",
+    "color",
+    "font-family",
+    "font-style",
+    "font-weight",
+    "text-decoration",
+];
+
+/// Elements that HTML knows are empty, whose self-closing tags it reads right.
+pub(crate) const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements ENML allows, as well as its own `<en-media>` and `<en-todo>`.
+const ENML_ELEMENTS: &[&str] = &[
+    "a",
+    "abbr",
+    "acronym",
+    "address",
+    "area",
+    "b",
+    "bdo",
+    "big",
+    "blockquote",
+    "br",
+    "caption",
+    "center",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "del",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "en-media",
+    "en-todo",
+    "font",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "map",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "s",
+    "samp",
+    "small",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "tt",
+    "u",
+    "ul",
+    "var",
+    "xmp",
+];
+
+/// Elements that aren't ENML and are dropped with what's in them, rather than leaving their
+/// contents in place.
+const DROPPED_ELEMENTS: &[&str] = &[
+    "applet", "button", "embed", "head", "iframe", "noscript", "object", "script", "select",
+    "style", "textarea", "title",
+];
+
+/// Attributes ENML doesn't allow on any element, besides `on...` event handlers.
+const DROPPED_ATTRIBUTES: &[&str] = &["accesskey", "class", "data", "dynsrc", "id", "tabindex"];
+
+/// The hash and MIME type of the resource a link or image refers to by its target and name.
+type MediaFn<'a> = dyn FnMut(&str, &str) -> Option<(String, String)> + 'a;
+
+/// Clean up `content`, returning the HTML of its `<en-note>`.
+pub fn sanitize(content: &str) -> String {
+    let dom = parse(content);
+    let root = find(&dom.document, "en-note")
+        .or_else(|| find(&dom.document, "body"))
+        .unwrap_or_else(|| dom.document.clone());
+    clean_element(&root);
+    to_html(root, TraversalScope::IncludeNode)
+}
+
+/// Clean up `content` like `sanitize`, returning only what's within its `<en-note>`, as HTML that
+/// renders outside Evernote: `<en-media>` becomes an image of or a link to the `media` with its
+/// hash, and `<en-todo>` a checkbox.
+pub fn inner_html(content: &str, media: &HashMap<String, Media>) -> String {
+    let dom = parse(content);
+    match find(&dom.document, "en-note").or_else(|| find(&dom.document, "body")) {
+        Some(root) => {
+            clean_element(&root);
+            replace_enml(&root, media);
+            to_html(root, TraversalScope::ChildrenOnly(None))
+        }
+        None => String::new(),
+    }
+}
+
+fn parse(content: &str) -> RcDom {
+    let dom = parse_document(RcDom::default(), ParseOpts::default()).one(&*repair(content));
+    if let Some(root) = find(&dom.document, "en-note") {
+        adopt_following(&root);
+    }
+    dom
+}
+
+/// `content` with self-closing tags of elements other than `VOID_ELEMENTS`, e.g. `<div/>` or
+/// `<en-media .../>`, closed by an end tag.
+pub fn repair(content: &str) -> Cow<'_, str> {
+    if !content.contains("/>") {
+        return Cow::Borrowed(content);
+    }
+    let mut repaired = String::with_capacity(content.len() + 64);
+    let mut rest = content;
+    while let Some(open) = rest.find('<') {
+        repaired.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let name_len = rest[1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == ':'))
+            .map_or(rest.len() - 1, |len| len);
+        let close = match tag_end(rest) {
+            Some(close) if name_len > 0 => close,
+            // Not a start tag: an end tag, comment, declaration or a stray `<`.
+            _ => {
+                repaired.push('<');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let name = &rest[1..1 + name_len];
+        let tag = &rest[..=close];
+        match tag.strip_suffix("/>") {
+            Some(start) if !VOID_ELEMENTS.contains(&name.to_lowercase().as_str()) => {
+                repaired.push_str(start.trim_end());
+                repaired.push_str("></");
+                repaired.push_str(name);
+                repaired.push('>');
+            }
+            _ => repaired.push_str(tag),
+        }
+        rest = &rest[close + 1..];
+    }
+    repaired.push_str(rest);
+    Cow::Owned(repaired)
+}
+
+/// Where the tag at the start of `html` ends, skipping `>` within quoted attribute values.
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '>') => return Some(i),
+            (None, '<') => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Move what a malformed note has after its `<en-note>` into it, e.g. after an early
+/// `</en-note>`.
+fn adopt_following(root: &Handle) {
+    let parent = match root.parent.take().and_then(|parent| parent.upgrade()) {
+        Some(parent) => parent,
+        None => return,
+    };
+    root.parent.set(Some(Rc::downgrade(&parent)));
+    let mut siblings = parent.children.borrow_mut();
+    let index = match siblings.iter().position(|child| Rc::ptr_eq(child, root)) {
+        Some(index) => index,
+        None => return,
+    };
+    for sibling in siblings.drain(index + 1..) {
+        sibling.parent.set(Some(Rc::downgrade(root)));
+        root.children.borrow_mut().push(sibling);
+    }
+}
+
+fn to_html(node: Handle, scope: TraversalScope) -> String {
+    let mut html = Vec::new();
+    let options = SerializeOpts {
+        traversal_scope: scope,
+        ..SerializeOpts::default()
+    };
+    serialize(&mut html, &SerializableHandle::from(node), options)
+        .expect("writing to a Vec doesn't fail");
+    String::from_utf8(html).expect("serialized from a str")
+}
+
+fn tag_name(node: &Handle) -> Option<&str> {
+    match node.data {
+        NodeData::Element { ref name, .. } => Some(&name.local),
+        _ => None,
+    }
+}
+
+/// The first element named `name` within `node`.
+fn find(node: &Handle, name: &str) -> Option<Handle> {
+    node.children.borrow().iter().find_map(|child| {
+        if tag_name(child) == Some(name) {
+            Some(child.clone())
+        } else {
+            find(child, name)
+        }
+    })
+}
+
+/// Clean up the attributes of `node` and what's within it.
+fn clean_element(node: &Handle) {
+    if let NodeData::Element { ref attrs, .. } = node.data {
+        clean_style(&mut attrs.borrow_mut());
+    }
+    clean(node);
+}
+
+/// Clean up what's within `node`.
+fn clean(node: &Handle) {
+    let children = node.children.take();
+    let mut cleaned = Vec::with_capacity(children.len());
+    for child in children {
+        clean(&child);
+        let mut unwrap = false;
+        if let NodeData::Element { ref attrs, .. } = child.data {
+            clean_style(&mut attrs.borrow_mut());
+            let bare = attrs.borrow().is_empty();
+            match tag_name(&child) {
+                Some("span" | "font") if child.children.borrow().is_empty() => continue,
+                Some("span" | "font") => unwrap = bare,
+                Some("div") => unwrap = bare && only_div(&child),
+                _ => {}
+            }
+        }
+        if unwrap {
+            for grandchild in child.children.take() {
+                grandchild.parent.set(Some(Rc::downgrade(node)));
+                cleaned.push(grandchild);
+            }
+        } else {
+            cleaned.push(child);
+        }
+    }
+    node.children.replace(cleaned);
+}
+
+/// Whether all there is within `node` is a single `<div>`.
+fn only_div(node: &Handle) -> bool {
+    let children = node.children.borrow();
+    let mut elements = children.iter().filter(|child| match child.data {
+        NodeData::Text { ref contents } => !contents.borrow().trim().is_empty(),
+        _ => true,
+    });
+    matches!((elements.next(), elements.next()), (Some(div), None) if tag_name(div) == Some("div"))
+}
+
+/// Replace the ENML elements within `node` with HTML ones.
+fn replace_enml(node: &Handle, media: &HashMap<String, Media>) {
+    let children = node.children.take();
+    let mut replaced = Vec::with_capacity(children.len());
+    for child in children {
+        replace_enml(&child, media);
+        let replacement = match tag_name(&child) {
+            Some("en-media") => get_tag_attr(&child, "hash")
+                .and_then(|hash| media.get(&hash))
+                .map(media_element),
+            Some("en-todo") => {
+                let mut attrs = vec![("type", "checkbox"), ("disabled", "")];
+                if get_tag_attr(&child, "checked").as_deref() == Some("true") {
+                    attrs.push(("checked", ""));
+                }
+                Some(element("input", &attrs))
+            }
+            _ => {
+                replaced.push(child);
+                continue;
+            }
+        };
+        replaced.extend(replacement);
+    }
+    for child in &replaced {
+        child.parent.set(Some(Rc::downgrade(node)));
+    }
+    node.children.replace(replaced);
+}
+
+/// An `<img>` of `media`, or a link to it if it isn't an image.
+fn media_element(media: &Media) -> Handle {
+    let target = media.target.replace(' ', "%20");
+    if media.is_image() {
+        element("img", &[("src", &target), ("alt", &media.name)])
+    } else {
+        let link = element("a", &[("href", &target)]);
+        let text = Node::new(NodeData::Text {
+            contents: RefCell::new(media.name.as_str().into()),
+        });
+        text.parent.set(Some(Rc::downgrade(&link)));
+        link.children.borrow_mut().push(text);
+        link
+    }
+}
+
+fn element(name: &str, attrs: &[(&str, &str)]) -> Handle {
+    let qual_name = |name: &str| QualName::new(None, ns!(), LocalName::from(name));
+    Node::new(NodeData::Element {
+        name: QualName::new(None, ns!(html), LocalName::from(name)),
+        attrs: RefCell::new(
+            attrs
+                .iter()
+                .map(|&(name, value)| Attribute {
+                    name: qual_name(name),
+                    value: value.into(),
+                })
+                .collect(),
+        ),
+        template_contents: RefCell::new(None),
+        mathml_annotation_xml_integration_point: false,
+    })
+}
+
+/// Drop the declarations of a `style` attribute that aren't `KEPT_STYLES`, and the attribute if
+/// none are left.
+fn clean_style(attrs: &mut Vec<Attribute>) {
+    for attr in attrs.iter_mut().filter(|attr| &*attr.name.local == "style") {
+        let kept: Vec<&str> = attr
+            .value
+            .split(';')
+            .map(str::trim)
+            .filter(|declaration| {
+                declaration
+                    .split_once(':')
+                    .is_some_and(|(property, _)| KEPT_STYLES.contains(&property.trim()))
+            })
+            .collect();
+        attr.value = kept.join("; ").into();
+    }
+    attrs.retain(|attr| &*attr.name.local != "style" || !attr.value.is_empty());
+}
+
+/// `html` as the `<en-note>` of a note. Elements and attributes Evernote doesn't accept are
+/// dropped, and list items starting with `[ ]` or `[x]` become to-dos. Images and links that
+/// `media` knows by their target and name become `<en-media>` with the hash and MIME type it
+/// returns.
+pub fn to_enml(
+    html: &str,
+    mut media: impl FnMut(&str, &str) -> Option<(String, String)>,
+) -> String {
+    let dom = parse_document(RcDom::default(), ParseOpts::default()).one(html);
+    let mut enml = String::from("<en-note>");
+    if let Some(body) = find(&dom.document, "body") {
+        add_todos(&body);
+        for child in body.children.borrow().iter() {
+            write_enml(child, &mut media, &mut enml);
+        }
+    }
+    enml.push_str("</en-note>");
+    enml
+}
+
+/// Write `node` as ENML to `enml`.
+fn write_enml(node: &Handle, media: &mut MediaFn, enml: &mut String) {
+    let (name, attrs) = match node.data {
+        NodeData::Text { ref contents } => {
+            enml.push_str(&enml::escape(&contents.borrow()));
+            return;
+        }
+        NodeData::Element {
+            ref name,
+            ref attrs,
+            ..
+        } => (&*name.local, attrs.borrow()),
+        _ => return,
+    };
+    if DROPPED_ELEMENTS.contains(&name) {
+        return;
+    }
+    if !ENML_ELEMENTS.contains(&name) {
+        for child in node.children.borrow().iter() {
+            write_enml(child, media, enml);
+        }
+        return;
+    }
+    let target = match name {
+        "img" => get_tag_attr(node, "src").map(|src| (src, get_tag_attr(node, "alt"))),
+        "a" => get_tag_attr(node, "href").map(|href| (href, Some(text(node)))),
+        _ => None,
+    };
+    if let Some((target, name)) = target {
+        if let Some((hash, mime)) = media(&target, name.as_deref().unwrap_or("")) {
+            let tag = format!(
+                "<en-media hash=\"{}\" type=\"{}\"/>",
+                hash,
+                enml::escape(&mime)
+            );
+            enml.push_str(&tag);
+            return;
+        }
+    }
+    enml.push('<');
+    enml.push_str(name);
+    for attr in attrs.iter() {
+        let attr_name = &*attr.name.local;
+        let script = attr_name == "href" && attr.value.trim_start().starts_with("javascript:");
+        if attr_name.starts_with("on") || DROPPED_ATTRIBUTES.contains(&attr_name) || script {
+            continue;
+        }
+        enml.push_str(&format!(" {}=\"{}\"", attr_name, enml::escape(&attr.value)));
+    }
+    let children = node.children.borrow();
+    if children.is_empty() && (VOID_ELEMENTS.contains(&name) || name.starts_with("en-")) {
+        enml.push_str("/>");
+        return;
+    }
+    enml.push('>');
+    for child in children.iter() {
+        write_enml(child, media, enml);
+    }
+    enml.push_str(&format!("</{}>", name));
+}
+
+/// The text within `node`.
+fn text(node: &Handle) -> String {
+    match node.data {
+        NodeData::Text { ref contents } => contents.borrow().to_string(),
+        _ => node.children.borrow().iter().map(text).collect(),
+    }
+}
+
+/// Put an `<en-todo>` in place of the `[ ]` or `[x]` that starts list items within `node`.
+fn add_todos(node: &Handle) {
+    for child in node.children.borrow().iter() {
+        add_todos(child);
+    }
+    if tag_name(node) != Some("li") {
+        return;
+    }
+    let text = match leading_text(node) {
+        Some(text) => text,
+        None => return,
+    };
+    let checked = match text.data {
+        NodeData::Text { ref contents } => {
+            let mut contents = contents.borrow_mut();
+            let checked = match contents.get(..4) {
+                Some("[ ] ") => "false",
+                Some("[x] " | "[X] ") => "true",
+                _ => return,
+            };
+            let rest = contents[4..].to_string();
+            *contents = rest.into();
+            checked
+        }
+        _ => return,
+    };
+    let parent = match text.parent.take().and_then(|parent| parent.upgrade()) {
+        Some(parent) => parent,
+        None => return,
+    };
+    text.parent.set(Some(Rc::downgrade(&parent)));
+    let todo = element("en-todo", &[("checked", checked)]);
+    todo.parent.set(Some(Rc::downgrade(&parent)));
+    let mut children = parent.children.borrow_mut();
+    let index = children
+        .iter()
+        .position(|child| Rc::ptr_eq(child, &text))
+        .unwrap_or(0);
+    children.insert(index, todo);
+}
+
+/// The text a list item starts with, directly or in a paragraph.
+fn leading_text(node: &Handle) -> Option<Handle> {
+    let children = node.children.borrow();
+    let first = children.iter().find(|child| match child.data {
+        NodeData::Text { ref contents } => !contents.borrow().trim().is_empty(),
+        _ => true,
+    })?;
+    match first.data {
+        NodeData::Text { .. } => Some(first.clone()),
+        _ if tag_name(first) == Some("p") => leading_text(first),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_sanitize() {
+    let content = r#"<?xml version="1.0" encoding="UTF-8"?><!DOCTYPE en-note SYSTEM "http://xml.evernote.com/pub/enml2.dtd">
+        <en-note style="word-wrap: break-word; -webkit-nbsp-mode: space;"><div><div><div style="-en-paragraph:true; font-weight: bold;">
+        <span>a</span> <span style="font-size: 12px;">b</span><span style="color: red;"></span></div></div></div>
+        <div><en-media hash="abc" type="image/png"/></div></en-note>"#;
+    assert_eq!(
+        sanitize(content),
+        "<en-note><div style=\"font-weight: bold\">\n        a b</div>\n        \
+         <div><en-media hash=\"abc\" type=\"image/png\"></en-media></div></en-note>"
+    );
+
+    let media = HashMap::from([(
+        "abc".to_string(),
+        Media {
+            target: "files/a b.png".to_string(),
+            name: "a b.png".to_string(),
+            mime: Some("image/png".to_string()),
+            size: None,
+            recognition: None,
+            attachment: false,
+            width: None,
+        },
+    )]);
+    let content = r#"<en-note><div><en-todo checked="true"/>milk</div><div><en-todo/>eggs</div>
+        <div><en-media hash="abc" type="image/png"/></div><en-media hash="def" type="image/png"/></en-note>"#;
+    assert_eq!(
+        inner_html(content, &media),
+        "<div><input type=\"checkbox\" disabled=\"\" checked=\"\">milk</div>\
+         <div><input type=\"checkbox\" disabled=\"\">eggs</div>\n        \
+         <div><img src=\"files/a%20b.png\" alt=\"a b.png\"></div>"
+    );
+}
+
+#[test]
+fn test_to_enml() {
+    let html = "<h1 id=\"x\" onclick=\"f()\">Hi</h1><p>a<br>b &amp; <img src=\"a.png\" alt=\"A\">\
+        <img src=\"http://x.com/b.png\"></p><form><input>c</form><script>d</script>\
+        <ul><li>[x] done</li><li><p>[ ] not yet</p></li></ul><p><a href=\"f.pdf\">f</a></p>";
+    let media = |target: &str, name: &str| match (target, name) {
+        ("a.png", "A") => Some(("abc".to_string(), "image/png".to_string())),
+        ("f.pdf", "f") => Some(("def".to_string(), "application/pdf".to_string())),
+        _ => None,
+    };
+    assert_eq!(
+        to_enml(html, media),
+        "<en-note><h1>Hi</h1><p>a<br/>b &amp; <en-media hash=\"abc\" type=\"image/png\"/>\
+         <img src=\"http://x.com/b.png\"/></p>c<ul><li><en-todo checked=\"true\"/>done</li>\
+         <li><p><en-todo checked=\"false\"/>not yet</p></li></ul>\
+         <p><en-media hash=\"def\" type=\"application/pdf\"/></p></en-note>"
+    );
+}
+
+#[test]
+fn test_repair() {
+    assert_eq!(
+        repair(r#"<div><en-media hash="x" type="a/b"/><br/><a title="/>"/></div>"#),
+        r#"<div><en-media hash="x" type="a/b"></en-media><br/><a title="/>"></a></div>"#
+    );
+    assert_eq!(
+        sanitize("<en-note><div><p/>one<div>two</en-note>three"),
+        "<en-note><div><p></p>one<div>twothree</div></div></en-note>"
+    );
+}
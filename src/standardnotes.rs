@@ -0,0 +1,202 @@
+//! Standard Notes decrypted backup: a JSON document of items, a note item per note and a tag item
+//! per tag and notebook, for Standard Notes' "Import backup".
+//!
+//! Tags refer to their notes by UUID. UUIDs are derived from MD5 hashes (as version 3 UUIDs) so
+//! repeated exports produce the same ones, and importing again updates notes rather than
+//! duplicating them.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::writer::{ExportInfo, NoteWriter};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::io::Write;
+
+/// The version of the backup format.
+const VERSION: &str = "004";
+
+/// A version 3 UUID for the item of `kind` identified by `key`.
+fn uuid(kind: &str, key: &str) -> String {
+    let mut hash = md5::compute(format!("{}:{}", kind, key)).0;
+    hash[6] = (hash[6] & 0x0f) | 0x30;
+    hash[8] = (hash[8] & 0x3f) | 0x80;
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn timestamp<Tz: TimeZone>(time: &DateTime<Tz>) -> String {
+    time.with_timezone(&Utc)
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+/// A tag and the UUIDs of its notes.
+struct Tag {
+    title: String,
+    notes: Vec<String>,
+}
+
+/// Writes notes to a Standard Notes backup, once all of them are known.
+pub struct StandardNotesWriter<W: Write> {
+    writer: W,
+    notes: Vec<Value>,
+    /// Tags in the order first seen, the notebook's first.
+    tags: Vec<Tag>,
+    notebook: String,
+    /// Number of notes so far, to give notes with the same title distinct UUIDs.
+    count: usize,
+    now: DateTime<Local>,
+}
+
+impl<W: Write> StandardNotesWriter<W> {
+    /// Start a backup. Call `begin` before writing notes.
+    pub fn new(writer: W) -> Self {
+        StandardNotesWriter {
+            writer,
+            notes: Vec::new(),
+            tags: Vec::new(),
+            notebook: String::new(),
+            count: 0,
+            now: Local::now(),
+        }
+    }
+
+    /// Tag the note `note_uuid` with `title`. Standard Notes matches tags case-sensitively.
+    fn tag(&mut self, title: &str, note_uuid: &str) {
+        let index = match self.tags.iter().position(|tag| tag.title == title) {
+            Some(index) => index,
+            None => {
+                self.tags.push(Tag {
+                    title: title.to_string(),
+                    notes: Vec::new(),
+                });
+                self.tags.len() - 1
+            }
+        };
+        let notes = &mut self.tags[index].notes;
+        if !notes.iter().any(|uuid| uuid == note_uuid) {
+            notes.push(note_uuid.to_string());
+        }
+    }
+}
+
+impl<W: Write> NoteWriter for StandardNotesWriter<W> {
+    /// Start a notebook, whose notes are tagged with its name.
+    fn begin(&mut self, export: &ExportInfo) -> Result<()> {
+        self.notebook = export.name.clone();
+        Ok(())
+    }
+
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        let title = note.title.as_deref().unwrap_or("untitled");
+        let note_uuid = uuid(
+            "note",
+            &format!("{}:{}:{}", self.notebook, self.count, title),
+        );
+        self.count += 1;
+        let created = timestamp(note.created.as_ref().unwrap_or(&self.now));
+        let updated = note
+            .updated
+            .as_ref()
+            .map_or_else(|| created.clone(), timestamp);
+        let mut content = json!({
+            "title": title,
+            "text": note.content_markdown(enml),
+            "references": [],
+            "appData": {
+                "org.standardnotes.sn": { "client_updated_at": updated },
+            },
+        });
+        if let Some(ref source_url) = note.attributes.source_url {
+            content["appData"]["com.evernote"] = json!({ "source_url": source_url });
+        }
+        self.notes.push(json!({
+            "uuid": note_uuid,
+            "content_type": "Note",
+            "created_at": created,
+            "updated_at": updated,
+            "content": content,
+        }));
+
+        let notebook = self.notebook.clone();
+        self.tag(&notebook, &note_uuid);
+        for tag in &note.tags {
+            self.tag(tag, &note_uuid);
+        }
+        Ok(())
+    }
+
+    /// Write the backup, with the notes before the tags that refer to them.
+    fn finish(&mut self) -> Result<()> {
+        let now = timestamp(&self.now);
+        let tags = self.tags.iter().map(|tag| {
+            let references: Vec<Value> = tag
+                .notes
+                .iter()
+                .map(|uuid| json!({ "uuid": uuid, "content_type": "Note" }))
+                .collect();
+            json!({
+                "uuid": uuid("tag", &tag.title),
+                "content_type": "Tag",
+                "created_at": now,
+                "updated_at": now,
+                "content": {
+                    "title": tag.title,
+                    "references": references,
+                    "appData": {},
+                },
+            })
+        });
+        let items: Vec<Value> = self.notes.drain(..).chain(tags).collect();
+        let backup = json!({ "version": VERSION, "items": items });
+        writeln!(self.writer, "{:#}", backup)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_standard_notes_writer() {
+    assert_eq!(uuid("tag", "food"), uuid("tag", "food"));
+    assert_eq!(uuid("tag", "food").as_bytes()[14], b'3');
+
+    let note = Note::builder()
+        .title("Soup")
+        .tag("food")
+        .content("<en-note><div>Stir</div></en-note>")
+        .build();
+    let mut output = Vec::new();
+    let mut writer = StandardNotesWriter::new(&mut output);
+    writer
+        .begin(&ExportInfo {
+            name: "Recipes".to_string(),
+            ..ExportInfo::default()
+        })
+        .unwrap();
+    writer.write_note(&note, &EnmlOptions::default()).unwrap();
+    writer.finish().unwrap();
+    drop(writer);
+
+    let backup: Value = serde_json::from_slice(&output).unwrap();
+    let items = backup["items"].as_array().unwrap();
+    assert_eq!(items.len(), 3);
+    assert_eq!(items[0]["content_type"], "Note");
+    assert_eq!(items[0]["content"]["text"], "Stir");
+    let tags: Vec<&Value> = items[1..]
+        .iter()
+        .map(|tag| &tag["content"]["title"])
+        .collect();
+    assert_eq!(tags, ["Recipes", "food"]);
+    assert_eq!(
+        items[2]["content"]["references"][0]["uuid"],
+        items[0]["uuid"]
+    );
+}
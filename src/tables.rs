@@ -0,0 +1,205 @@
+//! Converts ENML tables to GitHub-flavored Markdown tables. html2md's own conversion drops
+//! attachments and checkboxes in cells, doesn't escape `|`, and mixes in the rows of nested
+//! tables. Tables that GFM can't represent, with merged cells or tables within cells, are kept as
+//! HTML instead.
+
+use crate::enml::{handlers, EnmlOptions};
+use html2md::common::get_tag_attr;
+use html2md::{
+    parse_html_custom, Handle, NodeData, StructuredPrinter, TagHandler, TagHandlerFactory,
+};
+use std::rc::Rc;
+
+pub struct TableHandlerFactory {
+    pub options: Rc<EnmlOptions>,
+}
+
+impl TagHandlerFactory for TableHandlerFactory {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(TableHandler {
+            options: self.options.clone(),
+        })
+    }
+}
+
+/// Replaces `<table>` with a GFM table, or the table's HTML if it is too complex.
+struct TableHandler {
+    options: Rc<EnmlOptions>,
+}
+
+impl TagHandler for TableHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        let rows = rows(tag);
+        if rows.is_empty() {
+            return;
+        }
+        let table = if rows.iter().flatten().all(is_simple) {
+            to_markdown(&rows, &self.options)
+        } else {
+            let mut html = String::new();
+            to_html(tag, &mut html);
+            html
+        };
+        printer.insert_newline();
+        printer.insert_newline();
+        printer.append_str(&table);
+        printer.insert_newline();
+        printer.insert_newline();
+    }
+
+    fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+
+    fn skip_descendants(&self) -> bool {
+        true
+    }
+}
+
+fn tag_name(node: &Handle) -> Option<String> {
+    match node.data {
+        NodeData::Element { ref name, .. } => Some(name.local.to_string()),
+        _ => None,
+    }
+}
+
+/// Child elements of `node` named one of `names`.
+fn children(node: &Handle, names: &[&str]) -> Vec<Handle> {
+    node.children
+        .borrow()
+        .iter()
+        .filter(|child| tag_name(child).is_some_and(|name| names.contains(&name.as_str())))
+        .cloned()
+        .collect()
+}
+
+/// The cells of each row of `table`, but not of tables nested in it.
+fn rows(table: &Handle) -> Vec<Vec<Handle>> {
+    let mut rows = children(table, &["tr"]);
+    for section in children(table, &["thead", "tbody", "tfoot"]) {
+        rows.extend(children(&section, &["tr"]));
+    }
+    rows.iter()
+        .map(|row| children(row, &["td", "th"]))
+        .filter(|cells| !cells.is_empty())
+        .collect()
+}
+
+/// Whether `cell` fits in a GFM table: it isn't merged with others and holds no table.
+fn is_simple(cell: &Handle) -> bool {
+    fn contains_table(node: &Handle) -> bool {
+        node.children
+            .borrow()
+            .iter()
+            .any(|child| tag_name(child).as_deref() == Some("table") || contains_table(child))
+    }
+    let spans = ["colspan", "rowspan"]
+        .iter()
+        .any(|attr| get_tag_attr(cell, attr).is_some_and(|span| span.trim() != "1"));
+    !spans && !contains_table(cell)
+}
+
+/// A GFM table with the first row as its header, since GFM tables always have one.
+fn to_markdown(rows: &[Vec<Handle>], options: &EnmlOptions) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut table = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        table.push('|');
+        for column in 0..columns {
+            let text = row
+                .get(column)
+                .map(|cell| cell_markdown(cell, options))
+                .unwrap_or_default();
+            table.push_str(&format!(" {} |", text));
+        }
+        table.push('\n');
+        if i == 0 {
+            table.push('|');
+            table.push_str(&" --- |".repeat(columns));
+            table.push('\n');
+        }
+    }
+    table.pop();
+    table
+}
+
+/// The contents of `cell` as Markdown on one line, with breaks between its blocks.
+fn cell_markdown(cell: &Handle, options: &EnmlOptions) -> String {
+    let mut html = String::new();
+    for child in cell.children.borrow().iter() {
+        to_html(child, &mut html);
+    }
+    let markdown = parse_html_custom(&html, &handlers(options));
+    markdown
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("<br>")
+        .replace('|', "\\|")
+}
+
+/// Serialize `node` back to HTML.
+fn to_html(node: &Handle, html: &mut String) {
+    match node.data {
+        NodeData::Text { ref contents } => html.push_str(&escape(&contents.borrow(), false)),
+        NodeData::Element {
+            ref name,
+            ref attrs,
+            ..
+        } => {
+            html.push('<');
+            html.push_str(&name.local);
+            for attr in attrs.borrow().iter() {
+                html.push_str(&format!(
+                    " {}=\"{}\"",
+                    attr.name.local,
+                    escape(&attr.value, true)
+                ));
+            }
+            html.push('>');
+            // Void elements have no end tag.
+            if matches!(&*name.local, "br" | "hr" | "img" | "col") {
+                return;
+            }
+            for child in node.children.borrow().iter() {
+                to_html(child, html);
+            }
+            html.push_str(&format!("</{}>", name.local));
+        }
+        _ => {}
+    }
+}
+
+fn escape(text: &str, attribute: bool) -> String {
+    let text = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    if attribute {
+        text.replace('"', "&quot;")
+    } else {
+        text
+    }
+}
+
+#[test]
+fn test_tables() {
+    let content = "<en-note><table><tbody>\
+        <tr><td><div><b>Name</b></div></td><td>Qty | count</td></tr>\
+        <tr><td><div><en-todo checked=\"true\"/>Apples</div></td><td><div>3</div><div>more</div></td></tr>\
+        <tr><td>Pears</td></tr></tbody></table></en-note>";
+    let md = parse_html_custom(content, &handlers(&EnmlOptions::default()));
+    assert_eq!(
+        md,
+        "| **Name** | Qty \\| count |\n| --- | --- |\n\
+         | - [x] Apples | 3<br>more |\n| Pears |  |"
+    );
+
+    let content = "<en-note><table><tr><td colspan=\"2\">wide</td></tr>\
+        <tr><td>a</td><td>b &amp; c</td></tr></table></en-note>";
+    let md = parse_html_custom(content, &handlers(&EnmlOptions::default()));
+    assert_eq!(
+        md,
+        "<table><tbody><tr><td colspan=\"2\">wide</td></tr>\
+         <tr><td>a</td><td>b &amp; c</td></tr></tbody></table>"
+    );
+}
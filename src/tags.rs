@@ -0,0 +1,76 @@
+//! Renaming, merging and dropping tags while converting, as read from a file like:
+//!
+//! ```toml
+//! drop = ["imported"]
+//!
+//! [rename]
+//! "2remember" = "reference"
+//! "to read" = "reading"
+//! "@reading" = "reading"
+//! ```
+//!
+//! Tags are matched ignoring case, like Evernote does. Tags renamed to the same name are merged.
+
+use crate::error::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TagMap {
+    /// New names of tags.
+    rename: HashMap<String, String>,
+    /// Tags to remove from notes.
+    drop: Vec<String>,
+}
+
+impl TagMap {
+    /// Read the tag map in `path`.
+    pub fn load(path: &Path) -> Result<TagMap> {
+        TagMap::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<TagMap> {
+        let map: TagMap = toml::from_str(text)?;
+        Ok(TagMap {
+            rename: map
+                .rename
+                .into_iter()
+                .map(|(from, to)| (from.to_lowercase(), to))
+                .collect(),
+            drop: map.drop.iter().map(|tag| tag.to_lowercase()).collect(),
+        })
+    }
+
+    /// `tags` renamed and without those dropped, each only once.
+    pub fn apply(&self, tags: &[String]) -> Vec<String> {
+        let mut mapped: Vec<String> = Vec::with_capacity(tags.len());
+        for tag in tags {
+            let key = tag.to_lowercase();
+            if self.drop.contains(&key) {
+                continue;
+            }
+            let tag = self.rename.get(&key).unwrap_or(tag);
+            if !mapped.contains(tag) {
+                mapped.push(tag.clone());
+            }
+        }
+        mapped
+    }
+}
+
+#[test]
+fn test_tag_map() {
+    let map = TagMap::parse(
+        "drop = [\"Imported\"]\n[rename]\n\"2remember\" = \"reference\"\n\"@Ref\" = \"reference\"\n",
+    )
+    .unwrap();
+    let tags: Vec<String> = ["2Remember", "imported", "@ref", "work"]
+        .iter()
+        .map(|tag| tag.to_string())
+        .collect();
+    assert_eq!(map.apply(&tags), ["reference", "work"]);
+    assert!(TagMap::parse("rename = { a = 1 }").is_err());
+}
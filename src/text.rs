@@ -0,0 +1,208 @@
+//! Plain text output, for grepping an archive or feeding a search indexer, with the markup
+//! dropped.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::render::{render, Markup};
+use pulldown_cmark::Tag;
+use std::io::Write;
+
+/// Write a single note as text, with its title underlined and its metadata on the lines below.
+pub fn write_note<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    let title = note.title.as_deref().unwrap_or("untitled");
+    writeln!(writer, "{}", title)?;
+    writeln!(writer, "{}\n", "=".repeat(title.chars().count()))?;
+    let mut metadata = false;
+    if let Some(ref created) = note.created {
+        writeln!(writer, "Created: {}", created.format("%F %R"))?;
+        metadata = true;
+    }
+    if let Some(ref updated) = note.updated {
+        writeln!(writer, "Updated: {}", updated.format("%F %R"))?;
+        metadata = true;
+    }
+    if !note.tags.is_empty() {
+        writeln!(writer, "Tags: {}", note.tags.join(", "))?;
+        metadata = true;
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        writeln!(writer, "Source: {}", source_url)?;
+        metadata = true;
+    }
+    if metadata {
+        writeln!(writer)?;
+    }
+
+    let content = to_text(&note.content_markdown(enml));
+    if !content.is_empty() {
+        writeln!(writer, "{}\n", content)?;
+    }
+    Ok(())
+}
+
+/// Render Markdown as plain text. Links keep their URL after the text, images are their name in
+/// brackets, and quotes and code blocks are indented.
+pub fn to_text(markdown: &str) -> String {
+    render(markdown, &mut Text::default())
+}
+
+#[derive(Default)]
+struct Text {
+    /// Where each quote or code block we're in starts, to indent it at its end.
+    blocks: Vec<usize>,
+    /// Where the text of each link or table cell we're in starts, to leave out URLs that repeat
+    /// it or trim the cell.
+    starts: Vec<usize>,
+}
+
+impl Markup for Text {
+    fn start(&mut self, out: &mut String, tag: Tag<'_>) {
+        match tag {
+            Tag::BlockQuote | Tag::CodeBlock(_) => self.blocks.push(out.len()),
+            Tag::Rule => out.push_str("----\n\n"),
+            Tag::Link(_, _) | Tag::TableCell => self.starts.push(out.len()),
+            Tag::Image(_, _) => out.push('['),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, out: &mut String, tag: Tag<'_>) {
+        match tag {
+            Tag::Header(_) => out.push_str("\n\n"),
+            Tag::Table(_) => out.push('\n'),
+            Tag::BlockQuote | Tag::CodeBlock(_) => {
+                let start = self.blocks.pop().unwrap_or(0);
+                let block = out.split_off(start);
+                for line in block.trim_end().lines() {
+                    if !line.is_empty() {
+                        out.push_str("    ");
+                    }
+                    out.push_str(line);
+                    out.push('\n');
+                }
+                out.push('\n');
+            }
+            Tag::Link(url, _) => {
+                let start = self.starts.pop().unwrap_or(0);
+                if out[start..] != *url && !url.starts_with('#') {
+                    out.push_str(&format!(" <{}>", url.replace("%20", " ")));
+                }
+            }
+            Tag::Image(_, _) => out.push(']'),
+            Tag::TableCell => {
+                let start = self.starts.pop().unwrap_or(0);
+                let cell = out.split_off(start);
+                out.push_str(&cell.trim().replace('\n', " "));
+                out.push('\t');
+            }
+            Tag::TableHead | Tag::TableRow => {
+                let len = out.trim_end_matches('\t').len();
+                out.truncate(len);
+                out.push('\n');
+            }
+            _ => {}
+        }
+    }
+
+    fn item(&mut self, depth: usize, number: Option<usize>) -> String {
+        let indent = "  ".repeat(depth - 1);
+        match number {
+            Some(number) => format!("{}{}. ", indent, number),
+            None => format!("{}- ", indent),
+        }
+    }
+
+    fn html(&mut self, out: &mut String, html: &str) {
+        out.push_str(&strip_tags(html));
+    }
+
+    fn inline_html(&mut self, out: &mut String, html: &str) {
+        out.push_str(&strip_tags(html));
+    }
+
+    fn footnote_reference(&mut self, out: &mut String, name: &str) {
+        out.push_str(&format!("[{}]", name));
+    }
+}
+
+/// Most words of a title taken from a note's text.
+const TITLE_WORDS: usize = 10;
+
+/// A title for a note without one: the first line of its text with any letters or digits, up to
+/// `TITLE_WORDS` words.
+pub fn title(markdown: &str) -> Option<String> {
+    let text = to_text(markdown);
+    let line = text
+        .lines()
+        .map(|line| line.trim_start_matches(|c: char| c == '-' || c.is_whitespace()))
+        .find(|line| !line.starts_with('[') && line.chars().any(char::is_alphanumeric))?;
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() > TITLE_WORDS {
+        Some(format!("{}...", words[..TITLE_WORDS].join(" ")))
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+/// The text of an HTML fragment, with a line break per `<br>` and row, and tabs between cells.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&rest[..open]);
+        let close = match rest[open..].find('>') {
+            Some(close) => open + close,
+            None => break,
+        };
+        let end_tag = rest[open + 1..].starts_with('/');
+        let tag = rest[open + 1..close].trim_start_matches('/').to_lowercase();
+        let name = tag.split(|c: char| c.is_whitespace() || c == '/').next();
+        match name {
+            Some("br") => text.push('\n'),
+            Some("tr") if !text.is_empty() && !text.ends_with('\n') => text.push('\n'),
+            Some("td" | "th") if !end_tag && !text.is_empty() && !text.ends_with(['\n', '\t']) => {
+                text.push('\t')
+            }
+            _ => {}
+        }
+        rest = &rest[close + 1..];
+    }
+    text.push_str(rest);
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+#[test]
+fn test_to_text() {
+    let markdown = "## Shopping\n\n1. **milk**\n2. eggs\n   * brown\n\n\
+                    See [site](http://x.com), <http://y.com> and ![cat.png](files/my%20cat.png)\n\n\
+                    > quoted\n\n```sh\nls\n```\n\n\
+                    | a | b |\n| --- | --- |\n| 1 | 2<br><mark>x</mark> |\n\n\
+                    <table><tr><td>c</td><td>d &amp; e</td></tr></table>";
+    assert_eq!(
+        to_text(markdown),
+        "Shopping\n\n1. milk\n2. eggs\n  - brown\n\n\
+         See site <http://x.com>, http://y.com and [cat.png]\n\n    quoted\n\n    ls\n\n\
+         a\tb\n1\t2 x\n\nc\td & e"
+    );
+}
+
+#[test]
+fn test_title() {
+    assert_eq!(
+        title("![scan.png](scan.png)\n\n---\n\n* **Call** the bank"),
+        Some("Call the bank".to_string())
+    );
+    assert_eq!(
+        title("one two three four five six seven eight nine ten eleven").as_deref(),
+        Some("one two three four five six seven eight nine ten...")
+    );
+    assert_eq!(title("\n\n"), None);
+}
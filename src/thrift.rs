@@ -0,0 +1,334 @@
+//! Thrift's binary protocol, which the Evernote API speaks: just enough to write calls and to
+//! read whatever their replies hold, as fields by id.
+
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+
+const STOP: u8 = 0;
+const BOOL: u8 = 2;
+const BYTE: u8 = 3;
+const DOUBLE: u8 = 4;
+const I16: u8 = 6;
+const I32: u8 = 8;
+const I64: u8 = 10;
+const STRING: u8 = 11;
+const STRUCT: u8 = 12;
+const MAP: u8 = 13;
+const SET: u8 = 14;
+const LIST: u8 = 15;
+
+const VERSION_1: u32 = 0x8001_0000;
+const CALL: u32 = 1;
+const REPLY: u32 = 2;
+const EXCEPTION: u32 = 3;
+
+/// How deeply structs and lists may nest in a reply, which is far deeper than Evernote's go.
+const MAX_DEPTH: usize = 64;
+
+/// A value read from a reply. Sets are read as lists.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Byte(i8),
+    Double(f64),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    /// A string or binary.
+    Binary(Vec<u8>),
+    Struct(Struct),
+    Map(Vec<(Value, Value)>),
+    List(Vec<Value>),
+}
+
+/// The fields of a struct, by id.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Struct(pub BTreeMap<i16, Value>);
+
+impl Struct {
+    pub fn string(&self, id: i16) -> Option<String> {
+        match self.0.get(&id)? {
+            Value::Binary(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn binary(&self, id: i16) -> Option<&[u8]> {
+        match self.0.get(&id)? {
+            Value::Binary(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// An integer field of any width.
+    pub fn int(&self, id: i16) -> Option<i64> {
+        match *self.0.get(&id)? {
+            Value::Byte(n) => Some(n.into()),
+            Value::I16(n) => Some(n.into()),
+            Value::I32(n) => Some(n.into()),
+            Value::I64(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn double(&self, id: i16) -> Option<f64> {
+        match *self.0.get(&id)? {
+            Value::Double(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn get_struct(&self, id: i16) -> Option<&Struct> {
+        match self.0.get(&id)? {
+            Value::Struct(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// A list or set field, empty if it's missing.
+    pub fn list(&self, id: i16) -> &[Value] {
+        match self.0.get(&id) {
+            Some(Value::List(items)) => items,
+            _ => &[],
+        }
+    }
+
+    /// A map field, empty if it's missing.
+    pub fn map(&self, id: i16) -> &[(Value, Value)] {
+        match self.0.get(&id) {
+            Some(Value::Map(entries)) => entries,
+            _ => &[],
+        }
+    }
+}
+
+impl Value {
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            Value::Binary(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn as_struct(&self) -> Option<&Struct> {
+        match self {
+            Value::Struct(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Writes a call: the message header, then the fields of its arguments.
+pub struct Call {
+    bytes: Vec<u8>,
+}
+
+impl Call {
+    pub fn new(name: &str, seq: i32) -> Self {
+        let mut call = Call { bytes: Vec::new() };
+        call.bytes.extend((VERSION_1 | CALL).to_be_bytes());
+        call.binary(name.as_bytes());
+        call.bytes.extend(seq.to_be_bytes());
+        call
+    }
+
+    fn binary(&mut self, bytes: &[u8]) {
+        self.bytes.extend((bytes.len() as i32).to_be_bytes());
+        self.bytes.extend(bytes);
+    }
+
+    fn header(&mut self, field_type: u8, id: i16) {
+        self.bytes.push(field_type);
+        self.bytes.extend(id.to_be_bytes());
+    }
+
+    pub fn string(mut self, id: i16, value: &str) -> Self {
+        self.header(STRING, id);
+        self.binary(value.as_bytes());
+        self
+    }
+
+    pub fn i32(mut self, id: i16, value: i32) -> Self {
+        self.header(I32, id);
+        self.bytes.extend(value.to_be_bytes());
+        self
+    }
+
+    pub fn bool(mut self, id: i16, value: bool) -> Self {
+        self.header(BOOL, id);
+        self.bytes.push(value as u8);
+        self
+    }
+
+    /// Start a struct field; its fields follow, up to `end`.
+    pub fn begin(mut self, id: i16) -> Self {
+        self.header(STRUCT, id);
+        self
+    }
+
+    pub fn end(mut self) -> Self {
+        self.bytes.push(STOP);
+        self
+    }
+
+    /// The call, ready to send.
+    pub fn finish(self) -> Vec<u8> {
+        self.end().bytes
+    }
+}
+
+/// Read the reply to the call `name`, returning its result struct: the return value as field 0,
+/// or the exception thrown as another.
+pub fn read_reply(bytes: &[u8], name: &str) -> Result<Struct> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let version = reader.i32()? as u32;
+    if version & 0xffff_0000 != VERSION_1 {
+        return Err(Error::Api(format!(
+            "unknown protocol version {:#x}",
+            version
+        )));
+    }
+    let reply_name = String::from_utf8_lossy(reader.binary()?).into_owned();
+    reader.i32()?;
+    let result = reader.read_struct(0)?;
+    match version & 0xff {
+        REPLY if reply_name == name => Ok(result),
+        EXCEPTION => {
+            let message = result.string(1).unwrap_or_default();
+            Err(Error::Api(format!("{} failed: {}", name, message)))
+        }
+        _ => Err(Error::Api(format!(
+            "unexpected reply {} to {}",
+            reply_name, name
+        ))),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < len {
+            return Err(Error::Api("reply ends early".to_string()));
+        }
+        let taken = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(
+            self.take(2)?.try_into().expect("2 bytes"),
+        ))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(
+            self.take(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_be_bytes(
+            self.take(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        usize::try_from(self.i32()?).map_err(|_| Error::Api("negative length".to_string()))
+    }
+
+    fn binary(&mut self) -> Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn read_struct(&mut self, depth: usize) -> Result<Struct> {
+        let mut fields = BTreeMap::new();
+        loop {
+            let field_type = self.u8()?;
+            if field_type == STOP {
+                return Ok(Struct(fields));
+            }
+            let id = self.i16()?;
+            fields.insert(id, self.value(field_type, depth + 1)?);
+        }
+    }
+
+    fn value(&mut self, value_type: u8, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Api("reply nested too deeply".to_string()));
+        }
+        Ok(match value_type {
+            BOOL => Value::Bool(self.u8()? != 0),
+            BYTE => Value::Byte(self.u8()? as i8),
+            DOUBLE => Value::Double(f64::from_bits(self.i64()? as u64)),
+            I16 => Value::I16(self.i16()?),
+            I32 => Value::I32(self.i32()?),
+            I64 => Value::I64(self.i64()?),
+            STRING => Value::Binary(self.binary()?.to_vec()),
+            STRUCT => Value::Struct(self.read_struct(depth)?),
+            MAP => {
+                let (key_type, value_type) = (self.u8()?, self.u8()?);
+                let len = self.len()?;
+                let mut entries = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    let key = self.value(key_type, depth + 1)?;
+                    entries.push((key, self.value(value_type, depth + 1)?));
+                }
+                Value::Map(entries)
+            }
+            SET | LIST => {
+                let item_type = self.u8()?;
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    items.push(self.value(item_type, depth + 1)?);
+                }
+                Value::List(items)
+            }
+            _ => return Err(Error::Api(format!("unknown type {} in reply", value_type))),
+        })
+    }
+}
+
+#[test]
+fn test_read_reply() {
+    let call = Call::new("getNote", 7)
+        .string(1, "token")
+        .begin(2)
+        .i32(1, 5)
+        .bool(2, true)
+        .end()
+        .finish();
+    assert_eq!(&call[..4], &[0x80, 0x01, 0x00, 0x01]);
+
+    // A reply with the same fields as the call, plus a list, a map and a double.
+    let mut reply = call.clone();
+    reply[3] = REPLY as u8;
+    reply.pop();
+    reply.extend([LIST, 0, 3, STRING, 0, 0, 0, 1, 0, 0, 0, 1, b'a']);
+    reply.extend([MAP, 0, 4, STRING, I64, 0, 0, 0, 1, 0, 0, 0, 1, b'k']);
+    reply.extend(7i64.to_be_bytes());
+    reply.extend([DOUBLE, 0, 5]);
+    reply.extend(52.52f64.to_bits().to_be_bytes());
+    reply.push(STOP);
+    let result = read_reply(&reply, "getNote").unwrap();
+    assert_eq!(result.string(1).as_deref(), Some("token"));
+    let inner = result.get_struct(2).unwrap();
+    assert_eq!((inner.int(1), &inner.0[&2]), (Some(5), &Value::Bool(true)));
+    assert_eq!(result.list(3)[0].as_string().as_deref(), Some("a"));
+    assert_eq!(result.map(4)[0].1, Value::I64(7));
+    assert_eq!(result.double(5), Some(52.52));
+
+    assert!(read_reply(&reply, "listTags").is_err());
+    assert!(read_reply(&reply[..reply.len() - 3], "getNote").is_err());
+}
@@ -0,0 +1,138 @@
+//! TiddlyWiki output: a `.tid` file per note, or a JSON bundle of tiddlers to import in one go.
+//! The text is Markdown, which TiddlyWiki renders with its Markdown plugin.
+
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::writer::NoteWriter;
+use chrono::{DateTime, Local, Utc};
+use serde_json::{Map, Value};
+use std::io::Write;
+
+/// TiddlyWiki timestamps are UTC with milliseconds and no separators.
+fn timestamp(time: &DateTime<Local>) -> String {
+    time.with_timezone(&Utc)
+        .format("%Y%m%d%H%M%S%3f")
+        .to_string()
+}
+
+/// Tags are space-separated, with brackets around those containing spaces.
+fn tags(tags: &[String]) -> String {
+    let tags: Vec<_> = tags
+        .iter()
+        .map(|tag| {
+            if tag.contains(' ') {
+                format!("[[{}]]", tag)
+            } else {
+                tag.clone()
+            }
+        })
+        .collect();
+    tags.join(" ")
+}
+
+/// The fields of the tiddler for `note`, with the text last.
+fn fields(note: &Note, enml: &EnmlOptions) -> Vec<(&'static str, String)> {
+    let mut fields = vec![(
+        "title",
+        note.title.clone().unwrap_or_else(|| "untitled".to_string()),
+    )];
+    if let Some(ref created) = note.created {
+        fields.push(("created", timestamp(created)));
+    }
+    if let Some(modified) = note.updated.as_ref().or(note.created.as_ref()) {
+        fields.push(("modified", timestamp(modified)));
+    }
+    if !note.tags.is_empty() {
+        fields.push(("tags", tags(&note.tags)));
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        fields.push(("source", source_url.clone()));
+    }
+    fields.push(("type", "text/x-markdown".to_string()));
+    let text = note.content_markdown(enml);
+    fields.push(("text", text));
+    fields
+}
+
+/// Write `note` as a `.tid` file: `field: value` lines, a blank line, then the text.
+pub fn write_tid<W: Write + ?Sized>(writer: &mut W, note: &Note, enml: &EnmlOptions) -> Result<()> {
+    for (key, value) in fields(note, enml) {
+        if key == "text" {
+            writeln!(writer, "\n{}", value)?;
+        } else {
+            // Field values are single-line.
+            writeln!(writer, "{}: {}", key, value.replace('\n', " "))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a JSON array of tiddlers, as accepted by TiddlyWiki's import.
+pub struct TiddlerBundle<W: Write> {
+    writer: W,
+    empty: bool,
+}
+
+impl<W: Write> TiddlerBundle<W> {
+    /// Start a bundle, writing to `writer`.
+    pub fn new(writer: W) -> Self {
+        TiddlerBundle {
+            writer,
+            empty: true,
+        }
+    }
+}
+
+impl<W: Write> NoteWriter for TiddlerBundle<W> {
+    /// Add `note` to the bundle.
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        let tiddler: Map<String, Value> = fields(note, enml)
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.into()))
+            .collect();
+        let separator = if self.empty { "[" } else { "," };
+        writeln!(self.writer, "{}", separator)?;
+        write!(self.writer, "{}", Value::Object(tiddler))?;
+        self.empty = false;
+        Ok(())
+    }
+
+    /// Write out the tiddlers so far. The array stays open until `finish`.
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Write the end of the array.
+    fn finish(&mut self) -> Result<()> {
+        if self.empty {
+            write!(self.writer, "[")?;
+        }
+        writeln!(self.writer, "\n]")?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[test]
+fn test_write_tid() {
+    use chrono::TimeZone;
+
+    let note = Note {
+        title: Some("Hello".to_string()),
+        content: Some("<en-note><div>world</div></en-note>".to_string()),
+        created: Some(
+            Utc.with_ymd_and_hms(2018, 12, 19, 11, 13, 4)
+                .unwrap()
+                .with_timezone(&Local),
+        ),
+        tags: vec!["a".to_string(), "b c".to_string()],
+        ..Note::default()
+    };
+    let mut out = Vec::new();
+    write_tid(&mut out, &note, &EnmlOptions::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "title: Hello\ncreated: 20181219111304000\nmodified: 20181219111304000\n\
+         tags: a [[b c]]\ntype: text/x-markdown\n\nworld\n"
+    );
+}
@@ -0,0 +1,368 @@
+//! Checking exports against the rules of Evernote's `evernote-export3.dtd`: which elements go
+//! where, in what order and how often, and the formats of times, numbers and attributes. Unlike
+//! the parser, which stops at the first problem or skips it, this reports every violation.
+
+use crate::enex::Limits;
+use crate::error::{Error, Result};
+use crate::pull::{self, XmlPull};
+use chrono::NaiveDateTime;
+use std::fmt;
+use std::io::Read;
+use xml::attribute::OwnedAttribute;
+use xml::common::{Position, TextPosition};
+use xml::reader::XmlEvent;
+
+/// How often an element may appear in its parent.
+#[derive(Clone, Copy, PartialEq)]
+enum Occurs {
+    Once,
+    Optional,
+    Any,
+}
+
+use Occurs::*;
+
+type Model = &'static [(&'static str, Occurs)];
+
+/// The children of elements that hold elements, in the order the DTD gives them. Other elements
+/// hold text.
+fn children(element: &str) -> Option<Model> {
+    Some(match element {
+        "en-export" => &[("note", Any)],
+        "note" => &[
+            ("title", Once),
+            ("content", Once),
+            ("created", Optional),
+            ("updated", Optional),
+            ("tag", Any),
+            ("note-attributes", Optional),
+            ("resource", Any),
+        ],
+        "note-attributes" => &[
+            ("subject-date", Optional),
+            ("latitude", Optional),
+            ("longitude", Optional),
+            ("altitude", Optional),
+            ("author", Optional),
+            ("source", Optional),
+            ("source-url", Optional),
+            ("source-application", Optional),
+            ("reminder-order", Optional),
+            ("reminder-time", Optional),
+            ("reminder-done-time", Optional),
+            ("place-name", Optional),
+            ("content-class", Optional),
+            ("application-data", Any),
+        ],
+        "resource" => &[
+            ("data", Once),
+            ("mime", Once),
+            ("width", Optional),
+            ("height", Optional),
+            ("duration", Optional),
+            ("recognition", Optional),
+            ("resource-attributes", Optional),
+            ("alternate-data", Optional),
+        ],
+        "resource-attributes" => &[
+            ("source-url", Optional),
+            ("timestamp", Optional),
+            ("latitude", Optional),
+            ("longitude", Optional),
+            ("altitude", Optional),
+            ("camera-make", Optional),
+            ("camera-model", Optional),
+            ("reco-type", Optional),
+            ("file-name", Optional),
+            ("attachment", Optional),
+            ("application-data", Any),
+        ],
+        _ => return None,
+    })
+}
+
+/// A place where an export breaks the rules.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub position: TextPosition,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+/// An element being read.
+struct Frame {
+    name: String,
+    position: TextPosition,
+    /// The children it may have, or `None` if it holds text.
+    model: Option<Model>,
+    /// Where in `model` the next child may start.
+    next: usize,
+    /// The children read so far.
+    seen: Vec<&'static str>,
+    text: String,
+}
+
+/// Check the export in `reader`, returning the violations in the order they appear. Malformed XML
+/// or going beyond `limits` ends the check, as the last violation; only errors reading `reader`
+/// are returned as errors.
+pub fn validate<R: Read>(reader: R, limits: &Limits) -> Result<Vec<Violation>> {
+    let mut reader = pull::new(reader, limits);
+    let mut violations = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    // How deep in elements that aren't allowed where they are, which aren't checked.
+    let mut ignored = 0;
+    loop {
+        let event = match XmlPull::next(&mut reader) {
+            Ok(event) => event,
+            Err(Error::Io(err)) => return Err(Error::Io(err)),
+            Err(Error::Xml(err)) => {
+                let message = format!("malformed XML: {}", err.msg());
+                violations.push(Violation {
+                    position: err.position(),
+                    message,
+                });
+                break;
+            }
+            Err(err @ Error::LimitExceeded(_)) => {
+                violations.push(Violation {
+                    position: XmlPull::position(&reader),
+                    message: err.to_string(),
+                });
+                break;
+            }
+            Err(err) => {
+                violations.push(Violation {
+                    position: XmlPull::position(&reader),
+                    message: format!("malformed XML: {}", err),
+                });
+                break;
+            }
+        };
+        let position = XmlPull::position(&reader);
+        let mut violation = |message: String| violations.push(Violation { position, message });
+        match event {
+            XmlEvent::StartElement { .. } | XmlEvent::EndElement { .. } if ignored > 0 => {
+                match event {
+                    XmlEvent::StartElement { .. } => ignored += 1,
+                    _ => ignored -= 1,
+                }
+            }
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let name = name.local_name;
+                match stack.last_mut() {
+                    None if name != "en-export" => {
+                        violation(format!("the root element is <{}>, not <en-export>", name));
+                    }
+                    None => {}
+                    Some(parent) => match place(parent, &name) {
+                        Ok(()) => {}
+                        Err(Some(message)) => violation(message),
+                        Err(None) => {
+                            violation(format!("<{}> isn't allowed in <{}>", name, parent.name));
+                            ignored = 1;
+                            continue;
+                        }
+                    },
+                }
+                check_attributes(&name, &attributes, &mut violation);
+                stack.push(Frame {
+                    model: children(&name),
+                    name,
+                    position,
+                    next: 0,
+                    seen: Vec::new(),
+                    text: String::new(),
+                });
+            }
+            XmlEvent::EndElement { .. } => {
+                let frame = stack
+                    .pop()
+                    .expect("the XML parser checks elements are closed");
+                match frame.model {
+                    Some(model) => {
+                        for &(child, occurs) in model {
+                            if occurs == Once && !frame.seen.contains(&child) {
+                                violation(format!("<{}> has no <{}>", frame.name, child));
+                            }
+                        }
+                    }
+                    None => {
+                        if let Some(message) = check_text(&frame.name, &frame.text) {
+                            violations.push(Violation {
+                                position: frame.position,
+                                message,
+                            });
+                        }
+                    }
+                }
+            }
+            XmlEvent::Characters(text) if ignored == 0 => match stack.last_mut() {
+                Some(frame) if frame.model.is_none() => frame.text.push_str(&text),
+                Some(frame) => violation(format!("<{}> can't hold text", frame.name)),
+                None => {}
+            },
+            XmlEvent::EndDocument => {
+                // xml-rs reports this as an error, but quick-xml doesn't.
+                if let Some(frame) = stack.last() {
+                    violation(format!("malformed XML: <{}> isn't closed", frame.name));
+                }
+                break;
+            }
+            _ => {}
+        }
+    }
+    Ok(violations)
+}
+
+/// Move `parent` past a child `name`. Returns a violation if it's out of order or repeated, or
+/// `None` if it isn't allowed in `parent` at all.
+fn place(parent: &mut Frame, name: &str) -> std::result::Result<(), Option<String>> {
+    let model = match parent.model {
+        Some(model) => model,
+        None => return Err(None),
+    };
+    let (index, &(child, occurs)) = match model
+        .iter()
+        .enumerate()
+        .find(|(_, &(child, _))| child == name)
+    {
+        Some(found) => found,
+        None => return Err(None),
+    };
+    let repeated = occurs != Any && parent.seen.contains(&child);
+    if !repeated {
+        parent.seen.push(child);
+    }
+    if index >= parent.next {
+        parent.next = if occurs == Any { index } else { index + 1 };
+        return Ok(());
+    }
+    let message = if repeated {
+        format!("<{}> may appear only once in <{}>", name, parent.name)
+    } else {
+        let after = model[parent.next - 1].0;
+        format!(
+            "<{}> must come before <{}> in <{}>",
+            name, after, parent.name
+        )
+    };
+    Err(Some(message))
+}
+
+fn check_attributes(
+    element: &str,
+    attributes: &[OwnedAttribute],
+    violation: &mut impl FnMut(String),
+) {
+    let allowed: &[&str] = match element {
+        "en-export" => &["export-date", "application", "version"],
+        "data" | "alternate-data" => &["encoding"],
+        "application-data" => &["key"],
+        _ => &[],
+    };
+    for attribute in attributes {
+        let name = attribute.name.local_name.as_str();
+        if !allowed.contains(&name) {
+            violation(format!("<{}> has no attribute {}", element, name));
+        } else if name == "export-date" && !is_time(&attribute.value) {
+            violation(format!("export-date {:?} isn't a time", attribute.value));
+        } else if name == "encoding" && attribute.value != "base64" {
+            violation(format!("encoding {:?} isn't base64", attribute.value));
+        }
+    }
+    let required = match element {
+        "data" | "alternate-data" => "encoding",
+        "application-data" => "key",
+        _ => return,
+    };
+    if !attributes
+        .iter()
+        .any(|attribute| attribute.name.local_name == required)
+    {
+        violation(format!("<{}> has no {} attribute", element, required));
+    }
+}
+
+/// A violation if `text` isn't what `element` may hold.
+fn check_text(element: &str, text: &str) -> Option<String> {
+    let valid = match element {
+        "title" => (1..=255).contains(&text.chars().count()),
+        "tag" => (1..=100).contains(&text.chars().count()) && !text.contains(','),
+        "created" | "updated" | "subject-date" | "reminder-time" | "reminder-done-time"
+        | "timestamp" => is_time(text),
+        "latitude" | "longitude" | "altitude" => text.parse::<f64>().is_ok(),
+        "width" | "height" | "duration" | "reminder-order" => text.parse::<i64>().is_ok(),
+        "attachment" => text == "true" || text == "false",
+        "mime" => text.contains('/'),
+        "data" | "alternate-data" => text
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"+/=\r\n\t ".contains(&b)),
+        _ => true,
+    };
+    if valid {
+        return None;
+    }
+    Some(match element {
+        "title" => "<title> must have 1 to 255 characters".to_string(),
+        "tag" => "<tag> must have 1 to 100 characters and no commas".to_string(),
+        "data" | "alternate-data" => format!("<{}> isn't base64", element),
+        _ => format!("<{}> {:?} isn't {}", element, text, expected(element)),
+    })
+}
+
+fn expected(element: &str) -> &'static str {
+    match element {
+        "latitude" | "longitude" | "altitude" => "a number",
+        "width" | "height" | "duration" | "reminder-order" => "an integer",
+        "attachment" => "true or false",
+        "mime" => "a MIME type",
+        _ => "a time like 20181226T083916Z",
+    }
+}
+
+/// Whether `text` is a time as the DTD has them, e.g. `20181226T083916Z`.
+fn is_time(text: &str) -> bool {
+    text.strip_suffix('Z')
+        .is_some_and(|time| NaiveDateTime::parse_from_str(time, "%Y%m%dT%H%M%S").is_ok())
+}
+
+#[test]
+fn test_validate() {
+    let messages = |enex: &str| -> Vec<String> {
+        validate(enex.as_bytes(), &Limits::default())
+            .unwrap()
+            .iter()
+            .map(Violation::to_string)
+            .collect()
+    };
+    let valid = "<en-export export-date=\"20181226T083916Z\"><note><title>a</title>\
+        <content><![CDATA[<en-note/>]]></content><created>20181226T083916Z</created>\
+        <tag>x</tag><tag>y</tag><resource><data encoding=\"base64\">aGk=</data>\
+        <mime>text/plain</mime></resource></note></en-export>";
+    assert_eq!(messages(valid), Vec::<String>::new());
+    let invalid = "<en-export>\n<note><content/><title>a</title><title>b</title>\
+        <created>yesterday</created><foo><bar/></foo>\n<resource><mime>x/y</mime></resource>\
+        </note></en-export>";
+    assert_eq!(
+        messages(invalid),
+        [
+            "2:17: <title> must come before <content> in <note>",
+            "2:33: <title> may appear only once in <note>",
+            "2:49: <created> \"yesterday\" isn't a time like 20181226T083916Z",
+            "2:77: <foo> isn't allowed in <note>",
+            "3:27: <resource> has no <data>",
+        ]
+    );
+    // Where text starts depends on the XML parser.
+    let text = messages("<en-export><note><title>a</title>text<content/></note></en-export>");
+    assert!(text[0].ends_with(": <note> can't hold text"));
+    let unclosed = messages("<en-export><note>");
+    assert!(unclosed[0].starts_with("1:") && unclosed[0].contains("malformed XML"));
+}
@@ -0,0 +1,368 @@
+//! A common interface for the output formats, so the backend can be chosen at runtime.
+
+use crate::asciidoc::DocumentWriter;
+use crate::enex::{EnexParser, Note};
+use crate::enml::{EnmlOptions, HighlightMode};
+use crate::error::Result;
+use crate::frontmatter::MetadataStyle;
+use crate::html::PageWriter;
+use crate::jex::JexWriter;
+use crate::mindforger::{MetadataOptions, OutlineWriter};
+use crate::naming::UniqueNames;
+use crate::notion::NotionWriter;
+use crate::org::OrgWriter;
+use crate::standardnotes::StandardNotesWriter;
+use crate::tiddlywiki::TiddlerBundle;
+use crate::{json, markdown, text};
+use chrono::{DateTime, Local};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Names of the formats, as accepted by `Format::from_name`.
+pub const FORMATS: &[&str] = &[
+    "mindforger",
+    "markdown",
+    "obsidian",
+    "logseq",
+    "bear",
+    "jex",
+    "notion",
+    "standardnotes",
+    "org",
+    "json",
+    "html",
+    "tiddlywiki",
+    "asciidoc",
+    "text",
+    "zim",
+];
+
+/// An output format.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    MindForger,
+    Markdown,
+    Obsidian,
+    Logseq,
+    Bear,
+    Jex,
+    Notion,
+    StandardNotes,
+    Org,
+    Json,
+    Html,
+    TiddlyWiki,
+    AsciiDoc,
+    Text,
+    Zim,
+}
+
+impl Format {
+    /// Parse one of `FORMATS`.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "mindforger" => Some(Format::MindForger),
+            "markdown" => Some(Format::Markdown),
+            "obsidian" => Some(Format::Obsidian),
+            "logseq" => Some(Format::Logseq),
+            "bear" => Some(Format::Bear),
+            "jex" => Some(Format::Jex),
+            "notion" => Some(Format::Notion),
+            "standardnotes" => Some(Format::StandardNotes),
+            "org" => Some(Format::Org),
+            "json" => Some(Format::Json),
+            "html" => Some(Format::Html),
+            "tiddlywiki" => Some(Format::TiddlyWiki),
+            "asciidoc" => Some(Format::AsciiDoc),
+            "text" => Some(Format::Text),
+            "zim" => Some(Format::Zim),
+            _ => None,
+        }
+    }
+
+    /// Whether the format can be written as a file per note, rather than only as one stream.
+    pub fn writes_files(self) -> bool {
+        !matches!(
+            self,
+            Format::Jex | Format::Notion | Format::StandardNotes | Format::Json
+        )
+    }
+
+    /// Extension of the files written per note.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Org => "org",
+            Format::Html => "html",
+            Format::TiddlyWiki => "tid",
+            Format::AsciiDoc => "adoc",
+            Format::Text | Format::Zim => "txt",
+            _ => "md",
+        }
+    }
+
+    /// How highlighted text is marked unless chosen otherwise: as the format's own highlighting
+    /// if it has one, or with HTML if the format renders it. Text has no way to mark it.
+    pub fn highlight(self) -> HighlightMode {
+        match self {
+            Format::Text => HighlightMode::Off,
+            Format::Obsidian | Format::Logseq | Format::Bear => HighlightMode::Equals,
+            Format::Org | Format::AsciiDoc => HighlightMode::Bold,
+            _ => HighlightMode::Mark,
+        }
+    }
+
+    /// A backend writing the whole output to `writer`, or `None` for formats that are only
+    /// written as a file per note.
+    pub fn stream_writer<'a, W: Write + 'a>(
+        self,
+        writer: W,
+        metadata: MetadataOptions,
+    ) -> Option<Box<dyn NoteWriter + 'a>> {
+        Some(match self {
+            Format::MindForger => Box::new(OutlineWriter::new(writer, metadata)),
+            Format::Markdown => {
+                // Frontmatter can only start a file.
+                let metadata = MetadataOptions {
+                    style: MetadataStyle::Native,
+                    ..metadata
+                };
+                Box::new(NoteStream::new(
+                    writer,
+                    move |writer: &mut W, note: &Note, enml: &EnmlOptions| {
+                        markdown::write_file(writer, note, enml, &metadata)
+                    },
+                ))
+            }
+            Format::Text => Box::new(NoteStream::new(writer, text::write_note)),
+            Format::Json => Box::new(NoteStream::new(writer, json::write_note)),
+            Format::Html => Box::new(PageWriter::new(writer)),
+            Format::AsciiDoc => Box::new(DocumentWriter::new(writer)),
+            Format::Org => Box::new(OrgWriter::new(writer)),
+            Format::TiddlyWiki => Box::new(TiddlerBundle::new(writer)),
+            Format::Jex => Box::new(JexWriter::new(writer)),
+            Format::Notion => Box::new(NotionWriter::new(writer)),
+            Format::StandardNotes => Box::new(StandardNotesWriter::new(writer)),
+            Format::Obsidian | Format::Logseq | Format::Bear | Format::Zim => return None,
+        })
+    }
+}
+
+/// The notebook being written, for backends with a header or an item per notebook.
+#[derive(Clone, Debug, Default)]
+pub struct ExportInfo {
+    /// Name of the notebook, usually the export's file name.
+    pub name: String,
+    /// When the export was made, if known.
+    pub export_date: Option<DateTime<Local>>,
+    /// The app that made the export and its version, if known.
+    pub application: Option<String>,
+    pub version: Option<String>,
+    /// When the export file was last modified, if it's a file.
+    pub file_modified: Option<DateTime<Local>>,
+}
+
+impl ExportInfo {
+    /// The export being read by `parser`, named after its date. Call `parser.start()` first.
+    pub fn from_parser<R: Read>(parser: &EnexParser<R>) -> Self {
+        ExportInfo {
+            application: parser.application().map(String::from),
+            version: parser.version().map(String::from),
+            ..ExportInfo::from_date(parser.export_date())
+        }
+    }
+
+    /// An export without a file name to go by, named after its date.
+    pub fn from_date(export_date: Option<DateTime<Local>>) -> Self {
+        let name = match export_date {
+            Some(date) => format!("Evernote export {}", date.format("%F")),
+            None => "Evernote export".to_string(),
+        };
+        ExportInfo {
+            name,
+            export_date,
+            ..ExportInfo::default()
+        }
+    }
+}
+
+/// The options for converting a note, for `NoteWriter::write_notes`. Called from any thread.
+pub type EnmlFn<'a> = dyn Fn(&Note) -> Result<EnmlOptions> + Sync + 'a;
+
+/// An output format. `begin` is called at the start of each notebook, `write_note` for each of its
+/// notes, and `finish` once at the end of the output.
+pub trait NoteWriter {
+    /// Start a notebook, e.g. writing a header.
+    fn begin(&mut self, _export: &ExportInfo) -> Result<()> {
+        Ok(())
+    }
+
+    /// Write `note`, with its content converted per `enml`.
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()>;
+
+    /// Write `notes` in order, converting each per `enml(note)`. Backends that write each note
+    /// independently do it in parallel.
+    fn write_notes(&mut self, notes: &[Note], enml: &EnmlFn) -> Result<()> {
+        for note in notes {
+            self.write_note(note, &enml(note)?)?;
+        }
+        Ok(())
+    }
+
+    /// Write out what is buffered so far, e.g. so that a reader of the output sees each note as
+    /// it is converted.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Write anything that goes after the last note.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes notes one after another to a stream, with no notebook header.
+pub struct NoteStream<W, F> {
+    writer: W,
+    write: F,
+}
+
+impl<W, F> NoteStream<W, F>
+where
+    W: Write,
+    F: Fn(&mut W, &Note, &EnmlOptions) -> Result<()>,
+{
+    /// Write each note to `writer` with `write`, e.g. `markdown::write_note`.
+    pub fn new(writer: W, write: F) -> Self {
+        NoteStream { writer, write }
+    }
+}
+
+impl<W, F> NoteWriter for NoteStream<W, F>
+where
+    W: Write,
+    F: Fn(&mut W, &Note, &EnmlOptions) -> Result<()>,
+{
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        (self.write)(&mut self.writer, note, enml)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Writes each note to its own file in a directory, named after its title.
+pub struct NoteFiles<'a, F> {
+    dir: PathBuf,
+    extension: &'static str,
+    names: &'a mut UniqueNames,
+    write: F,
+}
+
+impl<'a, F> NoteFiles<'a, F>
+where
+    F: Fn(&mut BufWriter<File>, &Note, &EnmlOptions) -> Result<()>,
+{
+    /// Write each note to a file in `dir` with `write`. File names are unique among `names`,
+    /// which may be shared with other directories.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        extension: &'static str,
+        names: &'a mut UniqueNames,
+        write: F,
+    ) -> Self {
+        NoteFiles {
+            dir: dir.into(),
+            extension,
+            names,
+            write,
+        }
+    }
+}
+
+impl<F> NoteWriter for NoteFiles<'_, F>
+where
+    F: Fn(&mut BufWriter<File>, &Note, &EnmlOptions) -> Result<()> + Sync,
+{
+    fn begin(&mut self, _export: &ExportInfo) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        let name = self.names.note_file_name(note, self.extension);
+        write_file(&self.dir.join(name), note, enml, &self.write)
+    }
+
+    /// Names are handed out in order, so they don't depend on which note is written first.
+    fn write_notes(&mut self, notes: &[Note], enml: &EnmlFn) -> Result<()> {
+        let names: Vec<String> = notes
+            .iter()
+            .map(|note| self.names.note_file_name(note, self.extension))
+            .collect();
+        notes.par_iter().zip(names).try_for_each(|(note, name)| {
+            write_file(&self.dir.join(name), note, &enml(note)?, &self.write)
+        })
+    }
+}
+
+/// Write `note` to a new file at `path` with `write`.
+fn write_file<F>(path: &Path, note: &Note, enml: &EnmlOptions, write: &F) -> Result<()>
+where
+    F: Fn(&mut BufWriter<File>, &Note, &EnmlOptions) -> Result<()>,
+{
+    let mut file = BufWriter::new(File::create(path)?);
+    write(&mut file, note, enml)?;
+    // Dropping would flush too, but ignore any error.
+    file.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_note_files() {
+    let dir = std::env::temp_dir().join(format!("enex2mf-writer-{}", std::process::id()));
+    let notes: Vec<Note> = (0..10)
+        .map(|i| {
+            Note::builder()
+                .title("Note")
+                .content(format!("<en-note>{}</en-note>", i))
+                .build()
+        })
+        .collect();
+    let mut names = UniqueNames::default();
+    let mut writer = NoteFiles::new(&dir, "md", &mut names, crate::markdown::write_note);
+    writer.begin(&ExportInfo::default()).unwrap();
+    writer
+        .write_notes(&notes, &|_| Ok(EnmlOptions::default()))
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("Note.md")).unwrap(),
+        "# Note\n\n0\n\n"
+    );
+    assert!(fs::read_to_string(dir.join("Note 10.md"))
+        .unwrap()
+        .contains("9"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_note_stream() {
+    let note = Note::builder()
+        .title("Hello")
+        .content("<en-note><div>world</div></en-note>")
+        .build();
+    let mut out = Vec::new();
+    let mut writer = NoteStream::new(&mut out, crate::markdown::write_note);
+    let export = ExportInfo::default();
+    writer.begin(&export).unwrap();
+    writer.write_note(&note, &EnmlOptions::default()).unwrap();
+    writer.write_note(&note, &EnmlOptions::default()).unwrap();
+    writer.finish().unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.matches("# Hello\n").count(), 2);
+}
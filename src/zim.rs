@@ -0,0 +1,276 @@
+//! Zim Desktop Wiki output: a page per note in Zim's wiki markup, under a namespace per notebook,
+//! with the note's attachments in the page's attachment folder.
+
+use crate::attachments::{self, AttachmentOptions};
+use crate::enex::Note;
+use crate::enml::EnmlOptions;
+use crate::error::Result;
+use crate::naming::UniqueNames;
+use crate::render::{end_pipe_table, render, start_pipe_table, Markup};
+use crate::writer::{ExportInfo, NoteWriter};
+use pulldown_cmark::Tag;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// The file at the root of a Zim notebook.
+pub const NOTEBOOK_FILE: &str = "notebook.zim";
+
+/// Zim names files after pages with underscores for spaces.
+fn file_name(page: &str) -> String {
+    page.replace(' ', "_")
+}
+
+/// Targets without a scheme are files in the page's attachment folder.
+fn link_target(url: &str) -> String {
+    if url.contains(':') || url.starts_with('.') || url.starts_with('/') {
+        url.to_string()
+    } else {
+        format!("./{}", url)
+    }
+}
+
+/// Write `note` as a page: Zim's header, then the title, creation date and tags, then the content.
+pub fn write_page<W: Write + ?Sized>(
+    writer: &mut W,
+    note: &Note,
+    enml: &EnmlOptions,
+) -> Result<()> {
+    writeln!(writer, "Content-Type: text/x-zim-wiki")?;
+    writeln!(writer, "Wiki-Format: zim 0.6")?;
+    if let Some(ref created) = note.created {
+        writeln!(writer, "Creation-Date: {}", created.to_rfc3339())?;
+    }
+    writeln!(writer)?;
+
+    let title = note.title.as_deref().unwrap_or("untitled");
+    writeln!(writer, "====== {} ======", title)?;
+    if let Some(ref created) = note.created {
+        writeln!(writer, "Created {}", created.format("%A %d %B %Y"))?;
+    }
+    if !note.tags.is_empty() {
+        let tags: Vec<String> = note
+            .tags
+            .iter()
+            .map(|tag| format!("@{}", file_name(tag)))
+            .collect();
+        writeln!(writer, "{}", tags.join(" "))?;
+    }
+    if let Some(ref source_url) = note.attributes.source_url {
+        writeln!(writer, "Source: {}", source_url)?;
+    }
+    writeln!(writer)?;
+
+    let content = note.content_markdown(enml);
+    if !content.is_empty() {
+        writeln!(writer, "{}\n", to_zim(&content))?;
+    }
+    Ok(())
+}
+
+/// Writes each note to a page in a Zim notebook, each notebook a namespace of it.
+pub struct ZimWriter<'a> {
+    root: PathBuf,
+    /// The directory of the notebook being written.
+    dir: PathBuf,
+    names: &'a mut UniqueNames,
+    /// How to extract attachments, except where to.
+    attachments: AttachmentOptions,
+}
+
+impl<'a> ZimWriter<'a> {
+    /// Write pages into the Zim notebook at `root`, making it one if it isn't. Page names are
+    /// unique among `names`, which should replace spaces by underscores like Zim. Attachments
+    /// are extracted as `attachments` says, into each page's folder.
+    pub fn new(
+        root: impl Into<PathBuf>,
+        names: &'a mut UniqueNames,
+        attachments: AttachmentOptions,
+    ) -> Self {
+        let root = root.into();
+        ZimWriter {
+            dir: root.clone(),
+            root,
+            names,
+            attachments,
+        }
+    }
+}
+
+impl NoteWriter for ZimWriter<'_> {
+    /// Start a namespace named after the notebook.
+    fn begin(&mut self, export: &ExportInfo) -> Result<()> {
+        fs::create_dir_all(&self.root)?;
+        let notebook = self.root.join(NOTEBOOK_FILE);
+        if !notebook.exists() {
+            let name = self.root.file_name().unwrap_or_default().to_string_lossy();
+            fs::write(
+                &notebook,
+                format!("[Notebook]\nversion=0.4\nname={}\n", name),
+            )?;
+        }
+        self.dir = self.root.join(file_name(&export.name));
+        fs::create_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    /// Write `note` as a page, extracting its attachments to the page's folder.
+    fn write_note(&mut self, note: &Note, enml: &EnmlOptions) -> Result<()> {
+        let name = self.names.note_file_name(note, "txt");
+        let folder = self.dir.join(name.trim_end_matches(".txt"));
+        let options = AttachmentOptions {
+            dir: Some(folder.clone()),
+            links_relative_to: Some(folder),
+            ..self.attachments.clone()
+        };
+        let mut enml = enml.clone();
+        enml.media.extend(attachments::media(note, &options)?);
+
+        let mut file = BufWriter::new(File::create(self.dir.join(&name))?);
+        write_page(&mut file, note, &enml)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Render Markdown as Zim's wiki markup. Headings are one level below the page title.
+pub fn to_zim(markdown: &str) -> String {
+    let zim = render(markdown, &mut Zim::default());
+    // Zim's checkboxes take the place of the bullet.
+    let lines: Vec<String> = zim
+        .lines()
+        .map(|line| {
+            let indent = line.len() - line.trim_start_matches('\t').len();
+            let (tabs, item) = line.split_at(indent);
+            match item.strip_prefix("* [ ] ") {
+                Some(rest) => format!("{}[ ] {}", tabs, rest),
+                None => match item.strip_prefix("* [x] ") {
+                    Some(rest) => format!("{}[*] {}", tabs, rest),
+                    None => line.to_string(),
+                },
+            }
+        })
+        .collect();
+    lines.join("\n")
+}
+
+#[derive(Default)]
+struct Zim {
+    /// The `=`s around the heading being written.
+    heading: String,
+    in_image: bool,
+    columns: usize,
+}
+
+impl Markup for Zim {
+    fn start(&mut self, out: &mut String, tag: Tag<'_>) {
+        match tag {
+            Tag::Rule => out.push_str("--------------------\n\n"),
+            Tag::Header(level) => {
+                self.heading = "=".repeat((6 - level as usize).max(2));
+                out.push_str(&self.heading);
+                out.push(' ');
+            }
+            Tag::CodeBlock(_) => out.push_str("'''\n"),
+            Tag::Emphasis => out.push_str("//"),
+            Tag::Strong => out.push_str("**"),
+            Tag::Code => out.push_str("''"),
+            Tag::Link(url, _) => out.push_str(&format!("[[{}|", link_target(&url))),
+            Tag::Image(url, _) => {
+                out.push_str(&format!("{{{{{}}}}}", link_target(&url)));
+                self.in_image = true;
+            }
+            tag => start_pipe_table(out, &tag, &mut self.columns),
+        }
+    }
+
+    fn end(&mut self, out: &mut String, tag: Tag<'_>) {
+        match tag {
+            Tag::Header(_) => {
+                out.push(' ');
+                out.push_str(&self.heading);
+                out.push_str("\n\n");
+            }
+            Tag::CodeBlock(_) => out.push_str("'''\n\n"),
+            Tag::Emphasis => out.push_str("//"),
+            Tag::Strong => out.push_str("**"),
+            Tag::Code => out.push_str("''"),
+            Tag::Link(_, _) => out.push_str("]]"),
+            Tag::Image(_, _) => self.in_image = false,
+            tag => end_pipe_table(out, &tag, self.columns),
+        }
+    }
+
+    fn item(&mut self, depth: usize, number: Option<usize>) -> String {
+        let marker = if number.is_some() { "1. " } else { "* " };
+        format!("{}{}", "\t".repeat(depth - 1), marker)
+    }
+
+    fn text(&mut self, out: &mut String, text: &str) {
+        if !self.in_image {
+            out.push_str(text);
+        }
+    }
+
+    fn html(&mut self, out: &mut String, html: &str) {
+        out.push_str("'''\n");
+        out.push_str(html);
+        out.push_str("'''\n\n");
+    }
+
+    fn inline_html(&mut self, out: &mut String, html: &str) {
+        if let "<mark>" | "</mark>" = html {
+            out.push_str("__");
+        }
+    }
+
+    fn footnote_reference(&mut self, out: &mut String, name: &str) {
+        out.push_str(&format!("[{}]", name));
+    }
+}
+
+#[test]
+fn test_zim_writer() {
+    use crate::enex::Resource;
+
+    let markdown = "# Shopping\n\n- **milk**\n- [x] eggs\n    1. *brown*\n\n\
+                    See [site](http://x.com), ![cat.png](a%20b/cat.png) and <mark>this</mark>\n\n\
+                    | a | b |\n| --- | --- |\n| 1 | 2 |";
+    assert_eq!(
+        to_zim(markdown),
+        "===== Shopping =====\n\n* **milk**\n[*] eggs\n\t1. //brown//\n\n\
+         See [[http://x.com|site]], {{./a%20b/cat.png}} and __this__\n\n\
+         | a | b |\n|---|---|\n| 1 | 2 |"
+    );
+
+    let dir = std::env::temp_dir().join(format!("enex2mf-zim-{}", std::process::id()));
+    let note = Note::builder()
+        .title("Soup hot")
+        .tag("to cook")
+        .content(
+            "<en-note><div>Stir</div>\
+             <en-media hash=\"1ac2109d47dbc72551f71df89d01ed18\" type=\"image/gif\"/></en-note>",
+        )
+        .resource(Resource::new(b"GIF89a", "image/gif"))
+        .build();
+    let mut names = UniqueNames::default().underscores(true);
+    let mut writer = ZimWriter::new(&dir, &mut names, AttachmentOptions::default());
+    let export = ExportInfo {
+        name: "My Recipes".to_string(),
+        ..ExportInfo::default()
+    };
+    writer.begin(&export).unwrap();
+    writer.write_note(&note, &EnmlOptions::default()).unwrap();
+    let page = fs::read_to_string(dir.join("My_Recipes/Soup_hot.txt")).unwrap();
+    assert!(page.starts_with("Content-Type: text/x-zim-wiki\nWiki-Format: zim 0.6\n\n"));
+    assert!(page.contains(
+        "====== Soup hot ======\n@to_cook\n\nStir\n\n{{./1ac2109d47dbc72551f71df89d01ed18.gif}}"
+    ));
+    let image = "My_Recipes/Soup_hot/1ac2109d47dbc72551f71df89d01ed18.gif";
+    assert!(dir.join(image).exists());
+    assert!(dir.join(NOTEBOOK_FILE).exists());
+    let other = Note::builder().title("Soup_hot").build();
+    writer.write_note(&other, &EnmlOptions::default()).unwrap();
+    assert!(dir.join("My_Recipes/Soup_hot_2.txt").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,250 @@
+//! Writing zip archives, with files stored rather than compressed: attachments are mostly
+//! compressed already, and every importer reads stored files.
+
+use crate::error::{Error, Result};
+use chrono::{Datelike, Local, Timelike};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Zip 2.0, the version that stored files need.
+const VERSION: u16 = 20;
+/// Names are UTF-8.
+const UTF8_NAMES: u16 = 1 << 11;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// The CRC-32 of `data`, as zip checks files by.
+fn crc32(data: &[u8]) -> u32 {
+    !update_crc32(!0, data)
+}
+
+fn update_crc32(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Takes the CRC-32 and size of what is written to it, to add a file with `add_with` that is
+/// written a piece at a time.
+pub struct Checksum {
+    crc: u32,
+    size: u64,
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Checksum { crc: !0, size: 0 }
+    }
+}
+
+impl Checksum {
+    pub fn crc(&self) -> u32 {
+        !self.crc
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Write for Checksum {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.crc = update_crc32(self.crc, buf);
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Counts what is written through it.
+struct Counter<'a, W> {
+    writer: &'a mut W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes files to a zip archive, then its directory on `finish`.
+pub struct ZipWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    entries: Vec<Entry>,
+    /// When the files were modified, in MS-DOS format.
+    time: u16,
+    date: u16,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(writer: W) -> Self {
+        let now = Local::now();
+        ZipWriter {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+            time: (now.hour() << 11 | now.minute() << 5 | (now.second() / 2)) as u16,
+            date: (((now.year() - 1980).max(0) as u32) << 9 | (now.month() << 5) | now.day())
+                as u16,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Add the file `name`, a path with `/` between directories.
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let size = data.len() as u64;
+        self.add_with(
+            name,
+            crc32(data),
+            size,
+            |writer| Ok(writer.write_all(data)?),
+        )
+    }
+
+    /// Add the file `name` of `size` bytes with the CRC-32 `crc`, as taken by a [`Checksum`],
+    /// which `write` writes. Its contents needn't be in memory all at once.
+    pub fn add_with(
+        &mut self,
+        name: &str,
+        crc: u32,
+        size: u64,
+        write: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        let (offset, size) = match (u32::try_from(self.offset), u32::try_from(size)) {
+            (Ok(offset), Ok(size)) if self.entries.len() < usize::from(u16::MAX) => (offset, size),
+            _ => {
+                return Err(Error::LimitExceeded(
+                    "zip archives of 4 GB or 65535 files".to_string(),
+                ));
+            }
+        };
+        let entry = Entry {
+            name: name.to_string(),
+            crc,
+            size,
+            offset,
+        };
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(LOCAL_HEADER.to_le_bytes());
+        header.extend(VERSION.to_le_bytes());
+        self.file_fields(&entry, &mut header);
+        header.extend(0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+        self.write(&header)?;
+        let mut counter = Counter {
+            writer: &mut self.writer,
+            count: 0,
+        };
+        write(&mut counter)?;
+        self.offset += counter.count;
+        if counter.count != u64::from(size) {
+            let message = format!("{} changed while it was archived", name);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// The fields that local and central headers share, from the flags to the name's length.
+    fn file_fields(&self, entry: &Entry, header: &mut Vec<u8>) {
+        header.extend(UTF8_NAMES.to_le_bytes());
+        // Stored.
+        header.extend(0u16.to_le_bytes());
+        header.extend(self.time.to_le_bytes());
+        header.extend(self.date.to_le_bytes());
+        header.extend(entry.crc.to_le_bytes());
+        header.extend(entry.size.to_le_bytes());
+        header.extend(entry.size.to_le_bytes());
+        header.extend((entry.name.len() as u16).to_le_bytes());
+    }
+
+    /// Write the directory of the files, which ends the archive.
+    pub fn finish(&mut self) -> Result<()> {
+        let start = u32::try_from(self.offset)
+            .map_err(|_| Error::LimitExceeded("zip archives of 4 GB".to_string()))?;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend(CENTRAL_HEADER.to_le_bytes());
+            directory.extend(VERSION.to_le_bytes());
+            directory.extend(VERSION.to_le_bytes());
+            self.file_fields(entry, &mut directory);
+            // No extra field, comment, disk number or attributes.
+            directory.extend([0; 12]);
+            directory.extend(entry.offset.to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+        }
+        let count = self.entries.len() as u16;
+        let mut end = Vec::with_capacity(22);
+        end.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        end.extend([0; 4]);
+        end.extend(count.to_le_bytes());
+        end.extend(count.to_le_bytes());
+        end.extend((directory.len() as u32).to_le_bytes());
+        end.extend(start.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        self.write(&directory)?;
+        self.write(&end)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"hello"), 0x3610_a686);
+    let mut checksum = Checksum::default();
+    checksum.write_all(b"hel").unwrap();
+    checksum.write_all(b"lo").unwrap();
+    assert_eq!((checksum.crc(), checksum.size()), (0x3610_a686, 5));
+}